@@ -0,0 +1,303 @@
+//! The `access_log` module logs each completed request. The dispatcher calls its access log
+//! callback once per request with an entry describing it, and the module provides callbacks that
+//! log the entries in the Common Log Format or as JSON.
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use hyper::Body;
+use serde_json::{json, Value};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::context::Context;
+
+/// Log target of the entries logged by the built-in access log callbacks
+pub const ACCESS_LOG_TARGET: &str = "webmachine::access";
+
+/// Callback that is invoked once per completed request
+pub type AccessLogCallback = Arc<dyn Fn(&AccessLogEntry) + Send + Sync>;
+
+/// A completed request
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogEntry {
+    /// When the request was received
+    pub timestamp: DateTime<Utc>,
+    /// Address of the client
+    pub remote_addr: Option<SocketAddr>,
+    /// Address of the client forwarded by the trusted proxies the request came through, if it
+    /// came through any (see `ForwardedConfig`). This is logged instead of the remote address.
+    pub forwarded_for: Option<IpAddr>,
+    /// Name of the principal the request was authenticated as
+    pub user: Option<String>,
+    /// Method of the request
+    pub method: String,
+    /// Request target (the path and query string)
+    pub target: String,
+    /// HTTP version of the request (i.e. `HTTP/1.1`)
+    pub protocol: String,
+    /// Route that matched the request
+    pub route: Option<String>,
+    /// Status of the response
+    pub status: u16,
+    /// Number of bytes of the response body sent
+    pub bytes: u64,
+    /// Time taken to handle the request. For streamed bodies, this includes sending the body.
+    pub latency: Duration,
+    /// Media type selected by content negotiation
+    pub media_type: Option<String>,
+}
+
+impl AccessLogEntry {
+    /// Creates the entry of a request that has just been received
+    pub(crate) fn received(request: &http::Request<Body>) -> AccessLogEntry {
+        AccessLogEntry {
+            timestamp: Utc::now(),
+            remote_addr: None,
+            forwarded_for: None,
+            user: None,
+            method: request.method().to_string(),
+            target: request
+                .uri()
+                .path_and_query()
+                .map(|target| target.to_string())
+                .unwrap_or_else(|| "/".to_string()),
+            protocol: format!("{:?}", request.version()),
+            route: None,
+            status: 200,
+            bytes: 0,
+            latency: Duration::ZERO,
+            media_type: None,
+        }
+    }
+
+    /// Formats the entry in the Common Log Format
+    /// (i.e. `127.0.0.1 - alice [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326`)
+    pub fn common_log_format(&self) -> String {
+        format!(
+            "{} - {} [{}] \"{} {} {}\" {} {}",
+            self.client_ip()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            self.user.as_deref().unwrap_or("-"),
+            self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.target,
+            self.protocol,
+            self.status,
+            if self.bytes == 0 {
+                "-".to_string()
+            } else {
+                self.bytes.to_string()
+            }
+        )
+    }
+
+    /// Returns the address of the client, preferring the forwarded address
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.forwarded_for
+            .or_else(|| self.remote_addr.map(|addr| addr.ip()))
+    }
+
+    /// Returns the entry as a JSON object
+    pub fn to_json(&self) -> Value {
+        json!({
+            "timestamp": self.timestamp.to_rfc3339(),
+            "remote_addr": self.client_ip().map(|addr| addr.to_string()),
+            "user": self.user,
+            "method": self.method,
+            "target": self.target,
+            "protocol": self.protocol,
+            "route": self.route,
+            "status": self.status,
+            "bytes": self.bytes,
+            "latency_ms": self.latency.as_secs_f64() * 1000.0,
+            "media_type": self.media_type
+        })
+    }
+}
+
+/// Access log callback that logs the entries in the Common Log Format
+pub fn common_log_format() -> AccessLogCallback {
+    Arc::new(|entry| info!(target: ACCESS_LOG_TARGET, "{}", entry.common_log_format()))
+}
+
+/// Access log callback that logs the entries as JSON objects
+pub fn json_access_log() -> AccessLogCallback {
+    Arc::new(|entry| info!(target: ACCESS_LOG_TARGET, "{}", entry.to_json()))
+}
+
+/// Completes the entry from the context and invokes the callback. If the response body is
+/// streamed, the callback is invoked once the body has been sent (or the client goes away).
+pub(crate) fn log_access(
+    callback: &AccessLogCallback,
+    mut entry: AccessLogEntry,
+    context: &mut Context,
+    route: Option<String>,
+) {
+    entry.remote_addr = context.request.remote_addr;
+    entry.forwarded_for = context
+        .request
+        .forwarded
+        .as_ref()
+        .and_then(|forwarded| forwarded.client_ip);
+    entry.user = context
+        .principal
+        .as_ref()
+        .map(|principal| principal.name.clone());
+    entry.route = route;
+    entry.status = context.response.status;
+    entry.media_type = context.selected_media_type.clone();
+
+    let streamed = match (&context.response.body, &context.response.stream) {
+        (None, Some(stream)) => stream.take_chunks().map(|chunks| (chunks, stream.clone())),
+        _ => None,
+    };
+    match streamed {
+        Some((chunks, stream)) => {
+            let mut pending = PendingEntry {
+                entry,
+                started: context.started,
+                callback: callback.clone(),
+            };
+            let counted = chunks.map(move |chunk| {
+                if let Ok(chunk) = &chunk {
+                    pending.sent(chunk.len());
+                }
+                chunk
+            });
+            context.response.stream = Some(stream.with_chunks(counted));
+        }
+        None => {
+            entry.bytes = context
+                .response
+                .body
+                .as_ref()
+                .map_or(0, |body| body.len() as u64);
+            entry.latency = context.started.elapsed();
+            callback(&entry);
+        }
+    }
+}
+
+/// Entry of a request with a streamed body, which is logged when the body is dropped
+struct PendingEntry {
+    entry: AccessLogEntry,
+    started: std::time::Instant,
+    callback: AccessLogCallback,
+}
+
+impl PendingEntry {
+    fn sent(&mut self, bytes: usize) {
+        self.entry.bytes += bytes as u64;
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        self.entry.latency = self.started.elapsed();
+        (self.callback)(&self.entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::StreamingBody, owned_callback, Dispatcher, Resource};
+    use expectest::prelude::*;
+    use std::sync::Mutex;
+
+    fn entry() -> AccessLogEntry {
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("http://localhost/orders?dry_run=true")
+            .body(Body::empty())
+            .unwrap();
+        AccessLogEntry {
+            timestamp: DateTime::parse_from_rfc3339("2000-10-10T13:55:36Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            remote_addr: Some("127.0.0.1:54321".parse().unwrap()),
+            user: Some("alice".to_string()),
+            route: Some("/orders".to_string()),
+            status: 201,
+            bytes: 2326,
+            latency: Duration::from_millis(12),
+            media_type: Some("application/json".to_string()),
+            ..AccessLogEntry::received(&request)
+        }
+    }
+
+    #[test]
+    fn formats_entries_in_the_common_log_format() {
+        expect!(entry().common_log_format()).to(be_equal_to(
+            "127.0.0.1 - alice [10/Oct/2000:13:55:36 +0000] \"POST /orders?dry_run=true HTTP/1.1\" \
+            201 2326",
+        ));
+    }
+
+    #[test]
+    fn formats_entries_as_json() {
+        let json = entry().to_json();
+        expect!(json["target"].clone()).to(be_equal_to(json!("/orders?dry_run=true")));
+        expect!(json["route"].clone()).to(be_equal_to(json!("/orders")));
+        expect!(json["bytes"].clone()).to(be_equal_to(json!(2326)));
+        expect!(json["latency_ms"].clone()).to(be_equal_to(json!(12.0)));
+        expect!(json["media_type"].clone()).to(be_equal_to(json!("application/json")));
+    }
+
+    #[tokio::test]
+    async fn dispatcher_calls_the_access_log_once_the_response_is_sent() {
+        let entries = Arc::new(Mutex::new(Vec::<AccessLogEntry>::new()));
+        let logged = entries.clone();
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/orders" => Resource {
+                    render_response: owned_callback(|_, _| {
+                        Box::pin(async { Some("[1, 2]".to_string()) })
+                    }),
+                    ..Resource::default()
+                },
+                "/export" => Resource {
+                    render_response: owned_callback(|context, _| {
+                        let chunks = vec![Ok(b"1,2\n".to_vec()), Ok(b"3,4\n".to_vec())];
+                        context.response.stream =
+                            Some(StreamingBody::new(futures::stream::iter(chunks)));
+                        Box::pin(async { None })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            access_log: Some(Arc::new(move |entry: &AccessLogEntry| {
+                logged.lock().unwrap().push(entry.clone())
+            })),
+            ..Dispatcher::default()
+        };
+
+        let request = http::Request::builder()
+            .uri("/orders/1")
+            .header("Accept", "application/json")
+            .body(Body::empty())
+            .unwrap();
+        dispatcher.clone().dispatch(request).await.unwrap();
+        let request = http::Request::builder()
+            .uri("/export")
+            .body(Body::empty())
+            .unwrap();
+        let response = dispatcher.dispatch(request).await.unwrap();
+        expect!(entries.lock().unwrap().len()).to(be_equal_to(1));
+        hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        let entries = entries.lock().unwrap();
+        expect!(entries.len()).to(be_equal_to(2));
+        expect!(entries[0].target.clone()).to(be_equal_to("/orders/1"));
+        expect!(entries[0].route.clone()).to(be_some().value("/orders"));
+        expect!(entries[0].status).to(be_equal_to(200));
+        expect!(entries[0].bytes).to(be_equal_to(6));
+        expect!(entries[0].media_type.clone()).to(be_some().value("application/json"));
+        expect!(entries[1].route.clone()).to(be_some().value("/export"));
+        expect!(entries[1].bytes).to(be_equal_to(8));
+    }
+}
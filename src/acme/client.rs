@@ -0,0 +1,371 @@
+//! Client of the ACME protocol (RFC 8555). Requests are signed with the ES256 key of the account
+//! and sent with hyper, over rustls for `https` URLs.
+
+use std::{
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+};
+
+use hyper::body::Bytes;
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use rustls::{pki_types::ServerName, ClientConfig};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::TlsConnector;
+
+use super::AcmeError;
+
+/// Type of the problem returned for a request with a stale nonce, which is retried once with
+/// the nonce of the response
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+
+/// Generates a PKCS#8 encoded ECDSA P-256 account key
+pub(crate) fn generate_account_key() -> Result<Vec<u8>, AcmeError> {
+    EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+        .map(|key| key.as_ref().to_vec())
+        .map_err(|_| AcmeError::InvalidAccountKey("the key could not be generated".to_string()))
+}
+
+/// Response of the certificate authority
+pub(crate) struct AcmeResponse {
+    pub(crate) status: http::StatusCode,
+    pub(crate) location: Option<String>,
+    pub(crate) nonce: Option<String>,
+    pub(crate) body: Bytes,
+}
+
+impl AcmeResponse {
+    /// Parses the body of the response as JSON
+    pub(crate) fn json(&self) -> Result<Value, AcmeError> {
+        serde_json::from_slice(&self.body)
+            .map_err(|err| AcmeError::Request(format!("Response is not valid JSON - {}", err)))
+    }
+}
+
+/// URLs of the directory of the certificate authority
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+/// Account with the certificate authority, which signs the requests made to it
+pub(crate) struct Account {
+    key_pair: EcdsaKeyPair,
+    rng: SystemRandom,
+    client_config: Arc<ClientConfig>,
+    directory: Directory,
+    kid: Option<String>,
+    nonce: Mutex<Option<String>>,
+}
+
+impl Account {
+    /// Fetches the directory and creates the account of the key, or looks up the account if it
+    /// already exists
+    pub(crate) async fn create(
+        client_config: &Arc<ClientConfig>,
+        directory_url: &str,
+        contact: &[String],
+        account_key: &[u8],
+    ) -> Result<Account, AcmeError> {
+        let rng = SystemRandom::new();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key, &rng)
+                .map_err(|err| AcmeError::InvalidAccountKey(err.to_string()))?;
+        let request = http::Request::get(directory_url).body(hyper::Body::empty());
+        let directory = success(directory_url, send(client_config, request).await?)?.json()?;
+        let mut account = Account {
+            key_pair,
+            rng,
+            client_config: client_config.clone(),
+            directory: Directory {
+                new_nonce: url_field(&directory, "newNonce")?,
+                new_account: url_field(&directory, "newAccount")?,
+                new_order: url_field(&directory, "newOrder")?,
+            },
+            kid: None,
+            nonce: Mutex::new(None),
+        };
+        let payload = json!({ "termsOfServiceAgreed": true, "contact": contact });
+        let response = account
+            .post(&account.directory.new_account, Some(&payload))
+            .await?;
+        let kid = response.location.ok_or_else(|| {
+            AcmeError::Request("The new account response has no Location".to_string())
+        })?;
+        account.kid = Some(kid);
+        Ok(account)
+    }
+
+    /// Creates an order for the domains, returning its URL and the order
+    pub(crate) async fn new_order(&self, domains: &[String]) -> Result<(String, Value), AcmeError> {
+        let identifiers = domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect::<Vec<_>>();
+        let payload = json!({ "identifiers": identifiers });
+        let response = self.post(&self.directory.new_order, Some(&payload)).await?;
+        let url = response.location.clone().ok_or_else(|| {
+            AcmeError::Request("The new order response has no Location".to_string())
+        })?;
+        Ok((url, response.json()?))
+    }
+
+    /// Base64url encoded SHA-256 thumbprint of the JSON Web Key of the account (RFC 7638), with
+    /// the members in the lexicographic order the thumbprint is defined over
+    pub(crate) fn thumbprint(&self) -> String {
+        let (x, y) = self.coordinates();
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y);
+        encode(Sha256::digest(jwk.as_bytes()))
+    }
+
+    /// Sends a signed POST request with the JSON payload, or a POST-as-GET request if there is
+    /// no payload, failing if the response is not successful
+    pub(crate) async fn post(
+        &self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<AcmeResponse, AcmeError> {
+        let payload = payload.map(Value::to_string).unwrap_or_default();
+        let mut retried = false;
+        loop {
+            let nonce = self.nonce().await?;
+            let body = self.sign(url, &payload, &nonce)?;
+            let request = http::Request::post(url)
+                .header("Content-Type", "application/jose+json")
+                .body(hyper::Body::from(body.to_string()));
+            let response = send(&self.client_config, request).await?;
+            if let (Some(nonce), Ok(mut next)) = (&response.nonce, self.nonce.lock()) {
+                *next = Some(nonce.clone());
+            }
+            if !retried && !response.status.is_success() {
+                let problem = response.json().unwrap_or(Value::Null);
+                if problem["type"] == BAD_NONCE {
+                    retried = true;
+                    continue;
+                }
+            }
+            return success(url, response);
+        }
+    }
+
+    /// Takes the nonce of the last response, or fetches a new one
+    async fn nonce(&self) -> Result<String, AcmeError> {
+        if let Some(nonce) = self.nonce.lock().ok().and_then(|mut nonce| nonce.take()) {
+            return Ok(nonce);
+        }
+        let url = &self.directory.new_nonce;
+        let request = http::Request::head(url).body(hyper::Body::empty());
+        send(&self.client_config, request)
+            .await?
+            .nonce
+            .ok_or_else(|| AcmeError::Request(format!("'{}' returned no Replay-Nonce", url)))
+    }
+
+    /// Builds the flattened JWS of the payload (RFC 8555 section 6.2). The key of the account
+    /// is embedded until the account has been created, and then its URL is used instead.
+    fn sign(&self, url: &str, payload: &str, nonce: &str) -> Result<Value, AcmeError> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => {
+                let (x, y) = self.coordinates();
+                protected["jwk"] = json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y });
+            }
+        }
+        let protected = encode(protected.to_string());
+        let payload = encode(payload);
+        let signature = self
+            .key_pair
+            .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| AcmeError::InvalidAccountKey("the request could not be signed".into()))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": encode(signature.as_ref())
+        }))
+    }
+
+    /// Base64url encoded coordinates of the public key of the account
+    fn coordinates(&self) -> (String, String) {
+        let (x, y) = self.key_pair.public_key().as_ref()[1..].split_at(32);
+        (encode(x), encode(y))
+    }
+}
+
+fn encode<T: AsRef<[u8]>>(data: T) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn url_field(directory: &Value, name: &str) -> Result<String, AcmeError> {
+    directory[name]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| AcmeError::Request(format!("The directory has no {} URL", name)))
+}
+
+/// Fails with the problem detail of the response if it is not successful
+fn success(url: &str, response: AcmeResponse) -> Result<AcmeResponse, AcmeError> {
+    if response.status.is_success() {
+        Ok(response)
+    } else {
+        let problem = response.json().unwrap_or(Value::Null);
+        Err(AcmeError::Request(format!(
+            "Request to '{}' failed with status {} - {}",
+            url,
+            response.status,
+            problem["detail"].as_str().unwrap_or("no detail was given")
+        )))
+    }
+}
+
+/// Sends the request on a new connection to the host of its URL
+async fn send(
+    client_config: &Arc<ClientConfig>,
+    request: Result<http::Request<hyper::Body>, http::Error>,
+) -> Result<AcmeResponse, AcmeError> {
+    let request =
+        request.map_err(|err| AcmeError::Request(format!("Invalid request - {}", err)))?;
+    let url = request.uri().clone();
+    let failed = |err: String| AcmeError::Request(format!("Request to '{}' failed - {}", url, err));
+    let (host, authority) = match (url.host(), url.authority()) {
+        (Some(host), Some(authority)) => (host.to_string(), authority.to_string()),
+        _ => return Err(failed("the URL has no host".to_string())),
+    };
+    let https = url.scheme_str() == Some("https");
+    let port = url.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let (mut parts, body) = request.into_parts();
+    parts.uri = url
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/")
+        .parse()
+        .map_err(|err: http::uri::InvalidUri| failed(err.to_string()))?;
+    let host_header = authority
+        .parse()
+        .map_err(|err: http::header::InvalidHeaderValue| failed(err.to_string()))?;
+    parts.headers.insert(http::header::HOST, host_header);
+    let request = http::Request::from_parts(parts, body);
+
+    let stream = TcpStream::connect((host.as_str(), port))
+        .await
+        .map_err(|err| failed(err.to_string()))?;
+    let response = if https {
+        let name = ServerName::try_from(host).map_err(|err| failed(err.to_string()))?;
+        let stream = TlsConnector::from(client_config.clone())
+            .connect(name, stream)
+            .await
+            .map_err(|err| failed(err.to_string()))?;
+        exchange(stream, request).await
+    } else {
+        exchange(stream, request).await
+    }
+    .map_err(|err| failed(err.to_string()))?;
+
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    Ok(AcmeResponse {
+        status: response.status(),
+        location: header("Location"),
+        nonce: header("Replay-Nonce"),
+        body: response.body().clone(),
+    })
+}
+
+async fn exchange<S>(
+    stream: S,
+    request: http::Request<hyper::Body>,
+) -> Result<http::Response<Bytes>, hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+    tokio::spawn(connection);
+    let (parts, body) = sender.send_request(request).await?.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    Ok(http::Response::from_parts(parts, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use expectest::prelude::*;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+
+    use super::*;
+
+    fn account() -> Account {
+        let rng = SystemRandom::new();
+        let key = generate_account_key().unwrap();
+        Account {
+            key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &key, &rng)
+                .unwrap(),
+            rng,
+            client_config: super::super::AcmeConfig::default().client_config,
+            directory: Directory {
+                new_nonce: String::default(),
+                new_account: String::default(),
+                new_order: String::default(),
+            },
+            kid: None,
+            nonce: Mutex::new(None),
+        }
+    }
+
+    fn decode(data: &Value) -> Vec<u8> {
+        base64::decode_config(data.as_str().unwrap(), base64::URL_SAFE_NO_PAD).unwrap()
+    }
+
+    #[test]
+    fn sign_builds_a_jws_the_account_key_verifies() {
+        let mut account = account();
+        let jws = account
+            .sign("https://ca/new-account", "{}", "nonce1")
+            .unwrap();
+        let protected: Value = serde_json::from_slice(&decode(&jws["protected"])).unwrap();
+        expect!(protected["alg"].clone()).to(be_equal_to(json!("ES256")));
+        expect!(protected["nonce"].clone()).to(be_equal_to(json!("nonce1")));
+        expect!(protected["url"].clone()).to(be_equal_to(json!("https://ca/new-account")));
+        expect!(protected["jwk"]["crv"].clone()).to(be_equal_to(json!("P-256")));
+        expect!(decode(&jws["payload"])).to(be_equal_to(b"{}".to_vec()));
+
+        let signed = format!(
+            "{}.{}",
+            jws["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        );
+        let public_key = UnparsedPublicKey::new(
+            &ECDSA_P256_SHA256_FIXED,
+            account.key_pair.public_key().as_ref(),
+        );
+        expect!(public_key.verify(signed.as_bytes(), &decode(&jws["signature"]))).to(be_ok());
+
+        account.kid = Some("https://ca/account/1".to_string());
+        let jws = account.sign("https://ca/order/1", "", "nonce2").unwrap();
+        let protected: Value = serde_json::from_slice(&decode(&jws["protected"])).unwrap();
+        expect!(protected["kid"].clone()).to(be_equal_to(json!("https://ca/account/1")));
+        expect!(protected.get("jwk").is_none()).to(be_true());
+        expect!(jws["payload"].clone()).to(be_equal_to(json!("")));
+    }
+
+    #[test]
+    fn thumbprint_is_the_digest_of_the_required_members_of_the_key() {
+        let account = account();
+        let (x, y) = account.coordinates();
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y);
+        expect!(account.thumbprint()).to(be_equal_to(encode(Sha256::digest(jwk.as_bytes()))));
+        expect!(account.thumbprint().len()).to(be_equal_to(43));
+    }
+}
@@ -0,0 +1,632 @@
+//! The `acme` module obtains TLS certificates from an ACME certificate authority (i.e. Let's
+//! Encrypt) and renews them before they expire. It is enabled with the `acme` feature. The
+//! domains are validated with HTTP-01 challenges, whose key authorizations are put in the
+//! challenge store of the config, so an `AcmeChallengeResource` with the same store must be
+//! served on port 80 of the domains at `ACME_CHALLENGE_PATH`. The store is pluggable with the
+//! `AcmeChallengeStore` trait, so replicas behind a load balancer can share their challenges.
+//!
+//! The certificates are served by a rustls server config whose certificate resolver always
+//! returns the current certificate, so renewed certificates are used for new connections
+//! without restarting the listener.
+//!
+//! ```no_run
+//! # use webmachine::acme::{AcmeCertificates, AcmeConfig};
+//! # use webmachine::builtin::{AcmeChallengeResource, MemoryAcmeChallengeStore};
+//! # use std::sync::Arc;
+//! # async fn run() {
+//! let challenges = MemoryAcmeChallengeStore::new();
+//! let challenge_resource = AcmeChallengeResource::new(challenges.clone());
+//! let certificates = AcmeCertificates::new(AcmeConfig {
+//!   domains: vec!["example.com".to_string()],
+//!   contact: vec!["mailto:admin@example.com".to_string()],
+//!   challenges: Arc::new(challenges),
+//!   ..AcmeConfig::default()
+//! });
+//! let server_config = certificates.server_config();
+//! tokio::spawn(async move { certificates.run().await });
+//! # }
+//! ```
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rustls::{
+    crypto::ring::{default_provider, sign::any_ecdsa_type},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use serde_json::{json, Value};
+
+use crate::builtin::{AcmeChallengeStore, MemoryAcmeChallengeStore};
+
+use self::client::{generate_account_key, Account};
+
+mod client;
+
+/// Directory of the Let's Encrypt production environment
+pub const LETS_ENCRYPT_PRODUCTION_DIRECTORY: &str =
+    "https://acme-v02.api.letsencrypt.org/directory";
+/// Directory of the Let's Encrypt staging environment, which issues untrusted certificates
+pub const LETS_ENCRYPT_STAGING_DIRECTORY: &str =
+    "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// Configuration for obtaining certificates from an ACME certificate authority
+#[derive(Clone)]
+pub struct AcmeConfig {
+    /// Domains the certificate is issued for
+    pub domains: Vec<String>,
+    /// Contact URLs of the account (i.e. `mailto:admin@example.com`). Defaults to none.
+    pub contact: Vec<String>,
+    /// URL of the directory of the certificate authority. Defaults to the Let's Encrypt staging
+    /// directory, which issues untrusted certificates; use `LETS_ENCRYPT_PRODUCTION_DIRECTORY`
+    /// once the setup works.
+    pub directory_url: String,
+    /// Store the HTTP-01 challenges are added to while the domains are validated. It must be
+    /// the store of the `AcmeChallengeResource` that answers the challenges. Defaults to an
+    /// empty `MemoryAcmeChallengeStore`.
+    pub challenges: Arc<dyn AcmeChallengeStore>,
+    /// PKCS#8 encoded ECDSA P-256 key of the account. Defaults to None (a new account is
+    /// created each time the process starts).
+    pub account_key: Option<Vec<u8>>,
+    /// How long before the certificate expires it is renewed. Defaults to 30 days.
+    pub renew_before: Duration,
+    /// Delay between polls of the status of an order. Defaults to 2 seconds.
+    pub poll_interval: Duration,
+    /// Number of polls before an order that is still pending is abandoned. Defaults to 30.
+    pub poll_attempts: usize,
+    /// Delay before retrying after a certificate could not be obtained. Defaults to 1 hour.
+    pub retry_interval: Duration,
+    /// TLS config for the requests to the certificate authority. Defaults to trusting the
+    /// Mozilla root certificates. Directories with an `http` URL are requested without TLS,
+    /// which is only meant for testing against a local certificate authority.
+    pub client_config: Arc<ClientConfig>,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> AcmeConfig {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        AcmeConfig {
+            domains: vec![],
+            contact: vec![],
+            directory_url: LETS_ENCRYPT_STAGING_DIRECTORY.to_string(),
+            challenges: Arc::new(MemoryAcmeChallengeStore::new()),
+            account_key: None,
+            renew_before: Duration::from_secs(30 * 24 * 60 * 60),
+            poll_interval: Duration::from_secs(2),
+            poll_attempts: 30,
+            retry_interval: Duration::from_secs(60 * 60),
+            client_config: Arc::new(
+                ClientConfig::builder_with_provider(Arc::new(default_provider()))
+                    .with_safe_default_protocol_versions()
+                    .expect("the ring provider supports the default protocol versions")
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            ),
+        }
+    }
+}
+
+/// Error obtaining a certificate
+#[derive(Debug)]
+pub enum AcmeError {
+    /// A request to the certificate authority failed, or it rejected the request
+    Request(String),
+    /// The account key is not a PKCS#8 encoded ECDSA P-256 key
+    InvalidAccountKey(String),
+    /// The certificate authority offered no HTTP-01 challenge for the domain
+    NoHttp01Challenge(String),
+    /// The order or one of its authorizations is no longer valid
+    Invalid(String),
+    /// The order was still pending after all the polling attempts
+    Timeout,
+    /// The certificate could not be generated or the issued one could not be read
+    Certificate(String),
+}
+
+impl fmt::Display for AcmeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcmeError::Request(detail) => write!(f, "ACME request failed - {}", detail),
+            AcmeError::InvalidAccountKey(detail) => {
+                write!(f, "ACME account key is invalid - {}", detail)
+            }
+            AcmeError::NoHttp01Challenge(domain) => {
+                write!(f, "No HTTP-01 challenge was offered for '{}'", domain)
+            }
+            AcmeError::Invalid(detail) => write!(f, "ACME order is invalid - {}", detail),
+            AcmeError::Timeout => write!(f, "ACME order was not completed in time"),
+            AcmeError::Certificate(detail) => write!(f, "Certificate is invalid - {}", detail),
+        }
+    }
+}
+
+impl std::error::Error for AcmeError {}
+
+/// Certificate served for the domains of the config. Clones share the same certificate, so one
+/// clone can serve as the resolver of the server config while another renews it.
+#[derive(Clone)]
+pub struct AcmeCertificates {
+    config: Arc<AcmeConfig>,
+    account_key: Arc<Mutex<Option<Vec<u8>>>>,
+    current: Arc<RwLock<Option<Issued>>>,
+}
+
+/// Certificate that has been obtained, with the time it expires
+struct Issued {
+    key: Arc<CertifiedKey>,
+    expires: SystemTime,
+}
+
+impl AcmeCertificates {
+    /// Creates the certificates for the config. No certificate is served until one is obtained
+    /// with `obtain` or `run`.
+    pub fn new(config: AcmeConfig) -> AcmeCertificates {
+        AcmeCertificates {
+            account_key: Arc::new(Mutex::new(config.account_key.clone())),
+            config: Arc::new(config),
+            current: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Returns the current certificate, if one has been obtained
+    pub fn certified_key(&self) -> Option<Arc<CertifiedKey>> {
+        self.current
+            .read()
+            .ok()
+            .and_then(|current| current.as_ref().map(|issued| issued.key.clone()))
+    }
+
+    /// Returns when the current certificate expires, if one has been obtained
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.current
+            .read()
+            .ok()
+            .and_then(|current| current.as_ref().map(|issued| issued.expires))
+    }
+
+    /// Builds a rustls server config that serves the current certificate, with `h2` and
+    /// `http/1.1` as the ALPN protocols. For HTTP/3, set the ALPN protocols to `h3` and convert
+    /// the config with `quinn::crypto::rustls::QuicServerConfig`.
+    pub fn server_config(&self) -> ServerConfig {
+        let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("the ring provider supports the default protocol versions")
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(self.clone()));
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        config
+    }
+
+    /// Obtains a certificate and then renews it before it expires, for as long as the future is
+    /// polled. Failures are logged and retried after the retry interval of the config.
+    pub async fn run(&self) {
+        loop {
+            let delay = renewal_delay(self.expires(), self.config.renew_before, SystemTime::now());
+            tokio::time::sleep(delay).await;
+            match self.obtain().await {
+                Ok(expires) => info!(
+                    "Obtained a certificate for {:?}, which expires at {}",
+                    self.config.domains,
+                    chrono::DateTime::<chrono::Utc>::from(expires)
+                ),
+                Err(err) => {
+                    warn!(
+                        "Failed to obtain a certificate for {:?}: {}",
+                        self.config.domains, err
+                    );
+                    tokio::time::sleep(self.config.retry_interval).await;
+                }
+            }
+        }
+    }
+
+    /// Obtains a new certificate from the certificate authority and serves it, returning when
+    /// it expires
+    pub async fn obtain(&self) -> Result<SystemTime, AcmeError> {
+        let account = Account::create(
+            &self.config.client_config,
+            &self.config.directory_url,
+            &self.config.contact,
+            &self.account_key()?,
+        )
+        .await?;
+        let (order_url, order) = account.new_order(&self.config.domains).await?;
+
+        let mut tokens = vec![];
+        let validated = self
+            .validate(&account, &order_url, &order, &mut tokens)
+            .await;
+        for token in &tokens {
+            self.config.challenges.remove(token);
+        }
+        let order = validated?;
+
+        let key_pair = rcgen::KeyPair::generate().map_err(certificate_error)?;
+        let mut params = rcgen::CertificateParams::new(self.config.domains.clone())
+            .map_err(certificate_error)?;
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let csr = params
+            .serialize_request(&key_pair)
+            .map_err(certificate_error)?;
+        let payload = json!({ "csr": base64::encode_config(csr.der(), base64::URL_SAFE_NO_PAD) });
+        let finalize = url_of(&order, "finalize")?;
+        let order = account.post(&finalize, Some(&payload)).await?.json()?;
+        let order = self.poll_order(&account, &order_url, order).await?;
+        if order["status"] != "valid" {
+            return Err(AcmeError::Invalid(format!(
+                "order is {} after it was finalized",
+                order["status"]
+            )));
+        }
+        let response = account.post(&url_of(&order, "certificate")?, None).await?;
+        let chain = String::from_utf8_lossy(&response.body);
+
+        let (key, expires) = certified_key(&chain, key_pair.serialize_der())?;
+        if let Ok(mut current) = self.current.write() {
+            *current = Some(Issued {
+                key: Arc::new(key),
+                expires,
+            });
+        }
+        Ok(expires)
+    }
+
+    /// Returns the key of the account, generating one the first time if the config has none
+    fn account_key(&self) -> Result<Vec<u8>, AcmeError> {
+        let mut account_key = self
+            .account_key
+            .lock()
+            .map_err(|_| AcmeError::InvalidAccountKey("the key lock is poisoned".to_string()))?;
+        match account_key.as_ref() {
+            Some(key) => Ok(key.clone()),
+            None => {
+                let key = generate_account_key()?;
+                *account_key = Some(key.clone());
+                Ok(key)
+            }
+        }
+    }
+
+    /// Answers the HTTP-01 challenges of the pending authorizations of the order, and waits for
+    /// the order to be ready. The tokens of the challenges are added to `tokens`, so they can be
+    /// removed from the store whether or not the validation succeeds.
+    async fn validate(
+        &self,
+        account: &Account,
+        order_url: &str,
+        order: &Value,
+        tokens: &mut Vec<String>,
+    ) -> Result<Value, AcmeError> {
+        let thumbprint = account.thumbprint();
+        let authorizations = order["authorizations"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        for url in authorizations.iter().filter_map(Value::as_str) {
+            let auth = account.post(url, None).await?.json()?;
+            let domain = auth["identifier"]["value"]
+                .as_str()
+                .unwrap_or(url)
+                .to_string();
+            match auth["status"].as_str() {
+                Some("valid") => continue,
+                Some("pending") => (),
+                status => {
+                    return Err(AcmeError::Invalid(format!(
+                        "authorization for '{}' is {}",
+                        domain,
+                        status.unwrap_or("missing")
+                    )))
+                }
+            }
+            let challenge = auth["challenges"]
+                .as_array()
+                .and_then(|challenges| {
+                    challenges
+                        .iter()
+                        .find(|challenge| challenge["type"] == "http-01")
+                })
+                .ok_or_else(|| AcmeError::NoHttp01Challenge(domain.clone()))?;
+            let token = challenge["token"]
+                .as_str()
+                .ok_or_else(|| AcmeError::NoHttp01Challenge(domain.clone()))?;
+            self.config
+                .challenges
+                .insert(token, &key_authorization(token, &thumbprint));
+            tokens.push(token.to_string());
+            account
+                .post(&url_of(challenge, "url")?, Some(&json!({})))
+                .await?;
+        }
+
+        let order = self.poll_order(account, order_url, order.clone()).await?;
+        if order["status"] == "ready" {
+            Ok(order)
+        } else {
+            Err(AcmeError::Invalid(format!(
+                "order is {} after it was validated",
+                order["status"]
+            )))
+        }
+    }
+
+    /// Polls the order while it is pending or processing
+    async fn poll_order(
+        &self,
+        account: &Account,
+        order_url: &str,
+        mut order: Value,
+    ) -> Result<Value, AcmeError> {
+        for _ in 0..self.config.poll_attempts {
+            match order["status"].as_str() {
+                Some("pending") | Some("processing") => {
+                    tokio::time::sleep(self.config.poll_interval).await;
+                    order = account.post(order_url, None).await?.json()?;
+                }
+                Some("invalid") => {
+                    let detail = order["error"]["detail"]
+                        .as_str()
+                        .unwrap_or("no detail was given");
+                    return Err(AcmeError::Invalid(detail.to_string()));
+                }
+                _ => return Ok(order),
+            }
+        }
+        Err(AcmeError::Timeout)
+    }
+}
+
+impl fmt::Debug for AcmeCertificates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AcmeCertificates")
+            .field("domains", &self.config.domains)
+            .field("directory_url", &self.config.directory_url)
+            .field("expires", &self.expires())
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for AcmeCertificates {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.certified_key()
+    }
+}
+
+/// Returns how long to wait before the certificate expiring at `expires` is renewed
+fn renewal_delay(expires: Option<SystemTime>, renew_before: Duration, now: SystemTime) -> Duration {
+    expires
+        .and_then(|expires| expires.checked_sub(renew_before))
+        .and_then(|renew_at| renew_at.duration_since(now).ok())
+        .unwrap_or_default()
+}
+
+/// Key authorization of an HTTP-01 challenge (RFC 8555 section 8.1)
+fn key_authorization(token: &str, thumbprint: &str) -> String {
+    format!("{}.{}", token, thumbprint)
+}
+
+fn url_of(object: &Value, field: &str) -> Result<String, AcmeError> {
+    object[field]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| AcmeError::Invalid(format!("the response has no {} URL", field)))
+}
+
+fn certificate_error<E: fmt::Display>(err: E) -> AcmeError {
+    AcmeError::Certificate(err.to_string())
+}
+
+/// Builds the certified key from the PEM encoded certificate chain and the PKCS#8 encoded
+/// private key, returning it with the time the certificate expires
+fn certified_key(
+    chain: &str,
+    private_key: Vec<u8>,
+) -> Result<(CertifiedKey, SystemTime), AcmeError> {
+    let chain = CertificateDer::pem_slice_iter(chain.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(certificate_error)?;
+    let leaf = chain
+        .first()
+        .ok_or_else(|| AcmeError::Certificate("the chain is empty".to_string()))?;
+    let (_, certificate) =
+        x509_parser::parse_x509_certificate(leaf.as_ref()).map_err(certificate_error)?;
+    let not_after = certificate.validity().not_after.timestamp();
+    let expires = UNIX_EPOCH + Duration::from_secs(not_after.max(0) as u64);
+    let key = any_ecdsa_type(&PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(private_key)))
+        .map_err(certificate_error)?;
+    Ok((CertifiedKey::new(chain, key), expires))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use expectest::prelude::*;
+
+    use super::*;
+
+    fn self_signed(not_after: (i32, u8, u8)) -> (String, Vec<u8>) {
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        params.not_after = rcgen::date_time_ymd(not_after.0, not_after.1, not_after.2);
+        let certificate = params.self_signed(&key_pair).unwrap();
+        (certificate.pem(), key_pair.serialize_der())
+    }
+
+    /// Starts a certificate authority that issues a self signed certificate for `example.com`,
+    /// returning its directory URL and the key authorizations it found in the challenge store
+    /// when the challenges were answered
+    async fn certificate_authority(
+        challenges: Arc<dyn AcmeChallengeStore>,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let validated = Arc::new(Mutex::new(vec![]));
+        let state = Arc::new(Mutex::new(HashMap::new()));
+        let (certificate, _) = self_signed((2030, 1, 1));
+        let url = base.clone();
+        let found = validated.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (base, challenges, found, state, certificate) = (
+                    url.clone(),
+                    challenges.clone(),
+                    found.clone(),
+                    state.clone(),
+                    certificate.clone(),
+                );
+                let service = hyper::service::service_fn(move |req: http::Request<hyper::Body>| {
+                    let base = base.clone();
+                    let mut state = state.lock().unwrap();
+                    let mut response = http::Response::builder().header("Replay-Nonce", "nonce");
+                    let order = |status: &str| {
+                        json!({
+                            "status": status,
+                            "authorizations": [format!("{}/authz/1", base)],
+                            "finalize": format!("{}/finalize/1", base),
+                            "certificate": format!("{}/cert/1", base)
+                        })
+                    };
+                    let body = match req.uri().path() {
+                        "/directory" => json!({
+                            "newNonce": format!("{}/nonce", base),
+                            "newAccount": format!("{}/account", base),
+                            "newOrder": format!("{}/order", base)
+                        })
+                        .to_string(),
+                        "/account" if state.insert("account", "bad nonce").is_none() => {
+                            response = response.status(400);
+                            json!({ "type": "urn:ietf:params:acme:error:badNonce" }).to_string()
+                        }
+                        "/account" => {
+                            response = response.header("Location", format!("{}/account/1", base));
+                            "{}".to_string()
+                        }
+                        "/order" => {
+                            response = response.header("Location", format!("{}/order/1", base));
+                            order("pending").to_string()
+                        }
+                        "/authz/1" => {
+                            let (dns, http) = (base.clone() + "/dns/1", base.clone() + "/chall/1");
+                            json!({
+                                "status": "pending",
+                                "identifier": { "type": "dns", "value": "example.com" },
+                                "challenges": [
+                                    { "type": "dns-01", "url": dns, "token": "dns" },
+                                    { "type": "http-01", "url": http, "token": "token1" }
+                                ]
+                            })
+                            .to_string()
+                        }
+                        "/chall/1" => {
+                            let key_authorization = challenges.key_authorization("token1");
+                            if let Some(key_authorization) = key_authorization {
+                                found.lock().unwrap().push(key_authorization);
+                                state.insert("order", "ready");
+                            }
+                            "{}".to_string()
+                        }
+                        "/finalize/1" => {
+                            state.insert("order", "valid");
+                            order("processing").to_string()
+                        }
+                        "/order/1" => order(state.get("order").unwrap_or(&"pending")).to_string(),
+                        "/cert/1" => certificate.clone(),
+                        _ => String::default(),
+                    };
+                    let response = response.body(hyper::Body::from(body));
+                    async move { response }
+                });
+                tokio::spawn(hyper::server::conn::Http::new().serve_connection(stream, service));
+            }
+        });
+        (format!("{}/directory", base), validated)
+    }
+
+    #[tokio::test]
+    async fn obtain_answers_the_http_01_challenge_and_serves_the_certificate() {
+        let challenges: Arc<dyn AcmeChallengeStore> = Arc::new(MemoryAcmeChallengeStore::new());
+        let (directory_url, validated) = certificate_authority(challenges.clone()).await;
+        let certificates = AcmeCertificates::new(AcmeConfig {
+            domains: vec!["example.com".to_string()],
+            directory_url,
+            challenges: challenges.clone(),
+            poll_interval: Duration::from_millis(1),
+            ..AcmeConfig::default()
+        });
+
+        let expires = certificates.obtain().await.unwrap();
+        expect!(expires.duration_since(UNIX_EPOCH).unwrap().as_secs())
+            .to(be_equal_to(1_893_456_000));
+        expect!(certificates.expires()).to(be_some().value(expires));
+        expect!(certificates.certified_key().map(|key| key.cert.len())).to(be_some().value(1));
+        let validated = validated.lock().unwrap().clone();
+        expect!(validated.len()).to(be_equal_to(1));
+        expect!(validated[0].starts_with("token1.")).to(be_true());
+        expect!(challenges.key_authorization("token1")).to(be_none());
+    }
+
+    #[tokio::test]
+    async fn obtain_fails_if_the_directory_is_unreachable() {
+        let certificates = AcmeCertificates::new(AcmeConfig {
+            domains: vec!["example.com".to_string()],
+            directory_url: "http://127.0.0.1:1/directory".to_string(),
+            ..AcmeConfig::default()
+        });
+        expect!(certificates.obtain().await).to(be_err());
+        expect!(certificates.certified_key().is_none()).to(be_true());
+    }
+
+    #[test]
+    fn renewal_delay_waits_until_the_renew_before_period() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let day = Duration::from_secs(24 * 60 * 60);
+        expect!(renewal_delay(None, day, now)).to(be_equal_to(Duration::default()));
+        expect!(renewal_delay(Some(now + day * 3), day, now)).to(be_equal_to(day * 2));
+        expect!(renewal_delay(Some(now + day / 2), day, now)).to(be_equal_to(Duration::default()));
+        expect!(renewal_delay(Some(now - day), day, now)).to(be_equal_to(Duration::default()));
+    }
+
+    #[test]
+    fn certified_key_reads_the_chain_and_when_it_expires() {
+        let (chain, private_key) = self_signed((2030, 1, 1));
+        let (key, expires) = certified_key(&chain, private_key).unwrap();
+        expect!(key.cert.len()).to(be_equal_to(1));
+        expect!(expires.duration_since(UNIX_EPOCH).unwrap().as_secs())
+            .to(be_equal_to(1_893_456_000));
+    }
+
+    #[test]
+    fn certified_key_rejects_an_empty_chain() {
+        expect!(certified_key("", vec![])).to(be_err());
+    }
+
+    #[test]
+    fn server_config_has_no_certificate_until_one_is_obtained() {
+        let certificates = AcmeCertificates::new(AcmeConfig {
+            domains: vec!["example.com".to_string()],
+            ..AcmeConfig::default()
+        });
+        let config = certificates.server_config();
+        expect!(certificates.certified_key().is_none()).to(be_true());
+        expect!(config.alpn_protocols.len()).to(be_equal_to(2));
+    }
+
+    #[cfg(feature = "http3")]
+    #[test]
+    fn server_config_can_be_used_for_http3() {
+        let certificates = AcmeCertificates::new(AcmeConfig::default());
+        let mut config = certificates.server_config();
+        config.alpn_protocols = vec![b"h3".to_vec()];
+        use std::convert::TryFrom;
+        expect!(quinn::crypto::rustls::QuicServerConfig::try_from(config).is_ok()).to(be_true());
+    }
+}
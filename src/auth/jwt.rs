@@ -0,0 +1,163 @@
+//! JSON Web Token (JWT) bearer token authentication, enabled with the `jwt` feature
+
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::{
+    authorization_credentials, Authentication, AuthenticationFuture, Authenticator, Principal,
+};
+use crate::context::Request;
+
+/// Authenticator for bearer tokens that are JWTs signed with HS256 or RS256. The subject claim
+/// of a valid token is used as the name of the principal, and all the claims are added to the
+/// attributes of the principal (string claims as is, other claims as JSON).
+#[derive(Clone)]
+pub struct JwtAuthenticator {
+    /// Realm returned in the challenge
+    pub realm: String,
+    /// Key used to verify the token signatures
+    pub key: DecodingKey,
+    /// Validation of the token algorithm and claims (expiry, audience, issuer, clock skew)
+    pub validation: Validation,
+}
+
+impl JwtAuthenticator {
+    /// Creates an authenticator for tokens signed with HS256 and the shared secret
+    pub fn hs256<S: Into<String>>(realm: S, secret: &[u8]) -> JwtAuthenticator {
+        JwtAuthenticator {
+            realm: realm.into(),
+            key: DecodingKey::from_secret(secret),
+            validation: Validation::new(Algorithm::HS256),
+        }
+    }
+
+    /// Creates an authenticator for tokens signed with RS256, verified with the PEM encoded
+    /// public key
+    pub fn rs256<S: Into<String>>(
+        realm: S,
+        public_key_pem: &[u8],
+    ) -> Result<JwtAuthenticator, String> {
+        let key = DecodingKey::from_rsa_pem(public_key_pem)
+            .map_err(|err| format!("Invalid RSA public key - {}", err))?;
+        Ok(JwtAuthenticator {
+            realm: realm.into(),
+            key,
+            validation: Validation::new(Algorithm::RS256),
+        })
+    }
+
+    /// Requires the audience claim of tokens to be one of the given audiences
+    pub fn with_audience(mut self, audience: &[&str]) -> JwtAuthenticator {
+        self.validation.set_audience(audience);
+        self
+    }
+
+    /// Requires the issuer claim of tokens to be one of the given issuers
+    pub fn with_issuer(mut self, issuer: &[&str]) -> JwtAuthenticator {
+        self.validation.set_issuer(issuer);
+        self
+    }
+
+    /// Sets the number of seconds of clock skew allowed when checking the time based claims
+    pub fn with_leeway(mut self, seconds: u64) -> JwtAuthenticator {
+        self.validation.leeway = seconds;
+        self
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    fn authenticate(&self, request: &Request) -> AuthenticationFuture {
+        let result = match authorization_credentials(request, "Bearer") {
+            Some(token) => {
+                match decode::<HashMap<String, Value>>(token.trim(), &self.key, &self.validation) {
+                    Ok(data) => Authentication::Authenticated(principal_from_claims(data.claims)),
+                    Err(err) => Authentication::Failed(format!("Invalid JWT - {}", err)),
+                }
+            }
+            None => Authentication::NoCredentials,
+        };
+        Box::pin(async { result })
+    }
+
+    fn challenge(&self) -> String {
+        format!("Bearer realm=\"{}\"", self.realm)
+    }
+}
+
+fn principal_from_claims(claims: HashMap<String, Value>) -> Principal {
+    let attributes: HashMap<String, String> = claims
+        .into_iter()
+        .map(|(claim, value)| match value {
+            Value::String(value) => (claim, value),
+            value => (claim, value.to_string()),
+        })
+        .collect();
+    Principal {
+        name: attributes.get("sub").cloned().unwrap_or_default(),
+        scheme: "Bearer".to_string(),
+        attributes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::HeaderValue;
+    use expectest::prelude::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    fn request(token: &str) -> Request {
+        Request {
+            headers: hashmap! {
+                "Authorization".to_string() => vec![HeaderValue::basic(format!("Bearer {}", token))]
+            },
+            ..Request::default()
+        }
+    }
+
+    fn token(claims: Value) -> String {
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(b"secret")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn jwt_authenticator_validates_the_token_and_claims() {
+        let authenticator = JwtAuthenticator::hs256("api", b"secret")
+            .with_audience(&["my-api"])
+            .with_issuer(&["https://issuer.example.com"]);
+        let exp = chrono::Utc::now().timestamp() + 60;
+
+        let valid = token(json!({
+            "sub": "user-1", "aud": "my-api", "iss": "https://issuer.example.com",
+            "exp": exp, "roles": ["admin"]
+        }));
+        match authenticator.authenticate(&request(&valid)).await {
+            Authentication::Authenticated(principal) => {
+                expect!(principal.name).to(be_equal_to("user-1"));
+                expect!(principal.attributes.get("roles").cloned())
+                    .to(be_some().value("[\"admin\"]"));
+            }
+            result => panic!("Expected the token to be valid, got {:?}", result),
+        }
+
+        let wrong_audience = token(json!({
+            "sub": "user-1", "aud": "other", "iss": "https://issuer.example.com", "exp": exp
+        }));
+        expect!(matches!(
+            authenticator.authenticate(&request(&wrong_audience)).await,
+            Authentication::Failed(_)
+        ))
+        .to(be_true());
+
+        let expired = token(json!({
+            "sub": "user-1", "aud": "my-api", "iss": "https://issuer.example.com",
+            "exp": exp - 3600
+        }));
+        expect!(matches!(
+            authenticator.authenticate(&request(&expired)).await,
+            Authentication::Failed(_)
+        ))
+        .to(be_true());
+    }
+}
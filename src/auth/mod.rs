@@ -0,0 +1,508 @@
+//! The `auth` module provides pluggable authentication and authorization for resources. The
+//! authenticators of a resource are consulted at decision B8, and the principal of the first one
+//! that accepts the credentials of the request is stored in the context for the later decisions
+//! and callbacks. The permissions required by a resource are then checked by an authorizer at
+//! decision B7.
+
+use futures::Future;
+use std::{collections::HashMap, pin::Pin, sync::Arc};
+
+use crate::{context::Request, headers::HeaderValue};
+
+#[cfg(feature = "jwt")]
+mod jwt;
+#[cfg(feature = "jwt")]
+pub use self::jwt::*;
+
+/// Principal (user, client, service) that a request has been authenticated as
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Principal {
+    /// Name of the principal (user name, client ID, token subject, etc.)
+    pub name: String,
+    /// Authentication scheme that the principal was authenticated with (i.e. Basic)
+    pub scheme: String,
+    /// Additional attributes of the principal, like roles or token claims. They are added to
+    /// the metadata of the context prefixed with `principal.` (i.e. `principal.sub`).
+    pub attributes: HashMap<String, String>,
+}
+
+impl Principal {
+    /// Creates a principal authenticated with the given scheme
+    pub fn new<N: Into<String>, S: Into<String>>(name: N, scheme: S) -> Principal {
+        Principal {
+            name: name.into(),
+            scheme: scheme.into(),
+            attributes: HashMap::new(),
+        }
+    }
+}
+
+/// Outcome of an authenticator checking a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Authentication {
+    /// The request does not have credentials for this authenticator
+    NoCredentials,
+    /// The credentials are valid, and belong to the principal
+    Authenticated(Principal),
+    /// The request has credentials for this authenticator, but they are not valid
+    Failed(String),
+    /// The credentials are valid, but are not allowed to access the resource. This results in
+    /// a '403 Forbidden' response instead of a '401 Unauthorized'.
+    Forbidden(String),
+}
+
+/// Future returned by an authenticator
+pub type AuthenticationFuture = Pin<Box<dyn Future<Output = Authentication> + Send>>;
+
+/// Authenticates requests to a resource
+pub trait Authenticator: Send + Sync {
+    /// Checks the credentials of the request
+    fn authenticate(&self, request: &Request) -> AuthenticationFuture;
+
+    /// Challenge to return in the WWW-Authenticate header when a request is not authenticated
+    /// (i.e. `Basic realm="api"`)
+    fn challenge(&self) -> String;
+}
+
+/// Function that verifies a user name and password
+pub type PasswordVerifier =
+    Arc<dyn Fn(String, String) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync>;
+
+/// Function that verifies a token, returning the principal the token belongs to if it is valid
+pub type TokenVerifier = Arc<
+    dyn Fn(String) -> Pin<Box<dyn Future<Output = Option<Principal>> + Send>> + Send + Sync,
+>;
+
+/// Authenticator for the Basic authentication scheme (RFC 7617)
+#[derive(Clone)]
+pub struct BasicAuthenticator {
+    /// Realm returned in the challenge
+    pub realm: String,
+    /// Verifies the user name and password from the request
+    pub verifier: PasswordVerifier,
+}
+
+impl BasicAuthenticator {
+    /// Creates a Basic authenticator for the realm
+    pub fn new<S: Into<String>>(realm: S, verifier: PasswordVerifier) -> BasicAuthenticator {
+        BasicAuthenticator {
+            realm: realm.into(),
+            verifier,
+        }
+    }
+}
+
+impl Authenticator for BasicAuthenticator {
+    fn authenticate(&self, request: &Request) -> AuthenticationFuture {
+        let credentials = match authorization_credentials(request, "Basic") {
+            Some(credentials) => credentials,
+            None => return Box::pin(async { Authentication::NoCredentials }),
+        };
+        let decoded = base64::decode(credentials.trim())
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        let (user, password) = match decoded.as_ref().and_then(|d| d.split_once(':')) {
+            Some((user, password)) => (user.to_string(), password.to_string()),
+            None => {
+                return Box::pin(async {
+                    Authentication::Failed("Malformed Basic credentials".to_string())
+                })
+            }
+        };
+        let verify = (self.verifier)(user.clone(), password);
+        Box::pin(async move {
+            if verify.await {
+                Authentication::Authenticated(Principal::new(user, "Basic"))
+            } else {
+                Authentication::Failed("Invalid user name or password".to_string())
+            }
+        })
+    }
+
+    fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm)
+    }
+}
+
+/// Authenticator for the Bearer token authentication scheme (RFC 6750)
+#[derive(Clone)]
+pub struct BearerAuthenticator {
+    /// Realm returned in the challenge
+    pub realm: String,
+    /// Verifies the token from the request
+    pub verifier: TokenVerifier,
+}
+
+impl BearerAuthenticator {
+    /// Creates a Bearer authenticator for the realm
+    pub fn new<S: Into<String>>(realm: S, verifier: TokenVerifier) -> BearerAuthenticator {
+        BearerAuthenticator {
+            realm: realm.into(),
+            verifier,
+        }
+    }
+}
+
+impl Authenticator for BearerAuthenticator {
+    fn authenticate(&self, request: &Request) -> AuthenticationFuture {
+        let token = match authorization_credentials(request, "Bearer") {
+            Some(token) => token.trim().to_string(),
+            None => return Box::pin(async { Authentication::NoCredentials }),
+        };
+        let verify = (self.verifier)(token);
+        Box::pin(async move {
+            match verify.await {
+                Some(principal) => Authentication::Authenticated(principal),
+                None => Authentication::Failed("Invalid bearer token".to_string()),
+            }
+        })
+    }
+
+    fn challenge(&self) -> String {
+        format!("Bearer realm=\"{}\"", self.realm)
+    }
+}
+
+/// Where an API key is taken from in the request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiKeySource {
+    /// Request header with the given name
+    Header(String),
+    /// Query parameter with the given name
+    Query(String),
+}
+
+/// API key known to the application
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKey {
+    /// The key
+    pub key: String,
+    /// Principal that the key belongs to
+    pub principal: Principal,
+    /// If the key is enabled. Requests with a disabled key will get a '403 Forbidden' response.
+    pub enabled: bool,
+}
+
+/// Function that looks up the API key presented in a request
+pub type ApiKeyLookup =
+    Arc<dyn Fn(String) -> Pin<Box<dyn Future<Output = Option<ApiKey>> + Send>> + Send + Sync>;
+
+/// Authenticator for API keys passed in a header or query parameter. The key presented in the
+/// request is compared to the key returned from the lookup function in constant time, so the
+/// lookup can find keys by a prefix or hash without leaking the rest of the key through timing.
+#[derive(Clone)]
+pub struct ApiKeyAuthenticator {
+    /// Realm returned in the challenge
+    pub realm: String,
+    /// Where the key is taken from
+    pub source: ApiKeySource,
+    /// Looks up the key presented in the request
+    pub lookup: ApiKeyLookup,
+}
+
+impl ApiKeyAuthenticator {
+    /// Creates an authenticator for keys passed in the X-API-Key header
+    pub fn new<S: Into<String>>(realm: S, lookup: ApiKeyLookup) -> ApiKeyAuthenticator {
+        ApiKeyAuthenticator {
+            realm: realm.into(),
+            source: ApiKeySource::Header("X-API-Key".to_string()),
+            lookup,
+        }
+    }
+}
+
+impl Authenticator for ApiKeyAuthenticator {
+    fn authenticate(&self, request: &Request) -> AuthenticationFuture {
+        let presented = match &self.source {
+            ApiKeySource::Header(name) => request.find_header(name).first().map(|h| h.to_string()),
+            ApiKeySource::Query(name) => request
+                .query
+                .get(name)
+                .and_then(|values| values.first())
+                .cloned(),
+        };
+        let presented = match presented.filter(|key| !key.is_empty()) {
+            Some(key) => key,
+            None => return Box::pin(async { Authentication::NoCredentials }),
+        };
+        let lookup = (self.lookup)(presented.clone());
+        Box::pin(async move {
+            match lookup.await {
+                Some(api_key) if constant_time_eq(api_key.key.as_bytes(), presented.as_bytes()) => {
+                    if api_key.enabled {
+                        Authentication::Authenticated(api_key.principal)
+                    } else {
+                        Authentication::Forbidden("API key is disabled".to_string())
+                    }
+                }
+                _ => Authentication::Failed("Invalid API key".to_string()),
+            }
+        })
+    }
+
+    fn challenge(&self) -> String {
+        format!("ApiKey realm=\"{}\"", self.realm)
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Outcome of an authorizer checking the permissions of a principal
+#[derive(Debug, Clone, PartialEq)]
+pub enum Authorization {
+    /// The principal has the required permissions
+    Granted,
+    /// The principal does not have the required permissions. If a detail message is given, the
+    /// '403 Forbidden' response will have a problem details (RFC 7807) body with it.
+    Denied(Option<String>),
+}
+
+/// Future returned by an authorizer
+pub type AuthorizationFuture = Pin<Box<dyn Future<Output = Authorization> + Send>>;
+
+/// Checks that the principal of a request has the permissions required by a resource
+pub trait Authorizer: Send + Sync {
+    /// Checks the principal (None if the request was not authenticated) has all the required
+    /// permissions
+    fn authorize(
+        &self,
+        principal: Option<&Principal>,
+        required_permissions: &[&str],
+        request: &Request,
+    ) -> AuthorizationFuture;
+}
+
+/// Authorizer that grants the permissions listed in an attribute of the principal, like the
+/// `scope` or `roles` claims of a token. The attribute can be a JSON array of strings, or a list
+/// separated by spaces or commas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeAuthorizer {
+    /// Name of the principal attribute with the granted permissions
+    pub attribute: String,
+    /// If denied requests get a problem details body listing the missing permissions
+    pub problem_details: bool,
+}
+
+impl AttributeAuthorizer {
+    /// Creates an authorizer that reads the permissions from the attribute
+    pub fn new<S: Into<String>>(attribute: S) -> AttributeAuthorizer {
+        AttributeAuthorizer {
+            attribute: attribute.into(),
+            problem_details: true,
+        }
+    }
+
+    /// Returns the permissions granted to the principal
+    pub fn granted_permissions(&self, principal: &Principal) -> Vec<String> {
+        match principal.attributes.get(&self.attribute) {
+            Some(value) if value.trim_start().starts_with('[') => {
+                serde_json::from_str(value).unwrap_or_default()
+            }
+            Some(value) => value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|permission| !permission.is_empty())
+                .map(|permission| permission.to_string())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Authorizer for AttributeAuthorizer {
+    fn authorize(
+        &self,
+        principal: Option<&Principal>,
+        required_permissions: &[&str],
+        _request: &Request,
+    ) -> AuthorizationFuture {
+        let granted = principal
+            .map(|principal| self.granted_permissions(principal))
+            .unwrap_or_default();
+        let missing: Vec<&str> = required_permissions
+            .iter()
+            .filter(|permission| !granted.iter().any(|granted| granted == *permission))
+            .cloned()
+            .collect();
+        let result = if missing.is_empty() {
+            Authorization::Granted
+        } else if self.problem_details {
+            Authorization::Denied(Some(format!("Missing permissions: {}", missing.join(", "))))
+        } else {
+            Authorization::Denied(None)
+        };
+        Box::pin(async { result })
+    }
+}
+
+/// Returns the credentials from the Authorization header of the request if they are for the
+/// given scheme
+pub fn authorization_credentials(request: &Request, scheme: &str) -> Option<String> {
+    request
+        .find_header("Authorization")
+        .first()
+        .map(HeaderValue::to_string)
+        .and_then(|authorization| {
+            let (auth_scheme, credentials) = authorization.trim().split_once(' ')?;
+            if auth_scheme.eq_ignore_ascii_case(scheme) {
+                Some(credentials.to_string())
+            } else {
+                None
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    fn request(authorization: &str) -> Request {
+        Request {
+            headers: hashmap! {
+                "Authorization".to_string() => vec![HeaderValue::basic(authorization)]
+            },
+            ..Request::default()
+        }
+    }
+
+    fn basic() -> BasicAuthenticator {
+        BasicAuthenticator::new(
+            "api",
+            Arc::new(|user, password| {
+                Box::pin(async move { user == "bob" && password == "s3cr:t" })
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn basic_authenticator_checks_the_user_name_and_password() {
+        let authenticator = basic();
+        expect!(authenticator.authenticate(&request("Basic Ym9iOnMzY3I6dA==")).await)
+            .to(be_equal_to(Authentication::Authenticated(Principal::new("bob", "Basic"))));
+        expect!(authenticator.authenticate(&request("basic Ym9iOndyb25n")).await)
+            .to(be_equal_to(Authentication::Failed(
+                "Invalid user name or password".to_string(),
+            )));
+        expect!(authenticator.authenticate(&request("Basic !!!")).await).to(be_equal_to(
+            Authentication::Failed("Malformed Basic credentials".to_string()),
+        ));
+        expect!(authenticator.authenticate(&request("Bearer abc")).await)
+            .to(be_equal_to(Authentication::NoCredentials));
+        expect!(authenticator.authenticate(&Request::default()).await)
+            .to(be_equal_to(Authentication::NoCredentials));
+    }
+
+    #[tokio::test]
+    async fn bearer_authenticator_checks_the_token() {
+        let authenticator = BearerAuthenticator::new(
+            "api",
+            Arc::new(|token| {
+                Box::pin(async move {
+                    if token == "abc123" {
+                        Some(Principal::new("client", "Bearer"))
+                    } else {
+                        None
+                    }
+                })
+            }),
+        );
+        expect!(authenticator.authenticate(&request("Bearer abc123")).await).to(be_equal_to(
+            Authentication::Authenticated(Principal::new("client", "Bearer")),
+        ));
+        expect!(authenticator.authenticate(&request("Bearer nope")).await)
+            .to(be_equal_to(Authentication::Failed("Invalid bearer token".to_string())));
+        expect!(authenticator.challenge()).to(be_equal_to("Bearer realm=\"api\"".to_string()));
+    }
+
+    fn api_keys() -> ApiKeyAuthenticator {
+        ApiKeyAuthenticator::new(
+            "api",
+            Arc::new(|key| {
+                Box::pin(async move {
+                    // look up by prefix, so the full key comparison is up to the authenticator
+                    if key.starts_with("k1") {
+                        Some(ApiKey {
+                            key: "k1-secret".to_string(),
+                            principal: Principal::new("client-1", "ApiKey"),
+                            enabled: true,
+                        })
+                    } else if key.starts_with("k2") {
+                        Some(ApiKey {
+                            key: "k2-secret".to_string(),
+                            principal: Principal::new("client-2", "ApiKey"),
+                            enabled: false,
+                        })
+                    } else {
+                        None
+                    }
+                })
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn api_key_authenticator_checks_the_key_from_the_header() {
+        let authenticator = api_keys();
+        let request = |key: &str| Request {
+            headers: hashmap! { "x-api-key".to_string() => vec![HeaderValue::basic(key)] },
+            ..Request::default()
+        };
+        expect!(authenticator.authenticate(&request("k1-secret")).await).to(be_equal_to(
+            Authentication::Authenticated(Principal::new("client-1", "ApiKey")),
+        ));
+        expect!(authenticator.authenticate(&request("k1-guess")).await)
+            .to(be_equal_to(Authentication::Failed("Invalid API key".to_string())));
+        expect!(authenticator.authenticate(&request("k2-secret")).await)
+            .to(be_equal_to(Authentication::Forbidden("API key is disabled".to_string())));
+        expect!(authenticator.authenticate(&Request::default()).await)
+            .to(be_equal_to(Authentication::NoCredentials));
+    }
+
+    #[tokio::test]
+    async fn api_key_authenticator_can_take_the_key_from_the_query() {
+        let authenticator = ApiKeyAuthenticator {
+            source: ApiKeySource::Query("api_key".to_string()),
+            ..api_keys()
+        };
+        let request = Request {
+            query: hashmap! { "api_key".to_string() => vec!["k1-secret".to_string()] },
+            ..Request::default()
+        };
+        expect!(authenticator.authenticate(&request).await).to(be_equal_to(
+            Authentication::Authenticated(Principal::new("client-1", "ApiKey")),
+        ));
+    }
+
+    #[test]
+    fn constant_time_eq_test() {
+        expect!(constant_time_eq(b"abc", b"abc")).to(be_true());
+        expect!(constant_time_eq(b"abc", b"abd")).to(be_false());
+        expect!(constant_time_eq(b"abc", b"abcd")).to(be_false());
+    }
+
+    #[tokio::test]
+    async fn attribute_authorizer_checks_the_required_permissions() {
+        let authorizer = AttributeAuthorizer::new("scope");
+        let principal = Principal {
+            attributes: hashmap! { "scope".to_string() => "read:orders write:orders".to_string() },
+            ..Principal::new("client", "Bearer")
+        };
+        let request = Request::default();
+        expect!(authorizer.authorize(Some(&principal), &["read:orders"], &request).await)
+            .to(be_equal_to(Authorization::Granted));
+        expect!(authorizer.authorize(Some(&principal), &["read:orders", "admin"], &request).await)
+            .to(be_equal_to(Authorization::Denied(Some("Missing permissions: admin".to_string()))));
+        expect!(authorizer.authorize(None, &["read:orders"], &request).await).to(be_equal_to(
+            Authorization::Denied(Some("Missing permissions: read:orders".to_string())),
+        ));
+
+        let roles = Principal {
+            attributes: hashmap! { "roles".to_string() => "[\"admin\",\"user\"]".to_string() },
+            ..Principal::new("user", "Bearer")
+        };
+        expect!(AttributeAuthorizer::new("roles").granted_permissions(&roles))
+            .to(be_equal_to(vec!["admin".to_string(), "user".to_string()]));
+    }
+}
@@ -0,0 +1,305 @@
+//! The `availability` module restricts when a resource is served, for batch-backed endpoints
+//! that are only valid at certain times and for scheduled maintenance. Outside its availability
+//! windows, or during maintenance, requests to the resource get a '503 Service Unavailable'
+//! response with a Retry-After header of when it will next be available.
+//!
+//! Windows are declared with a cron-like spec of the days of the week and a time range in UTC,
+//! i.e. `Mon-Fri 09:00-17:00`, `Sat,Sun 10:00-12:30` or `* 22:00-02:00` (a window that ends
+//! at or before its start runs past midnight).
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+
+use crate::{context::Context, headers::HeaderValue, retry_after_seconds, Response};
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Recurring window of time, in UTC, that a resource is available in
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvailabilityWindow {
+    /// Days of the week the window starts on
+    pub days: Vec<Weekday>,
+    /// Time of day the window starts
+    pub start: NaiveTime,
+    /// Time of day the window ends. If this is not after the start, the window ends on the
+    /// next day.
+    pub end: NaiveTime,
+}
+
+impl AvailabilityWindow {
+    /// Parses a window spec of the days and the time range (i.e. `Mon-Fri 09:00-17:00`). Days
+    /// are a comma separated list of days or ranges of days, or `*` for every day.
+    pub fn parse(spec: &str) -> Result<AvailabilityWindow, String> {
+        let (days, times) = spec
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| format!("'{}' is not a window of days and times", spec))?;
+        let days = parse_days(days)?;
+        let (start, end) = times
+            .trim()
+            .split_once('-')
+            .ok_or_else(|| format!("'{}' is not a range of times", times))?;
+        Ok(AvailabilityWindow {
+            days,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+
+    /// Returns the occurrences of the window that start on the days around the time
+    fn occurrences(&self, time: DateTime<Utc>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        (-1..=7)
+            .map(|offset| time.date_naive() + Duration::days(offset))
+            .filter(|date| self.days.contains(&date.weekday()))
+            .map(|date| {
+                let start = date.and_time(self.start).and_utc();
+                let mut end = date.and_time(self.end).and_utc();
+                if end <= start {
+                    end += Duration::days(1);
+                }
+                (start, end)
+            })
+            .collect()
+    }
+}
+
+fn parse_days(days: &str) -> Result<Vec<Weekday>, String> {
+    if days == "*" {
+        return Ok(WEEKDAYS.to_vec());
+    }
+    let mut parsed = Vec::new();
+    for part in days.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+            None => (parse_day(part)?, parse_day(part)?),
+        };
+        let mut day = first;
+        loop {
+            if !parsed.contains(&day) {
+                parsed.push(day);
+            }
+            if day == last {
+                break;
+            }
+            day = day.succ();
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_day(day: &str) -> Result<Weekday, String> {
+    day.trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a day of the week", day))
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|_| format!("'{}' is not a time of day (HH:MM)", time))
+}
+
+/// Scheduled maintenance, during which a resource is not available
+#[derive(Debug, Clone, PartialEq)]
+pub struct Maintenance {
+    /// When the maintenance starts
+    pub start: DateTime<Utc>,
+    /// When the maintenance ends
+    pub end: DateTime<Utc>,
+}
+
+/// When a resource is available
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Availability {
+    /// Windows the resource is available in. Defaults to empty, which is always available
+    /// outside of maintenance.
+    pub windows: Vec<AvailabilityWindow>,
+    /// Scheduled maintenance of the resource. Defaults to empty.
+    pub maintenance: Vec<Maintenance>,
+}
+
+impl Availability {
+    /// Creates the availability of the window specs (see `AvailabilityWindow::parse`)
+    pub fn windows(specs: &[&str]) -> Result<Availability, String> {
+        Ok(Availability {
+            windows: specs
+                .iter()
+                .map(|spec| AvailabilityWindow::parse(spec))
+                .collect::<Result<_, _>>()?,
+            maintenance: Vec::new(),
+        })
+    }
+
+    /// Returns when the resource will next be available, if it is not available at the time.
+    /// Returns None if it is available, or will not be available again.
+    pub fn unavailable_until(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut available_at = time;
+        // each step moves past a maintenance or to the start of a window, so this only loops
+        // more than a few times for overlapping maintenance
+        for _ in 0..(self.maintenance.len() + 2) * 2 {
+            if let Some(maintenance) = self
+                .maintenance
+                .iter()
+                .find(|m| m.start <= available_at && available_at < m.end)
+            {
+                available_at = maintenance.end;
+                continue;
+            }
+            if self.windows.is_empty() {
+                break;
+            }
+            let occurrences: Vec<_> = self
+                .windows
+                .iter()
+                .flat_map(|window| window.occurrences(available_at))
+                .collect();
+            if occurrences
+                .iter()
+                .any(|(start, end)| *start <= available_at && available_at < *end)
+            {
+                break;
+            }
+            match occurrences
+                .iter()
+                .map(|(start, _)| *start)
+                .filter(|start| *start > available_at)
+                .min()
+            {
+                Some(start) => available_at = start,
+                None => return None,
+            }
+        }
+        if available_at > time {
+            Some(available_at)
+        } else {
+            None
+        }
+    }
+
+    /// Replaces the response with a '503 Service Unavailable' response if the resource is not
+    /// available now. Returns true if it was replaced.
+    pub(crate) fn check(&self, context: &mut Context) -> bool {
+        let now = Utc::now();
+        let available_at = match self.unavailable_until(now) {
+            Some(available_at) => available_at,
+            None => return false,
+        };
+        debug!(
+            "Resource for '{}' is not available until {}",
+            context.request.request_path, available_at
+        );
+        context.response = Response {
+            status: 503,
+            ..Response::default()
+        };
+        let retry_after = (available_at - now).to_std().unwrap_or_default();
+        context.response.add_header(
+            "Retry-After",
+            vec![HeaderValue::basic(
+                retry_after_seconds(retry_after).to_string(),
+            )],
+        );
+        context.error = Some(format!("Resource is not available until {}", available_at));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dispatcher, Resource};
+    use chrono::TimeZone;
+    use expectest::prelude::*;
+
+    fn time(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2024-01-01 is a Monday
+        Utc.with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_window_specs() {
+        let window = AvailabilityWindow::parse("Mon-Wed,Sat 09:00-17:30").unwrap();
+        expect!(window.days).to(be_equal_to(vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Sat,
+        ]));
+        expect!(window.end).to(be_equal_to(NaiveTime::from_hms_opt(17, 30, 0).unwrap()));
+        expect!(AvailabilityWindow::parse("* 22:00-02:00")
+            .unwrap()
+            .days
+            .len())
+        .to(be_equal_to(7));
+        expect!(AvailabilityWindow::parse("Mon-Fri 9am-5pm")).to(be_err());
+        expect!(AvailabilityWindow::parse("Someday 09:00-17:00")).to(be_err());
+    }
+
+    #[test]
+    fn unavailable_until_the_next_window_starts() {
+        let availability =
+            Availability::windows(&["Mon-Fri 09:00-17:00", "Sat 22:00-02:00"]).unwrap();
+        expect!(availability.unavailable_until(time(1, 12, 0))).to(be_none());
+        expect!(availability.unavailable_until(time(1, 17, 0))).to(be_some().value(time(2, 9, 0)));
+        expect!(availability.unavailable_until(time(5, 18, 0))).to(be_some().value(time(6, 22, 0)));
+        expect!(availability.unavailable_until(time(7, 1, 0))).to(be_none());
+        expect!(availability.unavailable_until(time(7, 3, 0))).to(be_some().value(time(8, 9, 0)));
+    }
+
+    #[test]
+    fn unavailable_until_maintenance_ends() {
+        let availability = Availability {
+            maintenance: vec![
+                Maintenance {
+                    start: time(1, 10, 0),
+                    end: time(1, 11, 0),
+                },
+                Maintenance {
+                    start: time(1, 10, 30),
+                    end: time(1, 12, 0),
+                },
+            ],
+            ..Availability::windows(&["* 09:00-17:00"]).unwrap()
+        };
+        expect!(availability.unavailable_until(time(1, 10, 15)))
+            .to(be_some().value(time(1, 12, 0)));
+        expect!(availability.unavailable_until(time(1, 12, 0))).to(be_none());
+    }
+
+    #[tokio::test]
+    async fn requests_outside_the_windows_get_a_503_with_retry_after() {
+        let now = Utc::now();
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/report" => Resource {
+                    availability: Some(Availability {
+                        maintenance: vec![Maintenance {
+                            start: now - Duration::minutes(5),
+                            end: now + Duration::minutes(10),
+                        }],
+                        ..Availability::default()
+                    }),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        };
+        let request = http::Request::get("/report")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = dispatcher.dispatch(request).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(503));
+        let retry_after: u64 = response.headers()["Retry-After"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        expect!(retry_after > 590 && retry_after <= 600).to(be_true());
+    }
+}
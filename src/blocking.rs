@@ -0,0 +1,81 @@
+//! The `blocking` module runs a resource without an async runtime, for embedding webmachine
+//! semantics in CLIs, test tools and thread-per-request servers. `execute_blocking` runs the
+//! state machine on the calling thread, and `sync_callback` wraps a plain closure as a
+//! resource callback. Callbacks must not depend on a tokio runtime (i.e. `tokio::time`), as
+//! there is none.
+
+use futures::future;
+
+use crate::{
+    context::{Context, Request, Response},
+    bind_path_params, finalise_response, Callback, Resource, StateMachine,
+};
+
+/// Executes the state machine of the resource for the request on the calling thread, blocking
+/// until the response is complete. The request is executed as if the resource was routed at
+/// `/`, and the settings of a dispatcher (i.e. rate limits and the caching profile) do not
+/// apply.
+pub fn execute_blocking(request: Request, resource: &Resource) -> Response {
+    let mut context = Context {
+        request,
+        ..Context::default()
+    };
+    if !bind_path_params(&mut context, resource) {
+        return context.response;
+    }
+    futures::executor::block_on(async {
+        StateMachine::new(&mut context, resource)
+            .run_to_completion()
+            .await;
+        finalise_response(&mut context, resource).await;
+    });
+    context.response
+}
+
+/// Wraps a synchronous callback in a structure that is safe to call between threads
+pub fn sync_callback<'a, T, RT>(cb: T) -> Callback<'a, RT>
+where
+    T: Fn(&mut Context, &Resource) -> RT + Send + Sync + 'a,
+    RT: Send + 'static,
+{
+    crate::owned_callback(move |context, resource| Box::pin(future::ready(cb(context, resource))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn executes_a_resource_without_a_runtime() {
+        let resource = Resource {
+            allowed_methods: vec!["GET", "PUT"],
+            resource_exists: sync_callback(|context, _| context.request.request_path != "/gone"),
+            render_response: sync_callback(|_, _| Some("{\"count\": 1}".to_string())),
+            process_put: sync_callback(|context, _| {
+                context.response.body = context.request.body.clone();
+                Ok(true)
+            }),
+            ..Resource::default()
+        };
+
+        let response = execute_blocking(Request::default(), &resource);
+        expect!(response.status).to(be_equal_to(200));
+        expect!(response.body).to(be_some().value(b"{\"count\": 1}".to_vec()));
+
+        let request = Request {
+            request_path: "/gone".to_string(),
+            ..Request::default()
+        };
+        expect!(execute_blocking(request, &resource).status).to(be_equal_to(404));
+
+        let request = Request {
+            method: "PUT".to_string(),
+            body: Some(b"{\"count\": 2}".to_vec()),
+            ..Request::default()
+        };
+        let response = execute_blocking(request, &resource);
+        expect!(response.status).to(be_equal_to(200));
+        expect!(response.body).to(be_some().value(b"{\"count\": 2}".to_vec()));
+    }
+}
@@ -0,0 +1,129 @@
+//! The `bot_policy` module controls how crawlers and other bots are served. Requests are
+//! classified with the User-Agent classifier of the dispatcher, and requests from bots can be
+//! given their own rate limit and denied expensive routes. Responses can also get an
+//! `X-Robots-Tag` header per route, telling crawlers how to index them.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    content_negotiation::UserAgentClass, context::Context, headers::HeaderValue, RateLimiter,
+};
+
+/// Policy for requests from bots
+#[derive(Debug, Clone)]
+pub struct BotPolicy {
+    /// Classes of client the policy applies to. Defaults to bots.
+    pub classes: Vec<UserAgentClass>,
+    /// Rate limiter for requests from bots, used instead of the rate limiter of the dispatcher.
+    /// Defaults to None (bots are limited like any other client).
+    pub rate_limiter: Option<RateLimiter>,
+    /// Routes that bots are denied with a '403 Forbidden' response (i.e. expensive searches or
+    /// reports). Defaults to empty.
+    pub denied_routes: Vec<String>,
+    /// Values of the `X-Robots-Tag` header of the responses of each route (i.e.
+    /// `noindex, nofollow`). This is added to responses to all clients, as crawlers may not
+    /// identify themselves. Defaults to empty.
+    pub robots_tags: BTreeMap<String, String>,
+    /// Value of the `X-Robots-Tag` header of the responses of routes without their own.
+    /// Defaults to None.
+    pub default_robots_tag: Option<String>,
+}
+
+impl Default for BotPolicy {
+    fn default() -> BotPolicy {
+        BotPolicy {
+            classes: vec![UserAgentClass::Bot],
+            rate_limiter: None,
+            denied_routes: Vec::new(),
+            robots_tags: BTreeMap::new(),
+            default_robots_tag: None,
+        }
+    }
+}
+
+impl BotPolicy {
+    /// If the policy applies to the request
+    pub fn applies_to(&self, context: &Context) -> bool {
+        matches!(context.user_agent_class, Some(class) if self.classes.contains(&class))
+    }
+
+    /// If bots are denied the route
+    pub fn is_denied(&self, route: &str) -> bool {
+        self.denied_routes.iter().any(|denied| denied == route)
+    }
+
+    /// Returns the `X-Robots-Tag` header value of the responses of the route
+    pub fn robots_tag(&self, route: Option<&str>) -> Option<&str> {
+        route
+            .and_then(|route| self.robots_tags.get(route))
+            .or(self.default_robots_tag.as_ref())
+            .map(|tag| tag.as_str())
+    }
+
+    /// Adds the `X-Robots-Tag` header of the route to the response
+    pub(crate) fn add_robots_tag(&self, context: &mut Context, route: Option<&str>) {
+        if let Some(tag) = self.robots_tag(route) {
+            context
+                .response
+                .add_header("X-Robots-Tag", vec![HeaderValue::basic(tag)]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dispatcher, RateLimitKey, Resource};
+    use expectest::prelude::*;
+
+    const GOOGLEBOT: &str =
+        "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+    const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+
+    async fn dispatch(
+        dispatcher: &Dispatcher<'static>,
+        path: &str,
+        user_agent: &str,
+    ) -> http::Response<hyper::Body> {
+        let request = http::Request::builder()
+            .uri(path)
+            .header("User-Agent", user_agent)
+            .header("X-Client", "1")
+            .body(hyper::Body::empty())
+            .unwrap();
+        dispatcher.clone().dispatch(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn bots_are_denied_expensive_routes_and_rate_limited_separately() {
+        let key = RateLimitKey::Header("X-Client".to_string());
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/products" => Resource::default(),
+                "/search" => Resource::default()
+            },
+            rate_limiter: Some(RateLimiter::token_bucket(key.clone(), 100, 1.0)),
+            bot_policy: Some(BotPolicy {
+                rate_limiter: Some(RateLimiter::token_bucket(key, 1, 0.001)),
+                denied_routes: vec!["/search".to_string()],
+                robots_tags: btreemap! { "/search".to_string() => "noindex".to_string() },
+                ..BotPolicy::default()
+            }),
+            ..Dispatcher::default()
+        };
+
+        let response = dispatch(&dispatcher, "/search?q=shoes", GOOGLEBOT).await;
+        expect!(response.status().as_u16()).to(be_equal_to(403));
+        let response = dispatch(&dispatcher, "/search?q=shoes", FIREFOX).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        expect!(response.headers()["X-Robots-Tag"].to_str().unwrap()).to(be_equal_to("noindex"));
+
+        let response = dispatch(&dispatcher, "/products/1", GOOGLEBOT).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        expect!(response.headers().contains_key("X-Robots-Tag")).to(be_false());
+        let response = dispatch(&dispatcher, "/products/2", GOOGLEBOT).await;
+        expect!(response.status().as_u16()).to(be_equal_to(429));
+        let response = dispatch(&dispatcher, "/products/2", FIREFOX).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+    }
+}
@@ -0,0 +1,741 @@
+//! The `builtin` module provides ready made resources for well-known paths like `/robots.txt`,
+//! `/favicon.ico`, `/sitemap.xml` and the ACME HTTP-01 challenge path, so that these requests are answered without reaching
+//! application resources.
+
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::{lock::Mutex, Future};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{context::Context, headers::HeaderValue, owned_callback, Resource};
+
+/// Default number of seconds clients may cache a built-in resource for (one day)
+pub const DEFAULT_MAX_AGE: u64 = 86_400;
+
+/// Resource that serves a `robots.txt` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotsResource {
+    /// Contents of the robots.txt file
+    pub content: String,
+    /// Number of seconds clients may cache the file for
+    pub max_age: u64,
+}
+
+impl RobotsResource {
+    /// Creates a robots.txt resource with the given contents
+    pub fn new<S: Into<String>>(content: S) -> RobotsResource {
+        RobotsResource {
+            content: content.into(),
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Creates a robots.txt resource that allows all user agents to crawl everything
+    pub fn allow_all() -> RobotsResource {
+        RobotsResource::new("User-agent: *\nDisallow:\n")
+    }
+
+    /// Creates a robots.txt resource that disallows all user agents from crawling anything
+    pub fn disallow_all() -> RobotsResource {
+        RobotsResource::new("User-agent: *\nDisallow: /\n")
+    }
+
+    /// Builds the webmachine resource that serves the file
+    pub fn resource(&self) -> Resource<'static> {
+        let max_age = self.max_age;
+        let etag = etag_for(self.content.as_bytes());
+        let content = self.content.clone();
+        Resource {
+            produces: vec!["text/plain"],
+            resource_exists: owned_callback(move |context, _| {
+                add_representation_headers(context, "text/plain;charset=UTF-8", max_age);
+                Box::pin(async { true })
+            }),
+            generate_etag: owned_callback(move |_, _| {
+                let etag = etag.clone();
+                Box::pin(async move { Some(etag) })
+            }),
+            render_response: owned_callback(move |_, _| {
+                let content = content.clone();
+                Box::pin(async move { Some(content) })
+            }),
+            ..Resource::default()
+        }
+    }
+}
+
+impl Default for RobotsResource {
+    fn default() -> RobotsResource {
+        RobotsResource::allow_all()
+    }
+}
+
+/// Where the bytes of a favicon come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaviconSource {
+    /// Icon held in memory
+    Bytes(Vec<u8>),
+    /// Icon read from a file, which is read again when its modification time or size changes
+    File(PathBuf),
+}
+
+/// Icon loaded from a source, with its ETag
+#[derive(Debug, Clone)]
+struct LoadedIcon {
+    /// Modification time and size of the file the icon was read from
+    version: Option<(SystemTime, u64)>,
+    bytes: Arc<Vec<u8>>,
+    etag: String,
+}
+
+impl FaviconSource {
+    /// Loads the icon, from the cache if the file has not changed since it was cached
+    fn load(&self, cache: &RwLock<Option<LoadedIcon>>) -> io::Result<LoadedIcon> {
+        let version = match self {
+            FaviconSource::Bytes(_) => None,
+            FaviconSource::File(path) => {
+                let metadata = fs::metadata(path)?;
+                Some((metadata.modified()?, metadata.len()))
+            }
+        };
+        if let Ok(cached) = cache.read() {
+            if let Some(icon) = cached.as_ref().filter(|icon| icon.version == version) {
+                return Ok(icon.clone());
+            }
+        }
+        let bytes = match self {
+            FaviconSource::Bytes(bytes) => bytes.clone(),
+            FaviconSource::File(path) => fs::read(path)?,
+        };
+        let icon = LoadedIcon {
+            version,
+            etag: etag_for(&bytes),
+            bytes: Arc::new(bytes),
+        };
+        if let Ok(mut cached) = cache.write() {
+            *cached = Some(icon.clone());
+        }
+        Ok(icon)
+    }
+
+    fn exists(&self) -> bool {
+        match self {
+            FaviconSource::Bytes(_) => true,
+            FaviconSource::File(path) => path.is_file(),
+        }
+    }
+
+}
+
+/// Resource that serves a favicon
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaviconResource {
+    /// Where the icon is loaded from
+    pub source: FaviconSource,
+    /// Content type of the icon
+    pub content_type: &'static str,
+    /// Number of seconds clients may cache the icon for
+    pub max_age: u64,
+}
+
+impl FaviconResource {
+    /// Creates a favicon resource that serves the given bytes
+    pub fn from_bytes(bytes: Vec<u8>) -> FaviconResource {
+        FaviconResource {
+            source: FaviconSource::Bytes(bytes),
+            content_type: "image/x-icon",
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Creates a favicon resource that serves the given file. A missing file results in a 404
+    /// response.
+    pub fn from_file<P: Into<PathBuf>>(path: P) -> FaviconResource {
+        FaviconResource {
+            source: FaviconSource::File(path.into()),
+            content_type: "image/x-icon",
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    /// Builds the webmachine resource that serves the icon. The icon is cached by the
+    /// resource, so a file is only read again once it changes.
+    pub fn resource(&self) -> Resource<'static> {
+        let max_age = self.max_age;
+        let content_type = self.content_type;
+        let cache = Arc::new(RwLock::new(None));
+        let exists_source = self.source.clone();
+        let (etag_source, etag_cache) = (self.source.clone(), cache.clone());
+        let (modified_source, modified_cache) = (self.source.clone(), cache.clone());
+        let (render_source, render_cache) = (self.source.clone(), cache);
+        Resource {
+            produces: vec![content_type],
+            resource_exists: owned_callback(move |context, _| {
+                let exists = exists_source.exists();
+                if exists {
+                    add_representation_headers(context, content_type, max_age);
+                }
+                Box::pin(async move { exists })
+            }),
+            generate_etag: owned_callback(move |_, _| {
+                let etag = etag_source.load(&etag_cache).ok().map(|icon| icon.etag);
+                Box::pin(async move { etag })
+            }),
+            last_modified: owned_callback(move |_, _| {
+                let last_modified = modified_source
+                    .load(&modified_cache)
+                    .ok()
+                    .and_then(|icon| icon.version)
+                    .map(|(modified, _)| DateTime::<Utc>::from(modified).into());
+                Box::pin(async move { last_modified })
+            }),
+            render_response: owned_callback(move |context, _| {
+                match render_source.load(&render_cache) {
+                    Ok(icon) => {
+                        if context.memory.allocate(icon.bytes.len()) {
+                            context.response.body = Some(icon.bytes.to_vec());
+                        } else {
+                            context.response.status = 500;
+                            context.error =
+                                Some("Favicon exceeds the request memory limit".to_string());
+                        }
+                    }
+                    Err(err) => {
+                        warn!("Failed to load the favicon - {}", err);
+                        context.response.status = 500;
+                        context.error = Some(format!("Failed to load the favicon - {}", err));
+                    }
+                }
+                Box::pin(async { None })
+            }),
+            ..Resource::default()
+        }
+    }
+}
+
+/// Maximum number of URLs a single sitemap may contain, as per the sitemaps protocol
+pub const MAX_SITEMAP_URLS: usize = 50_000;
+
+/// Entry in a sitemap
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapUrl {
+    /// Absolute URL of the page
+    pub loc: String,
+    /// When the page was last modified
+    pub last_modified: Option<DateTime<FixedOffset>>,
+    /// How frequently the page is likely to change (always, hourly, daily, weekly, monthly,
+    /// yearly or never)
+    pub change_frequency: Option<String>,
+    /// Priority of the page relative to the other pages of the site, from 0.0 to 1.0
+    pub priority: Option<f32>,
+}
+
+impl SitemapUrl {
+    /// Creates a sitemap entry for the given URL
+    pub fn new<S: Into<String>>(loc: S) -> SitemapUrl {
+        SitemapUrl {
+            loc: loc.into(),
+            last_modified: None,
+            change_frequency: None,
+            priority: None,
+        }
+    }
+}
+
+/// Async function that provides the URLs to include in a sitemap
+pub type SitemapUrlProvider =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Vec<SitemapUrl>> + Send>> + Send + Sync>;
+
+/// Resource that serves a sitemap built from the URLs returned by a provider. If there are more
+/// URLs than fit in a single sitemap, a sitemap index is served instead which refers to the
+/// individual sitemaps with a `page` query parameter (i.e. `/sitemap.xml?page=1`).
+#[derive(Clone)]
+pub struct SitemapResource {
+    /// Absolute URL the sitemap is served from, used for the entries of the sitemap index
+    pub url: String,
+    /// Provider of the URLs to include in the sitemap
+    pub provider: SitemapUrlProvider,
+    /// Maximum number of URLs in each sitemap before the sitemap is split
+    pub max_urls: usize,
+    /// How long the URLs returned by the provider are reused for before it is called again
+    pub refresh_interval: Duration,
+    /// Number of seconds clients may cache the sitemap for
+    pub max_age: u64,
+}
+
+type SitemapCache = Arc<Mutex<Option<(Instant, Arc<Vec<SitemapUrl>>)>>>;
+
+/// Sitemap XML, along with the date the URLs in it were last modified
+type SitemapDocument = (String, Option<DateTime<FixedOffset>>);
+
+type SitemapFuture = Pin<Box<dyn Future<Output = Option<SitemapDocument>> + Send>>;
+
+impl SitemapResource {
+    /// Creates a sitemap resource served from the given URL
+    pub fn new<S: Into<String>>(url: S, provider: SitemapUrlProvider) -> SitemapResource {
+        SitemapResource {
+            url: url.into(),
+            provider,
+            max_urls: MAX_SITEMAP_URLS,
+            refresh_interval: Duration::from_secs(300),
+            max_age: 3600,
+        }
+    }
+
+    /// Builds the webmachine resource that serves the sitemap
+    pub fn resource(&self) -> Resource<'static> {
+        let cache: SitemapCache = Arc::new(Mutex::new(None));
+        let exists_sitemap = self.document_builder(cache.clone());
+        let etag_sitemap = self.document_builder(cache.clone());
+        let modified_sitemap = self.document_builder(cache.clone());
+        let render_sitemap = self.document_builder(cache);
+        let max_age = self.max_age;
+        Resource {
+            produces: vec!["application/xml", "text/xml"],
+            resource_exists: owned_callback(move |context, _| {
+                add_representation_headers(context, "application/xml;charset=UTF-8", max_age);
+                let document = exists_sitemap(sitemap_page(context));
+                Box::pin(async move { document.await.is_some() })
+            }),
+            generate_etag: owned_callback(move |context, _| {
+                let document = etag_sitemap(sitemap_page(context));
+                Box::pin(async move { document.await.map(|(xml, _)| etag_for(xml.as_bytes())) })
+            }),
+            last_modified: owned_callback(move |context, _| {
+                let document = modified_sitemap(sitemap_page(context));
+                Box::pin(async move { document.await.and_then(|(_, modified)| modified) })
+            }),
+            render_response: owned_callback(move |context, _| {
+                let document = render_sitemap(sitemap_page(context));
+                Box::pin(async move { document.await.map(|(xml, _)| xml) })
+            }),
+            ..Resource::default()
+        }
+    }
+
+    /// Returns a function that builds the sitemap document for a page. The document is `None` if
+    /// the page does not exist.
+    fn document_builder(
+        &self,
+        cache: SitemapCache,
+    ) -> impl Fn(Result<Option<usize>, ()>) -> SitemapFuture + Send + Sync {
+        let sitemap = self.clone();
+        move |page| {
+            let sitemap = sitemap.clone();
+            let cache = cache.clone();
+            Box::pin(async move {
+                let page = page.ok()?;
+                let urls = sitemap.urls(&cache).await;
+                sitemap_document(&sitemap.url, &urls, sitemap.max_urls.max(1), page)
+            })
+        }
+    }
+
+    async fn urls(&self, cache: &SitemapCache) -> Arc<Vec<SitemapUrl>> {
+        let mut cache = cache.lock().await;
+        match cache.as_ref() {
+            Some((fetched, urls)) if fetched.elapsed() < self.refresh_interval => urls.clone(),
+            _ => {
+                let urls = Arc::new((self.provider)().await);
+                *cache = Some((Instant::now(), urls.clone()));
+                urls
+            }
+        }
+    }
+}
+
+fn sitemap_page(context: &Context) -> Result<Option<usize>, ()> {
+    match context.request.query.get("page").and_then(|values| values.first()) {
+        Some(page) => page.parse().map(Some).map_err(|_| ()),
+        None => Ok(None),
+    }
+}
+
+fn sitemap_document(
+    url: &str,
+    urls: &[SitemapUrl],
+    max_urls: usize,
+    page: Option<usize>,
+) -> Option<SitemapDocument> {
+    let pages: Vec<&[SitemapUrl]> = urls.chunks(max_urls).collect();
+    match page {
+        None if pages.len() <= 1 => Some((url_set(urls), latest_modification(urls))),
+        None => {
+            let mut xml = String::from(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+            );
+            for (index, page) in pages.iter().enumerate() {
+                xml.push_str("  <sitemap>\n");
+                let separator = if url.contains('?') { '&' } else { '?' };
+                let loc = format!("{}{}page={}", url, separator, index + 1);
+                xml.push_str(&format!("    <loc>{}</loc>\n", xml_escape(&loc)));
+                if let Some(modified) = latest_modification(page) {
+                    xml.push_str(&format!("    <lastmod>{}</lastmod>\n", modified.to_rfc3339()));
+                }
+                xml.push_str("  </sitemap>\n");
+            }
+            xml.push_str("</sitemapindex>\n");
+            Some((xml, latest_modification(urls)))
+        }
+        // the first page is valid even if there is only one (or no) page
+        Some(page) if page >= 1 && page <= pages.len().max(1) => {
+            let urls = pages.get(page - 1).copied().unwrap_or_default();
+            Some((url_set(urls), latest_modification(urls)))
+        }
+        Some(_) => None,
+    }
+}
+
+fn url_set(urls: &[SitemapUrl]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for url in urls {
+        xml.push_str("  <url>\n");
+        xml.push_str(&format!("    <loc>{}</loc>\n", xml_escape(&url.loc)));
+        if let Some(modified) = &url.last_modified {
+            xml.push_str(&format!("    <lastmod>{}</lastmod>\n", modified.to_rfc3339()));
+        }
+        if let Some(change_frequency) = &url.change_frequency {
+            xml.push_str(&format!(
+                "    <changefreq>{}</changefreq>\n",
+                xml_escape(change_frequency)
+            ));
+        }
+        if let Some(priority) = url.priority {
+            xml.push_str(&format!("    <priority>{:.1}</priority>\n", priority));
+        }
+        xml.push_str("  </url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn latest_modification(urls: &[SitemapUrl]) -> Option<DateTime<FixedOffset>> {
+    urls.iter().filter_map(|url| url.last_modified).max()
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Path that ACME servers fetch HTTP-01 challenge responses from
+pub const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge";
+
+/// Store of the key authorizations for pending ACME HTTP-01 challenges. Certificates can be
+/// obtained with the `acme` module (`acme` feature), which adds the challenges to the store.
+/// When several replicas answer the challenges, the store must be shared between them (i.e.
+/// backed by a database or cache), as the certificate authority can reach any of them.
+pub trait AcmeChallengeStore: Send + Sync {
+    /// Returns the key authorization for the challenge token, if the challenge is pending
+    fn key_authorization(&self, token: &str) -> Option<String>;
+
+    /// Adds a pending challenge
+    fn insert(&self, token: &str, key_authorization: &str);
+
+    /// Removes a challenge once it has been validated, returning its key authorization
+    fn remove(&self, token: &str) -> Option<String>;
+}
+
+/// In-memory ACME challenge store. Clones share the same set of challenges, so one clone can
+/// be given to the challenge resource while another is used to add and remove challenges.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryAcmeChallengeStore {
+    challenges: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl MemoryAcmeChallengeStore {
+    /// Creates an empty store
+    pub fn new() -> MemoryAcmeChallengeStore {
+        MemoryAcmeChallengeStore::default()
+    }
+}
+
+impl AcmeChallengeStore for MemoryAcmeChallengeStore {
+    fn key_authorization(&self, token: &str) -> Option<String> {
+        self.challenges
+            .read()
+            .ok()
+            .and_then(|challenges| challenges.get(token).cloned())
+    }
+
+    fn insert(&self, token: &str, key_authorization: &str) {
+        if let Ok(mut challenges) = self.challenges.write() {
+            challenges.insert(token.to_string(), key_authorization.to_string());
+        }
+    }
+
+    fn remove(&self, token: &str) -> Option<String> {
+        self.challenges
+            .write()
+            .ok()
+            .and_then(|mut challenges| challenges.remove(token))
+    }
+}
+
+/// Resource that answers ACME HTTP-01 challenges from a challenge store. It should be routed
+/// at `ACME_CHALLENGE_PATH`, and responds to `<ACME_CHALLENGE_PATH>/<token>` with the key
+/// authorization of the token, or a 404 if there is no pending challenge for it.
+#[derive(Clone)]
+pub struct AcmeChallengeResource {
+    /// Store the challenges are looked up in
+    pub store: Arc<dyn AcmeChallengeStore>,
+}
+
+impl AcmeChallengeResource {
+    /// Creates a challenge resource backed by the given store
+    pub fn new<S: AcmeChallengeStore + 'static>(store: S) -> AcmeChallengeResource {
+        AcmeChallengeResource {
+            store: Arc::new(store),
+        }
+    }
+
+    /// Builds the webmachine resource that answers the challenges
+    pub fn resource(&self) -> Resource<'static> {
+        let exists_store = self.store.clone();
+        let render_store = self.store.clone();
+        Resource {
+            produces: vec!["application/octet-stream"],
+            resource_exists: owned_callback(move |context, _| {
+                let exists = acme_token(context)
+                    .and_then(|token| exists_store.key_authorization(token))
+                    .is_some();
+                if exists {
+                    context.response.add_header(
+                        "Content-Type",
+                        vec![HeaderValue::basic("application/octet-stream")],
+                    );
+                }
+                Box::pin(async move { exists })
+            }),
+            render_response: owned_callback(move |context, _| {
+                let key_authorization =
+                    acme_token(context).and_then(|token| render_store.key_authorization(token));
+                Box::pin(async move { key_authorization })
+            }),
+            ..Resource::default()
+        }
+    }
+}
+
+fn acme_token(context: &Context) -> Option<&str> {
+    let token = context.request.request_path.trim_start_matches('/');
+    if token.is_empty() || token.contains('/') {
+        None
+    } else {
+        Some(token)
+    }
+}
+
+fn add_representation_headers(context: &mut Context, content_type: &str, max_age: u64) {
+    context
+        .response
+        .add_header("Content-Type", vec![HeaderValue::parse_string(content_type)]);
+    context.response.add_header(
+        "Cache-Control",
+        vec![
+            HeaderValue::basic("public"),
+            HeaderValue::basic(format!("max-age={}", max_age)),
+        ],
+    );
+}
+
+fn etag_for(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Request, execute_state_machine, finalise_response};
+    use expectest::prelude::*;
+
+    async fn get(resource: &Resource<'_>, headers: Vec<(&str, &str)>) -> Context {
+        let mut context = Context {
+            request: Request {
+                headers: headers
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), vec![HeaderValue::basic(*v)]))
+                    .collect(),
+                ..Request::default()
+            },
+            ..Context::default()
+        };
+        execute_state_machine(&mut context, resource).await;
+        finalise_response(&mut context, resource).await;
+        context
+    }
+
+    #[tokio::test]
+    async fn robots_resource_serves_the_file_with_caching_headers() {
+        let resource = RobotsResource::disallow_all().resource();
+        let context = get(&resource, vec![]).await;
+        expect!(context.response.status).to(be_equal_to(200));
+        expect!(context.response.body.clone())
+            .to(be_some().value(b"User-agent: *\nDisallow: /\n".to_vec()));
+        expect!(context.response.headers.get("Content-Type").cloned()).to(be_some().value(vec![
+            HeaderValue::parse_string("text/plain;charset=UTF-8"),
+        ]));
+        expect!(context.response.headers.get("Cache-Control").cloned()).to(be_some().value(vec![
+            HeaderValue::basic("public"),
+            HeaderValue::basic("max-age=86400"),
+        ]));
+        expect!(context.response.has_header("ETag")).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn robots_resource_returns_304_for_a_matching_etag() {
+        let resource = RobotsResource::allow_all().resource();
+        let etag = format!("\"{}\"", etag_for(b"User-agent: *\nDisallow:\n"));
+        let context = get(&resource, vec![("If-None-Match", etag.as_str())]).await;
+        expect!(context.response.status).to(be_equal_to(304));
+        expect!(context.response.has_header("Cache-Control")).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn favicon_resource_serves_the_icon_bytes() {
+        let resource = FaviconResource::from_bytes(vec![0, 0, 1, 0]).resource();
+        let context = get(&resource, vec![("Accept", "image/*")]).await;
+        expect!(context.response.status).to(be_equal_to(200));
+        expect!(context.response.body.clone()).to(be_some().value(vec![0, 0, 1, 0]));
+        expect!(context.response.headers.get("Content-Type").cloned())
+            .to(be_some().value(vec![HeaderValue::basic("image/x-icon")]));
+    }
+
+    #[tokio::test]
+    async fn favicon_resource_reads_the_file_again_once_it_changes() {
+        let path = std::env::temp_dir().join(format!("favicon-{}.ico", std::process::id()));
+        fs::write(&path, [0, 0, 1, 0]).unwrap();
+        let resource = FaviconResource::from_file(&path).resource();
+        let context = get(&resource, vec![]).await;
+        expect!(context.response.body.clone()).to(be_some().value(vec![0, 0, 1, 0]));
+        let etag = context.response.headers.get("ETag").cloned();
+        expect!(etag.clone())
+            .to(be_some().value(vec![HeaderValue::basic(etag_for(&[0, 0, 1, 0])).quote()]));
+        expect!(context.response.has_header("Last-Modified")).to(be_true());
+
+        fs::write(&path, [0, 0, 1, 0, 1]).unwrap();
+        let context = get(&resource, vec![]).await;
+        expect!(context.response.body.clone()).to(be_some().value(vec![0, 0, 1, 0, 1]));
+        expect!(context.response.headers.get("ETag").cloned()).to_not(be_equal_to(etag));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn favicon_resource_returns_404_if_the_file_does_not_exist() {
+        let resource = FaviconResource::from_file("/does/not/exist/favicon.ico").resource();
+        let context = get(&resource, vec![]).await;
+        expect!(context.response.status).to(be_equal_to(404));
+        expect!(context.response.has_header("Cache-Control")).to(be_false());
+    }
+
+    fn sitemap(urls: usize) -> SitemapResource {
+        let mut sitemap = SitemapResource::new(
+            "https://example.com/sitemap.xml",
+            Arc::new(move || {
+                Box::pin(async move {
+                    (1..=urls)
+                        .map(|i| SitemapUrl {
+                            last_modified: Some(
+                                DateTime::parse_from_rfc3339(&format!(
+                                    "2021-01-0{}T10:00:00+00:00",
+                                    i
+                                ))
+                                .unwrap(),
+                            ),
+                            ..SitemapUrl::new(format!("https://example.com/page?id={}&v=1", i))
+                        })
+                        .collect()
+                })
+            }),
+        );
+        sitemap.max_urls = 2;
+        sitemap
+    }
+
+    #[tokio::test]
+    async fn sitemap_resource_serves_a_url_set() {
+        let resource = sitemap(2).resource();
+        let context = get(&resource, vec![]).await;
+        expect!(context.response.status).to(be_equal_to(200));
+        let body = String::from_utf8(context.response.body.clone().unwrap()).unwrap();
+        expect!(body.contains("<urlset")).to(be_true());
+        expect!(body.contains("<loc>https://example.com/page?id=2&amp;v=1</loc>")).to(be_true());
+        expect!(body.contains("<lastmod>2021-01-02T10:00:00+00:00</lastmod>")).to(be_true());
+        expect!(context.response.has_header("ETag")).to(be_true());
+        expect!(context.response.has_header("Last-Modified")).to(be_true());
+
+        let mut context = Context::default();
+        context.request.query = hashmap! { "page".to_string() => vec!["1".to_string()] };
+        execute_state_machine(&mut context, &resource).await;
+        finalise_response(&mut context, &resource).await;
+        expect!(context.response.status).to(be_equal_to(200));
+        let body = String::from_utf8(context.response.body.clone().unwrap()).unwrap();
+        expect!(body.contains("<urlset")).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn sitemap_resource_splits_large_sitemaps_into_an_index() {
+        let resource = sitemap(3).resource();
+        let context = get(&resource, vec![]).await;
+        let body = String::from_utf8(context.response.body.clone().unwrap()).unwrap();
+        expect!(body.contains("<sitemapindex")).to(be_true());
+        expect!(body.contains("<loc>https://example.com/sitemap.xml?page=2</loc>")).to(be_true());
+
+        let mut context = Context::default();
+        context.request.query = hashmap! { "page".to_string() => vec!["2".to_string()] };
+        execute_state_machine(&mut context, &resource).await;
+        finalise_response(&mut context, &resource).await;
+        let body = String::from_utf8(context.response.body.clone().unwrap()).unwrap();
+        expect!(body.contains("id=3")).to(be_true());
+        expect!(body.contains("id=1")).to(be_false());
+
+        context = Context::default();
+        context.request.query = hashmap! { "page".to_string() => vec!["3".to_string()] };
+        execute_state_machine(&mut context, &resource).await;
+        expect!(context.response.status).to(be_equal_to(404));
+    }
+
+    #[tokio::test]
+    async fn sitemap_resource_returns_304_if_not_modified() {
+        let resource = sitemap(2).resource();
+        let context = get(&resource, vec![("If-Modified-Since", "Sat, 02 Jan 2021 10:00:00 GMT")])
+            .await;
+        expect!(context.response.status).to(be_equal_to(304));
+    }
+
+    #[tokio::test]
+    async fn acme_challenge_resource_serves_pending_challenges() {
+        let store = MemoryAcmeChallengeStore::new();
+        store.insert("token1", "token1.thumbprint");
+        let resource = AcmeChallengeResource::new(store.clone()).resource();
+
+        let mut context = Context::default();
+        context.request.request_path = "/token1".to_string();
+        execute_state_machine(&mut context, &resource).await;
+        finalise_response(&mut context, &resource).await;
+        expect!(context.response.status).to(be_equal_to(200));
+        expect!(context.response.body.clone())
+            .to(be_some().value(b"token1.thumbprint".to_vec()));
+
+        store.remove("token1");
+        let mut context = Context::default();
+        context.request.request_path = "/token1".to_string();
+        execute_state_machine(&mut context, &resource).await;
+        expect!(context.response.status).to(be_equal_to(404));
+    }
+}
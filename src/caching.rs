@@ -0,0 +1,235 @@
+//! The `caching` module provides caching profiles, which configure the Cache-Control header and
+//! ETags of the responses of a resource in one line (i.e.
+//! `caching: Some(CachingProfile::immutable_asset())`). The presets cover the common cases, so
+//! each route of a large route table does not need to get its caching headers right by hand.
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    context::Context,
+    headers::{EntityTag, HeaderValue},
+};
+
+/// How the ETags of the responses of a resource are generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EtagStrategy {
+    /// ETags come from the `generate_etag` callback of the resource
+    Resource,
+    /// Responses to GET requests without an ETag from the resource get a strong ETag computed
+    /// from the body, and requests with a matching If-None-Match header get a
+    /// '304 Not Modified' response. The body is still rendered, but not sent.
+    Body,
+    /// Responses are sent without an ETag, as clients never revalidate them
+    Omit,
+}
+
+/// Caching profile of the responses of a resource
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachingProfile {
+    /// Name of the profile, used in logs
+    pub name: String,
+    /// Directives of the Cache-Control header (i.e. `public` and `max-age=60`). The header is
+    /// added to successful and '304 Not Modified' responses to cacheable methods, unless the
+    /// resource has already set one.
+    pub cache_control: Vec<String>,
+    /// How the ETags of the responses are generated
+    pub etag: EtagStrategy,
+}
+
+impl CachingProfile {
+    /// Creates a profile with the Cache-Control directives and ETag strategy
+    pub fn new(name: &str, cache_control: &[&str], etag: EtagStrategy) -> CachingProfile {
+        CachingProfile {
+            name: name.to_string(),
+            cache_control: cache_control.iter().map(|d| d.to_string()).collect(),
+            etag,
+        }
+    }
+
+    /// Assets with a fingerprinted URL that never change: cached by any cache for a year
+    /// without revalidation, and sent without an ETag
+    pub fn immutable_asset() -> CachingProfile {
+        CachingProfile::new(
+            "immutable_asset",
+            &["public", "max-age=31536000", "immutable"],
+            EtagStrategy::Omit,
+        )
+    }
+
+    /// Data of the authenticated user: only cached by the browser, which must revalidate it
+    /// with the ETag computed from the body on every use
+    pub fn private_user_data() -> CachingProfile {
+        CachingProfile::new(
+            "private_user_data",
+            &["private", "no-cache"],
+            EtagStrategy::Body,
+        )
+    }
+
+    /// Responses that must never be stored by any cache (i.e. ones with secrets)
+    pub fn no_store() -> CachingProfile {
+        CachingProfile::new("no_store", &["no-store"], EtagStrategy::Omit)
+    }
+
+    /// API responses that may be cached by any cache for 60 seconds, and are then revalidated
+    /// with the ETag computed from the body
+    pub fn api_default_60s() -> CachingProfile {
+        CachingProfile::new(
+            "api_default_60s",
+            &["public", "max-age=60"],
+            EtagStrategy::Body,
+        )
+    }
+
+    /// Adds the caching headers to the response, replacing it with a '304 Not Modified'
+    /// response if the ETag computed from the body matches the If-None-Match header
+    pub(crate) fn apply(&self, context: &mut Context, cacheable: bool) {
+        if !cacheable {
+            return;
+        }
+        match self.etag {
+            EtagStrategy::Resource => (),
+            EtagStrategy::Body => self.add_body_etag(context),
+            EtagStrategy::Omit => {
+                context.response.remove_header("ETag");
+            }
+        }
+        let status = context.response.status;
+        if ((200..300).contains(&status) || status == 304)
+            && !context.response.has_header("Cache-Control")
+            && !self.cache_control.is_empty()
+        {
+            context.response.add_header(
+                "Cache-Control",
+                self.cache_control.iter().map(HeaderValue::basic).collect(),
+            );
+        }
+    }
+
+    fn add_body_etag(&self, context: &mut Context) {
+        if context.response.status != 200 || context.response.has_header("ETag") {
+            return;
+        }
+        let body = match &context.response.body {
+            Some(body) => body,
+            None => return,
+        };
+        let etag = EntityTag::strong(hex::encode(&Sha256::digest(body)[..16]));
+        let not_modified = context
+            .request
+            .find_header("If-None-Match")
+            .iter()
+            .any(|value| value.value == "*" || etag.weak_eq(&EntityTag::from_header_value(value)));
+        context
+            .response
+            .add_header("ETag", vec![etag.to_header_value()]);
+        if not_modified {
+            debug!(
+                "ETag of the body matches If-None-Match, sending '304 Not Modified' ({} profile)",
+                self.name
+            );
+            context.response.status = 304;
+            context.response.body = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{owned_callback, Dispatcher, Resource};
+    use expectest::prelude::*;
+
+    fn dispatcher(resource: Resource<'static>) -> Dispatcher<'static> {
+        Dispatcher {
+            routes: btreemap! {
+                "/profile" => Resource {
+                    render_response: owned_callback(|_, _| {
+                        Box::pin(async { Some("{\"name\": \"alice\"}".to_string()) })
+                    }),
+                    ..resource
+                }
+            },
+            ..Dispatcher::default()
+        }
+    }
+
+    async fn get(
+        dispatcher: &Dispatcher<'static>,
+        if_none_match: Option<&str>,
+    ) -> http::Response<hyper::Body> {
+        let mut request = http::Request::builder().uri("/profile");
+        if let Some(etag) = if_none_match {
+            request = request.header("If-None-Match", etag);
+        }
+        let request = request.body(hyper::Body::empty()).unwrap();
+        dispatcher.clone().dispatch(request).await.unwrap()
+    }
+
+    fn header(response: &http::Response<hyper::Body>, name: &str) -> Option<String> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn immutable_assets_are_cached_for_a_year_without_an_etag() {
+        let dispatcher = dispatcher(Resource {
+            generate_etag: owned_callback(|_, _| Box::pin(async { Some("v1".to_string()) })),
+            caching: Some(CachingProfile::immutable_asset()),
+            ..Resource::default()
+        });
+        let response = get(&dispatcher, None).await;
+        expect!(header(&response, "Cache-Control"))
+            .to(be_some().value("public, max-age=31536000, immutable"));
+        expect!(header(&response, "ETag")).to(be_none());
+    }
+
+    #[tokio::test]
+    async fn private_user_data_is_revalidated_with_an_etag_of_the_body() {
+        let dispatcher = dispatcher(Resource {
+            caching: Some(CachingProfile::private_user_data()),
+            ..Resource::default()
+        });
+        let response = get(&dispatcher, None).await;
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        expect!(header(&response, "Cache-Control")).to(be_some().value("private, no-cache"));
+        let etag = header(&response, "ETag").unwrap();
+        expect!(etag.len()).to(be_equal_to(34));
+
+        let response = get(&dispatcher, Some(&etag)).await;
+        expect!(response.status().as_u16()).to(be_equal_to(304));
+        expect!(header(&response, "ETag")).to(be_some().value(etag));
+        expect!(header(&response, "Cache-Control")).to(be_some().value("private, no-cache"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        expect!(body.is_empty()).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn resource_cache_control_takes_precedence_over_the_profile() {
+        let dispatcher = dispatcher(Resource {
+            finish_request: owned_callback(|context, _| {
+                context
+                    .response
+                    .add_header("Cache-Control", vec![HeaderValue::basic("max-age=5")]);
+                Box::pin(async {})
+            }),
+            caching: Some(CachingProfile::no_store()),
+            ..Resource::default()
+        });
+        let response = get(&dispatcher, None).await;
+        expect!(header(&response, "Cache-Control")).to(be_some().value("max-age=5"));
+    }
+
+    #[tokio::test]
+    async fn dispatcher_profile_applies_to_resources_without_their_own() {
+        let dispatcher = Dispatcher {
+            caching: Some(CachingProfile::api_default_60s()),
+            ..dispatcher(Resource::default())
+        };
+        let response = get(&dispatcher, None).await;
+        expect!(header(&response, "Cache-Control")).to(be_some().value("public, max-age=60"));
+        expect!(header(&response, "ETag")).to(be_some());
+    }
+}
@@ -0,0 +1,214 @@
+//! The `chaos` module injects faults into the responses of resources, so that clients of a
+//! service can test their retry and fallback behaviour against realistic failures. This is
+//! meant for development and test environments, and should not be enabled in production.
+
+use futures::{stream, StreamExt};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use crate::context::{Context, Response, StreamingBody};
+
+/// Faults to inject into the requests to a resource. Each fault is injected into a random
+/// fraction of the requests, given by its rate (0.0 to 1.0).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultInjector {
+    /// Fraction of requests that are delayed before the resource is executed. Defaults to 0.0.
+    pub latency_rate: f64,
+    /// Minimum latency added to delayed requests. Defaults to zero.
+    pub min_latency: Duration,
+    /// Maximum latency added to delayed requests. Defaults to one second.
+    pub max_latency: Duration,
+    /// Fraction of requests that fail with the `error_status`, without the resource being
+    /// executed. Defaults to 0.0.
+    pub error_rate: f64,
+    /// Status of the failed requests. Defaults to '503 Service Unavailable'.
+    pub error_status: u16,
+    /// Fraction of requests whose connection is dropped after the response headers are sent,
+    /// without a body. Defaults to 0.0.
+    pub drop_rate: f64,
+    /// Fraction of streamed response bodies that are cut off halfway through their first
+    /// chunk, after which the connection is dropped. Defaults to 0.0.
+    pub truncate_rate: f64,
+}
+
+impl Default for FaultInjector {
+    fn default() -> FaultInjector {
+        FaultInjector {
+            latency_rate: 0.0,
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_secs(1),
+            error_rate: 0.0,
+            error_status: 503,
+            drop_rate: 0.0,
+            truncate_rate: 0.0,
+        }
+    }
+}
+
+impl FaultInjector {
+    /// Injects the faults that apply before the resource is executed: added latency and failed
+    /// requests. Returns true if the request has been failed, in which case the resource should
+    /// not be executed.
+    pub async fn before_resource(&self, context: &mut Context) -> bool {
+        if chance(self.latency_rate) {
+            let max = self.max_latency.max(self.min_latency);
+            let latency = self.min_latency + (max - self.min_latency).mul_f64(random());
+            warn!("Injecting {:?} of latency", latency);
+            tokio::time::sleep(latency).await;
+        }
+        if chance(self.error_rate) {
+            warn!("Injecting a {} response", self.error_status);
+            context.response = Response {
+                status: self.error_status,
+                ..Response::default()
+            };
+            context.error = Some("Fault injected".to_string());
+            return true;
+        }
+        false
+    }
+
+    /// Injects the faults that apply to the response of the resource: dropped connections and
+    /// truncated streamed bodies
+    pub fn after_resource(&self, context: &mut Context) {
+        if chance(self.drop_rate) {
+            warn!("Injecting a dropped connection");
+            context.response.body = None;
+            context.response.stream = Some(StreamingBody::new(stream::once(async {
+                Err("Connection dropped by fault injection".to_string())
+            })));
+            return;
+        }
+        let chunks = match &context.response.stream {
+            Some(stream) if context.response.body.is_none() && chance(self.truncate_rate) => {
+                stream.take_chunks()
+            }
+            _ => None,
+        };
+        if let Some(chunks) = chunks {
+            warn!("Injecting a truncated response body");
+            let truncated = chunks.take(1).flat_map(|chunk| {
+                let first_half = chunk.map(|mut chunk| {
+                    chunk.truncate(chunk.len() / 2);
+                    chunk
+                });
+                stream::iter(vec![
+                    first_half,
+                    Err("Body truncated by fault injection".to_string()),
+                ])
+            });
+            context.response.stream = Some(StreamingBody::new(truncated));
+        }
+    }
+}
+
+/// Returns a random number between 0.0 (inclusive) and 1.0 (exclusive). Each `RandomState`
+/// is randomly keyed, which is random enough for injecting faults without a dependency on a
+/// random number generator.
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+fn chance(rate: f64) -> bool {
+    rate > 0.0 && random() < rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{callback, Dispatcher, Resource};
+    use expectest::prelude::*;
+    use std::time::Instant;
+
+    fn dispatcher(faults: FaultInjector) -> Dispatcher<'static> {
+        Dispatcher {
+            routes: btreemap! {
+                "/report" => Resource {
+                    render_response: callback(&|context, _| {
+                        let chunks = vec![Ok(b"0123456789".to_vec()), Ok(b"abcdef".to_vec())];
+                        context.response.stream =
+                            Some(StreamingBody::new(futures::stream::iter(chunks)));
+                        Box::pin(async { None })
+                    }),
+                    fault_injector: Some(faults),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        }
+    }
+
+    async fn body(dispatcher: Dispatcher<'static>) -> (u16, Result<Vec<u8>, Vec<u8>>) {
+        use hyper::body::HttpBody;
+
+        let request = http::Request::builder()
+            .uri("/report")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = dispatcher.dispatch(request).await.unwrap();
+        let status = response.status().as_u16();
+        let mut body = response.into_body();
+        let mut data = vec![];
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(_) => return (status, Err(data)),
+            }
+        }
+        (status, Ok(data))
+    }
+
+    #[tokio::test]
+    async fn faults_are_not_injected_by_default() {
+        let (status, data) = body(dispatcher(FaultInjector::default())).await;
+        expect!(status).to(be_equal_to(200));
+        expect!(data).to(be_ok().value(b"0123456789abcdef".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn injects_latency_and_errors() {
+        let started = Instant::now();
+        let (status, _) = body(dispatcher(FaultInjector {
+            latency_rate: 1.0,
+            min_latency: Duration::from_millis(20),
+            max_latency: Duration::from_millis(30),
+            error_rate: 1.0,
+            ..FaultInjector::default()
+        }))
+        .await;
+        expect!(status).to(be_equal_to(503));
+        expect!(started.elapsed() >= Duration::from_millis(20)).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn injects_dropped_connections_and_truncated_bodies() {
+        let (status, data) = body(dispatcher(FaultInjector {
+            drop_rate: 1.0,
+            ..FaultInjector::default()
+        }))
+        .await;
+        expect!(status).to(be_equal_to(200));
+        expect!(data).to(be_err().value(Vec::<u8>::new()));
+
+        let (_, data) = body(dispatcher(FaultInjector {
+            truncate_rate: 1.0,
+            ..FaultInjector::default()
+        }))
+        .await;
+        expect!(data).to(be_err().value(b"01234".to_vec()));
+    }
+
+    #[test]
+    fn random_is_between_zero_and_one() {
+        for _ in 0..100 {
+            let value = random();
+            expect!((0.0..1.0).contains(&value)).to(be_true());
+        }
+        expect!(chance(0.0)).to(be_false());
+        expect!(chance(1.0)).to(be_true());
+    }
+}
@@ -0,0 +1,236 @@
+//! The `coalesce` module collapses bursts of identical writes (i.e. a client retry storm) into a
+//! single execution of the resource. Requests are identical if they have the same method, path,
+//! query, headers, client and body, so a response is never shared between requests with
+//! different credentials, whatever the authentication scheme (i.e. an API key header or a client
+//! certificate).
+
+use futures::{
+    channel::oneshot,
+    future::{FutureExt, Shared},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    content_negotiation::normalize_accept_encoding,
+    context::{Request, Response},
+    headers::HeaderValue,
+};
+
+/// Coalesces identical requests with an idempotent, unsafe method (i.e. PUT and DELETE) that
+/// arrive within a window of each other. The first request executes the resource, and the
+/// others get a copy of its response. Streamed response bodies are not shared, so if the first
+/// request streams its body the others execute the resource themselves.
+#[derive(Clone)]
+pub struct RequestCoalescer {
+    /// How long after the first of a burst of identical requests arrives that the others get its
+    /// response
+    pub window: Duration,
+    /// Headers (case-insensitive) that differ between otherwise identical requests, and so are
+    /// not part of the key (i.e. tracing headers). Headers that carry credentials must never be
+    /// ignored. Defaults to the W3C trace context and common request ID headers.
+    pub ignored_headers: Vec<String>,
+    entries: Arc<Mutex<HashMap<String, CoalesceEntry>>>,
+}
+
+struct CoalesceEntry {
+    started: Instant,
+    response: Shared<oneshot::Receiver<Response>>,
+}
+
+/// Role of a request in a burst of identical requests
+pub(crate) enum Coalesced {
+    /// The request executes the resource, and shares the response when it completes
+    Leader(oneshot::Sender<Response>),
+    /// The request waits for the response of the request that executes the resource
+    Follower(Shared<oneshot::Receiver<Response>>),
+}
+
+impl RequestCoalescer {
+    /// Creates a coalescer with the window
+    pub fn new(window: Duration) -> RequestCoalescer {
+        RequestCoalescer {
+            window,
+            ignored_headers: vec![
+                "traceparent".to_string(),
+                "tracestate".to_string(),
+                "X-Request-ID".to_string(),
+                "X-Correlation-ID".to_string(),
+            ],
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the key identifying identical requests. All the headers of the request (other
+    /// than the ignored ones) are part of the key, along with the client certificate and IP
+    /// address, so a response is never shared between different credentials or clients, nor
+    /// between requests that negotiate a different representation. The query parameters are
+    /// sorted and the Accept-Encoding header is normalised first, so equivalent requests (i.e.
+    /// with `gzip;q=1.0` and `gzip`) do not split a burst.
+    pub fn key(&self, request: &Request) -> String {
+        let mut hasher = Sha256::new();
+        let mut query: Vec<_> = request.query.iter().collect();
+        query.sort();
+        for (name, values) in query {
+            hasher.update(format!("{:?}={:?}&", name, values).as_bytes());
+        }
+        hasher.update(b"\n");
+        let mut headers: Vec<(String, String)> = request
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                !self
+                    .ignored_headers
+                    .iter()
+                    .any(|ignored| ignored.eq_ignore_ascii_case(name))
+            })
+            .map(|(name, values)| (name.to_lowercase(), normalise_header(name, values)))
+            .collect();
+        headers.sort();
+        for (name, value) in headers {
+            hasher.update(format!("{}: {}\n", name, value).as_bytes());
+        }
+        if let Some(certificate) = &request.client_certificate {
+            hasher.update(format!("client-certificate: {}\n", certificate.fingerprint).as_bytes());
+        }
+        if let Some(ip) = request.client_ip() {
+            hasher.update(format!("client-ip: {}\n", ip).as_bytes());
+        }
+        hasher.update(request.body.as_deref().unwrap_or_default());
+        format!(
+            "{} {}{} {}",
+            request.method.to_uppercase(),
+            request.base_path,
+            request.request_path,
+            hex::encode(hasher.finalize())
+        )
+    }
+
+    /// Joins the burst of requests with the key, expiring the bursts that are older than the
+    /// window
+    pub(crate) fn join(&self, key: String) -> Coalesced {
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let window = self.window;
+        entries.retain(|_, entry| entry.started.elapsed() < window);
+        if let Some(entry) = entries.get(&key) {
+            return Coalesced::Follower(entry.response.clone());
+        }
+        let (sender, receiver) = oneshot::channel();
+        entries.insert(
+            key,
+            CoalesceEntry {
+                started: Instant::now(),
+                response: receiver.shared(),
+            },
+        );
+        Coalesced::Leader(sender)
+    }
+}
+
+/// Returns the values of the header in a normal form, with sorted parameters
+fn normalise_header(name: &str, values: &[HeaderValue]) -> String {
+    if name.eq_ignore_ascii_case("Accept-Encoding") {
+        return normalize_accept_encoding(values);
+    }
+    values
+        .iter()
+        .map(|value| {
+            let mut params: Vec<_> = value.params.iter().collect();
+            params.sort();
+            let params: Vec<_> = params
+                .iter()
+                .map(|(k, v)| format!(";{}={}", k, v))
+                .collect();
+            format!("{}{}", value.value, params.concat())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::HeaderValue;
+    use expectest::prelude::*;
+
+    #[test]
+    fn identical_requests_in_the_window_follow_the_first() {
+        let coalescer = RequestCoalescer::new(Duration::from_secs(60));
+        let request = |body: &str| Request {
+            method: "PUT".to_string(),
+            request_path: "/orders/1".to_string(),
+            body: Some(body.as_bytes().to_vec()),
+            ..Request::default()
+        };
+        let leader = match coalescer.join(coalescer.key(&request("{}"))) {
+            Coalesced::Leader(leader) => leader,
+            Coalesced::Follower(_) => panic!("expected the first request to lead"),
+        };
+        let follower = match coalescer.join(coalescer.key(&request("{}"))) {
+            Coalesced::Follower(follower) => follower,
+            Coalesced::Leader(_) => panic!("expected an identical request to follow"),
+        };
+        expect!(matches!(
+            coalescer.join(coalescer.key(&request("{\"a\":1}"))),
+            Coalesced::Leader(_)
+        ))
+        .to(be_true());
+        leader.send(Response::default()).unwrap();
+        expect!(follower.now_or_never()).to(be_some().value(Ok(Response::default())));
+    }
+
+    #[test]
+    fn key_includes_the_normalised_headers_and_sorted_query() {
+        let coalescer = RequestCoalescer::new(Duration::from_secs(60));
+        let request = |header: &str, value: &str| Request {
+            method: "PUT".to_string(),
+            request_path: "/orders/1".to_string(),
+            headers: hashmap! {
+                header.to_string() => vec![h!(value)],
+                "traceparent".to_string() => vec![HeaderValue::basic(format!("00-{}-01", value))]
+            },
+            query: hashmap! {
+                "a".to_string() => vec!["1".to_string()],
+                "b".to_string() => vec!["2".to_string()]
+            },
+            ..Request::default()
+        };
+        let key = coalescer.key(&request("Accept-Encoding", "gzip"));
+        expect!(coalescer.key(&request("Accept-Encoding", "GZIP;q=1.0")))
+            .to(be_equal_to(key.clone()));
+        expect!(coalescer.key(&request("Accept-Encoding", "br"))).to_not(be_equal_to(key.clone()));
+
+        let mut other_query = request("Accept-Encoding", "gzip");
+        other_query.query.insert("b".to_string(), vec!["3".to_string()]);
+        expect!(coalescer.key(&other_query)).to_not(be_equal_to(key));
+    }
+
+    #[test]
+    fn key_is_never_shared_between_credentials() {
+        let coalescer = RequestCoalescer::new(Duration::from_secs(60));
+        let request = |header: &str, value: &str| Request {
+            method: "PUT".to_string(),
+            request_path: "/orders/1".to_string(),
+            headers: hashmap! { header.to_string() => vec![h!(value)] },
+            ..Request::default()
+        };
+        for header in &["X-API-Key", "Authorization", "X-Custom-Token"] {
+            expect!(coalescer.key(&request(header, "one")))
+                .to_not(be_equal_to(coalescer.key(&request(header, "two"))));
+        }
+
+        let mut with_certificate = request("Accept", "*/*");
+        with_certificate.client_certificate = Some(crate::ClientCertificate::new(vec![vec![1]]));
+        let mut other_certificate = with_certificate.clone();
+        other_certificate.client_certificate = Some(crate::ClientCertificate::new(vec![vec![2]]));
+        expect!(coalescer.key(&with_certificate))
+            .to_not(be_equal_to(coalescer.key(&other_certificate)));
+    }
+}
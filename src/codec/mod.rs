@@ -0,0 +1,361 @@
+//! The `codec` module provides a registry of body codecs keyed by media type, so a resource can
+//! produce a single typed value and have it rendered in whichever media type was negotiated.
+//! Codecs convert between bytes and a `serde_json::Value`, which any serde type can be converted
+//! to and from. JSON and CSV are always supported, CBOR with the `cbor` feature, XML with the
+//! `xml` feature and XLSX (encoding only) with the `xlsx` feature.
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Number, Value};
+use std::sync::Arc;
+
+use crate::{context::Request, headers::HeaderValue};
+
+mod tabular;
+pub use self::tabular::*;
+
+/// Serializer and deserializer of bodies of a media type
+pub trait BodyCodec: Send + Sync {
+    /// Media type the codec handles (i.e. `application/json`)
+    fn media_type(&self) -> &str;
+
+    /// Encodes the value as a body
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String>;
+
+    /// Decodes a body to a value
+    fn decode(&self, body: &[u8]) -> Result<Value, String>;
+}
+
+/// Codec for JSON bodies
+#[derive(Debug, Clone, Default)]
+pub struct JsonCodec;
+
+impl BodyCodec for JsonCodec {
+    fn media_type(&self) -> &str {
+        "application/json"
+    }
+
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(value).map_err(|err| format!("Failed to encode JSON - {}", err))
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Value, String> {
+        serde_json::from_slice(body).map_err(|err| format!("Failed to decode JSON - {}", err))
+    }
+}
+
+/// Codec for CBOR (RFC 8949) bodies, enabled with the `cbor` feature
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl BodyCodec for CborCodec {
+    fn media_type(&self) -> &str {
+        "application/cbor"
+    }
+
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let mut body = Vec::new();
+        ciborium::ser::into_writer(value, &mut body)
+            .map_err(|err| format!("Failed to encode CBOR - {}", err))?;
+        Ok(body)
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Value, String> {
+        ciborium::de::from_reader(body).map_err(|err| format!("Failed to decode CBOR - {}", err))
+    }
+}
+
+/// Codec for XML bodies, enabled with the `xml` feature. XML has no arrays or scalar types, so
+/// repeated elements are decoded as arrays, attributes as `@name` fields, text content that is a
+/// number or boolean as that type, and empty elements as null. Arrays at the top level are
+/// encoded as repeated `item` elements.
+#[cfg(feature = "xml")]
+#[derive(Debug, Clone)]
+pub struct XmlCodec {
+    /// Name of the root element of encoded bodies. Defaults to `root`.
+    pub root_element: String,
+}
+
+#[cfg(feature = "xml")]
+impl Default for XmlCodec {
+    fn default() -> XmlCodec {
+        XmlCodec {
+            root_element: "root".to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "xml")]
+impl BodyCodec for XmlCodec {
+    fn media_type(&self) -> &str {
+        "application/xml"
+    }
+
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let result = match value {
+            Value::Array(_) => quick_xml::se::to_string_with_root(
+                &self.root_element,
+                &serde_json::json!({ "item": value }),
+            ),
+            _ => quick_xml::se::to_string_with_root(&self.root_element, value),
+        };
+        result
+            .map(String::into_bytes)
+            .map_err(|err| format!("Failed to encode XML - {}", err))
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Value, String> {
+        xml::decode(body).map_err(|err| format!("Failed to decode XML - {}", err))
+    }
+}
+
+/// Decodes text from a format without scalar types (i.e. XML or CSV). Text that is a number or
+/// boolean in its canonical form is decoded as one.
+fn scalar(text: &str) -> Value {
+    if let Ok(flag) = text.parse::<bool>() {
+        return Value::Bool(flag);
+    }
+    match serde_json::from_str::<Number>(text) {
+        Ok(number) if number.to_string() == text => Value::Number(number),
+        _ => Value::String(text.to_string()),
+    }
+}
+
+#[cfg(feature = "xml")]
+mod xml {
+    use quick_xml::{
+        events::{BytesStart, Event},
+        Reader,
+    };
+    use serde_json::{Map, Value};
+
+    use super::scalar;
+
+    #[derive(Default)]
+    struct Element {
+        name: String,
+        fields: Map<String, Value>,
+        text: String,
+    }
+
+    impl Element {
+        fn start(start: &BytesStart) -> Result<Element, quick_xml::Error> {
+            let mut element = Element {
+                name: String::from_utf8_lossy(start.name().as_ref()).to_string(),
+                ..Element::default()
+            };
+            for attribute in start.attributes() {
+                let attribute = attribute?;
+                let key = String::from_utf8_lossy(attribute.key.as_ref());
+                let value = attribute.unescape_value()?;
+                element
+                    .fields
+                    .insert(format!("@{}", key), scalar(value.trim()));
+            }
+            Ok(element)
+        }
+
+        fn into_value(self) -> Value {
+            let text = self.text.trim();
+            if self.fields.is_empty() {
+                if text.is_empty() {
+                    Value::Null
+                } else {
+                    scalar(text)
+                }
+            } else {
+                let mut fields = self.fields;
+                if !text.is_empty() {
+                    fields.insert("$text".to_string(), scalar(text));
+                }
+                Value::Object(fields)
+            }
+        }
+
+        fn add_child(&mut self, name: String, value: Value) {
+            match self.fields.get_mut(&name) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                None => {
+                    self.fields.insert(name, value);
+                }
+            }
+        }
+    }
+
+    /// Decodes the body to the value of the root element
+    pub(crate) fn decode(body: &[u8]) -> Result<Value, quick_xml::Error> {
+        let mut reader = Reader::from_reader(body);
+        let mut buffer = Vec::new();
+        let mut stack: Vec<Element> = Vec::new();
+        loop {
+            let element = match reader.read_event_into(&mut buffer)? {
+                Event::Start(start) => {
+                    stack.push(Element::start(&start)?);
+                    None
+                }
+                Event::Empty(start) => Some(Element::start(&start)?),
+                Event::End(_) => stack.pop(),
+                Event::Text(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&text.unescape()?);
+                    }
+                    None
+                }
+                Event::CData(data) => {
+                    if let Some(element) = stack.last_mut() {
+                        element
+                            .text
+                            .push_str(&String::from_utf8_lossy(&data.into_inner()));
+                    }
+                    None
+                }
+                Event::Eof => return Ok(Value::Null),
+                _ => None,
+            };
+            if let Some(element) = element {
+                match stack.last_mut() {
+                    Some(parent) => {
+                        let name = element.name.clone();
+                        parent.add_child(name, element.into_value());
+                    }
+                    None => return Ok(element.into_value()),
+                }
+            }
+            buffer.clear();
+        }
+    }
+}
+
+/// Registry of the body codecs available to a resource
+#[derive(Clone)]
+pub struct CodecRegistry {
+    /// Codecs in order of preference
+    pub codecs: Vec<Arc<dyn BodyCodec>>,
+}
+
+impl Default for CodecRegistry {
+    /// Creates a registry with the JSON and CSV codecs, and the CBOR, XML and XLSX codecs if the
+    /// `cbor`, `xml` and `xlsx` features are enabled
+    fn default() -> CodecRegistry {
+        let codecs: Vec<Arc<dyn BodyCodec>> = vec![
+            Arc::new(JsonCodec),
+            Arc::new(CsvCodec),
+            #[cfg(feature = "cbor")]
+            Arc::new(CborCodec),
+            #[cfg(feature = "xml")]
+            Arc::new(XmlCodec::default()),
+            #[cfg(feature = "xlsx")]
+            Arc::new(XlsxCodec),
+        ];
+        CodecRegistry { codecs }
+    }
+}
+
+impl CodecRegistry {
+    /// Registers a codec, replacing any existing codec for the same media type
+    pub fn register(&mut self, codec: Arc<dyn BodyCodec>) {
+        let media_type = codec.media_type().to_lowercase();
+        self.codecs
+            .retain(|existing| existing.media_type().to_lowercase() != media_type);
+        self.codecs.push(codec);
+    }
+
+    /// Returns the media types of the registered codecs, which can be used as the `produces`
+    /// of a resource
+    pub fn media_types(&self) -> Vec<&str> {
+        self.codecs.iter().map(|codec| codec.media_type()).collect()
+    }
+
+    /// Returns the codec for the media type, ignoring any parameters
+    pub fn codec_for(&self, media_type: &str) -> Option<&Arc<dyn BodyCodec>> {
+        let media_type = HeaderValue::parse_string(media_type).value;
+        self.codecs
+            .iter()
+            .find(|codec| codec.media_type().eq_ignore_ascii_case(media_type.trim()))
+    }
+
+    /// Encodes the value as a body of the media type
+    pub fn encode<T: Serialize>(&self, media_type: &str, value: &T) -> Result<Vec<u8>, String> {
+        let codec = self
+            .codec_for(media_type)
+            .ok_or_else(|| format!("No codec is registered for '{}'", media_type))?;
+        let value = serde_json::to_value(value).map_err(|err| err.to_string())?;
+        codec.encode(&value)
+    }
+
+    /// Decodes a body of the media type
+    pub fn decode<T: DeserializeOwned>(&self, media_type: &str, body: &[u8]) -> Result<T, String> {
+        let codec = self
+            .codec_for(media_type)
+            .ok_or_else(|| format!("No codec is registered for '{}'", media_type))?;
+        serde_json::from_value(codec.decode(body)?).map_err(|err| err.to_string())
+    }
+
+    /// Decodes the body of the request using the codec for its content type
+    pub fn decode_request<T: DeserializeOwned>(&self, request: &Request) -> Result<T, String> {
+        match &request.body {
+            Some(body) => self.decode(&request.content_type(), body),
+            None => Err("Request has no body".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+    use std::collections::BTreeMap;
+
+    fn order() -> BTreeMap<String, u32> {
+        btreemap! { "id".to_string() => 1, "quantity".to_string() => 2 }
+    }
+
+    #[test]
+    fn registry_encodes_and_decodes_by_media_type() {
+        let registry = CodecRegistry::default();
+        let body = registry
+            .encode("application/json; charset=UTF-8", &order())
+            .unwrap();
+        expect!(String::from_utf8(body.clone()).unwrap())
+            .to(be_equal_to("{\"id\":1,\"quantity\":2}"));
+        expect!(registry.decode::<BTreeMap<String, u32>>("application/json", &body))
+            .to(be_ok().value(order()));
+        expect!(registry.encode("text/html", &1).is_err()).to(be_true());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn registry_round_trips_cbor() {
+        let registry = CodecRegistry::default();
+        let body = registry.encode("application/cbor", &order()).unwrap();
+        expect!(registry.decode::<BTreeMap<String, u32>>("application/cbor", &body))
+            .to(be_ok().value(order()));
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn registry_round_trips_xml() {
+        let registry = CodecRegistry::default();
+        let body = registry.encode("application/xml", &order()).unwrap();
+        expect!(String::from_utf8(body.clone()).unwrap())
+            .to(be_equal_to("<root><id>1</id><quantity>2</quantity></root>"));
+        expect!(registry.decode::<BTreeMap<String, u32>>("application/xml", &body))
+            .to(be_ok().value(order()));
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn xml_codec_decodes_repeated_elements_and_attributes() {
+        let body = b"<order id=\"007\"><item>1</item><item>2</item><note>a &amp; b</note>\
+            <gift/><paid>true</paid></order>";
+        expect!(XmlCodec::default().decode(body)).to(be_ok().value(serde_json::json!({
+            "@id": "007",
+            "item": [1, 2],
+            "note": "a & b",
+            "gift": null,
+            "paid": true
+        })));
+    }
+}
@@ -0,0 +1,203 @@
+//! Codecs for tabular bodies (i.e. reports), which are rows of records. A body is an array of
+//! rows (or a single row), and the columns are the fields of the first row. Rows that are not
+//! objects have a single `value` column.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::Write;
+
+use super::{scalar, BodyCodec};
+
+/// Media type of XLSX (Excel) spreadsheets
+pub const XLSX_MEDIA_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+/// Codec for CSV (RFC 4180) bodies, with a header row. Decoded fields are strings, except for
+/// numbers and booleans, and empty fields are null.
+#[derive(Debug, Clone, Default)]
+pub struct CsvCodec;
+
+impl BodyCodec for CsvCodec {
+    fn media_type(&self) -> &str {
+        "text/csv"
+    }
+
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        write_csv(Vec::new(), rows(value))
+    }
+
+    fn decode(&self, body: &[u8]) -> Result<Value, String> {
+        let mut reader = csv::Reader::from_reader(body);
+        let headers = reader
+            .headers()
+            .map_err(|err| format!("Failed to decode CSV - {}", err))?
+            .clone();
+        reader
+            .records()
+            .map(|record| {
+                let record = record.map_err(|err| format!("Failed to decode CSV - {}", err))?;
+                Ok(Value::Object(
+                    headers
+                        .iter()
+                        .zip(record.iter())
+                        .map(|(header, field)| {
+                            let value = if field.is_empty() { Value::Null } else { scalar(field) };
+                            (header.to_string(), value)
+                        })
+                        .collect::<Map<String, Value>>(),
+                ))
+            })
+            .collect::<Result<Vec<Value>, String>>()
+            .map(Value::Array)
+    }
+}
+
+/// Writes the rows to the writer as CSV one at a time, so large reports do not have to be
+/// collected first. The header row is the fields of the first row, and fields that only later
+/// rows have are not written. Returns the writer.
+pub fn write_csv<W, T, I>(writer: W, rows: I) -> Result<W, String>
+where
+    W: Write,
+    T: Serialize,
+    I: IntoIterator<Item = T>,
+{
+    let mut csv = csv::Writer::from_writer(writer);
+    let mut columns: Option<Vec<String>> = None;
+    for row in rows {
+        let row = serde_json::to_value(row).map_err(|err| err.to_string())?;
+        let columns = match &mut columns {
+            Some(columns) => columns,
+            None => {
+                let names = column_names(&row);
+                csv.write_record(&names)
+                    .map_err(|err| format!("Failed to encode CSV - {}", err))?;
+                columns.insert(names)
+            }
+        };
+        csv.write_record(columns.iter().map(|column| cell(field(&row, column))))
+            .map_err(|err| format!("Failed to encode CSV - {}", err))?;
+    }
+    csv.into_inner()
+        .map_err(|err| format!("Failed to encode CSV - {}", err))
+}
+
+/// Codec for XLSX (Excel) spreadsheets with a single worksheet, enabled with the `xlsx`
+/// feature. Only encoding is supported.
+#[cfg(feature = "xlsx")]
+#[derive(Debug, Clone, Default)]
+pub struct XlsxCodec;
+
+#[cfg(feature = "xlsx")]
+impl BodyCodec for XlsxCodec {
+    fn media_type(&self) -> &str {
+        XLSX_MEDIA_TYPE
+    }
+
+    fn encode(&self, value: &Value) -> Result<Vec<u8>, String> {
+        use std::convert::TryFrom;
+
+        let error = |err: rust_xlsxwriter::XlsxError| format!("Failed to encode XLSX - {}", err);
+        let rows = rows(value);
+        let columns = rows.first().map(|row| column_names(row)).unwrap_or_default();
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        let worksheet = workbook.add_worksheet();
+        for (col, name) in columns.iter().enumerate() {
+            let col = u16::try_from(col).map_err(|_| "Too many columns for XLSX".to_string())?;
+            worksheet.write_string(0, col, name).map_err(error)?;
+        }
+        for (row_index, row) in rows.iter().enumerate() {
+            let row_index = u32::try_from(row_index + 1)
+                .map_err(|_| "Too many rows for XLSX".to_string())?;
+            for (col, name) in columns.iter().enumerate() {
+                let col = col as u16;
+                match field(row, name) {
+                    None | Some(Value::Null) => (),
+                    Some(Value::Bool(flag)) => {
+                        worksheet.write_boolean(row_index, col, *flag).map_err(error)?;
+                    }
+                    Some(Value::Number(number)) => {
+                        let number = number.as_f64().unwrap_or_default();
+                        worksheet.write_number(row_index, col, number).map_err(error)?;
+                    }
+                    value => {
+                        worksheet.write_string(row_index, col, cell(value)).map_err(error)?;
+                    }
+                }
+            }
+        }
+        workbook.save_to_buffer().map_err(error)
+    }
+
+    fn decode(&self, _body: &[u8]) -> Result<Value, String> {
+        Err("Decoding XLSX bodies is not supported".to_string())
+    }
+}
+
+fn rows(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(rows) => rows.iter().collect(),
+        row => vec![row],
+    }
+}
+
+fn column_names(row: &Value) -> Vec<String> {
+    match row {
+        Value::Object(fields) => fields.keys().cloned().collect(),
+        _ => vec!["value".to_string()],
+    }
+}
+
+fn field<'v>(row: &'v Value, column: &str) -> Option<&'v Value> {
+    match row {
+        Value::Object(fields) => fields.get(column),
+        row => Some(row),
+    }
+}
+
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn write_csv_streams_rows_with_a_header_from_the_first_row() {
+        let rows = vec![
+            btreemap! { "name" => Value::from("Widget, large"), "price" => Value::from(10.5) },
+            btreemap! { "name" => Value::from("Gadget"), "price" => Value::Null },
+        ];
+        let body = write_csv(Vec::new(), rows).unwrap();
+        expect!(String::from_utf8(body).unwrap())
+            .to(be_equal_to("name,price\n\"Widget, large\",10.5\nGadget,\n"));
+    }
+
+    #[test]
+    fn csv_codec_decodes_rows_to_records() {
+        let body = b"id,name,active,note\n7,Widget,true,\n";
+        expect!(CsvCodec.decode(body)).to(be_ok().value(serde_json::json!([
+            { "id": 7, "name": "Widget", "active": true, "note": null }
+        ])));
+        expect!(crate::codec::CodecRegistry::default()
+            .decode::<Vec<BTreeMap<String, Value>>>("text/csv", body)
+            .map(|rows| rows.len()))
+        .to(be_ok().value(1));
+    }
+
+    #[cfg(feature = "xlsx")]
+    #[test]
+    fn xlsx_codec_encodes_a_workbook() {
+        let body = XlsxCodec
+            .encode(&serde_json::json!([{ "id": 1, "name": "Widget" }]))
+            .unwrap();
+        // XLSX files are zip archives
+        expect!(body.starts_with(b"PK")).to(be_true());
+    }
+}
@@ -0,0 +1,310 @@
+//! The `compression` module compresses response bodies with the encoding negotiated from the
+//! Accept-Encoding header of the request. Compression is skipped by heuristics when it would not
+//! pay off (small bodies, media types that are already compressed), and can be disabled to
+//! mitigate BREACH for responses that embed per-request secrets. When compression is skipped,
+//! the response is sent with the identity encoding, and the ETag of the resource is for the
+//! identity encoding.
+
+use flate2::{
+    write::{DeflateEncoder, GzEncoder},
+    Compression,
+};
+use std::io::Write;
+
+use crate::context::Context;
+
+/// Configuration of compressing the response bodies of a resource. The resource must declare
+/// the encodings it provides (i.e. `encodings_provided: vec!["gzip", "identity"]`), and bodies
+/// are compressed when `gzip` or `deflate` is negotiated. Any other encoding is left to the
+/// resource to apply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionConfig {
+    /// Bodies smaller than this are not compressed, as the saving does not make up for the
+    /// overhead. Defaults to 1024 bytes.
+    pub min_size: usize,
+    /// Media types that are not compressed, as they are already compressed. Entries may end
+    /// with a wildcard subtype (i.e. `video/*`). Defaults to common image, audio, video, font and
+    /// archive formats.
+    pub incompressible_media_types: Vec<String>,
+    /// If the responses embed per-request secrets (i.e. CSRF tokens) along with data from the
+    /// request, in which case they are never compressed, as the compressed size can reveal the
+    /// secret (the BREACH attack). Defaults to false.
+    pub embeds_secrets: bool,
+    /// If responses to cross-site requests (with a `Sec-Fetch-Site: cross-site` header) are
+    /// compressed. Disabling this mitigates BREACH, which relies on an attacker making requests
+    /// from another site, while still compressing responses to the site itself. Defaults to
+    /// true.
+    pub compress_cross_site: bool,
+    /// Compression level, from 0 (none) to 9 (best). Defaults to 6.
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> CompressionConfig {
+        CompressionConfig {
+            min_size: 1024,
+            incompressible_media_types: [
+                "image/png",
+                "image/jpeg",
+                "image/gif",
+                "image/webp",
+                "image/avif",
+                "audio/*",
+                "video/*",
+                "font/woff",
+                "font/woff2",
+                "application/zip",
+                "application/gzip",
+                "application/x-bzip2",
+                "application/x-7z-compressed",
+                "application/zstd",
+            ]
+            .iter()
+            .map(|media_type| media_type.to_string())
+            .collect(),
+            embeds_secrets: false,
+            compress_cross_site: true,
+            level: 6,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Returns why the response should not be compressed, if it should not be
+    pub fn skip_reason(&self, context: &Context) -> Option<&'static str> {
+        let body = match (&context.response.body, &context.response.stream) {
+            (Some(body), _) => body,
+            (None, Some(_)) => return Some("streamed bodies are not compressed"),
+            (None, None) => return Some("there is no body"),
+        };
+        if self.embeds_secrets {
+            return Some("the response embeds secrets");
+        }
+        let cross_site = context
+            .request
+            .find_header("Sec-Fetch-Site")
+            .iter()
+            .any(|value| value.value.eq_ignore_ascii_case("cross-site"));
+        if cross_site && !self.compress_cross_site {
+            return Some("the request is cross-site");
+        }
+        let no_transform = context
+            .response
+            .headers
+            .get("Cache-Control")
+            .iter()
+            .flat_map(|values| values.iter())
+            .any(|value| value.value == "no-transform");
+        if no_transform {
+            return Some("the response has Cache-Control: no-transform");
+        }
+        if body.len() < self.min_size {
+            return Some("the body is smaller than the minimum size");
+        }
+        let media_type = context
+            .response
+            .headers
+            .get("Content-Type")
+            .and_then(|values| values.first())
+            .map(|value| value.value.to_lowercase())
+            .unwrap_or_default();
+        let incompressible = self
+            .incompressible_media_types
+            .iter()
+            .any(|excluded| match excluded.strip_suffix("/*") {
+                Some(main_type) => media_type.split('/').next() == Some(main_type),
+                None => *excluded == media_type,
+            });
+        if incompressible {
+            return Some("the media type is already compressed");
+        }
+        None
+    }
+
+    /// Compresses the body with the negotiated encoding, or removes the Content-Encoding header
+    /// if the body is not compressed
+    pub(crate) fn apply(&self, context: &mut Context) {
+        let encoding = match context.selected_encoding.as_deref() {
+            Some(encoding @ ("gzip" | "x-gzip" | "deflate")) => encoding.to_string(),
+            _ => return,
+        };
+        if context.response.status == 304 {
+            return;
+        }
+        let compressed = match self.skip_reason(context) {
+            Some(reason) => {
+                debug!("Not compressing the response, as {}", reason);
+                None
+            }
+            None => {
+                let body = context.response.body.as_deref().unwrap_or_default();
+                match compress(&encoding, body, self.level) {
+                    Ok(compressed) => Some(compressed),
+                    Err(err) => {
+                        warn!("Failed to compress the response body: {}", err);
+                        None
+                    }
+                }
+            }
+        };
+        match compressed {
+            Some(compressed) => context.response.body = Some(compressed),
+            None => {
+                context.response.remove_header("Content-Encoding");
+                context.selected_encoding = Some("identity".to_string());
+            }
+        }
+    }
+}
+
+fn compress(encoding: &str, body: &[u8], level: u32) -> std::io::Result<Vec<u8>> {
+    let level = Compression::new(level.min(9));
+    if encoding == "deflate" {
+        let mut encoder = DeflateEncoder::new(Vec::new(), level);
+        encoder.write_all(body)?;
+        encoder.finish()
+    } else {
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        encoder.write_all(body)?;
+        encoder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{owned_callback, Dispatcher, Resource};
+    use expectest::prelude::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn dispatcher(compression: CompressionConfig, body: &'static str) -> Dispatcher<'static> {
+        Dispatcher {
+            routes: btreemap! {
+                "/report" => Resource {
+                    encodings_provided: vec!["gzip", "identity"],
+                    render_response: owned_callback(move |_, _| {
+                        Box::pin(async move { Some(body.to_string()) })
+                    }),
+                    compression: Some(compression),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        }
+    }
+
+    async fn get(
+        dispatcher: Dispatcher<'static>,
+        headers: &[(&str, &str)],
+    ) -> (Option<String>, Vec<u8>) {
+        let mut request = http::Request::builder()
+            .uri("/report")
+            .header("Accept-Encoding", "gzip");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = request.body(hyper::Body::empty()).unwrap();
+        let response = dispatcher.dispatch(request).await.unwrap();
+        let encoding = response
+            .headers()
+            .get("Content-Encoding")
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (encoding, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn compresses_bodies_over_the_minimum_size() {
+        let body = "{\"total\": 1}".repeat(200);
+        let body: &'static str = Box::leak(body.into_boxed_str());
+        let (encoding, compressed) = get(dispatcher(CompressionConfig::default(), body), &[]).await;
+        expect!(encoding).to(be_some().value("gzip"));
+        expect!(compressed.len() < body.len()).to(be_true());
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        expect!(decompressed).to(be_equal_to(body));
+
+        let (encoding, _) = get(dispatcher(CompressionConfig::default(), "{}"), &[]).await;
+        expect!(encoding).to(be_none());
+    }
+
+    #[tokio::test]
+    async fn compression_is_skipped_to_mitigate_breach() {
+        let body = "<input name=\"csrf\" value=\"secret\">".repeat(100);
+        let body: &'static str = Box::leak(body.into_boxed_str());
+        let config = CompressionConfig {
+            compress_cross_site: false,
+            ..CompressionConfig::default()
+        };
+        let (encoding, _) = get(dispatcher(config.clone(), body), &[]).await;
+        expect!(encoding).to(be_some().value("gzip"));
+        let cross_site = [("Sec-Fetch-Site", "cross-site")];
+        let (encoding, sent) = get(dispatcher(config, body), &cross_site).await;
+        expect!(encoding).to(be_none());
+        expect!(sent).to(be_equal_to(body.as_bytes().to_vec()));
+
+        let config = CompressionConfig {
+            embeds_secrets: true,
+            ..CompressionConfig::default()
+        };
+        let (encoding, _) = get(dispatcher(config, body), &[]).await;
+        expect!(encoding).to(be_none());
+    }
+
+    #[tokio::test]
+    async fn each_encoding_gets_its_own_body_etag() {
+        let body = "{\"total\": 1}".repeat(200);
+        let body: &'static str = Box::leak(body.into_boxed_str());
+        let etag = |encoding: &str| {
+            let dispatcher = Dispatcher {
+                caching: Some(crate::CachingProfile::private_user_data()),
+                ..dispatcher(CompressionConfig::default(), body)
+            };
+            let request = http::Request::builder()
+                .uri("/report")
+                .header("Accept-Encoding", encoding)
+                .body(hyper::Body::empty())
+                .unwrap();
+            async move {
+                let response = dispatcher.dispatch(request).await.unwrap();
+                response.headers().get("ETag").cloned()
+            }
+        };
+        let gzip = etag("gzip").await;
+        expect!(gzip.is_some()).to(be_true());
+        expect!(etag("gzip;q=1.0").await).to(be_equal_to(gzip.clone()));
+        expect!(etag("identity").await).to_not(be_equal_to(gzip));
+    }
+
+    #[tokio::test]
+    async fn resource_etag_is_for_the_identity_encoding_if_compression_is_skipped() {
+        let resource = Resource {
+            encodings_provided: vec!["gzip", "identity"],
+            render_response: owned_callback(|_, _| Box::pin(async { Some("{}".to_string()) })),
+            generate_etag: owned_callback(|_, _| Box::pin(async { Some("v1".to_string()) })),
+            compression: Some(CompressionConfig::default()),
+            ..Resource::default()
+        };
+        let dispatcher = Dispatcher {
+            routes: btreemap! { "/report" => resource },
+            ..Dispatcher::default()
+        };
+        let request = |encoding: &str| {
+            http::Request::builder()
+                .uri("/report")
+                .header("Accept-Encoding", encoding)
+                .body(hyper::Body::empty())
+                .unwrap()
+        };
+        let response = dispatcher.clone().dispatch(request("gzip")).await.unwrap();
+        expect!(response.headers().get("Content-Encoding")).to(be_none());
+        let etag = response.headers().get("ETag").cloned();
+        expect!(etag.clone()).to(be_some().value("\"v1;identity\""));
+
+        let response = dispatcher.dispatch(request("identity")).await.unwrap();
+        expect!(response.headers().get("ETag").cloned()).to(be_equal_to(etag));
+    }
+}
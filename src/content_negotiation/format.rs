@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use crate::{codec::XLSX_MEDIA_TYPE, context::Request, headers::HeaderValue};
+
+/// Query parameter that overrides the Accept header when negotiating the media type (i.e.
+/// `?format=csv`), so APIs can be used from a browser address bar. The query string is part of
+/// the URL that responses are cached against, so responses selected with the parameter are
+/// cached separately from ones negotiated with the Accept header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatOverride {
+    /// Name of the query parameter. Defaults to `format`.
+    pub parameter: String,
+    /// Media types for the values of the parameter (case-insensitive). Defaults to `json`,
+    /// `xml`, `csv` and `xlsx`.
+    pub formats: HashMap<String, String>,
+}
+
+impl Default for FormatOverride {
+    fn default() -> FormatOverride {
+        FormatOverride {
+            parameter: "format".to_string(),
+            formats: hashmap! {
+                "json".to_string() => "application/json".to_string(),
+                "xml".to_string() => "application/xml".to_string(),
+                "csv".to_string() => "text/csv".to_string(),
+                "xlsx".to_string() => XLSX_MEDIA_TYPE.to_string(),
+            },
+        }
+    }
+}
+
+impl FormatOverride {
+    /// Returns the value of the query parameter of the request, if it has one
+    pub fn requested_format<'r>(&self, request: &'r Request) -> Option<&'r str> {
+        request
+            .query
+            .get(&self.parameter)
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+
+    /// Returns the media type for the format
+    pub fn media_type_for(&self, format: &str) -> Option<&str> {
+        self.formats
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(format))
+            .map(|(_, media_type)| media_type.as_str())
+    }
+
+    /// Returns the request with its Accept header replaced by the media type of the requested
+    /// format. The Accept header of a request for an unknown format is emptied, so no media type
+    /// is acceptable. Returns None if the request does not have the query parameter.
+    pub fn apply(&self, request: &Request) -> Option<Request> {
+        let format = self.requested_format(request)?;
+        let accept = self
+            .media_type_for(format)
+            .map(|media_type| vec![HeaderValue::basic(media_type)])
+            .unwrap_or_default();
+        let mut request = request.clone();
+        request
+            .headers
+            .retain(|header, _| !header.eq_ignore_ascii_case("Accept"));
+        request.headers.insert("Accept".to_string(), accept);
+        Some(request)
+    }
+}
@@ -1,4 +1,5 @@
 use itertools::Itertools;
+use std::collections::HashMap;
 
 /// Enum to represent a match with media types
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -22,45 +23,73 @@ pub struct MediaType {
     pub sub: String,
     /// Weight associated with the media type
     pub weight: f32,
+    /// Parameters of the media type (i.e. `level=1` of `text/html;level=1`), with lower case
+    /// names. The `q` weight is not a parameter.
+    pub params: HashMap<String, String>,
 }
 
 impl MediaType {
-    /// Parse a string into a MediaType struct
+    /// Creates a media type with no parameters and a weight of 1
+    pub fn new<M: Into<String>, S: Into<String>>(main: M, sub: S) -> MediaType {
+        MediaType {
+            main: main.into(),
+            sub: sub.into(),
+            weight: 1.0,
+            params: HashMap::new(),
+        }
+    }
+
+    /// Parse a string into a MediaType struct. Any parameters are parsed up to the `q` weight,
+    /// which sets the weight.
     pub fn parse_string(media_type: &str) -> MediaType {
-        let types: Vec<&str> = media_type.splitn(2, '/').collect_vec();
-        if types.is_empty() || types[0].is_empty() {
-            MediaType {
-                main: "*".to_string(),
-                sub: "*".to_string(),
-                weight: 1.0,
-            }
+        let mut parts = media_type.split(';');
+        let types: Vec<&str> = parts
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .splitn(2, '/')
+            .collect_vec();
+        let mut parsed = if types.is_empty() || types[0].is_empty() {
+            MediaType::new("*", "*")
+        } else if types.len() == 1 || types[1].is_empty() {
+            MediaType::new(types[0], "*")
         } else {
-            MediaType {
-                main: types[0].to_string(),
-                sub: if types.len() == 1 || types[1].is_empty() {
-                    "*".to_string()
-                } else {
-                    types[1].to_string()
-                },
-                weight: 1.0,
+            MediaType::new(types[0], types[1])
+        };
+        for param in parts {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+            if name == "q" {
+                parsed.weight = value.parse().unwrap_or(1.0);
+                break;
             }
+            parsed
+                .params
+                .insert(name, value.trim_matches('"').to_string());
         }
+        parsed
     }
 
     /// Adds a quality weight to the media type
     pub fn with_weight(&self, weight: &String) -> MediaType {
         MediaType {
-            main: self.main.clone(),
-            sub: self.sub.clone(),
             weight: weight.parse().unwrap_or(1.0),
+            ..self.clone()
         }
     }
 
-    /// Returns a weighting for this media type
+    /// Returns a weighting for this media type, which is its quality weight and its specificity,
+    /// where lower is more specific. Media types with parameters are more specific than the
+    /// same media type without, which are more specific than `type/*` ranges and then `*/*`
+    /// (RFC 9110 section 12.5.1).
     pub fn weight(&self) -> (f32, u8) {
         if self.main == "*" && self.sub == "*" {
-            (self.weight, 2)
+            (self.weight, 3)
         } else if self.sub == "*" {
+            (self.weight, 2)
+        } else if self.params.is_empty() {
             (self.weight, 1)
         } else {
             (self.weight, 0)
@@ -73,13 +102,25 @@ impl MediaType {
             MediaTypeMatch::Star
         } else if self.main == other.main && other.sub == "*" {
             MediaTypeMatch::SubStar
-        } else if self.main == other.main && self.sub == other.sub {
+        } else if self.main == other.main && self.sub == other.sub && !self.conflicts(other) {
             MediaTypeMatch::Full
         } else {
             MediaTypeMatch::None
         }
     }
 
+    /// If the other media type has a different value for one of the parameters of this media
+    /// type (i.e. `v=2` for `application/vnd.orders+json;v=3`). Parameters that only one of them
+    /// has are ignored.
+    fn conflicts(&self, other: &MediaType) -> bool {
+        other.params.iter().any(|(name, value)| {
+            self.params
+                .get(name)
+                .map(|own| !own.eq_ignore_ascii_case(value))
+                .unwrap_or(false)
+        })
+    }
+
     /// Converts this media type into a string
     pub fn to_string(&self) -> String {
         format!("{}/{}", self.main, self.sub)
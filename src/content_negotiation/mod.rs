@@ -21,6 +21,12 @@ pub use self::medialanguage::*;
 mod mediatype;
 pub use self::mediatype::*;
 
+mod format;
+pub use self::format::*;
+
+mod user_agent;
+pub use self::user_agent::*;
+
 /// Sorts the list of media types by their weights
 pub fn sort_media_types(media_types: &Vec<HeaderValue>) -> Vec<HeaderValue> {
     media_types
@@ -43,7 +49,8 @@ pub fn sort_media_types(media_types: &Vec<HeaderValue>) -> Vec<HeaderValue> {
 }
 
 /// Determines if the media types produced by the resource matches the acceptable media types
-/// provided by the client. Returns the match if there is one.
+/// provided by the client. Returns the match if there is one, as the resource declared it (with
+/// any parameters, i.e. `application/vnd.orders+json;v=3`).
 pub fn matching_content_type(
     resource: &Resource,
     request: &Request,
@@ -51,7 +58,7 @@ pub fn matching_content_type(
     if request.has_accept_header() {
         let acceptable_media_types = sort_media_types(&request.accept());
         resource
-            .produces
+            .produces_for(&request.method)
             .iter()
             .cloned()
             .cartesian_product(acceptable_media_types.iter())
@@ -59,7 +66,7 @@ pub fn matching_content_type(
                 let acceptable_media_type = acceptable.as_media_type();
                 let produced_media_type = MediaType::parse_string(produced);
                 (
-                    produced_media_type.clone(),
+                    produced,
                     acceptable_media_type.clone(),
                     produced_media_type.matches(&acceptable_media_type),
                 )
@@ -67,9 +74,12 @@ pub fn matching_content_type(
             .sorted_by(|a, b| Ord::cmp(&a.2, &b.2))
             .filter(|val| val.2 != MediaTypeMatch::None)
             .next()
-            .map(|result| result.0.to_string())
+            .map(|result| result.0.trim().to_string())
     } else {
-        resource.produces.first().map(|s| s.to_string())
+        resource
+            .produces_for(&request.method)
+            .first()
+            .map(|s| s.to_string())
     }
 }
 
@@ -235,3 +245,94 @@ pub fn matching_encoding(
         resource.encodings_provided.first().map(|s| s.to_string())
     }
 }
+
+/// Normalises the values of an Accept-Encoding header, so headers that select the same encoding
+/// compare equal (i.e. `gzip;q=1.0, BR` and `gzip, br`). Encodings are lower cased and ordered by
+/// weight, keeping the order of the header for equal weights as negotiation does, and the
+/// default weight of 1 is dropped.
+pub fn normalize_accept_encoding(encodings: &[HeaderValue]) -> String {
+    encodings
+        .iter()
+        .map(|encoding| encoding.as_encoding())
+        .sorted_by(|a, b| b.weight.partial_cmp(&a.weight).unwrap_or(Ordering::Equal))
+        .map(|encoding| {
+            let name = encoding.encoding.to_lowercase();
+            if encoding.weight == 1.0 {
+                name
+            } else {
+                format!("{};q={}", name, encoding.weight)
+            }
+        })
+        .join(", ")
+}
+
+/// Selects the declared variant of the resource that best matches the Accept and
+/// Accept-Language headers of the request. Each variant is scored by the product of the quality
+/// values of its media type and language (as per RFC 7231 section 3.4.1), so only combinations
+/// that actually exist are considered. Ties go to the variant declared first. Returns the index
+/// of the variant, or None if no variant is acceptable.
+pub fn matching_variant(resource: &Resource, request: &Request) -> Option<usize> {
+    let accept: Vec<MediaType> = request.accept().iter().map(|h| h.as_media_type()).collect();
+    let accept_language: Vec<MediaLanguage> = request
+        .accept_language()
+        .iter()
+        .map(|h| h.as_media_language())
+        .collect();
+    resource
+        .variants
+        .iter()
+        .enumerate()
+        .map(|(index, variant)| {
+            let media_quality = media_type_quality(variant.media_type, &accept);
+            let language_quality = variant
+                .language
+                .map(|language| language_quality(language, &accept_language))
+                .unwrap_or(1.0);
+            (index, media_quality * language_quality)
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .fold(None, |best: Option<(usize, f32)>, (index, quality)| match best {
+            Some((_, best_quality)) if best_quality >= quality => best,
+            _ => Some((index, quality)),
+        })
+        .map(|(index, _)| index)
+}
+
+/// Quality of the media type from the most specific matching media range
+fn media_type_quality(media_type: &str, accept: &[MediaType]) -> f32 {
+    if accept.is_empty() {
+        return 1.0;
+    }
+    let media_type = MediaType::parse_string(media_type);
+    accept
+        .iter()
+        .map(|range| (media_type.matches(range), range.weight))
+        .filter(|(matches, _)| *matches != MediaTypeMatch::None)
+        .min_by(|a, b| Ord::cmp(&a.0, &b.0))
+        .map(|(_, weight)| weight)
+        .unwrap_or(0.0)
+}
+
+/// Quality of the language from the most specific matching language range (RFC 4647 basic
+/// filtering)
+fn language_quality(language: &str, accept_language: &[MediaLanguage]) -> f32 {
+    if accept_language.is_empty() {
+        return 1.0;
+    }
+    let language = language.to_lowercase();
+    accept_language
+        .iter()
+        .filter_map(|range| {
+            let tag = range.to_string().to_lowercase();
+            if tag == "*" {
+                Some((0, range.weight))
+            } else if language == tag || language.starts_with(&format!("{}-", tag)) {
+                Some((tag.len(), range.weight))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, weight)| weight)
+        .unwrap_or(0.0)
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use crate::{context::Request, headers::HeaderValue};
+
+/// Class of client that made a request, from its User-Agent header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UserAgentClass {
+    /// A web browser operated by a person
+    Browser,
+    /// A command line tool or HTTP library (i.e. curl, wget, HTTPie)
+    CommandLine,
+    /// A crawler, spider or other automated agent
+    Bot,
+    /// Any other client, including requests without a User-Agent header
+    Other,
+}
+
+/// Classifies requests by their User-Agent header. The tokens are matched case-insensitively
+/// against the header, with bots checked first as they often claim to be browsers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgentClassifier {
+    /// Tokens that identify bots
+    pub bots: Vec<String>,
+    /// Tokens that identify command line tools and HTTP libraries
+    pub command_line: Vec<String>,
+    /// Tokens that identify browsers
+    pub browsers: Vec<String>,
+}
+
+impl Default for UserAgentClassifier {
+    fn default() -> UserAgentClassifier {
+        let tokens = |tokens: &[&str]| tokens.iter().map(|token| token.to_string()).collect();
+        UserAgentClassifier {
+            bots: tokens(&["bot", "crawler", "spider", "slurp", "facebookexternalhit"]),
+            command_line: tokens(&[
+                "curl",
+                "wget",
+                "httpie",
+                "python-requests",
+                "python-urllib",
+                "go-http-client",
+                "okhttp",
+                "java/",
+                "libwww-perl",
+                "postmanruntime",
+                "insomnia",
+            ]),
+            browsers: tokens(&["mozilla/", "opera/"]),
+        }
+    }
+}
+
+impl UserAgentClassifier {
+    /// Classifies the request by its User-Agent header
+    pub fn classify(&self, request: &Request) -> UserAgentClass {
+        let user_agent = request
+            .find_header("User-Agent")
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+            .to_lowercase();
+        let matches = |tokens: &[String]| {
+            tokens
+                .iter()
+                .any(|token| user_agent.contains(&token.to_lowercase()))
+        };
+        if user_agent.is_empty() {
+            UserAgentClass::Other
+        } else if matches(&self.bots) {
+            UserAgentClass::Bot
+        } else if matches(&self.command_line) {
+            UserAgentClass::CommandLine
+        } else if matches(&self.browsers) {
+            UserAgentClass::Browser
+        } else {
+            UserAgentClass::Other
+        }
+    }
+}
+
+/// Media types preferred by each class of client, used instead of the order of `produces` when
+/// a request has no Accept header. The class of the request is set on the context by the
+/// `user_agent_classifier` of the dispatcher, so this has no effect without one. As the selected
+/// media type depends on the User-Agent header, it is added to the Vary header of responses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgentNegotiation {
+    /// Preferred media types of the classes of client, most preferred first. Classes without an
+    /// entry get the first media type of `produces`. Defaults to HTML for browsers and JSON for
+    /// all the other classes.
+    pub preferred: HashMap<UserAgentClass, Vec<String>>,
+}
+
+impl Default for UserAgentNegotiation {
+    fn default() -> UserAgentNegotiation {
+        let json = vec!["application/json".to_string()];
+        UserAgentNegotiation {
+            preferred: hashmap! {
+                UserAgentClass::Browser => vec!["text/html".to_string()],
+                UserAgentClass::CommandLine => json.clone(),
+                UserAgentClass::Bot => json.clone(),
+                UserAgentClass::Other => json,
+            },
+        }
+    }
+}
+
+impl UserAgentNegotiation {
+    /// Returns the request with an Accept header for the media types preferred by the class of
+    /// client, followed by any other media type. Returns None if the request already has an
+    /// Accept header, or there are no preferred media types for the class.
+    pub fn apply(&self, request: &Request, class: UserAgentClass) -> Option<Request> {
+        if request.has_accept_header() {
+            return None;
+        }
+        let preferred = self
+            .preferred
+            .get(&class)
+            .filter(|media_types| !media_types.is_empty())?;
+        let count = preferred.len() as f32;
+        let mut accept = preferred
+            .iter()
+            .enumerate()
+            .map(|(index, media_type)| {
+                let weight = 1.0 - 0.5 * index as f32 / count;
+                HeaderValue::parse_string(&format!("{};q={}", media_type, weight))
+            })
+            .collect::<Vec<HeaderValue>>();
+        accept.push(HeaderValue::parse_string("*/*;q=0.1"));
+        let mut request = request.clone();
+        request.headers.insert("Accept".to_string(), accept);
+        Some(request)
+    }
+}
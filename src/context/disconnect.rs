@@ -0,0 +1,122 @@
+use futures::{
+    channel::oneshot,
+    future::{self, FutureExt, Shared},
+    Future,
+};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Signal that the client of a request has gone away. hyper drops the future processing a
+/// request when its connection is closed, and a streamed body fails to send, which both set the
+/// signal. Work that outlives the request future (spawned tasks, streamed bodies) can check or
+/// wait for it to stop early.
+#[derive(Clone)]
+pub struct ClientDisconnect {
+    disconnected: Arc<AtomicBool>,
+    sender: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    receiver: Shared<oneshot::Receiver<()>>,
+}
+
+impl Default for ClientDisconnect {
+    fn default() -> ClientDisconnect {
+        let (sender, receiver) = oneshot::channel();
+        ClientDisconnect {
+            disconnected: Arc::new(AtomicBool::new(false)),
+            sender: Arc::new(Mutex::new(Some(sender))),
+            receiver: receiver.shared(),
+        }
+    }
+}
+
+impl ClientDisconnect {
+    /// If the client has gone away
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+
+    /// Returns a future that completes when the client goes away. The future never completes
+    /// if the request finishes with the client still connected.
+    pub fn disconnected(&self) -> impl Future<Output = ()> + Send + 'static {
+        let receiver = self.receiver.clone();
+        async move {
+            if receiver.await.is_err() {
+                future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Signals that the client has gone away
+    pub fn signal(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+        if let Some(sender) = self.sender.lock().ok().and_then(|mut sender| sender.take()) {
+            let _ = sender.send(());
+        }
+    }
+
+    /// Returns a guard that signals that the client has gone away if it is dropped before it is
+    /// disarmed
+    pub fn guard(&self) -> DisconnectGuard {
+        DisconnectGuard {
+            disconnect: Some(self.clone()),
+        }
+    }
+}
+
+impl fmt::Debug for ClientDisconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientDisconnect")
+            .field("disconnected", &self.is_disconnected())
+            .finish()
+    }
+}
+
+impl PartialEq for ClientDisconnect {
+    fn eq(&self, other: &ClientDisconnect) -> bool {
+        Arc::ptr_eq(&self.disconnected, &other.disconnected)
+    }
+}
+
+/// Guard held by the future processing a request, which signals that the client has gone away
+/// if the future is dropped before the response is complete
+pub struct DisconnectGuard {
+    disconnect: Option<ClientDisconnect>,
+}
+
+impl DisconnectGuard {
+    /// Disarms the guard once the response is complete
+    pub fn disarm(mut self) {
+        self.disconnect = None;
+    }
+}
+
+impl Drop for DisconnectGuard {
+    fn drop(&mut self) {
+        if let Some(disconnect) = self.disconnect.take() {
+            disconnect.signal();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn guard_signals_if_it_is_dropped_before_it_is_disarmed() {
+        let disconnect = ClientDisconnect::default();
+        disconnect.guard().disarm();
+        expect!(disconnect.is_disconnected()).to(be_false());
+        expect!(disconnect.disconnected().now_or_never()).to(be_none());
+
+        let waiting = disconnect.disconnected();
+        drop(disconnect.guard());
+        expect!(disconnect.is_disconnected()).to(be_true());
+        expect!(waiting.now_or_never()).to(be_some());
+    }
+}
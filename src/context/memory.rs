@@ -0,0 +1,66 @@
+/// Tracks the memory allocated while processing a request (the buffered request body, decoded
+/// request parts and the rendered response body) against an optional cap.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MemoryAccount {
+    /// Number of bytes allocated so far
+    pub allocated: usize,
+    /// Maximum number of bytes that may be allocated for the request. None means no cap.
+    pub limit: Option<usize>,
+}
+
+impl MemoryAccount {
+    /// Creates a memory account with the given cap
+    pub fn new(limit: Option<usize>) -> MemoryAccount {
+        MemoryAccount {
+            allocated: 0,
+            limit,
+        }
+    }
+
+    /// Records an allocation of the given number of bytes. Returns false, without recording the
+    /// allocation, if it would take the request over its cap.
+    pub fn allocate(&mut self, bytes: usize) -> bool {
+        let allocated = self.allocated.saturating_add(bytes);
+        match self.limit {
+            Some(limit) if allocated > limit => false,
+            _ => {
+                self.allocated = allocated;
+                true
+            }
+        }
+    }
+
+    /// Records that the given number of bytes have been released
+    pub fn release(&mut self, bytes: usize) {
+        self.allocated = self.allocated.saturating_sub(bytes);
+    }
+
+    /// Number of bytes that can still be allocated. None means there is no cap.
+    pub fn remaining(&self) -> Option<usize> {
+        self.limit.map(|limit| limit.saturating_sub(self.allocated))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn memory_account_enforces_the_limit() {
+        let mut account = MemoryAccount::new(Some(10));
+        expect!(account.allocate(6)).to(be_true());
+        expect!(account.allocate(6)).to(be_false());
+        expect!(account.allocated).to(be_equal_to(6));
+        expect!(account.remaining()).to(be_some().value(4));
+        account.release(6);
+        expect!(account.allocate(10)).to(be_true());
+    }
+
+    #[test]
+    fn memory_account_without_a_limit_allows_everything() {
+        let mut account = MemoryAccount::default();
+        expect!(account.allocate(usize::MAX)).to(be_true());
+        expect!(account.remaining()).to(be_none());
+    }
+}
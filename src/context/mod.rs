@@ -2,7 +2,18 @@
 //! executing in. Basically wraps the request and response.
 
 use chrono::{DateTime, FixedOffset};
-use std::collections::HashMap;
+use futures::Future;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    auth::Principal, content_negotiation::UserAgentClass, DecisionId, Locale, PathParamError,
+    TraceContext,
+};
 
 mod request;
 pub use self::request::*;
@@ -10,6 +21,12 @@ pub use self::request::*;
 mod response;
 pub use self::response::*;
 
+mod memory;
+pub use self::memory::*;
+
+mod disconnect;
+pub use self::disconnect::*;
+
 /// Main context struct that holds the request and response.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Context {
@@ -35,6 +52,51 @@ pub struct Context {
     pub new_resource: bool,
     /// General store of metadata. You can use this to store attributes as the webmachine executes.
     pub metadata: HashMap<String, String>,
+    /// Cause of an error that terminated the request before the state machine was executed,
+    /// for example a failure reading the request body
+    pub error: Option<String>,
+    /// Memory allocated while processing the request
+    pub memory: MemoryAccount,
+    /// Principal the request was authenticated as by one of the authenticators of the resource
+    pub principal: Option<Principal>,
+    /// When processing of the request started
+    pub started: Instant,
+    /// Decision that ended the execution of the state machine
+    pub terminal_decision: Option<DecisionId>,
+    /// Number of decisions (and so resource callbacks) executed by the state machine
+    pub decisions_executed: usize,
+    /// Decisions executed by the state machine in order, which is the path the request took
+    pub decision_trace: Vec<DecisionStep>,
+    /// Signal that the client has gone away, so streamed bodies and long-running work can stop
+    pub client_disconnect: ClientDisconnect,
+    /// W3C trace context of the request, from its `traceparent` and `tracestate` headers. With
+    /// the `otel` feature and a tracer set on the dispatcher, this is the context of the server
+    /// span of the request, so downstream calls made with it are children of that span.
+    pub trace_context: Option<TraceContext>,
+    /// Class of client that made the request, set when the dispatcher has a User-Agent
+    /// classifier
+    pub user_agent_class: Option<UserAgentClass>,
+    /// Values of the parameters of the path template of the resource, if it has one (see
+    /// `Resource::path_template`)
+    pub path_params: HashMap<String, String>,
+    /// First error extracting a path parameter, which ends the request after the current
+    /// decision
+    pub(crate) path_param_error: Option<PathParamError>,
+}
+
+/// A decision executed by the state machine
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecisionStep {
+    /// Decision that was executed
+    pub decision: DecisionId,
+    /// Outcome of the decision. Decisions that result in a status code are recorded as false.
+    pub outcome: bool,
+    /// Decision the state machine transitioned to, or None if it ended with a status code
+    pub next: Option<DecisionId>,
+    /// Reason for the outcome
+    pub reason: String,
+    /// How long the decision (and so its resource callbacks) took to execute
+    pub duration: Duration,
 }
 
 impl Default for Context {
@@ -52,6 +114,112 @@ impl Default for Context {
             redirect: false,
             new_resource: false,
             metadata: HashMap::new(),
+            error: None,
+            memory: MemoryAccount::default(),
+            principal: None,
+            started: Instant::now(),
+            terminal_decision: None,
+            decisions_executed: 0,
+            decision_trace: Vec::new(),
+            client_disconnect: ClientDisconnect::default(),
+            trace_context: None,
+            user_agent_class: None,
+            path_params: HashMap::new(),
+            path_param_error: None,
         }
     }
 }
+
+impl Context {
+    /// Returns a future that completes if the client goes away before the response has been
+    /// sent. It does not borrow the context, so it can be moved into spawned tasks and streams.
+    pub fn client_disconnected(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.client_disconnect.disconnected()
+    }
+
+    /// If the client has gone away before the response has been sent
+    pub fn is_client_disconnected(&self) -> bool {
+        self.client_disconnect.is_disconnected()
+    }
+
+    /// Returns the locale to format numbers and dates of the response with, for the language
+    /// selected by content negotiation. Defaults to English if no language was selected.
+    pub fn locale(&self) -> Locale {
+        self.selected_language
+            .as_deref()
+            .map(Locale::for_language)
+            .unwrap_or_default()
+    }
+
+    /// Returns the path parameter with the name, parsed as the type. If the parameter is missing
+    /// or can not be parsed, the error is returned and the request ends after the current
+    /// callback, with the `invalid_path_param_status` of the resource (or a 500 if the path
+    /// template has no parameter with the name).
+    ///
+    /// ```
+    /// # use webmachine::context::Context;
+    /// let mut context = Context::default();
+    /// context.path_params.insert("id".to_string(), "100".to_string());
+    /// assert_eq!(context.path_param::<u64>("id"), Ok(100));
+    /// assert!(context.path_param::<u64>("item").is_err());
+    /// ```
+    pub fn path_param<T>(&mut self, name: &str) -> Result<T, PathParamError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let result = match self.path_params.get(name) {
+            Some(value) => value.parse::<T>().map_err(|err| PathParamError::Invalid {
+                name: name.to_string(),
+                value: value.clone(),
+                reason: err.to_string(),
+            }),
+            None => Err(PathParamError::Missing(name.to_string())),
+        };
+        if let Err(err) = &result {
+            if self.path_param_error.is_none() {
+                self.path_param_error = Some(err.clone());
+            }
+        }
+        result
+    }
+
+    /// Returns a single line summary of the request in logfmt (`key=value` pairs), with the
+    /// route, terminal decision, status, negotiated variant, number of decisions executed and
+    /// the latency so far. Missing values are logged as `-`.
+    pub fn summary(&self) -> String {
+        let fields = [
+            ("method", Some(self.request.method.clone())),
+            ("route", Some(self.request.base_path.clone())),
+            ("path", Some(self.request.request_path.clone())),
+            ("status", Some(self.response.status.to_string())),
+            ("decision", self.terminal_decision.map(|id| id.to_string())),
+            ("media_type", self.selected_media_type.clone()),
+            ("language", self.selected_language.clone()),
+            ("charset", self.selected_charset.clone()),
+            ("encoding", self.selected_encoding.clone()),
+            ("decisions", Some(self.decisions_executed.to_string())),
+            (
+                "latency_ms",
+                Some(format!("{:.3}", self.started.elapsed().as_secs_f64() * 1000.0)),
+            ),
+        ];
+        fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, logfmt_value(value.as_deref())))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+fn logfmt_value(value: Option<&str>) -> String {
+    match value {
+        Some(value) if value.is_empty() || value.contains(needs_quoting) => format!("{:?}", value),
+        Some(value) => value.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn needs_quoting(c: char) -> bool {
+    c == '"' || c == '=' || c.is_whitespace()
+}
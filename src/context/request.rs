@@ -1,34 +1,105 @@
-use std::collections::HashMap;
+use chrono::{DateTime, FixedOffset};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
 
-use crate::headers::HeaderValue;
+use crate::{
+    content_negotiation::MediaType, headers::HeaderValue, nest_query, ClientCertificate,
+    ForwardedInfo, QueryValue,
+};
 
 /// Request that the state machine is executing against
 #[derive(Debug, Clone, PartialEq)]
 pub struct Request {
-    /// Path of the request relative to the resource
+    /// Path of the request relative to the resource. Its segments are percent-decoded, except
+    /// for escaped slashes (`%2F`) and percent signs (`%25`), which are kept encoded so a
+    /// segment can not be split or decoded twice (see `decode_path`). The dispatcher removes its
+    /// dot segments (see `remove_dot_segments`) before it is routed.
     pub request_path: String,
     /// Resource base path
     pub base_path: String,
+    /// Full path of the request as it was received, before it was percent-decoded. None if the
+    /// request was not received over HTTP.
+    pub raw_path: Option<String>,
     /// Request method
     pub method: String,
     /// Request headers
     pub headers: HashMap<String, Vec<HeaderValue>>,
     /// Request body
     pub body: Option<Vec<u8>>,
-    /// Query parameters
+    /// Query parameters. The values of list keys (i.e. `tags[]`) are under the key without the
+    /// brackets (`tags`).
     pub query: HashMap<String, Vec<String>>,
+    /// Address of the client, if known. This is set when the dispatcher is served with
+    /// `server::serve`.
+    pub remote_addr: Option<SocketAddr>,
+    /// Name of the server listener that accepted the request, if it was served with the
+    /// `server` module
+    pub listener: Option<String>,
+    /// HTTP version of the request (i.e. `HTTP/1.1` or `HTTP/2.0`). Defaults to `HTTP/1.1`.
+    pub http_version: String,
+    /// Local address the connection of the request was accepted on, if known. This is set when
+    /// the dispatcher is served with `server::serve`.
+    pub local_addr: Option<SocketAddr>,
+    /// If the connection of the request was secured by the server (i.e. it terminated TLS).
+    /// This does not consider proxies; see `TlsPolicy` for that.
+    pub secure_connection: bool,
+    /// Certificate the client authenticated the connection of the request with, if TLS client
+    /// authentication was used. This is set when the dispatcher is served with
+    /// `server::serve_listeners` and an acceptor that returns the certificate.
+    pub client_certificate: Option<ClientCertificate>,
+    /// What the trusted proxies the request came through forwarded about the client, from the
+    /// `Forwarded` or `X-Forwarded-*` headers. This is only set if the dispatcher has a
+    /// `ForwardedConfig` and the request came from one of its trusted proxies.
+    pub forwarded: Option<ForwardedInfo>,
 }
 
+/// Error reading the body of a request as JSON
+#[derive(Debug)]
+pub enum JsonBodyError {
+    /// The request has no body
+    MissingBody,
+    /// The content type of the request is not JSON
+    UnsupportedContentType(String),
+    /// The body could not be deserialised
+    Invalid(serde_json::Error),
+}
+
+impl fmt::Display for JsonBodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonBodyError::MissingBody => write!(f, "Request has no body"),
+            JsonBodyError::UnsupportedContentType(content_type) => {
+                write!(f, "Expected a JSON body, but the content type is '{}'", content_type)
+            }
+            JsonBodyError::Invalid(err) => write!(f, "Request body is not valid JSON - {}", err),
+        }
+    }
+}
+
+impl std::error::Error for JsonBodyError {}
+
 impl Default for Request {
     /// Creates a default request (GET /)
     fn default() -> Request {
         Request {
             request_path: "/".to_string(),
             base_path: "/".to_string(),
+            raw_path: None,
             method: "GET".to_string(),
             headers: HashMap::new(),
             body: None,
             query: HashMap::new(),
+            remote_addr: None,
+            listener: None,
+            http_version: "HTTP/1.1".to_string(),
+            local_addr: None,
+            secure_connection: false,
+            client_certificate: None,
+            forwarded: None,
         }
     }
 }
@@ -50,6 +121,26 @@ impl Request {
         }
     }
 
+    /// Deserialises the body of the request from JSON. The content type of the request must be
+    /// `application/json` or a `+json` media type (the default if there is no Content-Type
+    /// header).
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, JsonBodyError> {
+        let content_type = self.content_type().to_lowercase();
+        if content_type != "application/json" && !content_type.ends_with("+json") {
+            return Err(JsonBodyError::UnsupportedContentType(content_type));
+        }
+        match &self.body {
+            Some(body) => serde_json::from_slice(body).map_err(JsonBodyError::Invalid),
+            None => Err(JsonBodyError::MissingBody),
+        }
+    }
+
+    /// Returns the query parameters with the keys with bracketed segments nested, so
+    /// `filter[status]=open` is the `status` key of the map of the `filter` key
+    pub fn nested_query(&self) -> BTreeMap<String, QueryValue> {
+        nest_query(&self.query)
+    }
+
     /// If the request is a put or post
     pub fn is_put_or_post(&self) -> bool {
         ["PUT", "POST"].contains(&self.method.to_uppercase().as_str())
@@ -85,6 +176,14 @@ impl Request {
         self.method.to_uppercase() == "DELETE"
     }
 
+    /// Returns the length of the request body from the Content-Length header. Returns None if
+    /// the header is not present or is not a valid length.
+    pub fn content_length(&self) -> Option<u64> {
+        self.find_header("Content-Length")
+            .first()
+            .and_then(|length| length.value.trim().parse().ok())
+    }
+
     /// If an Accept header exists
     pub fn has_accept_header(&self) -> bool {
         self.has_header("ACCEPT")
@@ -95,6 +194,12 @@ impl Request {
         self.find_header("ACCEPT")
     }
 
+    /// If the request is HTTP/1.0 or earlier, which do not support chunked transfer coding or
+    /// persistent connections by default
+    pub fn is_legacy_http(&self) -> bool {
+        matches!(self.http_version.as_str(), "HTTP/1.0" | "HTTP/0.9")
+    }
+
     /// If an Accept-Language header exists
     pub fn has_accept_language_header(&self) -> bool {
         self.has_header("ACCEPT-LANGUAGE")
@@ -146,6 +251,74 @@ impl Request {
         }
     }
 
+    /// Returns the value of the provided request header as a date. Returns None if the header
+    /// is not present or is not a valid HTTP date.
+    pub fn date_header(&self, header: &str) -> Option<DateTime<FixedOffset>> {
+        self.find_header(header).first().and_then(HeaderValue::as_date)
+    }
+
+    /// Returns the value of the provided request header as an integer. Returns None if the header
+    /// is not present or is not an integer.
+    pub fn integer_header(&self, header: &str) -> Option<u64> {
+        self.find_header(header).first().and_then(HeaderValue::as_integer)
+    }
+
+    /// Returns the value of the provided request header as a media type. Returns None if the
+    /// header is not present.
+    pub fn media_type_header(&self, header: &str) -> Option<MediaType> {
+        self.find_header(header).first().map(HeaderValue::as_media_type)
+    }
+
+    /// Returns the host (and port, if given) the request was made to, from the Host header
+    pub fn host(&self) -> Option<String> {
+        self.find_header("Host").first().map(|host| host.value.clone())
+    }
+
+    /// Returns the address of the client, which is the forwarded address if the request came
+    /// through a trusted proxy, otherwise the address of the connection
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        match &self.forwarded {
+            Some(forwarded) => forwarded.client_ip,
+            None => self.remote_addr.map(|addr| addr.ip()),
+        }
+    }
+
+    /// Returns the scheme the client made the request with (`http` or `https`), which is the
+    /// forwarded scheme if the request came through a trusted proxy
+    pub fn scheme(&self) -> String {
+        match self
+            .forwarded
+            .as_ref()
+            .and_then(|forwarded| forwarded.proto.clone())
+        {
+            Some(proto) => proto,
+            None if self.secure_connection => "https".to_string(),
+            None => "http".to_string(),
+        }
+    }
+
+    /// Returns the host (and port, if given) the client made the request to, which is the
+    /// forwarded host if the request came through a trusted proxy, otherwise the Host header
+    pub fn client_host(&self) -> Option<String> {
+        self.forwarded
+            .as_ref()
+            .and_then(|forwarded| forwarded.host.clone())
+            .or_else(|| self.host())
+    }
+
+    /// Returns the absolute URL of the path (i.e. `https://api.example.com/orders/1`) as the
+    /// client would request it, for links and Location headers. Returns None if the host the
+    /// request was made to is not known.
+    pub fn absolute_url(&self, path: &str) -> Option<String> {
+        let host = self.client_host().filter(|host| !host.is_empty())?;
+        Some(format!(
+            "{}://{}/{}",
+            self.scheme(),
+            host,
+            path.trim_start_matches('/')
+        ))
+    }
+
     /// If the header has a matching value
     pub fn has_header_value(&self, header: &str, value: &str) -> bool {
         match self
@@ -215,4 +388,45 @@ mod tests {
         expect!(request.has_header_value("HeaderA", "other")).to(be_true());
         expect!(request.has_header_value("HeaderA", "other2")).to(be_false());
     }
+
+    #[test]
+    fn request_typed_header_test() {
+        let request = Request {
+            headers: hashmap! {
+              "Content-Length".to_string() => vec![h!("100")],
+              "Content-Type".to_string() => vec![h!("text/html; charset=utf-8")],
+              "If-Modified-Since".to_string() => vec![HeaderValue::basic("Sun, 06 Nov 1994 08:49:37 GMT")]
+            },
+            ..Request::default()
+        };
+        expect!(request.integer_header("content-length")).to(be_some().value(100));
+        expect!(request.media_type_header("content-type"))
+            .to(be_some().value(MediaType::parse_string("text/html;charset=utf-8")));
+        expect!(request.date_header("If-Modified-Since")).to(be_some());
+        expect!(request.date_header("Date")).to(be_none());
+    }
+
+    #[test]
+    fn request_json_test() {
+        let request = Request {
+            headers: hashmap! {
+              "Content-Type".to_string() => vec![h!("application/vnd.order+json; charset=utf-8")]
+            },
+            body: Some(b"{\"quantity\": 2}".to_vec()),
+            ..Request::default()
+        };
+        expect!(request.json::<HashMap<String, u32>>())
+            .to(be_ok().value(hashmap! { "quantity".to_string() => 2 }));
+        expect!(request.json::<Vec<u32>>()).to(be_err());
+
+        let request = Request {
+            headers: hashmap! { "Content-Type".to_string() => vec![h!("text/plain")] },
+            ..request
+        };
+        expect!(request.json::<HashMap<String, u32>>().unwrap_err().to_string()).to(be_equal_to(
+            "Expected a JSON body, but the content type is 'text/plain'",
+        ));
+        expect!(Request::default().json::<HashMap<String, u32>>().unwrap_err().to_string())
+            .to(be_equal_to("Request has no body"));
+    }
 }
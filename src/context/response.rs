@@ -1,7 +1,83 @@
+use futures::Stream;
 use itertools::Itertools;
-use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use crate::headers::HeaderValue;
+use crate::{headers::HeaderValue, DigestAlgorithm};
+
+/// Stream of the chunks of a streamed response body. An error aborts the response.
+pub type BodyChunks = Pin<Box<dyn Stream<Item = Result<Vec<u8>, String>> + Send>>;
+
+/// Response body that is sent to the client as it is produced instead of being buffered, for
+/// bodies that are large or of unknown length. The body is sent with chunked transfer coding.
+#[derive(Clone)]
+pub struct StreamingBody {
+    chunks: Arc<Mutex<Option<BodyChunks>>>,
+    /// Algorithms of the `Content-Digest` trailer sent after the body. The digest is computed
+    /// as the chunks are sent. Trailers are only delivered to clients over HTTP/2. Defaults to
+    /// empty (no trailer).
+    pub trailer_digests: Vec<DigestAlgorithm>,
+    /// Maximum time the client may take to accept a chunk of the body, after which the body is
+    /// aborted, so a client that stops reading does not hold on to the response. Defaults to
+    /// None, in which case the `stream_write_timeout` of the dispatcher is used.
+    pub write_timeout: Option<Duration>,
+}
+
+impl StreamingBody {
+    /// Creates a body from the stream of chunks
+    pub fn new<S>(chunks: S) -> StreamingBody
+    where
+        S: Stream<Item = Result<Vec<u8>, String>> + Send + 'static,
+    {
+        StreamingBody {
+            chunks: Arc::new(Mutex::new(Some(Box::pin(chunks)))),
+            trailer_digests: vec![],
+            write_timeout: None,
+        }
+    }
+
+    /// Creates a body from another stream of chunks, with the same trailers and write timeout
+    /// as this body (i.e. to wrap the chunks of this body)
+    pub fn with_chunks<S>(&self, chunks: S) -> StreamingBody
+    where
+        S: Stream<Item = Result<Vec<u8>, String>> + Send + 'static,
+    {
+        StreamingBody {
+            trailer_digests: self.trailer_digests.clone(),
+            write_timeout: self.write_timeout,
+            ..StreamingBody::new(chunks)
+        }
+    }
+
+    /// Takes the stream of chunks. Returns None if it has already been taken, as the body can
+    /// only be sent once.
+    pub fn take_chunks(&self) -> Option<BodyChunks> {
+        self.chunks.lock().ok().and_then(|mut chunks| chunks.take())
+    }
+}
+
+impl fmt::Debug for StreamingBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamingBody")
+            .field("trailer_digests", &self.trailer_digests)
+            .field("write_timeout", &self.write_timeout)
+            .finish()
+    }
+}
+
+impl PartialEq for StreamingBody {
+    fn eq(&self, other: &StreamingBody) -> bool {
+        Arc::ptr_eq(&self.chunks, &other.chunks)
+            && self.trailer_digests == other.trailer_digests
+            && self.write_timeout == other.write_timeout
+    }
+}
 
 /// Response that is generated as a result of the webmachine execution
 #[derive(Debug, Clone, PartialEq)]
@@ -12,6 +88,8 @@ pub struct Response {
     pub headers: BTreeMap<String, Vec<HeaderValue>>,
     /// Response Body
     pub body: Option<Vec<u8>>,
+    /// Body that is streamed to the client, used if there is no `body`
+    pub stream: Option<StreamingBody>,
 }
 
 impl Response {
@@ -21,6 +99,7 @@ impl Response {
             status: 200,
             headers: BTreeMap::new(),
             body: None,
+            stream: None,
         }
     }
 
@@ -37,6 +116,33 @@ impl Response {
         self.headers.insert(header.to_string(), values);
     }
 
+    /// Removes the header (case-insensitive) from the headers, returning the values if the
+    /// header was present
+    pub fn remove_header(&mut self, header: &str) -> Option<Vec<HeaderValue>> {
+        let key = self
+            .headers
+            .keys()
+            .find(|k| k.to_uppercase() == header.to_uppercase())
+            .cloned();
+        key.and_then(|k| self.headers.remove(&k))
+    }
+
+    /// Serialises the value to JSON as the body of the response, and sets the Content-Type
+    /// header to `application/json` (replacing any existing value)
+    pub fn set_json<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), serde_json::Error> {
+        self.body = Some(serde_json::to_vec(value)?);
+        self.remove_header("Content-Type");
+        self.add_header(
+            "Content-Type",
+            vec![HeaderValue {
+                value: "application/json".to_string(),
+                params: hashmap! { "charset".to_string() => "UTF-8".to_string() },
+                quote: false,
+            }],
+        );
+        Ok(())
+    }
+
     /// Adds the headers from a HashMap to the headers
     pub fn add_headers(&mut self, headers: HashMap<String, Vec<String>>) {
         for (k, v) in headers {
@@ -62,10 +168,10 @@ impl Response {
         }
     }
 
-    /// If the response has a body
+    /// If the response has a body (or a streamed body)
     pub fn has_body(&self) -> bool {
         match &self.body {
-            &None => false,
+            &None => self.stream.is_some(),
             &Some(ref body) => !body.is_empty(),
         }
     }
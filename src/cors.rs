@@ -0,0 +1,216 @@
+//! The `cors` module provides the configuration for the Cross-Origin Resource Sharing (CORS)
+//! headers added to responses, for both normal and preflight (OPTIONS) requests.
+
+use std::collections::HashMap;
+
+use crate::context::Request;
+
+/// CORS configuration for a resource or dispatcher
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsConfig {
+    /// Origins that are allowed to access the resource. An entry of `*` allows any origin, and
+    /// entries can contain a single wildcard to match a set of origins (i.e.
+    /// `https://*.example.com`). Defaults to `*`.
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in preflight responses. If this is empty, the allowed methods of the
+    /// resource are used. Defaults to empty.
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in preflight responses. Defaults to `Content-Type`.
+    pub allowed_headers: Vec<String>,
+    /// Response headers that are exposed to the client on normal responses. Defaults to empty.
+    pub exposed_headers: Vec<String>,
+    /// If the client may send credentials (cookies, authorization headers). As the allowed
+    /// origin can not be `*` in this case, the request origin is echoed back, and only origins
+    /// explicitly listed in `allowed_origins` are allowed (a `*` entry allows no origin, so any
+    /// site can not make credentialed requests). Defaults to false.
+    pub allow_credentials: bool,
+    /// Number of seconds clients may cache a preflight response for. Defaults to None.
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: Vec::new(),
+            allowed_headers: vec!["Content-Type".to_string()],
+            exposed_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Creates a configuration that only allows the given origins
+    pub fn for_origins<S: Into<String>>(origins: Vec<S>) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.into_iter().map(|o| o.into()).collect(),
+            ..CorsConfig::default()
+        }
+    }
+
+    /// If the origin is allowed by this configuration. When credentials are allowed, the `*`
+    /// entry does not allow any origin.
+    pub fn origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .filter(|allowed| !(self.allow_credentials && allowed.as_str() == "*"))
+            .any(|allowed| origin_matches(allowed, origin))
+    }
+
+    /// Returns the CORS headers for a response to the request. No headers are returned if the
+    /// request origin is not allowed. Preflight responses include the allowed methods and
+    /// headers, while normal responses include the exposed headers.
+    pub fn headers(
+        &self,
+        request: &Request,
+        resource_methods: &[&str],
+        preflight: bool,
+    ) -> HashMap<String, Vec<String>> {
+        let mut headers = HashMap::new();
+        let origin = request
+            .find_header("Origin")
+            .first()
+            .map(|origin| origin.to_string());
+        let any_origin = self.allowed_origins.iter().any(|allowed| allowed == "*");
+        let allowed_origin = match origin {
+            Some(_) if any_origin && !self.allow_credentials => "*".to_string(),
+            Some(origin) if self.origin_allowed(&origin) => {
+                headers.insert("Vary".to_string(), vec!["Origin".to_string()]);
+                origin
+            }
+            None if any_origin && !self.allow_credentials => "*".to_string(),
+            _ => return headers,
+        };
+        headers.insert(
+            "Access-Control-Allow-Origin".to_string(),
+            vec![allowed_origin],
+        );
+        if self.allow_credentials {
+            headers.insert(
+                "Access-Control-Allow-Credentials".to_string(),
+                vec!["true".to_string()],
+            );
+        }
+
+        if preflight {
+            let methods = if self.allowed_methods.is_empty() {
+                resource_methods.iter().map(|m| m.to_string()).collect()
+            } else {
+                self.allowed_methods.clone()
+            };
+            if !methods.is_empty() {
+                headers.insert("Access-Control-Allow-Methods".to_string(), methods);
+            }
+            if !self.allowed_headers.is_empty() {
+                headers.insert(
+                    "Access-Control-Allow-Headers".to_string(),
+                    self.allowed_headers.clone(),
+                );
+            }
+            if let Some(max_age) = self.max_age {
+                headers.insert(
+                    "Access-Control-Max-Age".to_string(),
+                    vec![max_age.to_string()],
+                );
+            }
+        } else if !self.exposed_headers.is_empty() {
+            headers.insert(
+                "Access-Control-Expose-Headers".to_string(),
+                self.exposed_headers.clone(),
+            );
+        }
+
+        headers
+    }
+}
+
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+    let allowed = allowed.to_lowercase();
+    let origin = origin.to_lowercase();
+    match allowed.split_once('*') {
+        Some((prefix, suffix)) => {
+            origin.len() >= prefix.len() + suffix.len()
+                && origin.starts_with(prefix)
+                && origin.ends_with(suffix)
+        }
+        None => allowed == origin,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::HeaderValue;
+    use expectest::prelude::*;
+
+    fn request(origin: Option<&str>) -> Request {
+        Request {
+            headers: origin
+                .map(|origin| hashmap! { "Origin".to_string() => vec![HeaderValue::basic(origin)] })
+                .unwrap_or_default(),
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn origin_matches_wildcard_patterns() {
+        expect!(origin_matches("*", "https://example.com")).to(be_true());
+        expect!(origin_matches("https://*.example.com", "https://api.example.com")).to(be_true());
+        expect!(origin_matches("https://*.example.com", "https://example.com")).to(be_false());
+        expect!(origin_matches("https://*.example.com", "https://evil.com")).to(be_false());
+        expect!(origin_matches("https://example.com", "HTTPS://EXAMPLE.COM")).to(be_true());
+    }
+
+    #[test]
+    fn default_config_allows_any_origin() {
+        let headers = CorsConfig::default().headers(&request(None), &["GET", "HEAD"], true);
+        expect!(headers.get("Access-Control-Allow-Origin").cloned())
+            .to(be_some().value(vec!["*".to_string()]));
+        expect!(headers.get("Access-Control-Allow-Methods").cloned())
+            .to(be_some().value(vec!["GET".to_string(), "HEAD".to_string()]));
+        expect!(headers.get("Access-Control-Allow-Headers").cloned())
+            .to(be_some().value(vec!["Content-Type".to_string()]));
+    }
+
+    #[test]
+    fn echoes_allowed_origins_and_rejects_others() {
+        let config = CorsConfig {
+            allow_credentials: true,
+            exposed_headers: vec!["ETag".to_string()],
+            ..CorsConfig::for_origins(vec!["https://*.example.com"])
+        };
+        let headers = config.headers(&request(Some("https://app.example.com")), &["GET"], false);
+        expect!(headers.get("Access-Control-Allow-Origin").cloned())
+            .to(be_some().value(vec!["https://app.example.com".to_string()]));
+        expect!(headers.get("Access-Control-Allow-Credentials").cloned())
+            .to(be_some().value(vec!["true".to_string()]));
+        expect!(headers.get("Access-Control-Expose-Headers").cloned())
+            .to(be_some().value(vec!["ETag".to_string()]));
+        expect!(headers.get("Vary").cloned()).to(be_some().value(vec!["Origin".to_string()]));
+        expect!(headers.get("Access-Control-Allow-Methods")).to(be_none());
+
+        let headers = config.headers(&request(Some("https://evil.com")), &["GET"], false);
+        expect!(headers.is_empty()).to(be_true());
+    }
+
+    #[test]
+    fn any_origin_is_not_allowed_with_credentials() {
+        let config = CorsConfig {
+            allow_credentials: true,
+            ..CorsConfig::default()
+        };
+        let headers = config.headers(&request(Some("https://evil.com")), &["GET"], true);
+        expect!(headers.is_empty()).to(be_true());
+        expect!(config.headers(&request(None), &["GET"], true).is_empty()).to(be_true());
+
+        let config = CorsConfig {
+            allowed_origins: vec!["*".to_string(), "https://app.example.com".to_string()],
+            ..config
+        };
+        let headers = config.headers(&request(Some("https://app.example.com")), &["GET"], true);
+        expect!(headers.get("Access-Control-Allow-Origin").cloned())
+            .to(be_some().value(vec!["https://app.example.com".to_string()]));
+    }
+}
@@ -0,0 +1,441 @@
+//! The `debugger` module provides a visual debugger for resources, similar to the one of
+//! webmachine-ruby. The dispatcher records the decision traces of recent requests, and the
+//! debugger resource serves an HTML page that draws the decision graph with the path each
+//! request took through it highlighted. The recorded requests can also be replayed for load
+//! testing (see the `replay` function).
+
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    context::{Context, DecisionStep, Request},
+    enums::Transition,
+    error_renderer::escape_html,
+    headers::HeaderValue,
+    owned_callback, DecisionId, RecordedRequest, RedactionConfig, Resource, TRANSITION_MAP,
+};
+
+/// Default number of request traces kept by a trace recorder
+pub const DEFAULT_RECORDED_TRACES: usize = 50;
+
+/// Metadata key that marks requests to the debugger itself, which are not recorded
+const DEBUGGER_REQUEST: &str = "webmachine.debugger";
+
+/// Decision trace of a request recorded for the debugger
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedTrace {
+    /// Identifier of the trace, unique for the recorder
+    pub id: u64,
+    /// When the request completed
+    pub timestamp: DateTime<Utc>,
+    /// Method of the request
+    pub method: String,
+    /// Path of the request
+    pub path: String,
+    /// Status of the response
+    pub status: u16,
+    /// Decisions executed by the state machine in order
+    pub decisions: Vec<DecisionStep>,
+    /// The request, which can be replayed against a dispatcher (see `replay`)
+    pub request: RecordedRequest,
+}
+
+#[derive(Debug, Default)]
+struct TraceLog {
+    next_id: u64,
+    traces: VecDeque<RecordedTrace>,
+}
+
+/// Records the decision traces of the most recent requests for the debugger. Clones share the
+/// same recorded traces, so the recorder set on the dispatcher and the one the debugger resource
+/// is created with should be clones of each other.
+#[derive(Debug, Clone)]
+pub struct TraceRecorder {
+    /// Maximum number of traces kept. Once it is reached, the oldest trace is discarded when a
+    /// new one is recorded.
+    pub capacity: usize,
+    /// Redaction applied to the recorded requests. The redacted headers are not recorded, and
+    /// bodies are only recorded when `dump_bodies` is set. Defaults to the default redaction.
+    pub redaction: RedactionConfig,
+    log: Arc<Mutex<TraceLog>>,
+}
+
+impl TraceRecorder {
+    /// Creates a recorder that keeps the traces of the last `capacity` requests
+    pub fn new(capacity: usize) -> TraceRecorder {
+        TraceRecorder {
+            capacity,
+            redaction: RedactionConfig::default(),
+            log: Arc::new(Mutex::new(TraceLog::default())),
+        }
+    }
+
+    /// Records the decision trace of the request. Requests that did not reach the state machine
+    /// (i.e. they did not match a resource) and requests to the debugger are not recorded.
+    pub fn record(&self, context: &Context) {
+        if context.decision_trace.is_empty()
+            || context.metadata.contains_key(DEBUGGER_REQUEST)
+            || self.capacity == 0
+        {
+            return;
+        }
+        let mut log = match self.log.lock() {
+            Ok(log) => log,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        log.next_id += 1;
+        let trace = RecordedTrace {
+            id: log.next_id,
+            timestamp: Utc::now(),
+            method: context.request.method.clone(),
+            path: request_path(&context.request),
+            status: context.response.status,
+            decisions: context.decision_trace.clone(),
+            request: RecordedRequest::from_request(&context.request, &self.redaction),
+        };
+        log.traces.push_back(trace);
+        while log.traces.len() > self.capacity {
+            log.traces.pop_front();
+        }
+    }
+
+    /// Returns the recorded traces, most recent first
+    pub fn traces(&self) -> Vec<RecordedTrace> {
+        match self.log.lock() {
+            Ok(log) => log.traces.iter().rev().cloned().collect(),
+            Err(poisoned) => poisoned.into_inner().traces.iter().rev().cloned().collect(),
+        }
+    }
+
+    /// Returns the recorded requests in the order they were made, to replay them
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.traces()
+            .into_iter()
+            .rev()
+            .map(|trace| trace.request)
+            .collect()
+    }
+}
+
+impl Default for TraceRecorder {
+    fn default() -> TraceRecorder {
+        TraceRecorder::new(DEFAULT_RECORDED_TRACES)
+    }
+}
+
+pub(crate) fn request_path(request: &Request) -> String {
+    if let Some(raw_path) = &request.raw_path {
+        return raw_path.clone();
+    }
+    match request.request_path.as_str() {
+        "/" => request.base_path.clone(),
+        path => format!("{}{}", request.base_path.trim_end_matches('/'), path),
+    }
+}
+
+/// Resource that serves the visual debugger. The page lists the recorded requests, and draws
+/// the decision graph for the most recent one, or the one selected with the `trace` query
+/// parameter (i.e. `/_debug?trace=12`). The debugger exposes the internals of the resources, so
+/// should not be served in production.
+#[derive(Debug, Clone, Default)]
+pub struct DebuggerResource {
+    /// Recorder of the traces to show, which should be a clone of the `debugger` of the
+    /// dispatcher
+    pub recorder: TraceRecorder,
+}
+
+impl DebuggerResource {
+    /// Creates a debugger resource that shows the traces of the recorder
+    pub fn new(recorder: TraceRecorder) -> DebuggerResource {
+        DebuggerResource { recorder }
+    }
+
+    /// Builds the webmachine resource that serves the debugger page
+    pub fn resource(&self) -> Resource<'static> {
+        let recorder = self.recorder.clone();
+        Resource {
+            produces: vec!["text/html"],
+            malformed_request: owned_callback(|context, _| {
+                context
+                    .metadata
+                    .insert(DEBUGGER_REQUEST.to_string(), "true".to_string());
+                let malformed = selected_trace(context).is_err();
+                Box::pin(async move { malformed })
+            }),
+            resource_exists: owned_callback(move |context, _| {
+                let exists = match selected_trace(context) {
+                    Ok(Some(id)) => recorder.traces().iter().any(|trace| trace.id == id),
+                    _ => true,
+                };
+                context
+                    .response
+                    .add_header("Cache-Control", vec![HeaderValue::basic("no-store")]);
+                Box::pin(async move { exists })
+            }),
+            render_response: {
+                let recorder = self.recorder.clone();
+                owned_callback(move |context, _| {
+                    let selected = selected_trace(context).ok().flatten();
+                    let page = debugger_page(&recorder.traces(), selected);
+                    Box::pin(async move { Some(page) })
+                })
+            },
+            ..Resource::default()
+        }
+    }
+}
+
+fn selected_trace(context: &Context) -> Result<Option<u64>, ()> {
+    match context.request.query.get("trace").and_then(|values| values.first()) {
+        Some(id) => id.parse().map(Some).map_err(|_| ()),
+        None => Ok(None),
+    }
+}
+
+const PAGE_STYLE: &str = "body{font-family:sans-serif;margin:1em 2em}\
+    a{color:#0366d6}li.selected{font-weight:bold}\
+    table{border-collapse:collapse}td,th{border:1px solid #ccc;padding:2px 6px;text-align:left}\
+    line{stroke:#ddd;stroke-width:1}line.taken{stroke:#d33;stroke-width:3}\
+    circle{fill:#eee;stroke:#999}circle.true{fill:#9d9;stroke:#393}\
+    circle.false{fill:#f99;stroke:#933}text{font-size:9px;text-anchor:middle}";
+
+/// Returns the debugger page for the traces, showing the graph of the selected trace, or the most
+/// recent one if there is no selection
+fn debugger_page(traces: &[RecordedTrace], selected: Option<u64>) -> String {
+    let selected = match selected {
+        Some(id) => traces.iter().find(|trace| trace.id == id),
+        None => traces.first(),
+    };
+    let mut page = format!(
+        "<!DOCTYPE html><html><head><title>Webmachine debugger</title><style>{}</style></head>\
+         <body><h1>Webmachine debugger</h1>",
+        PAGE_STYLE
+    );
+    if traces.is_empty() {
+        page.push_str("<p>No requests have been recorded</p></body></html>");
+        return page;
+    }
+    page.push_str("<ul>");
+    for trace in traces {
+        let class = if Some(trace.id) == selected.map(|trace| trace.id) {
+            " class=\"selected\""
+        } else {
+            ""
+        };
+        let _ = write!(
+            page,
+            "<li{}><a href=\"?trace={}\">{} {} {}</a> {}</li>",
+            class,
+            trace.id,
+            escape_html(&trace.method),
+            escape_html(&trace.path),
+            trace.status,
+            trace.timestamp.to_rfc3339()
+        );
+    }
+    page.push_str("</ul>");
+    if let Some(trace) = selected {
+        let _ = write!(
+            page,
+            "<h2>{} {} &rarr; {}</h2>",
+            escape_html(&trace.method),
+            escape_html(&trace.path),
+            trace.status
+        );
+        page.push_str(&decision_graph(trace));
+        page.push_str(&decision_table(trace));
+    }
+    page.push_str("</body></html>");
+    page
+}
+
+/// Draws the decision graph as SVG, with the decisions laid out by their code on the classic
+/// webmachine diagram (column letter and row number). The decisions and transitions taken by
+/// the request are highlighted.
+fn decision_graph(trace: &RecordedTrace) -> String {
+    let (width, height) = DecisionId::ALL.iter().fold((0, 0), |(width, height), id| {
+        let (x, y) = node_position(*id);
+        (width.max(x + 60), height.max(y + 40))
+    });
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">",
+        width, height
+    );
+    let mut edges = TRANSITION_MAP
+        .iter()
+        .filter_map(|(decision, transition)| match transition {
+            Transition::Branch(on_true, on_false) => Some(
+                [on_true, on_false]
+                    .iter()
+                    .filter_map(|next| Some((decision.id()?, next.id()?)))
+                    .collect::<Vec<(DecisionId, DecisionId)>>(),
+            ),
+            Transition::To(_) => None,
+        })
+        .flatten()
+        .collect::<Vec<(DecisionId, DecisionId)>>();
+    edges.sort();
+    let taken = trace
+        .decisions
+        .iter()
+        .filter_map(|step| Some((step.decision, step.next?)))
+        .collect::<Vec<(DecisionId, DecisionId)>>();
+    for (from, to) in edges.iter().filter(|edge| !taken.contains(edge)) {
+        svg.push_str(&edge_line(*from, *to, ""));
+    }
+    for (from, to) in &taken {
+        svg.push_str(&edge_line(*from, *to, " class=\"taken\""));
+    }
+    for id in DecisionId::ALL {
+        let (x, y) = node_position(*id);
+        let step = trace.decisions.iter().find(|step| step.decision == *id);
+        let (class, reason) = match step {
+            Some(step) => (
+                format!(" class=\"{}\"", step.outcome),
+                format!("\n{} ({}us)", step.reason, step.duration.as_micros()),
+            ),
+            None => (String::new(), String::new()),
+        };
+        let _ = write!(
+            svg,
+            "<g><title>{}{}</title><circle cx=\"{}\" cy=\"{}\" r=\"12\"{}/>\
+             <text x=\"{}\" y=\"{}\">{}</text></g>",
+            escape_html(id.description()),
+            escape_html(&reason),
+            x,
+            y,
+            class,
+            x,
+            y + 3,
+            id.code()
+        );
+    }
+    if let Some(last) = trace.decisions.last().filter(|step| step.next.is_none()) {
+        let (x, y) = node_position(last.decision);
+        let _ = write!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" style=\"font-size:12px;font-weight:bold\">{}</text>",
+            x,
+            y + 26,
+            trace.status
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn edge_line(from: DecisionId, to: DecisionId, class: &str) -> String {
+    let (x1, y1) = node_position(from);
+    let (x2, y2) = node_position(to);
+    format!(
+        "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"{}/>",
+        x1, y1, x2, y2, class
+    )
+}
+
+/// Position of the decision on the graph, from its code (i.e. `B13a` is in column B, row 13,
+/// offset to the right of B13)
+fn node_position(id: DecisionId) -> (u32, u32) {
+    let code = id.code().as_bytes();
+    let column = u32::from(code[0] - b'A');
+    let (row, suffix) = code[1..]
+        .iter()
+        .fold((0, 0), |(row, suffix), c| match c {
+            b'0'..=b'9' => (row * 10 + u32::from(c - b'0'), suffix),
+            _ => (row, u32::from(c - b'a') + 1),
+        });
+    (30 + column * 80 + suffix * 22, row * 36)
+}
+
+fn decision_table(trace: &RecordedTrace) -> String {
+    let mut table = "<table><tr><th>Decision</th><th>Outcome</th><th>Next</th><th>Reason</th>\
+                     <th>Duration</th></tr>"
+        .to_string();
+    for step in &trace.decisions {
+        let _ = write!(
+            table,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}us</td></tr>",
+            escape_html(step.decision.description()),
+            step.outcome,
+            step.next
+                .map(|next| next.code().to_string())
+                .unwrap_or_else(|| trace.status.to_string()),
+            escape_html(&step.reason),
+            step.duration.as_micros()
+        );
+    }
+    table.push_str("</table>");
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+    use std::time::Duration;
+
+    fn step(decision: DecisionId, outcome: bool, next: Option<DecisionId>) -> DecisionStep {
+        DecisionStep {
+            decision,
+            outcome,
+            next,
+            reason: "<reason>".to_string(),
+            duration: Duration::from_micros(5),
+        }
+    }
+
+    fn traced_context(path: &str) -> Context {
+        Context {
+            request: Request {
+                request_path: path.to_string(),
+                ..Request::default()
+            },
+            decision_trace: vec![
+                step(DecisionId::B13Available, true, Some(DecisionId::B13aMisdirectedRequest)),
+                step(DecisionId::B13aMisdirectedRequest, true, None),
+            ],
+            ..Context::default()
+        }
+    }
+
+    #[test]
+    fn recorder_keeps_the_most_recent_traces() {
+        let recorder = TraceRecorder::new(2);
+        for path in ["/one", "/two", "/three"] {
+            recorder.record(&traced_context(path));
+        }
+        recorder.record(&Context::default());
+        let mut debugger_request = traced_context("/_debug");
+        debugger_request
+            .metadata
+            .insert(DEBUGGER_REQUEST.to_string(), "true".to_string());
+        recorder.clone().record(&debugger_request);
+
+        let traces = recorder.traces();
+        expect!(traces.iter().map(|trace| trace.path.as_str()).collect::<Vec<&str>>())
+            .to(be_equal_to(vec!["/three", "/two"]));
+        expect!(traces.iter().map(|trace| trace.id).collect::<Vec<u64>>())
+            .to(be_equal_to(vec![3, 2]));
+    }
+
+    #[test]
+    fn debugger_page_highlights_the_path_taken() {
+        let recorder = TraceRecorder::default();
+        let mut context = traced_context("/orders");
+        context.response.status = 421;
+        recorder.record(&context);
+
+        let page = debugger_page(&recorder.traces(), None);
+        expect!(page.contains("<a href=\"?trace=1\">GET /orders 421</a>")).to(be_true());
+        let taken = "<line x1=\"110\" y1=\"468\" x2=\"132\" y2=\"468\" class=\"taken\"/>";
+        expect!(page.contains(taken)).to(be_true());
+        expect!(page.contains("<circle cx=\"132\" cy=\"468\" r=\"12\" class=\"true\"/>"))
+            .to(be_true());
+        expect!(page.contains("&lt;reason&gt;")).to(be_true());
+        expect!(debugger_page(&[], None).contains("No requests have been recorded")).to(be_true());
+    }
+}
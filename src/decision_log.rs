@@ -0,0 +1,126 @@
+//! The `decision_log` module controls logging of the full trace of decisions the state machine
+//! made for a request. Logging every decision of every request is expensive in production, so
+//! traces can be sampled for a fraction of requests, and always logged for errors or slow
+//! requests.
+
+use log::Level;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::DecisionId;
+
+/// Count of the requests considered for sampling, shared by all configurations
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Configuration of decision trace logging
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecisionLogConfig {
+    /// Level the decisions are logged at. Defaults to Debug.
+    pub level: Level,
+    /// Levels to log specific decisions at instead of `level`. Defaults to empty.
+    pub decision_levels: HashMap<DecisionId, Level>,
+    /// Fraction of requests (between 0.0 and 1.0) to log the trace for. Requests are sampled
+    /// evenly, so a rate of 0.1 logs every tenth request. Defaults to 0.0.
+    pub sample_rate: f64,
+    /// Responses with a status at or above this are always logged. Defaults to 500.
+    pub error_status: Option<u16>,
+    /// Requests that take longer than this to execute the state machine are always logged.
+    /// Defaults to None.
+    pub slow_threshold: Option<Duration>,
+}
+
+impl Default for DecisionLogConfig {
+    fn default() -> DecisionLogConfig {
+        DecisionLogConfig {
+            level: Level::Debug,
+            decision_levels: HashMap::new(),
+            sample_rate: 0.0,
+            error_status: Some(500),
+            slow_threshold: None,
+        }
+    }
+}
+
+/// Why the trace of a request was logged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecisionLogReason {
+    /// The response had an error status
+    Error,
+    /// The request was slow
+    Slow,
+    /// The request was sampled
+    Sampled,
+}
+
+impl DecisionLogConfig {
+    /// Returns why the trace of a request with the status and duration should be logged, or None
+    /// if it should not be. Each call that reaches the sampling step counts towards the sample.
+    pub fn should_log(&self, status: u16, elapsed: Duration) -> Option<DecisionLogReason> {
+        if self.error_status.map(|error| status >= error).unwrap_or(false) {
+            Some(DecisionLogReason::Error)
+        } else if self.slow_threshold.map(|slow| elapsed > slow).unwrap_or(false) {
+            Some(DecisionLogReason::Slow)
+        } else if self.sample_rate > 0.0
+            && is_sampled(SAMPLE_COUNTER.fetch_add(1, Ordering::Relaxed), self.sample_rate)
+        {
+            Some(DecisionLogReason::Sampled)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the level to log the decision at
+    pub fn level_for(&self, decision: DecisionId) -> Level {
+        self.decision_levels
+            .get(&decision)
+            .cloned()
+            .unwrap_or(self.level)
+    }
+}
+
+/// If the nth request is sampled at the rate. This spreads the sampled requests evenly, so the
+/// count only has to be shared, not random.
+fn is_sampled(count: u64, rate: f64) -> bool {
+    let rate = rate.min(1.0);
+    ((count + 1) as f64 * rate).floor() > (count as f64 * rate).floor()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn is_sampled_spreads_the_sample_evenly() {
+        let sampled = (0..100).filter(|count| is_sampled(*count, 0.1)).count();
+        expect!(sampled).to(be_equal_to(10));
+        expect!((0..10).all(|count| is_sampled(count, 1.0))).to(be_true());
+        expect!((0..10).any(|count| is_sampled(count, 0.0))).to(be_false());
+    }
+
+    #[test]
+    fn should_log_errors_and_slow_requests() {
+        let config = DecisionLogConfig {
+            slow_threshold: Some(Duration::from_millis(100)),
+            ..DecisionLogConfig::default()
+        };
+        expect!(config.should_log(503, Duration::from_millis(1)))
+            .to(be_some().value(DecisionLogReason::Error));
+        expect!(config.should_log(200, Duration::from_millis(250)))
+            .to(be_some().value(DecisionLogReason::Slow));
+        expect!(config.should_log(404, Duration::from_millis(1))).to(be_none());
+    }
+
+    #[test]
+    fn level_for_uses_the_decision_overrides() {
+        let config = DecisionLogConfig {
+            decision_levels: hashmap! { DecisionId::B8Authorized => Level::Info },
+            ..DecisionLogConfig::default()
+        };
+        expect!(config.level_for(DecisionId::B8Authorized)).to(be_equal_to(Level::Info));
+        expect!(config.level_for(DecisionId::G7ResourceExists)).to(be_equal_to(Level::Debug));
+    }
+}
@@ -0,0 +1,344 @@
+//! The `deployment` module swaps the resource behind a route while the server is running, for
+//! blue/green deploys of resource logic. A swap is atomic: requests that start after it are
+//! dispatched to the new resource, while the requests in flight complete against the old one,
+//! and the swap returns once they have drained. The error rate of the new resource is then
+//! watched for a bake period, and the previous resource is restored if it spikes.
+
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+
+use crate::Resource;
+
+/// Callback invoked when a swap is rolled back automatically
+pub type RollbackHook = Arc<dyn Fn(&RollbackEvent) + Send + Sync>;
+
+/// Policy for rolling back a swap when the error rate of the new resource spikes
+#[derive(Clone)]
+pub struct RollbackPolicy {
+    /// Fraction of the requests to the new resource that may fail with a 5xx status before the
+    /// swap is rolled back. Defaults to 0.05.
+    pub max_error_rate: f64,
+    /// Number of requests to the new resource before the error rate is checked, so a single
+    /// failure does not roll the swap back. Defaults to 20.
+    pub min_requests: u64,
+    /// How long the error rate is watched after a swap. Once it has passed, the previous
+    /// resource is released and the swap can no longer be rolled back. Defaults to 5 minutes.
+    pub bake_time: Duration,
+    /// Called when a swap is rolled back. Defaults to None.
+    pub on_rollback: Option<RollbackHook>,
+}
+
+impl Default for RollbackPolicy {
+    fn default() -> RollbackPolicy {
+        RollbackPolicy {
+            max_error_rate: 0.05,
+            min_requests: 20,
+            bake_time: Duration::from_secs(300),
+            on_rollback: None,
+        }
+    }
+}
+
+/// A swap that was rolled back because the error rate of the new resource spiked
+#[derive(Debug, Clone, PartialEq)]
+pub struct RollbackEvent {
+    /// Generation of the resource that was rolled back
+    pub generation: u64,
+    /// Generation of the resource that was restored
+    pub restored_generation: u64,
+    /// Number of requests to the rolled back resource
+    pub requests: u64,
+    /// Number of those requests that failed with a 5xx status
+    pub errors: u64,
+}
+
+impl RollbackEvent {
+    /// Fraction of the requests to the rolled back resource that failed
+    pub fn error_rate(&self) -> f64 {
+        self.errors as f64 / self.requests.max(1) as f64
+    }
+}
+
+/// A resource that has been deployed to a route
+struct Instance<'a> {
+    resource: Arc<Resource<'a>>,
+    generation: u64,
+    /// Requests in flight hold a receiver, so the channel is closed once they have drained
+    in_flight: Arc<watch::Sender<()>>,
+    deployed_at: Instant,
+    requests: u64,
+    errors: u64,
+}
+
+impl<'a> Instance<'a> {
+    fn new(resource: Resource<'a>, generation: u64) -> Instance<'a> {
+        Instance {
+            resource: Arc::new(resource),
+            generation,
+            in_flight: Arc::new(watch::channel(()).0),
+            deployed_at: Instant::now(),
+            requests: 0,
+            errors: 0,
+        }
+    }
+}
+
+struct DeploymentState<'a> {
+    active: Instance<'a>,
+    /// Resource that was swapped out, kept until the bake time of the active one has passed
+    previous: Option<Instance<'a>>,
+    generations: u64,
+}
+
+impl<'a> DeploymentState<'a> {
+    /// Restores the previous resource, returning the rollback event if there was one
+    fn restore_previous(&mut self) -> Option<RollbackEvent> {
+        let previous = self.previous.take()?;
+        let rolled_back = mem::replace(&mut self.active, previous);
+        Some(RollbackEvent {
+            generation: rolled_back.generation,
+            restored_generation: self.active.generation,
+            requests: rolled_back.requests,
+            errors: rolled_back.errors,
+        })
+    }
+}
+
+/// Resource behind a route that can be swapped while the server is running. Add it to the
+/// `deployments` of the dispatcher instead of its `routes`, and keep a clone to swap the
+/// resource with. Clones share the same state.
+#[derive(Clone)]
+pub struct Deployment<'a> {
+    /// Policy for rolling back swaps automatically. Defaults to None (swaps are only rolled
+    /// back by calling `rollback`).
+    pub rollback_policy: Option<RollbackPolicy>,
+    state: Arc<Mutex<DeploymentState<'a>>>,
+}
+
+/// Resource that a request is dispatched to, which holds the request in flight against it
+pub(crate) struct DeployedResource<'a> {
+    pub(crate) resource: Arc<Resource<'a>>,
+    generation: u64,
+    _in_flight: watch::Receiver<()>,
+}
+
+impl<'a> Deployment<'a> {
+    /// Creates a deployment with the initial resource of the route, which is generation 1
+    pub fn new(resource: Resource<'a>) -> Deployment<'a> {
+        Deployment {
+            rollback_policy: None,
+            state: Arc::new(Mutex::new(DeploymentState {
+                active: Instance::new(resource, 1),
+                previous: None,
+                generations: 1,
+            })),
+        }
+    }
+
+    /// Generation of the active resource. Each swap increments it.
+    pub fn generation(&self) -> u64 {
+        self.state.lock().unwrap().active.generation
+    }
+
+    /// The active resource
+    pub fn active(&self) -> Arc<Resource<'a>> {
+        self.state.lock().unwrap().active.resource.clone()
+    }
+
+    /// Swaps in the resource, returning its generation once the requests in flight against the
+    /// old resource have completed. The `on_start` hook of the resource is called before it
+    /// receives any requests. The old resource is kept until the bake time of the rollback
+    /// policy has passed, so the swap can be rolled back.
+    pub async fn swap(&self, resource: Resource<'a>) -> u64 {
+        if let Some(on_start) = &resource.on_start {
+            on_start().await;
+        }
+        let (generation, draining) = {
+            let mut state = self.state.lock().unwrap();
+            state.generations += 1;
+            let instance = Instance::new(resource, state.generations);
+            let old = mem::replace(&mut state.active, instance);
+            let draining = old.in_flight.clone();
+            state.previous = Some(old);
+            (state.generations, draining)
+        };
+        info!(
+            "Swapped in generation {} of the resource, draining the requests in flight",
+            generation
+        );
+        draining.closed().await;
+        debug!("Requests in flight against the old resource have drained");
+        generation
+    }
+
+    /// Restores the resource that was swapped out by the last swap. Returns false if there is
+    /// none, as the bake time has passed or the swap was already rolled back.
+    pub fn rollback(&self) -> bool {
+        let event = self.state.lock().unwrap().restore_previous();
+        if let Some(event) = &event {
+            warn!(
+                "Rolled back generation {} of the resource to generation {}",
+                event.generation, event.restored_generation
+            );
+        }
+        event.is_some()
+    }
+
+    /// Returns the active resource for a request, which is counted as in flight against it until
+    /// it is dropped
+    pub(crate) fn acquire(&self) -> DeployedResource<'a> {
+        let state = self.state.lock().unwrap();
+        DeployedResource {
+            resource: state.active.resource.clone(),
+            generation: state.active.generation,
+            _in_flight: state.active.in_flight.subscribe(),
+        }
+    }
+
+    /// Records the status of a request, rolling the swap back if the error rate of the new
+    /// resource has spiked
+    pub(crate) fn record(&self, deployed: &DeployedResource<'a>, status: u16) {
+        let policy = match &self.rollback_policy {
+            Some(policy) => policy,
+            None => return,
+        };
+        let event = {
+            let mut state = self.state.lock().unwrap();
+            if state.previous.is_none() || state.active.generation != deployed.generation {
+                return;
+            }
+            if state.active.deployed_at.elapsed() > policy.bake_time {
+                debug!("Bake time has passed, releasing the previous resource");
+                state.previous = None;
+                return;
+            }
+            state.active.requests += 1;
+            if status >= 500 {
+                state.active.errors += 1;
+            }
+            let active = &state.active;
+            let error_rate = active.errors as f64 / active.requests as f64;
+            if active.requests < policy.min_requests || error_rate <= policy.max_error_rate {
+                return;
+            }
+            state.restore_previous()
+        };
+        if let Some(event) = event {
+            error!(
+                "Error rate of generation {} of the resource is {:.2}, rolled back to \
+                generation {}",
+                event.generation,
+                event.error_rate(),
+                event.restored_generation
+            );
+            if let Some(on_rollback) = &policy.on_rollback {
+                on_rollback(&event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{owned_callback, Dispatcher};
+    use expectest::prelude::*;
+    use tokio::sync::{mpsc, Notify};
+
+    fn resource(body: &'static str, status: u16) -> Resource<'static> {
+        Resource {
+            render_response: owned_callback(move |context, _| {
+                context.response.status = status;
+                Box::pin(async move { Some(body.to_string()) })
+            }),
+            ..Resource::default()
+        }
+    }
+
+    async fn dispatch(dispatcher: Dispatcher<'static>) -> (u16, String) {
+        let request = http::Request::builder()
+            .uri("/orders")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = dispatcher.dispatch(request).await.unwrap();
+        let status = response.status().as_u16();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn swap_drains_the_requests_in_flight_against_the_old_resource() {
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let gate = Arc::new(Notify::new());
+        let release = gate.clone();
+        let blue = Resource {
+            resource_exists: owned_callback(move |_, _| {
+                started_tx.send(()).unwrap();
+                let gate = gate.clone();
+                Box::pin(async move {
+                    gate.notified().await;
+                    true
+                })
+            }),
+            ..resource("blue", 200)
+        };
+        let deployment = Deployment::new(blue);
+        let dispatcher = Dispatcher {
+            deployments: btreemap! { "/orders" => deployment.clone() },
+            ..Dispatcher::default()
+        };
+
+        let in_flight = tokio::spawn(dispatch(dispatcher.clone()));
+        started_rx.recv().await.unwrap();
+        let swap = deployment.swap(resource("green", 200));
+        tokio::pin!(swap);
+        let draining = tokio::time::timeout(Duration::from_millis(20), &mut swap).await;
+        expect!(draining.is_err()).to(be_true());
+        expect!(deployment.generation()).to(be_equal_to(2));
+        expect!(dispatch(dispatcher.clone()).await).to(be_equal_to((200, "green".to_string())));
+
+        release.notify_one();
+        expect!(in_flight.await.unwrap()).to(be_equal_to((200, "blue".to_string())));
+        expect!(swap.await).to(be_equal_to(2));
+    }
+
+    #[tokio::test]
+    async fn swap_is_rolled_back_when_the_error_rate_spikes() {
+        let rollbacks = Arc::new(Mutex::new(Vec::new()));
+        let events = rollbacks.clone();
+        let deployment = Deployment {
+            rollback_policy: Some(RollbackPolicy {
+                min_requests: 4,
+                max_error_rate: 0.5,
+                on_rollback: Some(Arc::new(move |event: &RollbackEvent| {
+                    events.lock().unwrap().push(event.clone())
+                })),
+                ..RollbackPolicy::default()
+            }),
+            ..Deployment::new(resource("blue", 200))
+        };
+        let dispatcher = Dispatcher {
+            deployments: btreemap! { "/orders" => deployment.clone() },
+            ..Dispatcher::default()
+        };
+
+        expect!(deployment.swap(resource("green", 500)).await).to(be_equal_to(2));
+        for _ in 0..4 {
+            expect!(dispatch(dispatcher.clone()).await.0).to(be_equal_to(500));
+        }
+        expect!(dispatch(dispatcher.clone()).await).to(be_equal_to((200, "blue".to_string())));
+        expect!(deployment.generation()).to(be_equal_to(1));
+        expect!(rollbacks.lock().unwrap().clone()).to(be_equal_to(vec![RollbackEvent {
+            generation: 2,
+            restored_generation: 1,
+            requests: 4,
+            errors: 4,
+        }]));
+        expect!(deployment.rollback()).to(be_false());
+    }
+}
@@ -0,0 +1,298 @@
+//! The `diagnostics` module checks the route table of a dispatcher for mistakes that would
+//! otherwise only show up as surprising responses: routes that can never be matched, methods
+//! that are allowed but not handled, and media types that can never be negotiated. The
+//! dispatcher logs them when it starts, and `Dispatcher::diagnostics` returns them so a test
+//! can assert that there are none.
+//!
+//! Routes are matched by the longest prefix, so a more general route never hides a more
+//! specific one. A route is only shadowed by another route that matches exactly the same paths.
+
+use std::{borrow::Cow, cmp::Reverse, fmt};
+
+use crate::Resource;
+
+/// Problem found in the route table of a dispatcher
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteDiagnostic {
+    /// The route matches the same paths as another route (i.e. `/users` and `/users/`), which
+    /// is always selected instead
+    ShadowedRoute {
+        /// Route that is never selected
+        route: String,
+        /// Route that is selected instead
+        shadowed_by: String,
+    },
+    /// The resource allows an unsafe method that the state machine has no branches for (i.e.
+    /// PATCH), so requests with it are only processed by its `process_other_method` callback,
+    /// which by default answers them with the representation of the resource, as if they were
+    /// GET requests
+    UnhandledMethod {
+        /// Route of the resource
+        route: String,
+        /// Allowed method
+        method: String,
+    },
+    /// The resource allows a method that is not in its `known_methods`, so requests with it
+    /// always get a '501 Not Implemented' response
+    UnknownMethod {
+        /// Route of the resource
+        route: String,
+        /// Allowed method
+        method: String,
+    },
+    /// A media type of the resource is not valid (RFC 9110 section 8.3.1), so it never matches
+    /// the media type of a request
+    InvalidMediaType {
+        /// Route of the resource
+        route: String,
+        /// Attribute of the resource the media type is declared in (i.e. `produces`)
+        attribute: String,
+        /// Invalid media type
+        media_type: String,
+    },
+}
+
+impl fmt::Display for RouteDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteDiagnostic::ShadowedRoute { route, shadowed_by } => write!(
+                f,
+                "Route '{}' matches the same paths as route '{}', so it is never selected",
+                route, shadowed_by
+            ),
+            RouteDiagnostic::UnhandledMethod { route, method } => write!(
+                f,
+                "Route '{}' allows {} requests, which are only processed by its \
+                process_other_method callback, otherwise they get the representation of the \
+                resource",
+                route, method
+            ),
+            RouteDiagnostic::UnknownMethod { route, method } => write!(
+                f,
+                "Route '{}' allows {} requests, but {} is not a known method, so they get a \
+                '501 Not Implemented' response",
+                route, method, method
+            ),
+            RouteDiagnostic::InvalidMediaType {
+                route,
+                attribute,
+                media_type,
+            } => write!(
+                f,
+                "Route '{}' has an invalid media type '{}' in {}",
+                route, media_type, attribute
+            ),
+        }
+    }
+}
+
+/// Unsafe methods that the state machine processes with a callback of the resource
+const HANDLED_UNSAFE_METHODS: [&str; 3] = ["POST", "PUT", "DELETE"];
+
+/// Checks the routes, in the order they are matched when their paths are the same length
+pub(crate) fn diagnose(routes: &[(&str, Cow<'_, Resource<'_>>)]) -> Vec<RouteDiagnostic> {
+    let mut diagnostics = Vec::new();
+    for (index, (route, resource)) in routes.iter().enumerate() {
+        let shadowed_by = routes
+            .iter()
+            .enumerate()
+            .filter(|(other, (path, other_resource))| {
+                *other != index
+                    && segments(path) == segments(route)
+                    && (path.len() > route.len() || (path.len() == route.len() && *other < index))
+                    && served_on_same_listener(resource, other_resource)
+            })
+            .map(|(_, (path, _))| *path)
+            .min_by_key(|path| Reverse(path.len()));
+        if let Some(shadowed_by) = shadowed_by {
+            diagnostics.push(RouteDiagnostic::ShadowedRoute {
+                route: route.to_string(),
+                shadowed_by: shadowed_by.to_string(),
+            });
+        }
+        diagnostics.extend(method_diagnostics(route, resource));
+        diagnostics.extend(media_type_diagnostics(route, resource));
+    }
+    diagnostics
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+fn served_on_same_listener(a: &Resource, b: &Resource) -> bool {
+    a.listeners.is_empty()
+        || b.listeners.is_empty()
+        || a.listeners
+            .iter()
+            .any(|listener| b.listeners.contains(listener))
+}
+
+fn method_diagnostics(route: &str, resource: &Resource) -> Vec<RouteDiagnostic> {
+    resource
+        .allowed_methods
+        .iter()
+        .filter_map(|method| {
+            if !resource.known_methods.contains(method) {
+                Some(RouteDiagnostic::UnknownMethod {
+                    route: route.to_string(),
+                    method: method.to_string(),
+                })
+            } else if !resource.known_methods.is_safe(method)
+                && !HANDLED_UNSAFE_METHODS
+                    .iter()
+                    .any(|handled| handled.eq_ignore_ascii_case(method))
+            {
+                Some(RouteDiagnostic::UnhandledMethod {
+                    route: route.to_string(),
+                    method: method.to_string(),
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn media_type_diagnostics<'r>(route: &str, resource: &Resource<'r>) -> Vec<RouteDiagnostic> {
+    let mut media_types: Vec<(String, &'r str)> = Vec::new();
+    let mut add = |attribute: String, declared: &[&'r str]| {
+        media_types.extend(
+            declared
+                .iter()
+                .map(|media_type| (attribute.clone(), *media_type)),
+        )
+    };
+    add("produces".to_string(), &resource.produces);
+    for (method, produces) in &resource.method_produces {
+        add(format!("method_produces[{}]", method), produces);
+    }
+    add(
+        "acceptable_content_types".to_string(),
+        &resource.acceptable_content_types,
+    );
+    for (method, acceptable) in &resource.method_acceptable_content_types {
+        add(
+            format!("method_acceptable_content_types[{}]", method),
+            acceptable,
+        );
+    }
+    for variant in &resource.variants {
+        add("variants".to_string(), &[variant.media_type]);
+    }
+    media_types.sort();
+    media_types
+        .into_iter()
+        .filter(|(_, media_type)| !is_valid_media_type(media_type))
+        .map(
+            |(attribute, media_type)| RouteDiagnostic::InvalidMediaType {
+                route: route.to_string(),
+                attribute,
+                media_type: media_type.to_string(),
+            },
+        )
+        .collect()
+}
+
+/// If the media type is a `type/subtype` pair of tokens, followed by any `name=value` parameters
+pub fn is_valid_media_type(media_type: &str) -> bool {
+    let mut parts = media_type.split(';');
+    let valid_type = match parts.next().unwrap_or_default().trim().split_once('/') {
+        Some((main, sub)) => is_token(main) && is_token(sub),
+        None => false,
+    };
+    valid_type
+        && parts.all(|param| match param.trim().split_once('=') {
+            Some((name, value)) => {
+                is_token(name)
+                    && (is_token(value)
+                        || (value.len() >= 2 && value.starts_with('"') && value.ends_with('"')))
+            }
+            None => false,
+        })
+}
+
+/// If the value is a token (RFC 9110 section 5.6.2)
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dispatcher;
+    use expectest::prelude::*;
+
+    #[test]
+    fn media_type_syntax() {
+        expect!(is_valid_media_type("application/json")).to(be_true());
+        expect!(is_valid_media_type("text/*")).to(be_true());
+        expect!(is_valid_media_type("text/html; charset=UTF-8")).to(be_true());
+        expect!(is_valid_media_type("text/plain;format=\"flowed\"")).to(be_true());
+        expect!(is_valid_media_type("json")).to(be_false());
+        expect!(is_valid_media_type("application/ json")).to(be_false());
+        expect!(is_valid_media_type("text/html; charset")).to(be_false());
+        expect!(is_valid_media_type("application/json,text/html")).to(be_false());
+    }
+
+    #[test]
+    fn diagnostics_report_problems_in_the_route_table() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/users" => Resource::default(),
+                "/users/" => Resource {
+                    allowed_methods: vec!["GET", "PATCH", "PURGE"],
+                    produces: vec!["application/json", "json"],
+                    ..Resource::default()
+                },
+                "/users/admin" => Resource {
+                    allowed_methods: vec!["GET", "POST", "PUT", "DELETE"],
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        };
+        expect!(dispatcher.diagnostics()).to(be_equal_to(vec![
+            RouteDiagnostic::ShadowedRoute {
+                route: "/users".to_string(),
+                shadowed_by: "/users/".to_string(),
+            },
+            RouteDiagnostic::UnhandledMethod {
+                route: "/users/".to_string(),
+                method: "PATCH".to_string(),
+            },
+            RouteDiagnostic::UnknownMethod {
+                route: "/users/".to_string(),
+                method: "PURGE".to_string(),
+            },
+            RouteDiagnostic::InvalidMediaType {
+                route: "/users/".to_string(),
+                attribute: "produces".to_string(),
+                media_type: "json".to_string(),
+            },
+        ]));
+    }
+
+    #[test]
+    fn routes_on_different_listeners_do_not_shadow_each_other() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/status" => Resource {
+                    listeners: vec!["public"],
+                    ..Resource::default()
+                },
+                "/status/" => Resource {
+                    listeners: vec!["admin"],
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        };
+        expect!(dispatcher.diagnostics()).to(be_equal_to(vec![]));
+    }
+}
@@ -0,0 +1,231 @@
+//! The `digest` module supports the integrity fields of RFC 9530. Responses can have
+//! `Content-Digest` and `Repr-Digest` headers computed over their final body, and the
+//! `Content-Digest` of request bodies can be validated before the request is processed.
+
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{
+    context::{Context, Request},
+    headers::HeaderValue,
+};
+
+/// Hashing algorithms for digest fields (the active algorithms of RFC 9530 section 5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    /// SHA-256
+    Sha256,
+    /// SHA-512
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// Returns the algorithm for the key of a digest field (i.e. `sha-256`), case-insensitive
+    pub fn from_key(key: &str) -> Option<DigestAlgorithm> {
+        match key.trim().to_lowercase().as_str() {
+            "sha-256" => Some(DigestAlgorithm::Sha256),
+            "sha-512" => Some(DigestAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    /// Key of the algorithm in digest fields
+    pub fn key(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Sha256 => "sha-256",
+            DigestAlgorithm::Sha512 => "sha-512",
+        }
+    }
+
+    /// Returns the digest of the data
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            DigestAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            DigestAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    /// Returns the member of a digest field for the data (i.e. `sha-256=:<base64>:`)
+    pub fn field_member(&self, data: &[u8]) -> String {
+        format!("{}=:{}:", self.key(), base64::encode(self.digest(data)))
+    }
+
+    /// Returns a hasher that computes the digest incrementally, for data that is not all
+    /// available at once (i.e. a streamed body)
+    pub fn hasher(&self) -> DigestHasher {
+        match self {
+            DigestAlgorithm::Sha256 => DigestHasher::Sha256(Sha256::new()),
+            DigestAlgorithm::Sha512 => DigestHasher::Sha512(Sha512::new()),
+        }
+    }
+}
+
+/// Incremental digest computation for an algorithm
+#[derive(Debug, Clone)]
+pub enum DigestHasher {
+    /// SHA-256
+    Sha256(Sha256),
+    /// SHA-512
+    Sha512(Sha512),
+}
+
+impl DigestHasher {
+    /// Adds the data to the digest
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            DigestHasher::Sha256(hasher) => hasher.update(data),
+            DigestHasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Returns the member of a digest field for all the data added (i.e. `sha-256=:<base64>:`)
+    pub fn field_member(self) -> String {
+        let (key, digest) = match self {
+            DigestHasher::Sha256(hasher) => ("sha-256", hasher.finalize().to_vec()),
+            DigestHasher::Sha512(hasher) => ("sha-512", hasher.finalize().to_vec()),
+        };
+        format!("{}=:{}:", key, base64::encode(digest))
+    }
+}
+
+/// Configuration of the integrity digests of a resource
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigestConfig {
+    /// Algorithms to compute response digests with. Defaults to SHA-256.
+    pub algorithms: Vec<DigestAlgorithm>,
+    /// If responses with a body should have a `Content-Digest` header. Defaults to true.
+    pub content_digest: bool,
+    /// If responses with a body should have a `Repr-Digest` header. Responses are not encoded or
+    /// partial, so it is the same as the `Content-Digest`. Streamed bodies do not get one.
+    /// Defaults to false.
+    pub repr_digest: bool,
+    /// If the `Content-Digest` of request bodies should be validated. A request with a digest
+    /// that does not match its body will result in a '400 Bad Request' response. Digests with
+    /// unsupported algorithms are ignored. Defaults to true.
+    pub validate_requests: bool,
+    /// If requests with a body must have a `Content-Digest` with a supported algorithm.
+    /// Defaults to false.
+    pub require_request_digest: bool,
+}
+
+impl Default for DigestConfig {
+    fn default() -> DigestConfig {
+        DigestConfig {
+            algorithms: vec![DigestAlgorithm::Sha256],
+            content_digest: true,
+            repr_digest: false,
+            validate_requests: true,
+            require_request_digest: false,
+        }
+    }
+}
+
+impl DigestConfig {
+    /// Validates the `Content-Digest` header of the request against its body. Returns the
+    /// reason the request is invalid if it is not.
+    pub fn validate_request(&self, request: &Request) -> Result<(), String> {
+        if !self.validate_requests {
+            return Ok(());
+        }
+        let body = request.body.as_deref().unwrap_or_default();
+        let mut verified = false;
+        for member in request.find_header("Content-Digest") {
+            let (key, value) = match member.value.split_once('=') {
+                Some(field) => field,
+                None => return Err(format!("Content-Digest '{}' is invalid", member.value)),
+            };
+            let algorithm = match DigestAlgorithm::from_key(key) {
+                Some(algorithm) => algorithm,
+                None => continue,
+            };
+            let expected = value
+                .trim()
+                .strip_prefix(':')
+                .and_then(|value| value.strip_suffix(':'))
+                .and_then(|value| base64::decode(value).ok())
+                .ok_or_else(|| format!("Content-Digest '{}' is invalid", member.value))?;
+            if expected != algorithm.digest(body) {
+                return Err(format!("{} Content-Digest does not match the body", key.trim()));
+            }
+            verified = true;
+        }
+        if !verified && self.require_request_digest && !body.is_empty() {
+            return Err("Request body does not have a supported Content-Digest".to_string());
+        }
+        Ok(())
+    }
+
+    /// Adds the digest headers for the body of the response, if it has one. The digest of a
+    /// streamed body is not known until it has been sent, so the `Content-Digest` of the body is
+    /// sent as a trailer instead (advertised with the `Trailer` header).
+    pub fn add_response_headers(&self, context: &mut Context) {
+        if self.algorithms.is_empty() {
+            return;
+        }
+        if let (None, Some(stream)) = (&context.response.body, &mut context.response.stream) {
+            if self.content_digest {
+                stream.trailer_digests = self.algorithms.clone();
+                context
+                    .response
+                    .add_header("Trailer", vec![h!("Content-Digest")]);
+            }
+            return;
+        }
+        let body = match &context.response.body {
+            Some(body) => body,
+            None => return,
+        };
+        let members = self
+            .algorithms
+            .iter()
+            .map(|algorithm| HeaderValue::basic(algorithm.field_member(body)))
+            .collect::<Vec<HeaderValue>>();
+        if self.content_digest {
+            context.response.add_header("Content-Digest", members.clone());
+        }
+        if self.repr_digest {
+            context.response.add_header("Repr-Digest", members);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn algorithms_produce_rfc_9530_field_members() {
+        // example from RFC 9530 appendix D
+        let body = b"{\"hello\": \"world\"}";
+        expect!(DigestAlgorithm::Sha256.field_member(body)).to(be_equal_to(
+            "sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:",
+        ));
+        expect!(DigestAlgorithm::from_key("SHA-512")).to(be_some().value(DigestAlgorithm::Sha512));
+        expect!(DigestAlgorithm::from_key("md5")).to(be_none());
+    }
+
+    #[test]
+    fn validate_request_checks_the_supported_digests() {
+        let request = |digest: &str| Request {
+            body: Some(b"{\"hello\": \"world\"}".to_vec()),
+            headers: hashmap! {
+                "Content-Digest".to_string() => HeaderValue::parse_list(digest)
+            },
+            ..Request::default()
+        };
+        let config = DigestConfig::default();
+        expect!(config.validate_request(&request(
+            "unixsum=:dGVzdA==:, sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:"
+        )))
+        .to(be_ok());
+        expect!(config.validate_request(&request("sha-256=:dGVzdA==:")))
+            .to(be_err().value("sha-256 Content-Digest does not match the body"));
+        expect!(config.validate_request(&request("sha-256=abc"))).to(be_err());
+        expect!(config.validate_request(&request("unixsum=:dGVzdA==:"))).to(be_ok());
+        let required = DigestConfig {
+            require_request_digest: true,
+            ..DigestConfig::default()
+        };
+        expect!(required.validate_request(&request("unixsum=:dGVzdA==:"))).to(be_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Consul service registry, enabled with the `consul` feature
+
+use serde_json::json;
+
+use super::{send_json, RegistryFuture, ServiceRegistration, ServiceRegistry};
+
+/// Registers services with the HTTP API of a local Consul agent. The routes of the service are
+/// added to its metadata as a comma separated `routes` entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsulRegistry {
+    /// Base URL of the Consul agent. Defaults to `http://127.0.0.1:8500`.
+    pub url: String,
+    /// ACL token to authenticate with. Defaults to None.
+    pub token: Option<String>,
+    /// Interval the agent polls the health check of the service at. Defaults to `10s`.
+    pub check_interval: String,
+    /// How long the health check of the service may be failing before Consul deregisters it,
+    /// so instances that did not shut down gracefully are removed. Defaults to `1m`.
+    pub deregister_critical_after: String,
+}
+
+impl Default for ConsulRegistry {
+    fn default() -> ConsulRegistry {
+        ConsulRegistry {
+            url: "http://127.0.0.1:8500".to_string(),
+            token: None,
+            check_interval: "10s".to_string(),
+            deregister_critical_after: "1m".to_string(),
+        }
+    }
+}
+
+impl ConsulRegistry {
+    /// Creates a registry for the Consul agent at the URL
+    pub fn new<S: Into<String>>(url: S) -> ConsulRegistry {
+        ConsulRegistry {
+            url: url.into(),
+            ..ConsulRegistry::default()
+        }
+    }
+
+    fn send(&self, path: String, body: Option<serde_json::Value>) -> RegistryFuture {
+        let url = format!("{}{}", self.url.trim_end_matches('/'), path);
+        let token = self.token.clone();
+        Box::pin(async move {
+            let headers = token
+                .as_deref()
+                .map(|token| vec![("X-Consul-Token", token)])
+                .unwrap_or_default();
+            send_json(http::Method::PUT, &url, &headers, body).await
+        })
+    }
+}
+
+impl ServiceRegistry for ConsulRegistry {
+    fn register(&self, service: &ServiceRegistration) -> RegistryFuture {
+        let mut metadata = service.metadata.clone();
+        if !service.routes.is_empty() {
+            metadata.insert("routes".to_string(), service.routes.join(","));
+        }
+        let mut body = json!({
+            "ID": service.id,
+            "Name": service.name,
+            "Address": service.address,
+            "Port": service.port,
+            "Tags": service.tags,
+            "Meta": metadata
+        });
+        if let (Some(url), Some(fields)) = (&service.health_check, body.as_object_mut()) {
+            fields.insert(
+                "Check".to_string(),
+                json!({
+                    "HTTP": url,
+                    "Interval": self.check_interval,
+                    "DeregisterCriticalServiceAfter": self.deregister_critical_after
+                }),
+            );
+        }
+        self.send("/v1/agent/service/register".to_string(), Some(body))
+    }
+
+    fn deregister(&self, service: &ServiceRegistration) -> RegistryFuture {
+        self.send(format!("/v1/agent/service/deregister/{}", service.id), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::tests::recording_server;
+    use expectest::prelude::*;
+
+    #[tokio::test]
+    async fn registers_with_the_agent_api() {
+        let (addr, mut requests) = recording_server().await;
+        let registry = ConsulRegistry::new(format!("http://{}/", addr));
+        let service = ServiceRegistration {
+            routes: vec!["/orders".to_string()],
+            health_check: Some("http://10.0.0.5:8080/health".to_string()),
+            ..ServiceRegistration::new("orders", "10.0.0.5:8080".parse().unwrap())
+        };
+
+        expect!(registry.register(&service).await).to(be_ok());
+        let (method, path, body) = requests.recv().await.unwrap();
+        expect!(method).to(be_equal_to("PUT"));
+        expect!(path).to(be_equal_to("/v1/agent/service/register"));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        expect!(body["ID"].clone()).to(be_equal_to(json!("orders-10.0.0.5-8080")));
+        expect!(body["Meta"]["routes"].clone()).to(be_equal_to(json!("/orders")));
+        expect!(body["Check"]["HTTP"].clone())
+            .to(be_equal_to(json!("http://10.0.0.5:8080/health")));
+
+        expect!(registry.deregister(&service).await).to(be_ok());
+        let (method, path, _) = requests.recv().await.unwrap();
+        expect!(method).to(be_equal_to("PUT"));
+        expect!(path).to(be_equal_to("/v1/agent/service/deregister/orders-10.0.0.5-8080"));
+    }
+}
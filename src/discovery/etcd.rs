@@ -0,0 +1,91 @@
+//! etcd service registry, enabled with the `etcd` feature
+
+use serde_json::json;
+
+use super::{send_json, RegistryFuture, ServiceRegistration, ServiceRegistry};
+
+/// Registers services in etcd through the JSON gateway of its v3 API. Each instance is stored
+/// as a JSON document under `<prefix><name>/<id>`. The key is not leased, so it is only removed
+/// when the instance is deregistered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EtcdRegistry {
+    /// Base URL of the etcd server. Defaults to `http://127.0.0.1:2379`.
+    pub url: String,
+    /// Prefix of the keys services are stored under. Defaults to `/services/`.
+    pub prefix: String,
+}
+
+impl Default for EtcdRegistry {
+    fn default() -> EtcdRegistry {
+        EtcdRegistry {
+            url: "http://127.0.0.1:2379".to_string(),
+            prefix: "/services/".to_string(),
+        }
+    }
+}
+
+impl EtcdRegistry {
+    /// Creates a registry for the etcd server at the URL
+    pub fn new<S: Into<String>>(url: S) -> EtcdRegistry {
+        EtcdRegistry {
+            url: url.into(),
+            ..EtcdRegistry::default()
+        }
+    }
+
+    /// Returns the key the service is stored under
+    pub fn key(&self, service: &ServiceRegistration) -> String {
+        format!("{}{}/{}", self.prefix, service.name, service.id)
+    }
+
+    fn send(&self, path: &str, body: serde_json::Value) -> RegistryFuture {
+        let url = format!("{}{}", self.url.trim_end_matches('/'), path);
+        Box::pin(async move { send_json(http::Method::POST, &url, &[], Some(body)).await })
+    }
+}
+
+impl ServiceRegistry for EtcdRegistry {
+    fn register(&self, service: &ServiceRegistration) -> RegistryFuture {
+        let body = json!({
+            "key": base64::encode(self.key(service)),
+            "value": base64::encode(service.to_json().to_string())
+        });
+        self.send("/v3/kv/put", body)
+    }
+
+    fn deregister(&self, service: &ServiceRegistration) -> RegistryFuture {
+        let body = json!({ "key": base64::encode(self.key(service)) });
+        self.send("/v3/kv/deleterange", body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::tests::recording_server;
+    use expectest::prelude::*;
+
+    #[tokio::test]
+    async fn stores_the_service_under_its_key() {
+        let (addr, mut requests) = recording_server().await;
+        let registry = EtcdRegistry::new(format!("http://{}", addr));
+        let service = ServiceRegistration::new("orders", "10.0.0.5:8080".parse().unwrap());
+        expect!(registry.key(&service)).to(be_equal_to("/services/orders/orders-10.0.0.5-8080"));
+
+        expect!(registry.register(&service).await).to(be_ok());
+        let (method, path, body) = requests.recv().await.unwrap();
+        expect!(method).to(be_equal_to("POST"));
+        expect!(path).to(be_equal_to("/v3/kv/put"));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let value = base64::decode(body["value"].as_str().unwrap()).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&value).unwrap();
+        expect!(value).to(be_equal_to(service.to_json()));
+
+        expect!(registry.deregister(&service).await).to(be_ok());
+        let (_, path, body) = requests.recv().await.unwrap();
+        expect!(path).to(be_equal_to("/v3/kv/deleterange"));
+        expect!(body).to(be_equal_to(
+            json!({ "key": base64::encode(registry.key(&service)) }).to_string(),
+        ));
+    }
+}
@@ -0,0 +1,250 @@
+//! The `discovery` module registers the server with a service registry when the dispatcher
+//! starts serving, and deregisters it when the dispatcher stops, so load balancers and other
+//! services only route to it while it is up. Registries are pluggable with the
+//! `ServiceRegistry` trait, with adapters for Consul (`consul` feature) and etcd (`etcd`
+//! feature).
+
+use futures::Future;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, net::SocketAddr, pin::Pin, sync::Arc};
+
+#[cfg(feature = "consul")]
+mod consul;
+#[cfg(feature = "consul")]
+pub use self::consul::*;
+
+#[cfg(feature = "etcd")]
+mod etcd;
+#[cfg(feature = "etcd")]
+pub use self::etcd::*;
+
+/// Service advertised to a registry
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceRegistration {
+    /// Identifier of the instance of the service, unique within the registry
+    pub id: String,
+    /// Name of the service
+    pub name: String,
+    /// Address that clients should connect to the service on
+    pub address: String,
+    /// Port that clients should connect to the service on
+    pub port: u16,
+    /// Route paths served by the instance. If this is empty when the dispatcher starts, the
+    /// paths of the routes of the dispatcher are advertised.
+    pub routes: Vec<String>,
+    /// Tags of the service
+    pub tags: Vec<String>,
+    /// Additional metadata of the service
+    pub metadata: BTreeMap<String, String>,
+    /// URL the registry can poll to check the health of the instance. Defaults to None.
+    pub health_check: Option<String>,
+}
+
+impl ServiceRegistration {
+    /// Creates a registration for the named service advertised on the address. The identifier
+    /// of the instance is made from the name and address (i.e. `orders-10.0.0.5-8080`).
+    pub fn new<S: Into<String>>(name: S, address: SocketAddr) -> ServiceRegistration {
+        let name = name.into();
+        ServiceRegistration {
+            id: format!("{}-{}-{}", name, address.ip(), address.port()),
+            name,
+            address: address.ip().to_string(),
+            port: address.port(),
+            routes: Vec::new(),
+            tags: Vec::new(),
+            metadata: BTreeMap::new(),
+            health_check: None,
+        }
+    }
+
+    /// Returns the registration as JSON, for registries that store it as a document
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "address": self.address,
+            "port": self.port,
+            "routes": self.routes,
+            "tags": self.tags,
+            "metadata": self.metadata,
+            "health_check": self.health_check
+        })
+    }
+}
+
+/// Future returned by a service registry
+pub type RegistryFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+
+/// Registry that services are advertised in (i.e. Consul or etcd)
+pub trait ServiceRegistry: Send + Sync {
+    /// Registers the service, replacing any existing registration with the same identifier
+    fn register(&self, service: &ServiceRegistration) -> RegistryFuture;
+
+    /// Removes the registration of the service
+    fn deregister(&self, service: &ServiceRegistration) -> RegistryFuture;
+}
+
+/// Registers a service with a registry while the dispatcher is serving. Failures to register
+/// or deregister are logged, and do not stop the dispatcher from serving.
+#[derive(Clone)]
+pub struct ServiceDiscovery {
+    /// Registry to advertise the service in
+    pub registry: Arc<dyn ServiceRegistry>,
+    /// Service to advertise
+    pub registration: ServiceRegistration,
+}
+
+impl ServiceDiscovery {
+    /// Creates a service discovery hook for the registry and service
+    pub fn new(
+        registry: Arc<dyn ServiceRegistry>,
+        registration: ServiceRegistration,
+    ) -> ServiceDiscovery {
+        ServiceDiscovery {
+            registry,
+            registration,
+        }
+    }
+
+    /// Registers the service, advertising the routes if the registration does not have any
+    pub async fn register(&self, routes: &[&str]) {
+        let mut registration = self.registration.clone();
+        if registration.routes.is_empty() {
+            registration.routes = routes.iter().map(|route| route.to_string()).collect();
+        }
+        match self.registry.register(&registration).await {
+            Ok(()) => info!("Registered service '{}'", registration.id),
+            Err(err) => warn!("Failed to register service '{}' - {}", registration.id, err),
+        }
+    }
+
+    /// Deregisters the service
+    pub async fn deregister(&self) {
+        match self.registry.deregister(&self.registration).await {
+            Ok(()) => info!("Deregistered service '{}'", self.registration.id),
+            Err(err) => warn!(
+                "Failed to deregister service '{}' - {}",
+                self.registration.id, err
+            ),
+        }
+    }
+}
+
+/// Sends a request with a JSON body to the HTTP API of a registry, failing if the response is
+/// not successful
+#[cfg(any(feature = "consul", feature = "etcd"))]
+async fn send_json(
+    method: http::Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<Value>,
+) -> Result<(), String> {
+    let mut request = http::Request::builder().method(method).uri(url);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let body = match body {
+        Some(body) => {
+            request = request.header("Content-Type", "application/json");
+            hyper::Body::from(body.to_string())
+        }
+        None => hyper::Body::empty(),
+    };
+    let request = request
+        .body(body)
+        .map_err(|err| format!("Invalid registry request - {}", err))?;
+    let response = hyper::Client::new()
+        .request(request)
+        .await
+        .map_err(|err| format!("Registry request to '{}' failed - {}", url, err))?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Registry request to '{}' failed with status {}",
+            url,
+            response.status()
+        ))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{Dispatcher, Resource};
+    use expectest::prelude::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingRegistry {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl ServiceRegistry for RecordingRegistry {
+        fn register(&self, service: &ServiceRegistration) -> RegistryFuture {
+            let event = format!("register {} {}", service.id, service.routes.join(","));
+            self.events.lock().unwrap().push(event);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn deregister(&self, service: &ServiceRegistration) -> RegistryFuture {
+            let event = format!("deregister {}", service.id);
+            self.events.lock().unwrap().push(event);
+            Box::pin(async { Err("registry is down".to_string()) })
+        }
+    }
+
+    /// Starts an HTTP server that records the requests made to it, returning its address and
+    /// the receiver of the method, path and body of each request
+    #[cfg(any(feature = "consul", feature = "etcd"))]
+    pub(crate) async fn recording_server() -> (
+        SocketAddr,
+        tokio::sync::mpsc::UnboundedReceiver<(String, String, String)>,
+    ) {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let sender = sender.clone();
+                let service = hyper::service::service_fn(move |req: http::Request<hyper::Body>| {
+                    let sender = sender.clone();
+                    async move {
+                        let (parts, body) = req.into_parts();
+                        let body = hyper::body::to_bytes(body).await?;
+                        let body = String::from_utf8_lossy(&body).to_string();
+                        let request = (parts.method.to_string(), parts.uri.to_string(), body);
+                        let _ = sender.send(request);
+                        Ok::<_, hyper::Error>(http::Response::new(hyper::Body::empty()))
+                    }
+                });
+                tokio::spawn(hyper::server::conn::Http::new().serve_connection(stream, service));
+            }
+        });
+        (addr, receiver)
+    }
+
+    #[tokio::test]
+    async fn dispatcher_registers_the_service_while_it_is_serving() {
+        let registry = Arc::new(RecordingRegistry::default());
+        let discovery = ServiceDiscovery::new(
+            registry.clone(),
+            ServiceRegistration::new("orders", "10.0.0.5:8080".parse().unwrap()),
+        );
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/orders" => Resource::default(),
+                "/health" => Resource::default()
+            },
+            service_discovery: Some(discovery),
+            ..Dispatcher::default()
+        };
+        dispatcher.start().await;
+        dispatcher.stop().await;
+        expect!(registry.events.lock().unwrap().clone()).to(be_equal_to(vec![
+            "register orders-10.0.0.5-8080 /health,/orders".to_string(),
+            "deregister orders-10.0.0.5-8080".to_string(),
+        ]));
+    }
+}
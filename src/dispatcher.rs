@@ -1,71 +1,776 @@
-use std::task;
+use std::{
+    any::Any, borrow::Cow, cmp::Reverse, collections::VecDeque, panic::AssertUnwindSafe, task,
+    time::Duration,
+};
 
+use futures::{future, FutureExt, StreamExt};
 use hyper::Body;
 
 use super::*;
+use crate::context::{BodyChunks, ClientDisconnect, MemoryAccount};
 
 /// The main hyper dispatcher
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Dispatcher<'a> {
     /// Map of routes to webmachine resources
     pub routes: BTreeMap<&'a str, Resource<'a>>,
+    /// Map of routes to resources that can be swapped while the server is running. Requests to
+    /// these routes are dispatched to the active resource of the deployment. Defaults to empty.
+    pub deployments: BTreeMap<&'a str, Deployment<'a>>,
+    /// Maximum number of bytes that may be allocated for a single request, counting the buffered
+    /// request body and the rendered response body. A request body over the cap will result in a
+    /// '413 Request Entity Too Large' response, and a response body over the cap in a
+    /// '500 Internal Server Error'. Defaults to None (no cap).
+    pub max_request_memory: Option<usize>,
+    /// Maximum length in bytes of a request body for the resources that do not declare their
+    /// own. Requests with a larger body will result in a '413 Request Entity Too Large'
+    /// response. Defaults to None (no maximum).
+    pub max_entity_length: Option<u64>,
+    /// CORS configuration applied to all the resources that do not have their own. Defaults to
+    /// None.
+    pub cors: Option<CorsConfig>,
+    /// Authorizer used for the resources that do not have their own. Defaults to None.
+    pub authorizer: Option<Arc<dyn auth::Authorizer>>,
+    /// Hosts that requests may be made to, matched against the Host header (or the authority of
+    /// an absolute-form request target) ignoring any port. Entries may start with a wildcard to
+    /// allow subdomains (i.e. `*.example.com`). Requests to any other host will result in a
+    /// '421 Misdirected Request' response, and requests with a missing or repeated Host header
+    /// in a '400 Bad Request'. Defaults to empty, which allows any host.
+    pub allowed_hosts: Vec<String>,
+    /// Rules redirecting legacy paths, which are evaluated in order before routing. A request
+    /// whose path matches a rule gets a permanent redirect to the rewritten path. Defaults to
+    /// empty.
+    pub redirects: Vec<RedirectRule>,
+    /// Minimum HTTP version that requests must be made with. Requests made with an older
+    /// version will result in a '505 HTTP Version Not Supported' response. Defaults to None
+    /// (any version).
+    pub minimum_http_version: Option<http::Version>,
+    /// Policy requiring requests to be made over TLS. Plaintext requests are redirected to the
+    /// https URL or rejected, and secure responses get a Strict-Transport-Security header.
+    /// Defaults to None (plaintext requests are allowed).
+    pub require_tls: Option<TlsPolicy>,
+    /// Proxies the `Forwarded` and `X-Forwarded-*` headers of requests are trusted from. The
+    /// forwarded client address, scheme and host of requests from these proxies are used for
+    /// the client IP (i.e. for rate limiting), the TLS policy and absolute URLs. Defaults to
+    /// None (the headers are ignored).
+    pub forwarded: Option<ForwardedConfig>,
+    /// Rate limiter applied to all requests before they are dispatched to a resource. Requests
+    /// over the limit will result in a '429 Too Many Requests' response. Defaults to None.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Configuration of logging the decisions made for requests, used for the resources that
+    /// do not have their own. Defaults to None (decisions are only logged at trace level).
+    pub decision_log: Option<DecisionLogConfig>,
+    /// Configuration of redacting headers and bodies when responses are logged, used for the
+    /// resources that do not have their own. Defaults to None (the default redaction).
+    pub redaction: Option<RedactionConfig>,
+    /// Renderer of the bodies of error (4xx and 5xx) responses that do not already have one,
+    /// negotiated against the Accept header of the request. Defaults to None, in which case
+    /// error responses have no body.
+    pub error_renderer: Option<ErrorRenderer>,
+    /// Maximum number of transitions the state machine may make for a request before it is
+    /// stopped with a '500 Internal Server Error' response. Defaults to None (100 transitions).
+    pub max_state_machine_transitions: Option<usize>,
+    /// If the decision trace of requests is returned for debugging, in an `X-Webmachine-Trace`
+    /// header (i.e. `B13=true, B12=true, ..., L7=false`). Requests that accept the
+    /// `TRACE_MEDIA_TYPE` get the trace as a JSON body instead of the representation. This
+    /// exposes the internals of the resources, so should not be enabled in production. Defaults
+    /// to false.
+    pub decision_trace: bool,
+    /// Recorder of the decision traces of requests for the visual debugger, which is served by
+    /// a `DebuggerResource` created with a clone of the recorder. Defaults to None (traces are
+    /// not recorded).
+    pub debugger: Option<TraceRecorder>,
+    /// Request metrics, labelled by the route that matched the request and its method. They are
+    /// served for Prometheus by a resource created with a clone of the metrics (see
+    /// `Metrics::resource`). Defaults to None (metrics are not collected).
+    pub metrics: Option<Metrics>,
+    /// Callback invoked once per completed request with an entry describing it (see
+    /// `common_log_format` and `json_access_log` for the built-in ones). For streamed bodies, it
+    /// is invoked once the body has been sent. Defaults to None.
+    pub access_log: Option<AccessLogCallback>,
+    /// Classifies requests by their User-Agent header, setting the class on the context for the
+    /// `user_agent_negotiation` of resources and the bot policy. Defaults to None (requests are
+    /// only classified, with the default classifier, if there is a bot policy).
+    pub user_agent_classifier: Option<content_negotiation::UserAgentClassifier>,
+    /// Policy for requests from bots: their own rate limit, routes they are denied, and the
+    /// `X-Robots-Tag` headers of the routes. Defaults to None.
+    pub bot_policy: Option<BotPolicy>,
+    /// Deadline for handling requests to the resources that do not have their own. Defaults to
+    /// None (no deadline).
+    pub request_timeout: Option<RequestTimeout>,
+    /// Caching profile for the resources that do not have their own. Defaults to None.
+    pub caching: Option<CachingProfile>,
+    /// Compression configuration for the resources that do not have their own. Defaults to None.
+    pub compression: Option<CompressionConfig>,
+    /// Serves documentation pages of the routes, for OPTIONS requests from browsers and GET
+    /// requests to the docs suffix of a route. Defaults to None (no pages are served).
+    pub docs: Option<DocsConfig>,
+    /// Registers the server with a service registry when the dispatcher starts serving, and
+    /// deregisters it when the dispatcher stops. Defaults to None.
+    pub service_discovery: Option<discovery::ServiceDiscovery>,
+    /// Readiness gate of the dispatcher. The dispatcher is not ready until the `on_start` hooks
+    /// of the resources and the warm-up tasks have completed, and is not ready again once it
+    /// starts stopping. When this is set, the serve helpers of the `server` module accept
+    /// connections while the dispatcher is starting, so liveness and readiness probes are
+    /// answered. Defaults to None.
+    pub readiness: Option<Readiness>,
+    /// Maximum time the serve helpers of the `server` module wait for the open connections to
+    /// finish their requests on shutdown. Connections still open after it are closed. Defaults
+    /// to None (connections are waited on until they finish).
+    pub drain_timeout: Option<std::time::Duration>,
+    /// Write timeout of the streamed response bodies that do not have their own (see
+    /// `StreamingBody::write_timeout`). Defaults to None (a body waits for the client for as
+    /// long as it takes).
+    pub stream_write_timeout: Option<Duration>,
+    /// Tracer used to emit an OpenTelemetry HTTP server span for each request, continuing the
+    /// trace of the `traceparent` header of the request. Enabled with the `otel` feature.
+    /// Defaults to None (no spans are emitted).
+    #[cfg(feature = "otel")]
+    pub tracer: Option<Arc<opentelemetry::global::BoxedTracer>>,
 }
 
+/// Media type of the JSON decision trace bodies returned when `decision_trace` is enabled
+pub const TRACE_MEDIA_TYPE: &str = "application/vnd.webmachine-trace+json";
+
 impl<'a> Dispatcher<'a> {
     /// Main dispatch function for the Webmachine. This will look for a matching resource
-    /// based on the request path. If one is not found, a 404 Not Found response is returned
+    /// based on the request path. If one is not found, a 404 Not Found response is returned.
+    /// If a callback of the resource panics, the panic is logged and a '500 Internal Server
+    /// Error' response is returned, so the connection is not torn down.
     pub async fn dispatch(self, req: http::Request<Body>) -> http::Result<http::Response<Body>> {
+        let started = std::time::Instant::now();
+        let access_log_entry = self.access_log.as_ref().map(|_| AccessLogEntry::received(&req));
         let mut context = self.context_from_http_request(req).await;
-        self.dispatch_to_resource(&mut context).await;
-        self.generate_http_response(&context)        
+        // hyper drops this future if the client goes away, which drops the guard
+        let disconnect_guard = context.client_disconnect.guard();
+        let route = self.respond(&mut context, started, access_log_entry).await;
+        let response = self.generate_http_response(&context, route.as_deref());
+        disconnect_guard.disarm();
+        response
+    }
+
+    /// Dispatches a request that has been read by the adapter of a transport, returning the
+    /// response converted by the adapter. This goes through the same steps as `dispatch`, other
+    /// than the ones that depend on the transport: the HTTP version, TLS policy and access log
+    /// of the dispatcher are not applied, and the adapter is responsible for reading the body
+    /// and any limits on its size.
+    pub async fn dispatch_with<A: transport::Adapter>(
+        &self,
+        adapter: &A,
+        request: A::Request,
+    ) -> A::Response {
+        let started = std::time::Instant::now();
+        let mut context = match adapter.read_request(request).await {
+            Ok(request) => self.context_from_request(request),
+            Err(status) => {
+                warn!(
+                    "Adapter failed to read the request, responding with {}",
+                    status
+                );
+                let mut context = Context::default();
+                context.response.status = status;
+                context.error = Some("Failed to read the request".to_string());
+                context
+            }
+        };
+        self.respond(&mut context, started, None).await;
+        adapter.write_response(context.response)
+    }
+
+    /// Dispatches the request to the matching resource, and then records the response with the
+    /// debugger, metrics and access log of the dispatcher. Returns the route that matched the
+    /// request.
+    async fn respond(
+        &self,
+        context: &mut Context,
+        started: std::time::Instant,
+        access_log_entry: Option<AccessLogEntry>,
+    ) -> Option<String> {
+        let trace_body = self.decision_trace && take_trace_media_type(&mut context.request);
+        // the request path is made relative to the route when it is dispatched
+        let route = self.longest_matching_path(&context.request);
+        #[cfg(feature = "otel")]
+        let span = self
+            .tracer
+            .as_ref()
+            .map(|tracer| telemetry::start_server_span(tracer, context));
+        if context.error.is_none() {
+            apply_redirects(&self.redirects, context);
+        }
+        if context.error.is_none() {
+            self.dispatch_to_resource(context).await;
+        } else {
+            self.add_cors_headers(context);
+        }
+        if let Some(error_renderer) = &self.error_renderer {
+            error_renderer.render_error(context);
+        }
+        #[cfg(feature = "otel")]
+        if let Some(span) = span {
+            telemetry::end_server_span(span, context, route.as_deref());
+        }
+        if let Some(debugger) = &self.debugger {
+            debugger.record(context);
+        }
+        if let Some(bot_policy) = &self.bot_policy {
+            bot_policy.add_robots_tag(context, route.as_deref());
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.record(context, route.as_deref(), started.elapsed());
+        }
+        if self.decision_trace {
+            add_decision_trace(context, trace_body);
+        }
+        if let (Some(access_log), Some(entry)) = (&self.access_log, access_log_entry) {
+            access_log::log_access(access_log, entry, context, route.clone());
+        }
+        info!(target: "webmachine::summary", "{}", context.summary());
+        route
+    }
+
+    pub(crate) async fn context_from_http_request(&self, req: http::Request<Body>) -> Context {
+        let (parts, body) = req.into_parts();
+        let mut context = self.new_context(self.request_from_http_parts(&parts));
+        if context.error.is_some() {
+            return context;
+        }
+        if let Some(version) = self.minimum_http_version {
+            if parts.version < version {
+                warn!("Request HTTP version {:?} is not supported", parts.version);
+                context.response.status = 505;
+                context.error = Some(format!("HTTP version {:?} is not supported", parts.version));
+                return context;
+            }
+        }
+        let max_entity_length = self.max_entity_length_for(&context.request);
+        if let (Some(max), Some(length)) = (max_entity_length, context.request.content_length()) {
+            if length > max {
+                warn!("Request body of {} bytes exceeds the maximum of {} bytes", length, max);
+                context.response.status = 413;
+                context.error = Some("Request body exceeds the maximum entity length".to_string());
+                return context;
+            }
+        }
+        match read_body(body, &mut context.memory, max_entity_length).await {
+            Ok(body) => context.request.body = body,
+            Err(BodyReadError::Read(err)) => {
+                error!("Failed to read the request body: {}", err);
+                context.response.status = if err.is_timeout() { 408 } else { 400 };
+                context.error = Some(format!("Failed to read the request body: {}", err));
+            }
+            Err(BodyReadError::MemoryLimitExceeded) => {
+                warn!(
+                    "Request body exceeds the memory limit of {:?} bytes",
+                    self.max_request_memory
+                );
+                context.response.status = 413;
+                context.error = Some("Request body exceeds the request memory limit".to_string());
+            }
+            Err(BodyReadError::EntityTooLarge) => {
+                warn!("Request body exceeds the maximum of {:?} bytes", max_entity_length);
+                context.response.status = 413;
+                context.error = Some("Request body exceeds the maximum entity length".to_string());
+            }
+        }
+        if context.error.is_none() {
+            self.validate_host(&mut context);
+        }
+        if context.error.is_none() {
+            self.apply_tls_policy(&parts, &mut context);
+        }
+        self.admit(&mut context);
+        context
+    }
+
+    /// Creates the context of a request read by the adapter of a transport, checking the body
+    /// against the limits of the dispatcher and admitting the request as `dispatch` does
+    fn context_from_request(&self, request: Request) -> Context {
+        let mut context = self.new_context(request);
+        if context.error.is_some() {
+            return context;
+        }
+        let length = context.request.body.as_ref().map_or(0, Vec::len);
+        let max_entity_length = self.max_entity_length_for(&context.request);
+        if matches!(max_entity_length, Some(max) if length as u64 > max) {
+            warn!(
+                "Request body of {} bytes exceeds the maximum of {:?} bytes",
+                length, max_entity_length
+            );
+            context.response.status = 413;
+            context.error = Some("Request body exceeds the maximum entity length".to_string());
+            return context;
+        }
+        if !context.memory.allocate(length) {
+            warn!(
+                "Request body exceeds the memory limit of {:?} bytes",
+                self.max_request_memory
+            );
+            context.response.status = 413;
+            context.error = Some("Request body exceeds the request memory limit".to_string());
+            return context;
+        }
+        self.validate_host(&mut context);
+        self.admit(&mut context);
+        context
     }
 
-    async fn context_from_http_request(&self, req: http::Request<Body>) -> Context {
-        let request = self.request_from_http_request(req).await;
-        Context {
+    fn new_context(&self, mut request: Request) -> Context {
+        let normalised = remove_dot_segments(&request.request_path);
+        if let Some(path) = &normalised {
+            request.request_path = path.clone();
+        }
+        let mut context = Context {
+            trace_context: TraceContext::from_request(&request),
+            user_agent_class: self.classify_user_agent(&request),
             request,
             response: Response::default(),
+            memory: MemoryAccount::new(self.max_request_memory),
             ..Context::default()
+        };
+        if normalised.is_none() {
+            warn!("Request path '{}' escapes the root", context.request.request_path);
+            context.response.status = 400;
+            context.error = Some("Request path escapes the root".to_string());
+        }
+        context
+    }
+
+    /// Applies the bot policy and rate limits of the dispatcher to a request without an error
+    fn admit(&self, context: &mut Context) {
+        let bot_policy = self
+            .bot_policy
+            .as_ref()
+            .filter(|policy| policy.applies_to(context));
+        if let (None, Some(policy)) = (&context.error, bot_policy) {
+            let route = self.longest_matching_path(&context.request);
+            if matches!(route, Some(route) if policy.is_denied(&route)) {
+                warn!("Bot was denied the route of '{}'", context.request.request_path);
+                context.response.status = 403;
+                context.error = Some("Route is not available to bots".to_string());
+            }
+        }
+        if context.error.is_none() {
+            let rate_limiter = match bot_policy {
+                Some(policy) if policy.rate_limiter.is_some() => &policy.rate_limiter,
+                _ => &self.rate_limiter,
+            };
+            if let Some(rate_limiter) = rate_limiter {
+                if !check_rate_limit(rate_limiter, context) {
+                    context.response.status = 429;
+                    context.error = Some("Request rate limit exceeded".to_string());
+                }
+            }
         }
     }
 
+    fn classify_user_agent(
+        &self,
+        request: &Request,
+    ) -> Option<content_negotiation::UserAgentClass> {
+        match (&self.user_agent_classifier, &self.bot_policy) {
+            (Some(classifier), _) => Some(classifier.classify(request)),
+            (None, Some(_)) => {
+                Some(content_negotiation::UserAgentClassifier::default().classify(request))
+            }
+            (None, None) => None,
+        }
+    }
+
+    fn apply_tls_policy(&self, parts: &Parts, context: &mut Context) {
+        let policy = match &self.require_tls {
+            Some(policy) => policy,
+            None => return,
+        };
+        if policy.is_secure(parts) || context.request.scheme() == "https" {
+            if let Some(hsts) = &policy.hsts {
+                context.response.add_header(
+                    "Strict-Transport-Security",
+                    vec![HeaderValue::basic(hsts.header_value())],
+                );
+            }
+            return;
+        }
+        let redirect = match policy.plaintext_action {
+            PlaintextAction::Redirect(status) => self
+                .tls_redirect_host(policy, &context.request)
+                .and_then(|host| policy.redirect_url(parts, Some(&host)))
+                .map(|url| (status, url)),
+            PlaintextAction::Reject(_) => None,
+        };
+        match redirect {
+            Some((status, url)) => {
+                context.response.status = status;
+                context.response.add_header("Location", vec![HeaderValue::basic(url)]);
+                context.error = Some("Plaintext request redirected to https".to_string());
+            }
+            None => {
+                context.response.status = match policy.plaintext_action {
+                    PlaintextAction::Reject(status) => status,
+                    PlaintextAction::Redirect(_) => 403,
+                };
+                if context.response.status == 426 {
+                    context
+                        .response
+                        .add_header("Upgrade", vec![h!("TLS/1.2"), h!("HTTP/1.1")]);
+                    context.response.add_header("Connection", vec![h!("Upgrade")]);
+                }
+                context.error = Some("Request was not made over TLS".to_string());
+            }
+        }
+    }
+
+    /// Returns the host to redirect a plaintext request to, which is the canonical host of the
+    /// policy or the host the request was made to if it is allowed. The Host header is set by
+    /// the client, so it is never used without an allow-list (that would be an open redirect).
+    /// The port of the request is the plaintext port, so it is replaced with the https port.
+    fn tls_redirect_host(&self, policy: &TlsPolicy, request: &Request) -> Option<String> {
+        if let Some(host) = &policy.canonical_host {
+            return Some(host.clone());
+        }
+        let host = request.client_host()?;
+        if self
+            .allowed_hosts
+            .iter()
+            .any(|allowed| host_matches(allowed, &host))
+        {
+            let hostname = strip_port(&host);
+            match policy.https_port {
+                Some(port) => Some(format!("{}:{}", hostname, port)),
+                None => Some(hostname.to_string()),
+            }
+        } else {
+            warn!("Not redirecting a plaintext request to host '{}', it is not allowed", host);
+            None
+        }
+    }
+
+    fn validate_host(&self, context: &mut Context) {
+        if self.allowed_hosts.is_empty() {
+            return;
+        }
+        let hosts = context.request.find_header("Host");
+        if hosts.len() != 1 || hosts[0].value.is_empty() {
+            warn!("Request has a missing or invalid Host header: {:?}", hosts);
+            context.response.status = 400;
+            context.error = Some("Request has a missing or invalid Host header".to_string());
+        } else if !self
+            .allowed_hosts
+            .iter()
+            .any(|allowed| host_matches(allowed, &hosts[0].value))
+        {
+            warn!("Request was made to a host that is not allowed: {}", hosts[0].value);
+            context.response.status = 421;
+            context.error = Some(format!("Host '{}' is not allowed", hosts[0].value));
+        }
+    }
+
+    /// Returns the routes that match the request path, excluding the resources that are not
+    /// served on the listener that accepted the request
     pub(crate) fn match_paths(&self, request: &Request) -> Vec<String> {
         let request_path = sanitise_path(&request.request_path);
+        let listener = request.listener.as_deref();
+        let deployed = self
+            .deployments
+            .iter()
+            .filter(|(_, deployment)| deployment.active().is_served_on(listener))
+            .map(|(k, _)| *k);
         self.routes
-            .keys()
-            .filter(|k| request_path.starts_with(&sanitise_path(k)))
+            .iter()
+            .filter(|(_, resource)| resource.is_served_on(listener))
+            .map(|(k, _)| *k)
+            .chain(deployed)
+            .filter(|k| request_path.starts_with(&sanitise_path(&decode_path(k))))
             .map(|k| k.to_string())
             .collect()
     }
 
+    pub(crate) fn longest_matching_path(&self, request: &Request) -> Option<String> {
+        self.match_paths(request)
+            .into_iter()
+            .min_by_key(|path| Reverse(path.len()))
+    }
+
+    /// Returns the maximum entity length of the resource the request will be dispatched to,
+    /// falling back to the maximum of the dispatcher
+    fn max_entity_length_for(&self, request: &Request) -> Option<u64> {
+        self.longest_matching_path(request)
+            .and_then(|path| match self.deployments.get(path.as_str()) {
+                Some(deployment) => deployment.active().max_entity_length,
+                None => self
+                    .lookup_resource(&path)
+                    .and_then(|resource| resource.max_entity_length),
+            })
+            .or(self.max_entity_length)
+    }
+
     pub(crate) fn lookup_resource(&self, path: &str) -> Option<&Resource<'a>> {
         self.routes.get(path)
     }
 
+    /// Logs any problems with the route table (see `diagnostics`), and then calls the `on_start`
+    /// hooks of all the resources, concurrently, returning when they have all completed. The
+    /// warm-up tasks of the readiness gate are then run, and the server is registered for service
+    /// discovery, if configured. The serve helpers of the `server` module call this before
+    /// accepting any connections, or while accepting them if the dispatcher has a readiness gate.
+    pub async fn start(&self) {
+        for diagnostic in self.diagnostics() {
+            warn!("{}", diagnostic);
+        }
+        future::join_all(
+            self.resources()
+                .iter()
+                .filter_map(|(_, resource)| resource.on_start.as_ref().map(|on_start| on_start())),
+        )
+        .await;
+        if let Some(readiness) = &self.readiness {
+            readiness.warm_up().await;
+        }
+        if let Some(discovery) = &self.service_discovery {
+            discovery
+                .register(
+                    &self
+                        .routes
+                        .keys()
+                        .chain(self.deployments.keys())
+                        .cloned()
+                        .collect::<Vec<&str>>(),
+                )
+                .await;
+        }
+    }
+
+    /// Marks the dispatcher as not ready and deregisters the server for service discovery, if
+    /// configured, and then calls the `on_stop` hooks of all the resources, concurrently,
+    /// returning when they have all completed. The serve helpers of the `server` module call
+    /// this once all their listeners have stopped accepting connections.
+    pub async fn stop(&self) {
+        if let Some(readiness) = &self.readiness {
+            readiness.set_ready(false);
+        }
+        if let Some(discovery) = &self.service_discovery {
+            discovery.deregister().await;
+        }
+        future::join_all(
+            self.resources()
+                .iter()
+                .filter_map(|(_, resource)| resource.on_stop.as_ref().map(|on_stop| on_stop())),
+        )
+        .await;
+    }
+
+    /// Checks the route table for problems: routes that are shadowed by another route, allowed
+    /// methods that are not handled, and media types with invalid syntax. The resources of the
+    /// deployments are checked as they are currently deployed.
+    pub fn diagnostics(&self) -> Vec<RouteDiagnostic> {
+        diagnostics::diagnose(&self.resources())
+    }
+
+    /// Generates the OpenAPI document of the routes, from the declarations of their resources.
+    /// The resources of the deployments are described as they are currently deployed.
+    pub fn openapi(&self, info: &OpenApiInfo) -> serde_json::Value {
+        let resources = self.resources();
+        let routes: Vec<(&str, &Resource)> = resources
+            .iter()
+            .map(|(route, resource)| (*route, resource.as_ref()))
+            .collect();
+        openapi::openapi_document(info, &routes)
+    }
+
+    /// Returns the routes with their resources, followed by the deployments with their active
+    /// resources
+    fn resources(&self) -> Vec<(&'a str, Cow<'_, Resource<'a>>)> {
+        self.routes
+            .iter()
+            .map(|(route, resource)| (*route, Cow::Borrowed(resource)))
+            .chain(self.deployments.iter().map(|(route, deployment)| {
+                (*route, Cow::Owned(deployment.active().as_ref().clone()))
+            }))
+            .collect()
+    }
+
     /// Dispatches to the matching webmachine resource. If there is no matching resource, returns
     /// 404 Not Found response
     pub async fn dispatch_to_resource(&self, context: &mut Context) {
-        let matching_paths = self.match_paths(&context.request);
-        let ordered_by_length: Vec<String> = matching_paths
-            .iter()
-            .cloned()
-            .sorted_by(|a, b| Ord::cmp(&b.len(), &a.len()))
-            .collect();
-        match ordered_by_length.first() {
+        match self.longest_matching_path(&context.request) {
             Some(path) => {
-                update_paths_for_resource(&mut context.request, path);
-                if let Some(resource) = self.lookup_resource(path) {
-                    execute_state_machine(context, &resource).await;
-                    finalise_response(context, &resource).await;
+                update_paths_for_resource(&mut context.request, &path);
+                let deployed = self
+                    .deployments
+                    .get(path.as_str())
+                    .map(|deployment| (deployment, deployment.acquire()));
+                let resource = match &deployed {
+                    Some((_, deployed)) => Some(deployed.resource.as_ref()),
+                    None => self.lookup_resource(&path),
+                };
+                if let Some(resource) = resource {
+                    let resource = self.apply_resource_defaults(resource);
+                    if let Some(docs) = &self.docs {
+                        if docs.respond(context, &resource, &path) {
+                            return;
+                        }
+                    }
+                    let methods = &resource.known_methods;
+                    let method = &context.request.method;
+                    let coalescable = methods.is_idempotent(method) && !methods.is_safe(method);
+                    let coalesced = match &resource.coalescer {
+                        Some(coalescer) if coalescable => {
+                            Some(coalescer.join(coalescer.key(&context.request)))
+                        }
+                        _ => None,
+                    };
+                    let leader = match coalesced {
+                        Some(Coalesced::Follower(response)) => match response.await {
+                            Ok(response) => {
+                                debug!("Request coalesced with an identical request");
+                                context.response = response;
+                                return;
+                            }
+                            // the request executing the resource was dropped, or streamed its body
+                            Err(_) => None,
+                        },
+                        Some(Coalesced::Leader(leader)) => Some(leader),
+                        None => None,
+                    };
+                    self.execute_resource(context, &resource, &path).await;
+                    if let Some((deployment, deployed)) = &deployed {
+                        deployment.record(deployed, context.response.status);
+                    }
+                    // a streamed body can not be shared, so the leader is dropped instead, and
+                    // the requests that followed it execute the resource themselves
+                    if let Some(leader) = leader.filter(|_| context.response.stream.is_none()) {
+                        let _ = leader.send(context.response.clone());
+                    }
                 } else {
                     context.response.status = 404;
+                    self.add_cors_headers(context);
                 }
             }
-            None => context.response.status = 404,
+            None => {
+                context.response.status = 404;
+                self.add_cors_headers(context);
+            }
+        };
+    }
+
+    async fn execute_resource(&self, context: &mut Context, resource: &Resource<'a>, path: &str) {
+        if let Some(faults) = &resource.fault_injector {
+            if faults.before_resource(context).await {
+                self.add_cors_headers(context);
+                return;
+            }
+        }
+        if let Some(availability) = &resource.availability {
+            if availability.check(context) {
+                self.add_cors_headers(context);
+                return;
+            }
+        }
+        if !bind_path_params(context, resource) {
+            self.add_cors_headers(context);
+            return;
+        }
+        let execution = AssertUnwindSafe(async {
+            let mut machine = StateMachine::new(context, resource);
+            if let Some(max) = self.max_state_machine_transitions {
+                machine = machine.with_max_transitions(max);
+            }
+            machine.run_to_completion().await;
+            finalise_response(context, resource).await;
+        })
+        .catch_unwind();
+        let result = match &resource.request_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout.duration, execution).await {
+                Ok(result) => result,
+                Err(_) => {
+                    timeout.timed_out(context);
+                    self.add_cors_headers(context);
+                    Ok(())
+                }
+            },
+            None => execution.await,
         };
+        if let Err(panic) = result {
+            let message = panic_message(panic.as_ref());
+            error!("Resource for path '{}' panicked: {}", path, message);
+            context.response = Response {
+                status: 500,
+                ..Response::default()
+            };
+            context.error = Some(format!("Resource panicked: {}", message));
+            self.add_cors_headers(context);
+        }
+        if let Some(faults) = &resource.fault_injector {
+            faults.after_resource(context);
+        }
+    }
+
+    /// Adds the CORS headers of the dispatcher to responses that are not generated by a resource,
+    /// so browsers can read the errors
+    fn add_cors_headers(&self, context: &mut Context) {
+        if let Some(cors) = &self.cors {
+            add_cors_headers(context, cors, &[]);
+        }
     }
 
-    fn generate_http_response(&self, context: &Context) -> http::Result<http::Response<Body>> {
+    /// Applies the CORS configuration, authorizer, maximum entity length, decision logging,
+    /// redaction, request timeout, caching profile and compression configuration of the
+    /// dispatcher to a resource that does not have its own
+    fn apply_resource_defaults<'r>(&self, resource: &'r Resource<'a>) -> Cow<'r, Resource<'a>> {
+        let cors = resource.cors.is_none() && self.cors.is_some();
+        let authorizer = resource.authorizer.is_none() && self.authorizer.is_some();
+        let max_entity_length =
+            resource.max_entity_length.is_none() && self.max_entity_length.is_some();
+        let decision_log = resource.decision_log.is_none() && self.decision_log.is_some();
+        let redaction = resource.redaction.is_none() && self.redaction.is_some();
+        let request_timeout =
+            resource.request_timeout.is_none() && self.request_timeout.is_some();
+        let caching = resource.caching.is_none() && self.caching.is_some();
+        let compression = resource.compression.is_none() && self.compression.is_some();
+        if cors
+            || authorizer
+            || max_entity_length
+            || decision_log
+            || redaction
+            || request_timeout
+            || caching
+            || compression
+        {
+            let mut resource = resource.clone();
+            if cors {
+                resource.cors = self.cors.clone();
+            }
+            if authorizer {
+                resource.authorizer = self.authorizer.clone();
+            }
+            if max_entity_length {
+                resource.max_entity_length = self.max_entity_length;
+            }
+            if decision_log {
+                resource.decision_log = self.decision_log.clone();
+            }
+            if redaction {
+                resource.redaction = self.redaction.clone();
+            }
+            if request_timeout {
+                resource.request_timeout = self.request_timeout.clone();
+            }
+            if caching {
+                resource.caching = self.caching.clone();
+            }
+            if compression {
+                resource.compression = self.compression.clone();
+            }
+            Cow::Owned(resource)
+        } else {
+            Cow::Borrowed(resource)
+        }
+    }
+
+    fn generate_http_response(
+        &self,
+        context: &Context,
+        route: Option<&str>,
+    ) -> http::Result<http::Response<Body>> {
         let mut response = http::Response::builder().status(context.response.status);
     
         for (header, values) in context.response.headers.clone() {
@@ -75,50 +780,360 @@ impl<'a> Dispatcher<'a> {
     
         match context.response.body.clone() {
             Some(body) => response.body(body.into()),
-            None => response.body(Body::empty()),
+            None => match context.response.stream.as_ref() {
+                Some(stream) => match stream.take_chunks() {
+                    Some(chunks) => response.body(stream_body(
+                        chunks,
+                        &stream.trailer_digests,
+                        stream.write_timeout.or(self.stream_write_timeout),
+                        context.client_disconnect.clone(),
+                        self.metrics.clone().map(|metrics| {
+                            let route = route.map(str::to_string);
+                            let method = context.request.method.clone();
+                            Box::new(move |stats: &StreamStats| {
+                                metrics.record_stream(route.as_deref(), &method, stats)
+                            }) as StreamRecorder
+                        }),
+                    )),
+                    None => response.body(Body::empty()),
+                },
+                None => response.body(Body::empty()),
+            },
         }
     }
 
-    async fn request_from_http_request(&self, req: http::Request<Body>) -> Request {
-        let (parts, body) = req.into_parts();
-        let request_path = parts.uri.path().to_string();
-    
-        let req_body = body
-            .try_fold(Vec::new(), |mut data, chunk| async move {
-                data.extend_from_slice(&chunk);
-                Ok(data)
-            })
-            .await;
-        let body = match req_body {
-            Ok(body) => {
-                if body.is_empty() {
-                    None
-                } else {
-                    Some(body.clone())
-                }
-            }
-            Err(err) => {
-                error!("Failed to read the request body: {}", err);
-                None
-            }
-        };
-    
+    pub(crate) fn request_from_http_parts(&self, parts: &Parts) -> Request {
+        let raw_path = parts.uri.path().to_string();
+        let request_path = decode_path(&raw_path);
+
         let query = match parts.uri.query() {
             Some(query) => parse_query(query),
             None => HashMap::new(),
         };
+        // absolute-form request targets replace any Host header, as per RFC 9112 section 3.2.2
+        let mut headers = headers_from_http_request(parts);
+        if let Some(authority) = parts.uri.authority() {
+            let host = match authority.port() {
+                Some(port) => format!("{}:{}", authority.host(), port),
+                None => authority.host().to_string(),
+            };
+            headers.retain(|name, _| name.to_lowercase() != "host");
+            headers.insert("host".to_string(), vec![HeaderValue::basic(host)]);
+        }
+        let connection = parts.extensions.get::<server::ConnectionInfo>();
         Request {
-            request_path: request_path.clone(),
+            request_path,
             base_path: "/".to_string(),
+            raw_path: Some(raw_path),
             method: parts.method.as_str().into(),
-            headers: headers_from_http_request(&parts),
-            body,
+            headers,
+            body: None,
             query,
+            remote_addr: connection.and_then(|info| info.remote_addr),
+            listener: connection.map(|info| info.listener.clone()),
+            http_version: format!("{:?}", parts.version),
+            local_addr: connection.and_then(|info| info.local_addr),
+            secure_connection: connection.map(|info| info.secure).unwrap_or_default(),
+            client_certificate: connection.and_then(|info| info.client_certificate.clone()),
+            forwarded: self.forwarded.as_ref().and_then(|config| {
+                config.forwarded_info(&parts.headers, connection.and_then(|info| info.remote_addr))
+            }),
+        }
+    }
+}
+
+fn host_matches(allowed: &str, host: &str) -> bool {
+    let host = host.to_lowercase();
+    let hostname = strip_port(&host);
+    let allowed = allowed.to_lowercase();
+    match allowed.strip_prefix('*') {
+        Some(suffix) => hostname.ends_with(suffix) && hostname.len() > suffix.len(),
+        None => allowed == hostname || allowed == host,
+    }
+}
+
+/// Returns the host without its port, taking care with IPv6 literals (i.e. `[::1]:8080`)
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') => &host[..index],
+        _ => host,
+    }
+}
+
+/// Removes the trace media type from the Accept header of the request, so the representation is
+/// negotiated as normal. Returns true if the request accepted the trace media type.
+fn take_trace_media_type(request: &mut Request) -> bool {
+    let header = match request
+        .headers
+        .keys()
+        .find(|header| header.eq_ignore_ascii_case("Accept"))
+        .cloned()
+    {
+        Some(header) => header,
+        None => return false,
+    };
+    let values = request.headers.remove(&header).unwrap_or_default();
+    let (trace, values): (Vec<HeaderValue>, Vec<HeaderValue>) = values
+        .into_iter()
+        .partition(|value| value.value.eq_ignore_ascii_case(TRACE_MEDIA_TYPE));
+    if !values.is_empty() {
+        request.headers.insert(header, values);
+    }
+    !trace.is_empty()
+}
+
+/// Adds the decision trace of the request to the response, as a header or (if the request
+/// accepted the trace media type) as the body
+fn add_decision_trace(context: &mut Context, trace_body: bool) {
+    if trace_body {
+        let decisions = context
+            .decision_trace
+            .iter()
+            .map(|step| {
+                serde_json::json!({
+                    "decision": step.decision.code(),
+                    "name": step.decision.name(),
+                    "outcome": step.outcome,
+                    "next": step.next.map(|next| next.code()),
+                    "reason": step.reason,
+                    "duration_us": step.duration.as_micros() as u64
+                })
+            })
+            .collect::<Vec<serde_json::Value>>();
+        let trace = serde_json::json!({
+            "status": context.response.status,
+            "decisions": decisions
+        });
+        context.response.stream = None;
+        context.response.body = Some(trace.to_string().into_bytes());
+        context.response.remove_header("Content-Type");
+        context
+            .response
+            .add_header("Content-Type", vec![HeaderValue::basic(TRACE_MEDIA_TYPE)]);
+    } else if !context.decision_trace.is_empty() {
+        let trace = context
+            .decision_trace
+            .iter()
+            .map(|step| format!("{}={}", step.decision, step.outcome))
+            .collect::<Vec<String>>()
+            .join(", ");
+        context
+            .response
+            .add_header("X-Webmachine-Trace", vec![HeaderValue::basic(trace)]);
+    }
+}
+
+/// Callback that records how a streamed response body was sent to the client
+type StreamRecorder = Box<dyn FnOnce(&StreamStats) + Send>;
+
+/// Maximum number of chunks of a streamed response body that are read ahead of the client
+const SEND_QUEUE_CAPACITY: usize = 8;
+
+/// Sends the chunks through a body channel from a separate task, computing the digests of the
+/// body as it goes and sending them as a `Content-Digest` trailer once the body is complete.
+/// Chunks are read ahead of the client into a send queue, and the body is aborted if the client
+/// does not accept a chunk within the write timeout. Failing to send a chunk signals that the
+/// client has gone away.
+fn stream_body(
+    chunks: BodyChunks,
+    trailer_digests: &[DigestAlgorithm],
+    write_timeout: Option<Duration>,
+    disconnect: ClientDisconnect,
+    recorder: Option<StreamRecorder>,
+) -> Body {
+    let (mut sender, body) = Body::channel();
+    let mut hashers = trailer_digests
+        .iter()
+        .map(DigestAlgorithm::hasher)
+        .collect::<Vec<DigestHasher>>();
+    tokio::spawn(async move {
+        let mut stats = StreamStats::default();
+        let outcome = send_chunks(
+            chunks,
+            &mut sender,
+            &mut hashers,
+            write_timeout,
+            &disconnect,
+            &mut stats,
+        )
+        .await;
+        if outcome == SendOutcome::Aborted {
+            sender.abort();
+        } else if outcome == SendOutcome::Complete && !hashers.is_empty() {
+            let digest = hashers
+                .into_iter()
+                .map(DigestHasher::field_member)
+                .collect::<Vec<String>>()
+                .join(", ");
+            let mut trailers = http::HeaderMap::new();
+            if let Ok(value) = http::HeaderValue::from_str(&digest) {
+                trailers.insert("content-digest", value);
+            }
+            if sender.send_trailers(trailers).await.is_err() {
+                debug!("Client went away before the response trailers were sent");
+            }
+        }
+        if let Some(recorder) = recorder {
+            recorder(&stats);
+        }
+    });
+    body
+}
+
+/// How sending the chunks of a streamed response body ended
+#[derive(Debug, PartialEq)]
+enum SendOutcome {
+    /// All the chunks were sent
+    Complete,
+    /// The stream of chunks failed or the write timeout passed, so the body is to be aborted
+    Aborted,
+    /// The client went away
+    Disconnected,
+}
+
+/// Sends the chunks to the client. The time each chunk waits at the head of the send queue for
+/// the client counts towards the stall of the body.
+async fn send_chunks(
+    mut chunks: BodyChunks,
+    sender: &mut hyper::body::Sender,
+    hashers: &mut [DigestHasher],
+    write_timeout: Option<Duration>,
+    disconnect: &ClientDisconnect,
+    stats: &mut StreamStats,
+) -> SendOutcome {
+    let mut queue = VecDeque::with_capacity(SEND_QUEUE_CAPACITY);
+    let mut waiting_since = tokio::time::Instant::now();
+    let mut finished = false;
+    let mut failed = false;
+    while !(finished && queue.is_empty()) {
+        let deadline = waiting_since + write_timeout.unwrap_or_default();
+        let timeout_armed = write_timeout.is_some() && !queue.is_empty();
+        tokio::select! {
+            chunk = chunks.next(), if !finished && queue.len() < SEND_QUEUE_CAPACITY => {
+                match chunk {
+                    Some(Ok(chunk)) => {
+                        for hasher in hashers.iter_mut() {
+                            hasher.update(&chunk);
+                        }
+                        if queue.is_empty() {
+                            waiting_since = tokio::time::Instant::now();
+                        }
+                        queue.push_back(chunk);
+                        stats.max_queue_depth = stats.max_queue_depth.max(queue.len());
+                    }
+                    // the chunks before the error are still sent
+                    Some(Err(err)) => {
+                        error!("Failed to stream the response body: {}", err);
+                        failed = true;
+                        finished = true;
+                    }
+                    None => finished = true,
+                }
+            }
+            ready = future::poll_fn(|cx| sender.poll_ready(cx)), if !queue.is_empty() => {
+                stats.stall += waiting_since.elapsed();
+                waiting_since = tokio::time::Instant::now();
+                let sent = match (ready, queue.pop_front()) {
+                    (Ok(()), Some(chunk)) => sender.try_send_data(chunk.into()).is_ok(),
+                    _ => false,
+                };
+                if !sent {
+                    debug!("Client went away while the response body was streamed");
+                    disconnect.signal();
+                    return SendOutcome::Disconnected;
+                }
+            }
+            _ = tokio::time::sleep_until(deadline), if timeout_armed => {
+                warn!(
+                    "Client did not accept a chunk of the response body within {:?}, aborting it",
+                    write_timeout.unwrap_or_default()
+                );
+                stats.stall += waiting_since.elapsed();
+                stats.timed_out = true;
+                return SendOutcome::Aborted;
+            }
+        }
+    }
+    if failed {
+        SendOutcome::Aborted
+    } else {
+        SendOutcome::Complete
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown cause".to_string()
+    }
+}
+
+enum BodyReadError {
+    Read(hyper::Error),
+    MemoryLimitExceeded,
+    EntityTooLarge,
+}
+
+async fn read_body(
+    mut body: Body,
+    memory: &mut MemoryAccount,
+    max_length: Option<u64>,
+) -> Result<Option<Vec<u8>>, BodyReadError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.try_next().await.map_err(BodyReadError::Read)? {
+        if let Some(max_length) = max_length {
+            if (data.len() + chunk.len()) as u64 > max_length {
+                return Err(BodyReadError::EntityTooLarge);
+            }
+        }
+        if !memory.allocate(chunk.len()) {
+            return Err(BodyReadError::MemoryLimitExceeded);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if data.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(data))
+    }
+}
+
+/// Converts a request body into a hyper body. Bodies of other types are streamed into a hyper
+/// body, so the limits on the request size still apply.
+pub(crate) fn into_hyper_body<B>(body: B) -> Body
+where
+    B: hyper::body::HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let body: Box<dyn Any> = Box::new(body);
+    match body.downcast::<Body>() {
+        Ok(body) => *body,
+        Err(body) => {
+            let body = Box::pin(*body.downcast::<B>().expect("the body has the type B"));
+            Body::wrap_stream(futures::stream::unfold(body, |mut body| async move {
+                use hyper::body::{Buf, HttpBody};
+                let chunk = body.data().await?;
+                let chunk = chunk
+                    .map(|mut data| data.copy_to_bytes(data.remaining()))
+                    .map_err(|err| err.into());
+                Some((chunk, body))
+            }))
         }
     }
 }
 
-impl Service<http::Request<Body>> for Dispatcher<'static> {
+/// The dispatcher is a `tower::Service`, so it can be wrapped by tower layers (i.e. timeouts,
+/// load shedding, tracing or compression). It accepts requests with any body type.
+impl<B> Service<http::Request<B>> for Dispatcher<'static>
+where
+    B: hyper::body::HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
     type Response = http::Response<Body>;
     type Error = http::Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
@@ -127,7 +1142,7 @@ impl Service<http::Request<Body>> for Dispatcher<'static> {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
-        Box::pin(self.clone().dispatch(req))
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        Box::pin(self.clone().dispatch(req.map(into_hyper_body)))
     }
 }
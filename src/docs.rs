@@ -0,0 +1,269 @@
+//! The `docs` module renders lightweight documentation pages of resources, for browsing an API
+//! without running a separate docs site. A page describes the route from the declaration of its
+//! resource: the description, the allowed methods, the media types it accepts and produces, and
+//! what requests need to be authenticated and authorized. Pages are served for OPTIONS requests
+//! that prefer HTML (as opposed to CORS preflights), and for GET requests to the route with a
+//! docs suffix (i.e. `/orders/_docs`).
+
+use itertools::Itertools;
+
+use crate::{
+    content_negotiation::{sort_media_types, MediaType, MediaTypeMatch},
+    context::{Context, Request},
+    error_renderer::escape_html,
+    headers::HeaderValue,
+    Resource,
+};
+
+/// Configuration of the documentation pages of the routes of a dispatcher
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocsConfig {
+    /// Path, relative to a route, that GET requests get the page of the route for. Defaults to
+    /// `/_docs`. If None, pages are only served for OPTIONS requests.
+    pub suffix: Option<String>,
+    /// If OPTIONS requests that prefer `text/html` get the page of the route, instead of the
+    /// response of the resource. CORS preflight requests never do. Defaults to true.
+    pub options_pages: bool,
+}
+
+impl Default for DocsConfig {
+    fn default() -> DocsConfig {
+        DocsConfig {
+            suffix: Some("/_docs".to_string()),
+            options_pages: true,
+        }
+    }
+}
+
+impl DocsConfig {
+    /// If the request is for the documentation page of the route it matched. The path of the
+    /// request must already be relative to the route.
+    pub fn is_docs_request(&self, request: &Request) -> bool {
+        if request.is_options() {
+            self.options_pages
+                && !request.has_header("Access-Control-Request-Method")
+                && prefers_html(request)
+        } else {
+            request.is_get()
+                && self
+                    .suffix
+                    .as_deref()
+                    .map(|suffix| suffix.trim_end_matches('/'))
+                    == Some(request.request_path.trim_end_matches('/'))
+        }
+    }
+
+    /// Responds with the documentation page of the resource if the request is for it. Returns
+    /// true if it did.
+    pub(crate) fn respond(&self, context: &mut Context, resource: &Resource, route: &str) -> bool {
+        if !self.is_docs_request(&context.request) {
+            return false;
+        }
+        debug!("Rendering the documentation page of route '{}'", route);
+        context.response.status = 200;
+        context.response.add_header(
+            "Allow",
+            resource
+                .allowed_methods
+                .iter()
+                .cloned()
+                .map(HeaderValue::basic)
+                .collect(),
+        );
+        context.response.add_header(
+            "Content-Type",
+            vec![HeaderValue {
+                value: "text/html".to_string(),
+                params: hashmap! { "charset".to_string() => "UTF-8".to_string() },
+                quote: false,
+            }],
+        );
+        context.response.body = Some(docs_page(route, resource).into_bytes());
+        true
+    }
+}
+
+/// If `text/html` is the most preferred media type of the Accept header of the request, ignoring
+/// wildcards
+fn prefers_html(request: &Request) -> bool {
+    let html = MediaType::parse_string("text/html");
+    sort_media_types(&request.accept())
+        .iter()
+        .map(HeaderValue::as_media_type)
+        .find(|media_type| media_type.weight > 0.0 && media_type.main != "*")
+        .map(|media_type| html.matches(&media_type) != MediaTypeMatch::None)
+        .unwrap_or(false)
+}
+
+/// Renders the documentation page of the resource at the route
+pub fn docs_page(route: &str, resource: &Resource) -> String {
+    let mut page = format!(
+        "<!DOCTYPE html><html><head><title>{0}</title></head><body><h1>{0}</h1>",
+        escape_html(route)
+    );
+    if let Some(description) = resource.description {
+        page.push_str(&format!("<p>{}</p>", escape_html(description)));
+    }
+    page.push_str(&section("Methods", &resource.allowed_methods));
+    let produces = resource
+        .produces
+        .iter()
+        .cloned()
+        .chain(resource.variants.iter().map(|variant| variant.media_type))
+        .unique()
+        .collect::<Vec<&str>>();
+    page.push_str(&section("Produces", &produces));
+    if resource
+        .allowed_methods
+        .iter()
+        .any(|method| matches!(method.to_uppercase().as_str(), "POST" | "PUT" | "PATCH"))
+    {
+        let mut accepts = resource
+            .acceptable_content_types
+            .iter()
+            .map(|media_type| media_type.to_string())
+            .collect::<Vec<String>>();
+        for (method, media_types) in resource.method_acceptable_content_types.iter().sorted() {
+            accepts.extend(
+                media_types
+                    .iter()
+                    .map(|media_type| format!("{} ({})", media_type, method)),
+            );
+        }
+        page.push_str(&section("Accepts", &accepts));
+    }
+    if !resource.languages_provided.is_empty() {
+        page.push_str(&section("Languages", &resource.languages_provided));
+    }
+    let mut auth = resource
+        .authenticators
+        .iter()
+        .map(|authenticator| format!("Authentication: {}", authenticator.challenge()))
+        .collect::<Vec<String>>();
+    auth.extend(
+        resource
+            .required_permissions
+            .iter()
+            .map(|permission| format!("Permission: {}", permission)),
+    );
+    auth.extend(resource.required_headers.iter().map(|requirement| {
+        if requirement.methods.is_empty() {
+            format!("Header: {}", requirement.name)
+        } else {
+            format!(
+                "Header: {} ({})",
+                requirement.name,
+                requirement.methods.join(", ")
+            )
+        }
+    }));
+    if !auth.is_empty() {
+        page.push_str(&section("Requirements", &auth));
+    }
+    page.push_str("</body></html>");
+    page
+}
+
+fn section<S: AsRef<str>>(title: &str, items: &[S]) -> String {
+    format!(
+        "<h2>{}</h2><ul>{}</ul>",
+        title,
+        items
+            .iter()
+            .map(|item| format!("<li>{}</li>", escape_html(item.as_ref())))
+            .collect::<String>()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dispatcher, HeaderRequirement};
+    use expectest::prelude::*;
+
+    fn request(method: &str, uri: &str, accept: &str) -> http::Request<hyper::Body> {
+        http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Accept", accept)
+            .body(hyper::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn docs_page_describes_the_resource() {
+        let resource = Resource {
+            description: Some("Orders of the <current> user"),
+            allowed_methods: vec!["GET", "POST"],
+            produces: vec!["application/json", "text/csv"],
+            method_acceptable_content_types: hashmap! {
+                "POST" => vec!["application/x-www-form-urlencoded"]
+            },
+            required_permissions: vec!["orders:read"],
+            required_headers: vec![
+                HeaderRequirement::new("Idempotency-Key").for_methods(&["POST"])
+            ],
+            ..Resource::default()
+        };
+        expect!(docs_page("/orders", &resource)).to(be_equal_to(
+            "<!DOCTYPE html><html><head><title>/orders</title></head><body><h1>/orders</h1>\
+             <p>Orders of the &lt;current&gt; user</p>\
+             <h2>Methods</h2><ul><li>GET</li><li>POST</li></ul>\
+             <h2>Produces</h2><ul><li>application/json</li><li>text/csv</li></ul>\
+             <h2>Accepts</h2><ul><li>application/json</li>\
+             <li>application/x-www-form-urlencoded (POST)</li></ul>\
+             <h2>Requirements</h2><ul><li>Permission: orders:read</li>\
+             <li>Header: Idempotency-Key (POST)</li></ul></body></html>",
+        ));
+    }
+
+    #[tokio::test]
+    async fn browsers_get_docs_pages_for_options_and_the_docs_suffix() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/orders" => Resource {
+                    description: Some("Orders"),
+                    ..Resource::default()
+                }
+            },
+            docs: Some(DocsConfig::default()),
+            ..Dispatcher::default()
+        };
+        let html = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+        let response = dispatcher
+            .clone()
+            .dispatch(request("OPTIONS", "/orders", html))
+            .await
+            .unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        expect!(response.headers()["Content-Type"].to_str().unwrap())
+            .to(be_equal_to("text/html; charset=UTF-8"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        expect!(String::from_utf8_lossy(&body).contains("<p>Orders</p>")).to(be_true());
+
+        let response = dispatcher
+            .clone()
+            .dispatch(request("GET", "/orders/_docs", "*/*"))
+            .await
+            .unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+
+        let response = dispatcher
+            .clone()
+            .dispatch(request("OPTIONS", "/orders", "application/json"))
+            .await
+            .unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(204));
+
+        let preflight = http::Request::builder()
+            .method("OPTIONS")
+            .uri("/orders")
+            .header("Accept", html)
+            .header("Access-Control-Request-Method", "GET")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = dispatcher.dispatch(preflight).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(204));
+    }
+}
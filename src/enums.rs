@@ -1,4 +1,6 @@
-pub(crate) const MAX_STATE_MACHINE_TRANSITIONS: u8 = 100;
+use std::{fmt, str::FromStr};
+
+pub(crate) const MAX_STATE_MACHINE_TRANSITIONS: usize = 100;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) enum Decision {
@@ -16,6 +18,10 @@ pub(crate) enum Decision {
     B11UriTooLong,
     B12KnownMethod,
     B13Available,
+    B13aMisdirectedRequest,
+    B13bUpgradeRequired,
+    B13cRateLimited,
+    C2VariantSelected,
     C3AcceptExists,
     C4AcceptableMediaTypeAvailable,
     D4AcceptLanguageExists,
@@ -42,6 +48,7 @@ pub(crate) enum Decision {
     K13ETagInIfNoneMatch,
     L5HasMovedTemporarily,
     L7Post,
+    L7aOtherMethod,
     L13IfModifiedSinceExists,
     L14IfModifiedSinceValid,
     L15IfModifiedSinceGreaterThanNow,
@@ -55,6 +62,7 @@ pub(crate) enum Decision {
     N16Post,
     O14Conflict,
     O16Put,
+    O16aOtherMethod,
     O18MultipleRepresentations,
     O20ResponseHasBody,
     P3Conflict,
@@ -71,15 +79,158 @@ impl Decision {
     }
 }
 
+/// Declares the public identifiers of the decisions, with their code on the classic webmachine
+/// diagram and a description, and maps the internal decisions to them
+macro_rules! decision_ids {
+    ($($variant:ident => $code:literal, $doc:literal;)*) => {
+        /// Public identifier of a decision of the state machine, for use in traces, logging
+        /// configuration and metrics labels. Displays as the code of the decision on the
+        /// classic webmachine diagram (i.e. `B13`).
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum DecisionId {
+            $(
+                #[doc = $doc]
+                $variant,
+            )*
+        }
+
+        impl DecisionId {
+            /// All the decisions, in diagram order
+            pub const ALL: &'static [DecisionId] = &[$(DecisionId::$variant),*];
+
+            /// Returns the code of the decision on the classic webmachine diagram (i.e. `B13`)
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $(DecisionId::$variant => $code,)*
+                }
+            }
+
+            /// Returns the name of the decision (i.e. `B13Available`)
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(DecisionId::$variant => stringify!($variant),)*
+                }
+            }
+
+            /// Returns the description of the decision, as the question it answers (i.e.
+            /// `B13: Is the service available?`)
+            pub fn description(&self) -> &'static str {
+                match self {
+                    $(DecisionId::$variant => $doc,)*
+                }
+            }
+        }
+
+        impl Decision {
+            /// Returns the public identifier of the decision. The start and end states are not
+            /// decisions, so have no identifier.
+            pub(crate) fn id(&self) -> Option<DecisionId> {
+                match self {
+                    $(Decision::$variant => Some(DecisionId::$variant),)*
+                    Decision::Start | Decision::End(_) => None,
+                }
+            }
+        }
+    };
+}
+
+decision_ids! {
+    A3Options => "A3", "A3: Is the request an OPTIONS request that can be answered directly?";
+    B3Options => "B3", "B3: Is the request an OPTIONS request?";
+    B4RequestEntityTooLarge => "B4", "B4: Is the request entity too large?";
+    B5UnknownContentType => "B5", "B5: Is the content type of the request unknown?";
+    B6UnsupportedContentHeader => "B6", "B6: Are any of the Content-* headers unsupported?";
+    B7Forbidden => "B7", "B7: Is access to the resource forbidden?";
+    B8Authorized => "B8", "B8: Is the request authorized?";
+    B9MalformedRequest => "B9", "B9: Is the request malformed?";
+    B10MethodAllowed => "B10", "B10: Is the method allowed on the resource?";
+    B11UriTooLong => "B11", "B11: Is the URI too long?";
+    B12KnownMethod => "B12", "B12: Is the method known?";
+    B13Available => "B13", "B13: Is the service available?";
+    B13aMisdirectedRequest => "B13a", "B13a: Was the request misdirected?";
+    B13bUpgradeRequired => "B13b", "B13b: Is a protocol upgrade required?";
+    B13cRateLimited => "B13c", "B13c: Has the request exceeded the rate limit?";
+    C2VariantSelected => "C2", "C2: Was a declared variant selected?";
+    C3AcceptExists => "C3", "C3: Does the Accept header exist?";
+    C4AcceptableMediaTypeAvailable => "C4", "C4: Is an acceptable media type available?";
+    D4AcceptLanguageExists => "D4", "D4: Does the Accept-Language header exist?";
+    D5AcceptableLanguageAvailable => "D5", "D5: Is an acceptable language available?";
+    E5AcceptCharsetExists => "E5", "E5: Does the Accept-Charset header exist?";
+    E6AcceptableCharsetAvailable => "E6", "E6: Is an acceptable charset available?";
+    F6AcceptEncodingExists => "F6", "F6: Does the Accept-Encoding header exist?";
+    F7AcceptableEncodingAvailable => "F7", "F7: Is an acceptable encoding available?";
+    G7ResourceExists => "G7", "G7: Does the resource exist?";
+    G8IfMatchExists => "G8", "G8: Does the If-Match header exist?";
+    G9IfMatchStarExists => "G9", "G9: Is the If-Match header `*`?";
+    G11EtagInIfMatch => "G11", "G11: Is the ETag of the resource in the If-Match header?";
+    H7IfMatchStarExists => "H7", "H7: Is the If-Match header `*` for a missing resource?";
+    H10IfUnmodifiedSinceExists => "H10", "H10: Does the If-Unmodified-Since header exist?";
+    H11IfUnmodifiedSinceValid => "H11", "H11: Is the If-Unmodified-Since header a valid date?";
+    H12LastModifiedGreaterThanUMS => "H12", "H12: Is Last-Modified after If-Unmodified-Since?";
+    I4HasMovedPermanently => "I4", "I4: Has the resource moved permanently (for a PUT)?";
+    I12IfNoneMatchExists => "I12", "I12: Does the If-None-Match header exist?";
+    I13IfNoneMatchStarExists => "I13", "I13: Is the If-None-Match header `*`?";
+    I7Put => "I7", "I7: Is the request a PUT to a missing resource?";
+    J18GetHead => "J18", "J18: Is the request a GET or HEAD?";
+    K5HasMovedPermanently => "K5", "K5: Has the resource moved permanently?";
+    K7ResourcePreviouslyExisted => "K7", "K7: Did the resource previously exist?";
+    K13ETagInIfNoneMatch => "K13", "K13: Is the ETag of the resource in the If-None-Match header?";
+    L5HasMovedTemporarily => "L5", "L5: Has the resource moved temporarily?";
+    L7Post => "L7", "L7: Is the request a POST to a missing resource?";
+    L7aOtherMethod => "L7a", "L7a: Did another method create the missing resource?";
+    L13IfModifiedSinceExists => "L13", "L13: Does the If-Modified-Since header exist?";
+    L14IfModifiedSinceValid => "L14", "L14: Is the If-Modified-Since header a valid date?";
+    L15IfModifiedSinceGreaterThanNow => "L15", "L15: Is the If-Modified-Since date in the future?";
+    L17IfLastModifiedGreaterThanMS => "L17", "L17: Is Last-Modified after If-Modified-Since?";
+    M5Post => "M5", "M5: Is the request a POST to a resource that previously existed?";
+    M7PostToMissingResource => "M7", "M7: Are POSTs to the missing resource allowed?";
+    M16Delete => "M16", "M16: Is the request a DELETE?";
+    M20DeleteEnacted => "M20", "M20: Has the delete been enacted?";
+    N5PostToMissingResource => "N5", "N5: Are POSTs to the previously existing resource allowed?";
+    N11Redirect => "N11", "N11: Should the response to the POST be a redirect?";
+    N16Post => "N16", "N16: Is the request a POST?";
+    O14Conflict => "O14", "O14: Does the PUT conflict with the resource?";
+    O16Put => "O16", "O16: Is the request a PUT?";
+    O16aOtherMethod => "O16a", "O16a: Did another method create a new resource?";
+    O18MultipleRepresentations => "O18", "O18: Are there multiple representations of the resource?";
+    O20ResponseHasBody => "O20", "O20: Does the response have a body?";
+    P3Conflict => "P3", "P3: Does the PUT to a missing resource conflict?";
+    P11NewResource => "P11", "P11: Was a new resource created?";
+}
+
+impl fmt::Display for DecisionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl FromStr for DecisionId {
+    type Err = String;
+
+    /// Parses a decision from its diagram code (i.e. `B13`) or name (i.e. `B13Available`),
+    /// ignoring case
+    fn from_str(s: &str) -> Result<DecisionId, String> {
+        DecisionId::ALL
+            .iter()
+            .find(|id| id.code().eq_ignore_ascii_case(s) || id.name().eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or_else(|| format!("'{}' is not a known decision", s))
+    }
+}
+
 pub(crate) enum Transition {
     To(Decision),
     Branch(Decision, Decision),
 }
 
+/// Outcome of executing a decision of the state machine
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub(crate) enum DecisionResult {
+pub enum DecisionResult {
+    /// The decision is true, with the reason
     True(String),
+    /// The decision is false, with the reason
     False(String),
+    /// The decision ends the state machine with the status code
     StatusCode(u16),
 }
 
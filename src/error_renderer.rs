@@ -0,0 +1,304 @@
+//! The `error_renderer` module renders the bodies of error (4xx and 5xx) responses, negotiated
+//! against the Accept header of the request so browsers can get HTML and APIs JSON. With a
+//! message catalog, the title and detail are translated to the language of the request.
+
+use std::sync::Arc;
+
+use crate::{
+    content_negotiation::{sort_media_types, MediaType, MediaTypeMatch},
+    context::{Context, Request},
+    headers::HeaderValue,
+    MessageCatalog,
+};
+
+/// Type of a function that renders the body of an error response in the media type, or returns
+/// None if it has no body for the response
+pub type ErrorRenderFn = Arc<dyn Fn(&Context, &str) -> Option<String> + Send + Sync>;
+
+/// Renderer of the bodies of error responses that do not already have a body
+#[derive(Clone)]
+pub struct ErrorRenderer {
+    /// Media types the renderer produces, in order of preference. The first one is used if none
+    /// are acceptable to the client.
+    pub produces: Vec<String>,
+    /// Function to render the body in the negotiated media type
+    pub render: ErrorRenderFn,
+    /// Catalog the error responses are localized with. If set, the Content-Language header of
+    /// rendered error responses is set to the language of the catalog negotiated for the
+    /// request. Defaults to None.
+    pub catalog: Option<Arc<MessageCatalog>>,
+}
+
+impl Default for ErrorRenderer {
+    /// Creates a renderer of RFC 7807 problem details documents and HTML pages, with the error
+    /// of the context as the detail
+    fn default() -> ErrorRenderer {
+        ErrorRenderer {
+            produces: vec![
+                "application/problem+json".to_string(),
+                "text/html".to_string(),
+            ],
+            render: Arc::new(|context, media_type| render_default(context, media_type, None)),
+            catalog: None,
+        }
+    }
+}
+
+impl ErrorRenderer {
+    /// Creates a renderer of the media types with the function
+    pub fn new<F>(produces: &[&str], render: F) -> ErrorRenderer
+    where
+        F: Fn(&Context, &str) -> Option<String> + Send + Sync + 'static,
+    {
+        ErrorRenderer {
+            produces: produces.iter().map(|media_type| media_type.to_string()).collect(),
+            render: Arc::new(render),
+            catalog: None,
+        }
+    }
+
+    /// Creates the default renderer, with the title and detail of the errors translated with
+    /// the catalog to the language negotiated for the request (see
+    /// `MessageCatalog::language_for`). The detail is translated if the catalog has the error
+    /// of the context as a message.
+    pub fn with_catalog(catalog: MessageCatalog) -> ErrorRenderer {
+        let catalog = Arc::new(catalog);
+        let render_catalog = catalog.clone();
+        ErrorRenderer {
+            render: Arc::new(move |context, media_type| {
+                render_default(context, media_type, Some(&render_catalog))
+            }),
+            catalog: Some(catalog),
+            ..ErrorRenderer::default()
+        }
+    }
+
+    /// Returns the media type to render the error for the request in. A structured syntax
+    /// suffix (i.e. `application/problem+json`) also matches its base type (`application/json`).
+    pub fn negotiate(&self, request: &Request) -> Option<&str> {
+        let acceptable = sort_media_types(&request.accept())
+            .iter()
+            .map(HeaderValue::as_media_type)
+            .filter(|media_type| media_type.weight > 0.0)
+            .collect::<Vec<MediaType>>();
+        acceptable
+            .iter()
+            .find_map(|accepted| {
+                self.produces
+                    .iter()
+                    .find(|produced| suffix_matches(produced, accepted))
+            })
+            .or_else(|| self.produces.first())
+            .map(|media_type| media_type.as_str())
+    }
+
+    /// Renders the body of the error response of the context if it does not have one, setting
+    /// the Content-Type header
+    pub fn render_error(&self, context: &mut Context) {
+        if context.response.status < 400 || context.response.has_body() {
+            return;
+        }
+        let media_type = match self.negotiate(&context.request) {
+            Some(media_type) => media_type.to_string(),
+            None => return,
+        };
+        if let Some(body) = (self.render)(context, &media_type) {
+            context.response.remove_header("Content-Type");
+            context.response.add_header(
+                "Content-Type",
+                vec![HeaderValue {
+                    value: media_type,
+                    params: hashmap! { "charset".to_string() => "UTF-8".to_string() },
+                    quote: false,
+                }],
+            );
+            context.response.body = Some(body.into_bytes());
+            if let Some(catalog) = &self.catalog {
+                let language = catalog.language_for(context);
+                context.response.remove_header("Content-Language");
+                context
+                    .response
+                    .add_header("Content-Language", vec![HeaderValue::basic(language)]);
+            }
+        }
+    }
+}
+
+fn render_default(
+    context: &Context,
+    media_type: &str,
+    catalog: Option<&MessageCatalog>,
+) -> Option<String> {
+    let status = context.response.status;
+    let mut title = status_title(status);
+    let mut detail = context.error.as_deref();
+    if let Some(catalog) = catalog {
+        let language = catalog.language_for(context);
+        title = catalog.translate(&language, title);
+        detail = detail.map(|detail| catalog.translate(&language, detail));
+    }
+    if media_type == "text/html" {
+        Some(html_page(status, title, detail))
+    } else {
+        let mut problem = problem_document(status, detail);
+        problem["title"] = title.into();
+        Some(problem.to_string())
+    }
+}
+
+fn suffix_matches(produced: &str, accepted: &MediaType) -> bool {
+    let produced = MediaType::parse_string(produced);
+    if produced.matches(accepted) != MediaTypeMatch::None {
+        return true;
+    }
+    match produced.sub.rsplit_once('+') {
+        Some((_, suffix)) => {
+            let base = MediaType {
+                sub: suffix.to_string(),
+                ..produced.clone()
+            };
+            base.matches(accepted) != MediaTypeMatch::None
+        }
+        None => false,
+    }
+}
+
+/// Returns an RFC 7807 problem details document for the status
+pub fn problem_document(status: u16, detail: Option<&str>) -> serde_json::Value {
+    let mut problem = serde_json::json!({
+        "type": "about:blank",
+        "title": status_title(status),
+        "status": status
+    });
+    if let (Some(fields), Some(detail)) = (problem.as_object_mut(), detail) {
+        fields.insert("detail".to_string(), detail.into());
+    }
+    problem
+}
+
+fn status_title(status: u16) -> &'static str {
+    http::StatusCode::from_u16(status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or("Error")
+}
+
+fn html_page(status: u16, title: &str, detail: Option<&str>) -> String {
+    let title = format!("{} {}", status, escape_html(title));
+    let detail = detail
+        .map(|detail| format!("<p>{}</p>", escape_html(detail)))
+        .unwrap_or_default();
+    format!(
+        "<!DOCTYPE html><html><head><title>{0}</title></head><body><h1>{0}</h1>{1}</body></html>",
+        title, detail
+    )
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Response;
+    use expectest::prelude::*;
+
+    fn error_context(accept: &str) -> Context {
+        Context {
+            request: Request {
+                headers: hashmap! { "Accept".to_string() => vec![h!(accept)] },
+                ..Request::default()
+            },
+            response: Response {
+                status: 404,
+                ..Response::default()
+            },
+            error: Some("No <route> matched".to_string()),
+            ..Context::default()
+        }
+    }
+
+    #[test]
+    fn render_error_negotiates_html_for_browsers() {
+        let mut context = error_context("text/html,application/xhtml+xml,*/*;q=0.8");
+        ErrorRenderer::default().render_error(&mut context);
+        expect!(context.response.headers.get("Content-Type").cloned())
+            .to(be_some().value(vec![h!("text/html;charset=UTF-8")]));
+        expect!(String::from_utf8(context.response.body.unwrap()).unwrap()).to(be_equal_to(
+            "<!DOCTYPE html><html><head><title>404 Not Found</title></head><body>\
+             <h1>404 Not Found</h1><p>No &lt;route&gt; matched</p></body></html>",
+        ));
+    }
+
+    #[test]
+    fn render_error_negotiates_problem_json_for_apis() {
+        let mut context = error_context("application/json");
+        ErrorRenderer::default().render_error(&mut context);
+        let body: serde_json::Value =
+            serde_json::from_slice(&context.response.body.unwrap()).unwrap();
+        expect!(body).to(be_equal_to(serde_json::json!({
+            "type": "about:blank",
+            "title": "Not Found",
+            "status": 404,
+            "detail": "No <route> matched"
+        })));
+    }
+
+    #[test]
+    fn render_error_does_not_replace_bodies_or_render_success() {
+        let mut context = error_context("text/html");
+        context.response.body = Some(b"existing".to_vec());
+        ErrorRenderer::default().render_error(&mut context);
+        expect!(context.response.body).to(be_some().value(b"existing".to_vec()));
+
+        let mut context = error_context("text/html");
+        context.response.status = 200;
+        ErrorRenderer::default().render_error(&mut context);
+        expect!(context.response.body).to(be_none());
+    }
+
+    #[test]
+    fn render_error_localizes_the_title_and_detail_with_the_catalog() {
+        let renderer = ErrorRenderer::with_catalog(MessageCatalog::default().with_language(
+            "de",
+            &[
+                ("Not Found", "Nicht gefunden"),
+                ("No <route> matched", "Keine <Route> passt"),
+            ],
+        ));
+        let mut context = error_context("application/problem+json");
+        context.request.headers.insert(
+            "Accept-Language".to_string(),
+            vec![h!("de-AT"), h!("en;q=0.5")],
+        );
+        renderer.render_error(&mut context);
+        let body: serde_json::Value =
+            serde_json::from_slice(&context.response.body.unwrap()).unwrap();
+        expect!(body).to(be_equal_to(serde_json::json!({
+            "type": "about:blank",
+            "title": "Nicht gefunden",
+            "status": 404,
+            "detail": "Keine <Route> passt"
+        })));
+        expect!(context.response.headers.get("Content-Language").cloned())
+            .to(be_some().value(vec![h!("de")]));
+
+        let mut context = error_context("text/html");
+        context.error = Some("Unknown error".to_string());
+        context
+            .request
+            .headers
+            .insert("Accept-Language".to_string(), vec![h!("fr")]);
+        renderer.render_error(&mut context);
+        expect!(String::from_utf8(context.response.body.unwrap()).unwrap()).to(be_equal_to(
+            "<!DOCTYPE html><html><head><title>404 Not Found</title></head><body>\
+             <h1>404 Not Found</h1><p>Unknown error</p></body></html>",
+        ));
+        expect!(context.response.headers.get("Content-Language").cloned())
+            .to(be_some().value(vec![h!("en")]));
+    }
+}
@@ -0,0 +1,352 @@
+//! The `forwarded` module parses the `Forwarded` (RFC 7239) and `X-Forwarded-For`,
+//! `X-Forwarded-Proto` and `X-Forwarded-Host` headers that reverse proxies add to requests, so
+//! the client address, scheme and host the client made the request with are known behind a
+//! proxy. The headers can be set by anyone, so they are only used if the request came from a
+//! trusted proxy, and only the hops added by trusted proxies are believed: the client is the
+//! nearest address in the chain that is not a trusted proxy.
+
+use std::net::{IpAddr, SocketAddr};
+
+use http::HeaderMap;
+
+/// Range of IP addresses, in CIDR notation (i.e. `10.0.0.0/8` or `fd00::/8`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    /// Address of the range
+    pub addr: IpAddr,
+    /// Length of the prefix of the address that addresses in the range share
+    pub prefix: u8,
+}
+
+impl IpRange {
+    /// Parses an address range in CIDR notation. A single address is a range of just that
+    /// address.
+    pub fn parse(range: &str) -> Result<IpRange, String> {
+        let (addr, prefix) = match range.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (range.trim(), None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address", addr))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("'{}' is not a valid prefix length", prefix))?,
+            None => max_prefix,
+        };
+        Ok(IpRange { addr, prefix })
+    }
+
+    /// If the address is in the range
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, to_canonical(addr)) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => {
+                prefix_matches(&range.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                prefix_matches(&range.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(range: &[u8], addr: &[u8], prefix: u8) -> bool {
+    let bytes = usize::from(prefix / 8);
+    let bits = prefix % 8;
+    range[..bytes] == addr[..bytes]
+        && (bits == 0 || (range[bytes] ^ addr[bytes]) >> (8 - bits) == 0)
+}
+
+/// Converts IPv4-mapped IPv6 addresses (i.e. `::ffff:10.0.0.1`, from dual stack listeners) to
+/// IPv4 addresses
+fn to_canonical(addr: &IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(*v6)),
+        IpAddr::V4(_) => *addr,
+    }
+}
+
+/// Configuration of the proxies the forwarding headers of requests are trusted from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ForwardedConfig {
+    /// Address ranges of the trusted proxies. Defaults to empty, which trusts no proxies.
+    pub trusted_proxies: Vec<IpRange>,
+}
+
+impl ForwardedConfig {
+    /// Creates the configuration trusting the proxies in the address ranges (see
+    /// `IpRange::parse`)
+    pub fn trusting(ranges: &[&str]) -> Result<ForwardedConfig, String> {
+        Ok(ForwardedConfig {
+            trusted_proxies: ranges
+                .iter()
+                .map(|range| IpRange::parse(range))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// If the address is of a trusted proxy
+    pub fn is_trusted(&self, addr: &IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|range| range.contains(addr))
+    }
+
+    /// Returns what the trusted proxies the request came through forwarded about the client.
+    /// The `Forwarded` header is used if the request has one, otherwise the `X-Forwarded-*`
+    /// headers. Returns None if the request did not come from a trusted proxy, or has no
+    /// forwarding headers.
+    pub fn forwarded_info(
+        &self,
+        headers: &HeaderMap,
+        remote_addr: Option<SocketAddr>,
+    ) -> Option<ForwardedInfo> {
+        let remote_addr = remote_addr?.ip();
+        if !self.is_trusted(&remote_addr) {
+            return None;
+        }
+        let hops = if headers.contains_key("Forwarded") {
+            forwarded_hops(headers)
+        } else {
+            x_forwarded_hops(headers)
+        };
+        if hops.is_empty() {
+            return None;
+        }
+        // each proxy appends the hop it received the request from, so walk back from the
+        // nearest until a hop that was not added by a trusted proxy
+        let mut proxies = vec![remote_addr];
+        let mut index = hops.len() - 1;
+        while let Some(addr) = hops[index].addr.filter(|addr| self.is_trusted(addr)) {
+            if index == 0 {
+                break;
+            }
+            proxies.push(addr);
+            index -= 1;
+        }
+        let client = &hops[index];
+        Some(ForwardedInfo {
+            client_ip: client.addr,
+            proto: hops[index..].iter().find_map(|hop| hop.proto.clone()),
+            host: hops[index..].iter().find_map(|hop| hop.host.clone()),
+            proxies,
+        })
+    }
+}
+
+/// What the trusted proxies a request came through forwarded about the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardedInfo {
+    /// Address of the client. None if the proxy did not disclose it (i.e. `for=unknown`).
+    pub client_ip: Option<IpAddr>,
+    /// Scheme the client made the request with (i.e. `https`), lowercased
+    pub proto: Option<String>,
+    /// Host (and port, if given) the client made the request to
+    pub host: Option<String>,
+    /// Addresses of the trusted proxies the request came through, starting with the nearest
+    pub proxies: Vec<IpAddr>,
+}
+
+/// A hop of the request through a proxy
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Hop {
+    addr: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Hop> {
+    header_values(headers, "Forwarded")
+        .iter()
+        .map(|element| {
+            let mut hop = Hop::default();
+            for pair in element.split(';') {
+                if let Some((name, value)) = pair.split_once('=') {
+                    let value = value.trim().trim_matches('"');
+                    match name.trim().to_lowercase().as_str() {
+                        "for" => hop.addr = parse_node(value),
+                        "proto" => hop.proto = Some(value.to_lowercase()),
+                        "host" => hop.host = Some(value.to_string()),
+                        _ => (),
+                    }
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+fn x_forwarded_hops(headers: &HeaderMap) -> Vec<Hop> {
+    let mut hops: Vec<Hop> = header_values(headers, "X-Forwarded-For")
+        .iter()
+        .map(|node| Hop {
+            addr: parse_node(node),
+            ..Hop::default()
+        })
+        .collect();
+    if hops.is_empty() {
+        hops.push(Hop::default());
+    }
+    // proxies that set the proto and host may not all do so, so the values are matched to the
+    // hops from the nearest
+    let protos = header_values(headers, "X-Forwarded-Proto");
+    for (hop, proto) in hops.iter_mut().rev().zip(protos.iter().rev()) {
+        hop.proto = Some(proto.to_lowercase());
+    }
+    let hosts = header_values(headers, "X-Forwarded-Host");
+    for (hop, host) in hops.iter_mut().rev().zip(hosts.iter().rev()) {
+        hop.host = Some(host.to_string());
+    }
+    if hops.iter().all(|hop| hop == &Hop::default()) {
+        hops.clear();
+    }
+    hops
+}
+
+/// Parses the address of a node, which may have a port (i.e. `192.0.2.43:47011` or
+/// `[2001:db8::1]:4711`). Returns None for obfuscated or unknown nodes.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let addr = match node.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None if node.matches(':').count() == 1 => node.split(':').next().unwrap_or_default(),
+        None => node,
+    };
+    addr.parse().ok().map(|addr| to_canonical(&addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    fn headers(headers: &[(&str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                http::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        map
+    }
+
+    fn addr(addr: &str) -> Option<SocketAddr> {
+        Some(format!("{}:443", addr).parse().unwrap())
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn ip_ranges_match_addresses_by_prefix() {
+        let range = IpRange::parse("10.1.0.0/16").unwrap();
+        expect!(range.contains(&ip("10.1.200.3"))).to(be_true());
+        expect!(range.contains(&ip("10.2.0.1"))).to(be_false());
+        expect!(range.contains(&ip("::ffff:10.1.0.9"))).to(be_true());
+        expect!(IpRange::parse("172.16.0.0/12")
+            .unwrap()
+            .contains(&ip("172.31.255.255")))
+        .to(be_true());
+        expect!(IpRange::parse("fd00::/8").unwrap().contains(&ip("fd12::1"))).to(be_true());
+        expect!(IpRange::parse("127.0.0.1")
+            .unwrap()
+            .contains(&ip("127.0.0.2")))
+        .to(be_false());
+        expect!(IpRange::parse("10.0.0.0/33")).to(be_err());
+        expect!(IpRange::parse("proxy.local")).to(be_err());
+    }
+
+    #[test]
+    fn forwarding_headers_are_ignored_from_untrusted_clients() {
+        let config = ForwardedConfig::trusting(&["10.0.0.0/8"]).unwrap();
+        let headers = headers(&[
+            ("X-Forwarded-For", "203.0.113.7"),
+            ("X-Forwarded-Proto", "https"),
+        ]);
+        expect!(config.forwarded_info(&headers, addr("198.51.100.2"))).to(be_none());
+        expect!(config.forwarded_info(&headers, None)).to(be_none());
+        expect!(ForwardedConfig::default().forwarded_info(&headers, addr("10.0.0.1")))
+            .to(be_none());
+        expect!(config.forwarded_info(&HeaderMap::new(), addr("10.0.0.1"))).to(be_none());
+    }
+
+    #[test]
+    fn the_client_is_the_nearest_untrusted_hop() {
+        let config = ForwardedConfig::trusting(&["10.0.0.0/8"]).unwrap();
+        // the client spoofed the first address, the edge proxy appended the real one
+        let headers = headers(&[
+            ("X-Forwarded-For", "1.2.3.4, 203.0.113.7"),
+            ("X-Forwarded-For", "10.0.0.5"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "api.example.com"),
+        ]);
+        expect!(config.forwarded_info(&headers, addr("10.0.0.1"))).to(be_some().value(
+            ForwardedInfo {
+                client_ip: Some(ip("203.0.113.7")),
+                proto: Some("https".to_string()),
+                host: Some("api.example.com".to_string()),
+                proxies: vec![ip("10.0.0.1"), ip("10.0.0.5")],
+            },
+        ));
+
+        let headers = self::headers(&[("X-Forwarded-For", "10.0.0.9, 10.0.0.5")]);
+        expect!(
+            config
+                .forwarded_info(&headers, addr("10.0.0.1"))
+                .unwrap()
+                .client_ip
+        )
+        .to(be_some().value(ip("10.0.0.9")));
+    }
+
+    #[test]
+    fn forwarded_header_takes_precedence() {
+        let config = ForwardedConfig::trusting(&["10.0.0.0/8", "2001:db8::/32"]).unwrap();
+        let headers = headers(&[
+            (
+                "Forwarded",
+                "for=\"[2001:db8:cafe::17]:4711\";proto=HTTPS;host=shop.example.com, \
+                 for=10.0.0.5:8080",
+            ),
+            ("X-Forwarded-For", "192.0.2.60"),
+        ]);
+        expect!(config.forwarded_info(&headers, addr("10.0.0.1"))).to(be_some().value(
+            ForwardedInfo {
+                client_ip: Some(ip("2001:db8:cafe::17")),
+                proto: Some("https".to_string()),
+                host: Some("shop.example.com".to_string()),
+                proxies: vec![ip("10.0.0.1"), ip("10.0.0.5")],
+            },
+        ));
+
+        let headers = self::headers(&[("Forwarded", "for=unknown;proto=http")]);
+        expect!(config.forwarded_info(&headers, addr("10.0.0.1"))).to(be_some().value(
+            ForwardedInfo {
+                client_ip: None,
+                proto: Some("http".to_string()),
+                host: None,
+                proxies: vec![ip("10.0.0.1")],
+            },
+        ));
+    }
+}
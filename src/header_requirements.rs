@@ -0,0 +1,190 @@
+//! The `header_requirements` module lets a resource declare the request headers it requires
+//! (i.e. `X-Tenant-Id`, or `Idempotency-Key` for a POST), with a validator of their values.
+//! They are checked when the state machine asks if the request is malformed, and a missing or
+//! invalid header short-circuits the request with a problem details body naming the header,
+//! before the `malformed_request` callback is called.
+
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crate::context::Request;
+
+/// Type of a function that validates the value of a required header, returning an Err with the
+/// reason the value is not valid
+pub type HeaderValidator = Arc<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// A request header a resource requires
+#[derive(Clone)]
+pub struct HeaderRequirement {
+    /// Name of the header
+    pub name: String,
+    /// Methods the header is required for. Defaults to empty, which is all methods.
+    pub methods: Vec<String>,
+    /// Status of the response if the header is missing. Defaults to '400 Bad Request'; use
+    /// '428 Precondition Required' for headers that make a request safe to repeat (i.e.
+    /// `Idempotency-Key`). Invalid values always get a '400 Bad Request' response.
+    pub missing_status: u16,
+    /// Validator of the value of the header. Defaults to None, which accepts any value.
+    pub validator: Option<HeaderValidator>,
+}
+
+impl Debug for HeaderRequirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeaderRequirement")
+            .field("name", &self.name)
+            .field("methods", &self.methods)
+            .field("missing_status", &self.missing_status)
+            .field("validator", &self.validator.is_some())
+            .finish()
+    }
+}
+
+impl HeaderRequirement {
+    /// Creates a requirement of the header for all methods
+    pub fn new(name: &str) -> HeaderRequirement {
+        HeaderRequirement {
+            name: name.to_string(),
+            methods: Vec::new(),
+            missing_status: 400,
+            validator: None,
+        }
+    }
+
+    /// Only requires the header for the methods
+    pub fn for_methods(self, methods: &[&str]) -> HeaderRequirement {
+        HeaderRequirement {
+            methods: methods.iter().map(|method| method.to_uppercase()).collect(),
+            ..self
+        }
+    }
+
+    /// Responds with the status if the header is missing
+    pub fn with_missing_status(self, status: u16) -> HeaderRequirement {
+        HeaderRequirement {
+            missing_status: status,
+            ..self
+        }
+    }
+
+    /// Validates the value of the header with the function. The value of a header with multiple
+    /// values is passed as a comma separated list.
+    pub fn with_validator<F>(self, validator: F) -> HeaderRequirement
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        HeaderRequirement {
+            validator: Some(Arc::new(validator)),
+            ..self
+        }
+    }
+
+    /// Checks the request meets the requirement. Returns an Err with the status and the detail
+    /// of the response if it does not.
+    pub fn check(&self, request: &Request) -> Result<(), (u16, String)> {
+        if !self.methods.is_empty() && !self.methods.contains(&request.method.to_uppercase()) {
+            return Ok(());
+        }
+        let value = request
+            .find_header(&self.name)
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        if value.trim().is_empty() {
+            return Err((
+                self.missing_status,
+                format!("Required header '{}' is missing", self.name),
+            ));
+        }
+        match &self.validator {
+            Some(validator) => validator(&value).map_err(|reason| {
+                (
+                    400,
+                    format!("Header '{}' is not valid: {}", self.name, reason),
+                )
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{headers::HeaderValue, owned_callback, Dispatcher, Resource};
+    use expectest::prelude::*;
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: method.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), vec![HeaderValue::basic(*value)]))
+                .collect(),
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn check_reports_missing_and_invalid_headers() {
+        let tenant = HeaderRequirement::new("X-Tenant-Id").with_validator(|value| {
+            if value.chars().all(|c| c.is_ascii_digit()) {
+                Ok(())
+            } else {
+                Err("must be numeric".to_string())
+            }
+        });
+        expect!(tenant.check(&request("GET", &[("x-tenant-id", "42")]))).to(be_ok());
+        expect!(tenant.check(&request("GET", &[("X-Tenant-Id", "acme")]))).to(be_err().value((
+            400,
+            "Header 'X-Tenant-Id' is not valid: must be numeric".to_string(),
+        )));
+        expect!(tenant.check(&request("GET", &[("X-Tenant-Id", " ")])))
+            .to(be_err().value((400, "Required header 'X-Tenant-Id' is missing".to_string())));
+
+        let idempotency_key = HeaderRequirement::new("Idempotency-Key")
+            .for_methods(&["post"])
+            .with_missing_status(428);
+        expect!(idempotency_key.check(&request("GET", &[]))).to(be_ok());
+        expect!(idempotency_key.check(&request("POST", &[]))).to(be_err().value((
+            428,
+            "Required header 'Idempotency-Key' is missing".to_string(),
+        )));
+    }
+
+    #[tokio::test]
+    async fn missing_headers_short_circuit_with_a_problem_body() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/payments" => Resource {
+                    allowed_methods: vec!["POST"],
+                    required_headers: vec![HeaderRequirement::new("Idempotency-Key")
+                        .with_missing_status(428)],
+                    process_post: owned_callback(|_, _| Box::pin(async { Ok(true) })),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        };
+        let request = |idempotency_key: Option<&str>| {
+            let mut request = http::Request::post("/payments");
+            if let Some(key) = idempotency_key {
+                request = request.header("Idempotency-Key", key);
+            }
+            request.body(hyper::Body::empty()).unwrap()
+        };
+
+        let response = dispatcher.clone().dispatch(request(None)).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(428));
+        expect!(response.headers()["Content-Type"].to_str().unwrap())
+            .to(be_equal_to("application/problem+json"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        expect!(problem["detail"].as_str())
+            .to(be_some().value("Required header 'Idempotency-Key' is missing"));
+        expect!(problem["header"].as_str()).to(be_some().value("Idempotency-Key"));
+
+        let response = dispatcher.dispatch(request(Some("a1b2"))).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(204));
+    }
+}
@@ -1,8 +1,12 @@
 //! The `headers` deals with parsing and formatting request and response headers
 
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use http::header::{HeaderMap, HeaderName};
 use itertools::Itertools;
 use std::{
     collections::HashMap,
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
     hash::{Hash, Hasher},
     iter::Peekable,
     str::Chars,
@@ -114,6 +118,49 @@ fn parse_header_parameter_value(chars: &mut Peekable<Chars>, values: &mut Vec<St
     }
 }
 
+// list -> element *( "," element ), where commas in quoted strings and <URI-references> are
+// part of the element. Empty elements are ignored as per RFC 7230 section 7.
+fn split_header_list(s: &str) -> Vec<String> {
+    let mut elements = vec![];
+    let mut current = String::new();
+    let mut chars = s.chars();
+    let mut in_quotes = false;
+    let mut in_brackets = false;
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if in_quotes => {
+                current.push(ch);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            '"' if !in_brackets => {
+                in_quotes = !in_quotes;
+                current.push(ch);
+            }
+            '<' if !in_quotes => {
+                in_brackets = true;
+                current.push(ch);
+            }
+            '>' if !in_quotes => {
+                in_brackets = false;
+                current.push(ch);
+            }
+            ',' if !in_quotes && !in_brackets => {
+                if !current.trim().is_empty() {
+                    elements.push(current.trim().to_string());
+                }
+                current.clear();
+            }
+            _ => current.push(ch),
+        }
+    }
+    if !current.trim().is_empty() {
+        elements.push(current.trim().to_string());
+    }
+    elements
+}
+
 fn skip_whitespace(chars: &mut Peekable<Chars>) {
     while chars.peek().is_some() && chars.peek().unwrap().is_whitespace() {
         chars.next();
@@ -154,6 +201,39 @@ impl HeaderValue {
         }
     }
 
+    /// Parses a media range of an Accept header (RFC 9110 section 12.5.1). Parameters after the
+    /// `q` weight are accept extensions (allowed by RFC 7231, but since removed), which are
+    /// dropped so they are not taken as parameters of the media type.
+    pub fn parse_media_range(s: &str) -> HeaderValue {
+        let values = parse_header(s);
+        let (first, second) = values.split_first().unwrap();
+        let mut params = HashMap::new();
+        for (name, value) in batch(second) {
+            if name.is_empty() {
+                continue;
+            }
+            if name.eq_ignore_ascii_case("q") {
+                params.insert("q".to_string(), value);
+                break;
+            }
+            params.insert(name, value);
+        }
+        HeaderValue {
+            value: first.clone(),
+            params,
+            quote: false,
+        }
+    }
+
+    /// Parses a comma-separated header list (as per RFC 7230 section 7) into HeaderValue structs.
+    /// Commas within quoted strings do not split the list, and empty elements are ignored.
+    pub fn parse_list(s: &str) -> Vec<HeaderValue> {
+        split_header_list(s)
+            .iter()
+            .map(|element| HeaderValue::parse_string(element))
+            .collect()
+    }
+
     /// Creates a basic header value that has no parameters
     pub fn basic<S: Into<String>>(s: S) -> HeaderValue {
         HeaderValue {
@@ -201,12 +281,31 @@ impl HeaderValue {
         self
     }
 
-    /// Converts the header value into a media type
+    /// Converts the header value into a date, if it is a valid HTTP date
+    pub fn as_date(&self) -> Option<DateTime<FixedOffset>> {
+        parse_http_date(&self.value).ok()
+    }
+
+    /// Converts the header value into an integer, if it is one
+    pub fn as_integer(&self) -> Option<u64> {
+        self.value.trim().parse().ok()
+    }
+
+    /// Converts the header value into a media type, with the parameters of the header value
+    /// (other than the `q` weight). Values of Accept headers are parsed with
+    /// `parse_media_range`, so they have no parameters from after the weight.
     pub fn as_media_type(&self) -> MediaType {
+        let mut media_type = MediaType::parse_string(&self.value);
+        media_type.params.extend(
+            self.params
+                .iter()
+                .filter(|(name, _)| name.as_str() != "q")
+                .map(|(name, value)| (name.to_lowercase(), value.clone())),
+        );
         if self.params.contains_key("q") {
-            MediaType::parse_string(&self.value).with_weight(self.params.get("q").unwrap())
+            media_type.with_weight(self.params.get("q").unwrap())
         } else {
-            MediaType::parse_string(&self.value)
+            media_type
         }
     }
 
@@ -266,6 +365,211 @@ impl Hash for HeaderValue {
     }
 }
 
+impl TryFrom<&HeaderValue> for http::HeaderValue {
+    type Error = http::header::InvalidHeaderValue;
+
+    fn try_from(value: &HeaderValue) -> Result<Self, Self::Error> {
+        http::HeaderValue::from_str(&value.to_string())
+    }
+}
+
+/// Converts a `http::HeaderMap` into a map of header names to parsed header values. Repeated
+/// header lines are combined into a single list of values. Values that are not valid strings
+/// are treated as empty.
+pub fn from_header_map(headers: &HeaderMap) -> HashMap<String, Vec<HeaderValue>> {
+    headers
+        .iter()
+        .fold(HashMap::new(), |mut map, (name, value)| {
+            let value = value.to_str().unwrap_or_default();
+            let values = match name.as_str() {
+                "if-match" | "if-none-match" => EntityTag::parse_list(value)
+                    .iter()
+                    .map(EntityTag::to_header_value)
+                    .collect(),
+                // HTTP dates contain commas, so these headers are not lists
+                "if-modified-since" | "if-unmodified-since" | "date" => {
+                    vec![HeaderValue::basic(value.trim())]
+                }
+                // Product comments contain semicolons (i.e. `(compatible; Googlebot/2.1)`), so the
+                // User-Agent header is neither a list nor has parameters
+                "user-agent" => vec![HeaderValue::basic(value.trim())],
+                "accept" => split_header_list(value)
+                    .iter()
+                    .map(|range| HeaderValue::parse_media_range(range))
+                    .collect(),
+                // HTTP message signature fields are dictionaries that are signed as sent, so the
+                // members are kept as is
+                "signature" | "signature-input" => split_header_list(value)
+                    .into_iter()
+                    .map(HeaderValue::basic)
+                    .collect(),
+                _ => HeaderValue::parse_list(value),
+            };
+            map.entry(name.to_string())
+                .or_insert_with(Vec::new)
+                .extend(values);
+            map
+        })
+}
+
+/// Converts a map of header names to header values into a `http::HeaderMap`. Each header value is
+/// added as a separate header line. Any header names or values that are not valid are skipped.
+pub fn to_header_map<'h, I>(headers: I) -> HeaderMap
+where
+    I: IntoIterator<Item = (&'h String, &'h Vec<HeaderValue>)>,
+{
+    let mut header_map = HeaderMap::new();
+    for (name, values) in headers {
+        match HeaderName::from_bytes(name.as_bytes()) {
+            Ok(header_name) => {
+                for value in values {
+                    match http::HeaderValue::try_from(value) {
+                        Ok(header_value) => {
+                            header_map.append(header_name.clone(), header_value);
+                        }
+                        Err(err) => warn!("Ignoring invalid value for header '{}' - {}", name, err),
+                    }
+                }
+            }
+            Err(err) => warn!("Ignoring invalid header name '{}' - {}", name, err),
+        }
+    }
+    header_map
+}
+
+/// Struct to represent an entity tag (ETag) as per [https://tools.ietf.org/html/rfc9110#section-8.8.3][1].
+///
+/// [1]: https://tools.ietf.org/html/rfc9110#section-8.8.3
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntityTag {
+    /// Opaque tag value, without the surrounding quotes
+    pub tag: String,
+    /// If this is a weak entity tag
+    pub weak: bool,
+}
+
+impl EntityTag {
+    /// Creates a strong entity tag
+    pub fn strong<S: Into<String>>(tag: S) -> EntityTag {
+        EntityTag {
+            tag: tag.into(),
+            weak: false,
+        }
+    }
+
+    /// Creates a weak entity tag
+    pub fn weak<S: Into<String>>(tag: S) -> EntityTag {
+        EntityTag {
+            tag: tag.into(),
+            weak: true,
+        }
+    }
+
+    /// Parses an entity tag in the form `"tag"` or `W/"tag"`. Unquoted values are treated as the
+    /// opaque tag of a strong entity tag.
+    pub fn parse_string(s: &str) -> EntityTag {
+        let s = s.trim();
+        let (weak, tag) = match s.strip_prefix("W/") {
+            Some(tag) => (true, tag),
+            None => (false, s),
+        };
+        let tag = if tag.len() > 1 && tag.starts_with('"') && tag.ends_with('"') {
+            &tag[1..tag.len() - 1]
+        } else {
+            tag
+        };
+        EntityTag {
+            tag: tag.to_string(),
+            weak,
+        }
+    }
+
+    /// Parses a comma-separated list of entity tags, as sent in If-Match and If-None-Match
+    /// headers. Commas inside the quoted opaque tags do not split the list.
+    pub fn parse_list(s: &str) -> Vec<EntityTag> {
+        let mut tags = vec![];
+        let mut current = String::new();
+        let mut in_quotes = false;
+        for ch in s.chars() {
+            match ch {
+                '"' => {
+                    in_quotes = !in_quotes;
+                    current.push(ch);
+                }
+                ',' if !in_quotes => {
+                    if !current.trim().is_empty() {
+                        tags.push(EntityTag::parse_string(&current));
+                    }
+                    current.clear();
+                }
+                _ => current.push(ch),
+            }
+        }
+        if !current.trim().is_empty() {
+            tags.push(EntityTag::parse_string(&current));
+        }
+        tags
+    }
+
+    /// Converts a parsed header value into an entity tag
+    pub fn from_header_value(value: &HeaderValue) -> EntityTag {
+        EntityTag::parse_string(&value.value)
+    }
+
+    /// Strong comparison: both entity tags must be strong and have the same opaque tag. This is
+    /// the comparison used for If-Match.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison: the opaque tags must match, regardless of either being weak. This is
+    /// the comparison used for If-None-Match.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+
+    /// Converts this entity tag into a header value suitable for the ETag header
+    pub fn to_header_value(&self) -> HeaderValue {
+        if self.weak {
+            HeaderValue::basic(self.to_string())
+        } else {
+            HeaderValue::basic(self.tag.as_str()).quote()
+        }
+    }
+}
+
+impl Display for EntityTag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+/// Parses a HTTP date as per [https://tools.ietf.org/html/rfc9110#section-5.6.7][1]. As well as
+/// the preferred IMF-fixdate format, the obsolete RFC 850 and asctime formats are accepted, as
+/// recipients are required to. Any surrounding quotes added by clients are ignored.
+///
+/// [1]: https://tools.ietf.org/html/rfc9110#section-5.6.7
+pub fn parse_http_date(value: &str) -> Result<DateTime<FixedOffset>, String> {
+    let value = value.trim().trim_matches('"').trim();
+    DateTime::parse_from_rfc2822(value)
+        .or_else(|_| parse_obsolete_http_date(value, "%A, %d-%b-%y %H:%M:%S GMT"))
+        .or_else(|_| parse_obsolete_http_date(value, "%a %b %e %H:%M:%S %Y"))
+        .map_err(|err| format!("'{}' is not a valid HTTP date - {}", value, err))
+}
+
+// Obsolete date formats are always in GMT
+fn parse_obsolete_http_date(
+    value: &str,
+    format: &str,
+) -> chrono::ParseResult<DateTime<FixedOffset>> {
+    NaiveDateTime::parse_from_str(value, format)
+        .map(|datetime| Utc.from_utc_datetime(&datetime).into())
+}
+
 /// Simple macro to convert a string to a `HeaderValue` struct.
 #[macro_export]
 macro_rules! h {
@@ -349,6 +653,35 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn parse_header_list_test() {
+        expect!(HeaderValue::parse_list("").iter()).to(be_empty());
+        expect!(HeaderValue::parse_list("a, b,,c ,"))
+            .to(be_equal_to(vec![h!("a"), h!("b"), h!("c")]));
+        expect!(HeaderValue::parse_list("\"abc,def\""))
+            .to(be_equal_to(vec![HeaderValue::basic("abc,def")]));
+        expect!(HeaderValue::parse_list("A;title=\"a, b\", B")).to(be_equal_to(vec![
+            HeaderValue {
+                value: "A".to_string(),
+                params: hashmap! { "title".to_string() => "a, b".to_string() },
+                quote: false,
+            },
+            h!("B"),
+        ]));
+        expect!(HeaderValue::parse_list("A;b=\"c\\\",d\", E")).to(be_equal_to(vec![
+            HeaderValue {
+                value: "A".to_string(),
+                params: hashmap! { "b".to_string() => "c\",d".to_string() },
+                quote: false,
+            },
+            h!("E"),
+        ]));
+        expect!(split_header_list("<http://a/b,c>; rel=next, <http://d>")).to(be_equal_to(vec![
+            "<http://a/b,c>; rel=next".to_string(),
+            "<http://d>".to_string(),
+        ]));
+    }
+
     #[test]
     fn parse_qouted_header_value_test() {
         expect!(HeaderValue::parse_string("\"*\"")).to(be_equal_to(HeaderValue {
@@ -384,4 +717,95 @@ mod tests {
         }));
         expect!(weak_etag_value.weak_etag()).to(be_some().value("1234567890"));
     }
+
+    #[test]
+    fn header_map_conversion_test() {
+        let mut header_map = HeaderMap::new();
+        header_map.append("accept", http::HeaderValue::from_static("text/html, text/plain"));
+        header_map.append("accept", http::HeaderValue::from_static("application/json;q=0.5"));
+        header_map.append("content-length", http::HeaderValue::from_static("42"));
+        let headers = from_header_map(&header_map);
+        expect!(headers.get("accept").cloned()).to(be_some().value(vec![
+            h!("text/html"),
+            h!("text/plain"),
+            h!("application/json;q=0.5"),
+        ]));
+
+        let converted = to_header_map(&headers);
+        expect!(converted.get_all("accept").iter().count()).to(be_equal_to(3));
+        expect!(converted.get("content-length").cloned())
+            .to(be_some().value(http::HeaderValue::from_static("42")));
+    }
+
+    #[test]
+    fn media_ranges_have_no_parameters_after_the_weight() {
+        let range = HeaderValue::parse_media_range("text/html;level=1;Q=0.7;ext=1");
+        expect!(range.clone()).to(be_equal_to(h!("text/html;level=1;q=0.7")));
+        let media_type = range.as_media_type();
+        expect!(media_type.params.clone())
+            .to(be_equal_to(hashmap! { "level".to_string() => "1".to_string() }));
+        expect!(media_type.weight).to(be_equal_to(0.7));
+
+        let mut header_map = HeaderMap::new();
+        header_map.append("accept", http::HeaderValue::from_static("text/html;q=0.5;ext=1"));
+        expect!(from_header_map(&header_map).get("accept").cloned())
+            .to(be_some().value(vec![h!("text/html;q=0.5")]));
+    }
+
+    #[test]
+    fn typed_header_value_test() {
+        expect!(h!("42").as_integer()).to(be_some().value(42));
+        expect!(h!("abc").as_integer()).to(be_none());
+        expect!(HeaderValue::basic("Sun, 06 Nov 1994 08:49:37 GMT").as_date())
+            .to(be_some().value(Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap()));
+        expect!(h!("text/html").as_date()).to(be_none());
+    }
+
+    #[test]
+    fn parse_http_date_test() {
+        let expected = Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap();
+        expect!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT")).to(be_ok().value(expected));
+        expect!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT")).to(be_ok().value(expected));
+        expect!(parse_http_date("Sun Nov  6 08:49:37 1994")).to(be_ok().value(expected));
+        expect!(parse_http_date("\"Sun, 06 Nov 1994 08:49:37 GMT\"")).to(be_ok().value(expected));
+        expect!(parse_http_date("06/11/1994")).to(be_err());
+    }
+
+    #[test]
+    fn entity_tag_parse_test() {
+        expect!(EntityTag::parse_string("\"1234\"")).to(be_equal_to(EntityTag::strong("1234")));
+        expect!(EntityTag::parse_string("W/\"1234\"")).to(be_equal_to(EntityTag::weak("1234")));
+        expect!(EntityTag::parse_string("1234")).to(be_equal_to(EntityTag::strong("1234")));
+        expect!(EntityTag::from_header_value(&HeaderValue::parse_string("W/\"1234\"")))
+            .to(be_equal_to(EntityTag::weak("1234")));
+        expect!(EntityTag::weak("1234").to_string()).to(be_equal_to("W/\"1234\"".to_string()));
+        expect!(EntityTag::strong("1234").to_string()).to(be_equal_to("\"1234\"".to_string()));
+    }
+
+    #[test]
+    fn entity_tag_parse_list_test() {
+        expect!(EntityTag::parse_list("")).to(be_equal_to(vec![]));
+        expect!(EntityTag::parse_list("*")).to(be_equal_to(vec![EntityTag::strong("*")]));
+        expect!(EntityTag::parse_list("\"a\", W/\"b\"")).to(be_equal_to(vec![
+            EntityTag::strong("a"),
+            EntityTag::weak("b"),
+        ]));
+        expect!(EntityTag::parse_list("\"a,b\",W/\"c, d\" , ")).to(be_equal_to(vec![
+            EntityTag::strong("a,b"),
+            EntityTag::weak("c, d"),
+        ]));
+    }
+
+    #[test]
+    fn entity_tag_comparison_test() {
+        let strong = EntityTag::strong("1");
+        let weak = EntityTag::weak("1");
+        expect!(strong.strong_eq(&EntityTag::strong("1"))).to(be_true());
+        expect!(strong.strong_eq(&weak)).to(be_false());
+        expect!(weak.strong_eq(&EntityTag::weak("1"))).to(be_false());
+        expect!(strong.strong_eq(&EntityTag::strong("2"))).to(be_false());
+        expect!(strong.weak_eq(&weak)).to(be_true());
+        expect!(weak.weak_eq(&EntityTag::weak("1"))).to(be_true());
+        expect!(weak.weak_eq(&EntityTag::weak("2"))).to(be_false());
+    }
 }
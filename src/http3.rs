@@ -0,0 +1,199 @@
+//! The `http3` module serves a dispatcher over HTTP/3, with `quinn` for QUIC and `h3` for
+//! HTTP/3. It is enabled with the experimental `http3` feature. Each request stream is converted
+//! to a request of the dispatcher with its body streamed, so the same state machine and limits
+//! apply as for requests served with hyper, and the response is written back to the stream with
+//! any streamed chunks and trailers.
+//!
+//! The endpoint must be configured with a TLS server config that has `h3` as an ALPN protocol.
+//! Connections are always secure, so the connection info of their requests has `secure` set.
+//!
+//! ```no_run
+//! # use webmachine::{http3, Dispatcher};
+//! # async fn run(endpoint: quinn::Endpoint, dispatcher: Dispatcher<'static>) {
+//! http3::serve("h3", endpoint, dispatcher).await;
+//! # }
+//! ```
+
+use futures::stream;
+use h3::{
+    error::{ConnectionError, StreamError},
+    quic::{BidiStream, RecvStream},
+    server::RequestStream,
+};
+use hyper::body::{Buf, Bytes, HttpBody};
+
+use crate::{
+    hyper1::{from_parts, to_header_map},
+    server::ConnectionInfo,
+    Dispatcher,
+};
+
+/// Headers that are specific to an HTTP/1.1 connection, which must not be sent in HTTP/3
+/// (RFC 9114 section 4.2)
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Serves the dispatcher on the QUIC endpoint, until the endpoint is closed. Each connection is
+/// served on its own task, and the connection info of its requests has the name of the listener.
+pub async fn serve(name: &str, endpoint: quinn::Endpoint, dispatcher: Dispatcher<'static>) {
+    let local_addr = endpoint.local_addr().ok();
+    while let Some(incoming) = endpoint.accept().await {
+        let dispatcher = dispatcher.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    warn!("Failed to accept a QUIC connection: {}", err);
+                    return;
+                }
+            };
+            let info = ConnectionInfo {
+                listener: name,
+                remote_addr: Some(connection.remote_address()),
+                local_addr,
+                secure: true,
+                client_certificate: None,
+            };
+            if let Err(err) = serve_connection(dispatcher, connection, info).await {
+                warn!("HTTP/3 connection failed: {}", err);
+            }
+        });
+    }
+}
+
+/// Serves the requests of the QUIC connection with the dispatcher, each on its own task, until
+/// the connection is closed
+pub async fn serve_connection(
+    dispatcher: Dispatcher<'static>,
+    connection: quinn::Connection,
+    info: ConnectionInfo,
+) -> Result<(), ConnectionError> {
+    let mut connection = h3::server::Connection::new(h3_quinn::Connection::new(connection)).await?;
+    loop {
+        match connection.accept().await {
+            Ok(Some(resolver)) => {
+                let dispatcher = dispatcher.clone();
+                let info = info.clone();
+                tokio::spawn(async move {
+                    let (mut request, stream) = match resolver.resolve_request().await {
+                        Ok(request) => request,
+                        Err(err) => {
+                            warn!("Failed to accept an HTTP/3 request: {}", err);
+                            return;
+                        }
+                    };
+                    request.extensions_mut().insert(info);
+                    if let Err(err) = serve_request(dispatcher, request, stream).await {
+                        warn!("Failed to respond to an HTTP/3 request: {}", err);
+                    }
+                });
+            }
+            Ok(None) => return Ok(()),
+            // the client closed the connection
+            Err(err) if err.is_h3_no_error() => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Dispatches the request of the HTTP/3 stream, and writes the response to the stream. The
+/// connection info of the request is kept if it has been added to its extensions.
+pub async fn serve_request<S>(
+    dispatcher: Dispatcher<'static>,
+    request: http_1::Request<()>,
+    stream: RequestStream<S, Bytes>,
+) -> Result<(), StreamError>
+where
+    S: BidiStream<Bytes> + Send + 'static,
+    S::SendStream: Send,
+    S::RecvStream: Send + 'static,
+{
+    let (mut send, recv) = stream.split();
+    let (parts, _) = request.into_parts();
+    let response = match from_parts(&parts, request_body(recv)) {
+        Ok(request) => dispatcher.dispatch(request).await,
+        Err(err) => Err(err),
+    };
+    let mut response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            error!("Failed to convert the HTTP/3 request or response: {}", err);
+            let mut response = http::Response::new(hyper::Body::empty());
+            *response.status_mut() = http::StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    };
+    send.send_response(response_head(&response)).await?;
+    let body = response.body_mut();
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => send.send_data(chunk).await?,
+            Err(err) => {
+                // the response has started, so all that can be done is to end the stream early
+                error!("Failed to stream the HTTP/3 response body: {}", err);
+                return send.finish().await;
+            }
+        }
+    }
+    if let Ok(Some(trailers)) = body.trailers().await {
+        send.send_trailers(to_header_map(&trailers)).await?;
+    }
+    send.finish().await
+}
+
+/// Streams the body of the request from the receiving half of its stream
+fn request_body<S: RecvStream + Send + 'static>(recv: RequestStream<S, Bytes>) -> hyper::Body {
+    let chunks = stream::unfold(Some(recv), |recv| async move {
+        let mut recv = recv?;
+        match recv.recv_data().await {
+            Ok(Some(mut data)) => Some((Ok(data.copy_to_bytes(data.remaining())), Some(recv))),
+            Ok(None) => None,
+            Err(err) => Some((Err(err), None)),
+        }
+    });
+    hyper::Body::wrap_stream(chunks)
+}
+
+/// Returns the status and headers of the response, without the headers that are specific to an
+/// HTTP/1.1 connection
+fn response_head<B>(response: &http::Response<B>) -> http_1::Response<()> {
+    let mut head = http_1::Response::new(());
+    *head.status_mut() = http_1::StatusCode::from_u16(response.status().as_u16())
+        .unwrap_or(http_1::StatusCode::INTERNAL_SERVER_ERROR);
+    *head.headers_mut() = to_header_map(response.headers());
+    for name in CONNECTION_HEADERS.iter() {
+        head.headers_mut().remove(*name);
+    }
+    head
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn response_head_drops_connection_specific_headers() {
+        let response = http::Response::builder()
+            .status(201)
+            .header("Content-Type", "application/json")
+            .header("Connection", "keep-alive")
+            .header("Transfer-Encoding", "chunked")
+            .body(())
+            .unwrap();
+        let head = response_head(&response);
+        expect!(head.status().as_u16()).to(be_equal_to(201));
+        expect!(head
+            .headers()
+            .keys()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>())
+        .to(be_equal_to(vec!["content-type"]));
+    }
+}
@@ -0,0 +1,265 @@
+//! The `hyper1` module serves a dispatcher with hyper 1.x. The dispatcher itself is built on
+//! hyper 0.14, which stays the default so existing servers are not affected. With the `hyper1`
+//! feature enabled, `DispatcherService` adapts it to the hyper 1.x `Service` trait: requests
+//! with any `http-body` 1.0 body (i.e. `Incoming`) are converted as they are dispatched, and
+//! responses are returned with a body that streams the chunks and trailers of the dispatcher
+//! response.
+//!
+//! ```no_run
+//! # use webmachine::{hyper1::DispatcherService, Dispatcher};
+//! # async fn serve<I>(io: I, dispatcher: Dispatcher<'static>) -> Result<(), hyper_1::Error>
+//! # where
+//! #     I: hyper_1::rt::Read + hyper_1::rt::Write + Unpin + Send + 'static,
+//! # {
+//! hyper_1::server::conn::http1::Builder::new()
+//!     .serve_connection(io, DispatcherService::new(dispatcher))
+//!     .await
+//! # }
+//! ```
+
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt, StreamExt,
+};
+use http_body_1::{Body as HttpBody, Frame, SizeHint};
+use http_body_util::BodyStream;
+use hyper::body::{Bytes, HttpBody as _};
+use std::{
+    error::Error,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{server::ConnectionInfo, Dispatcher};
+
+/// Adapts a dispatcher to the hyper 1.x `Service` trait
+#[derive(Clone)]
+pub struct DispatcherService {
+    /// Dispatcher that the requests are dispatched to
+    pub dispatcher: Dispatcher<'static>,
+}
+
+impl DispatcherService {
+    /// Creates a service that dispatches requests to the dispatcher
+    pub fn new(dispatcher: Dispatcher<'static>) -> DispatcherService {
+        DispatcherService { dispatcher }
+    }
+}
+
+impl<B> hyper_1::service::Service<http_1::Request<B>> for DispatcherService
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Response = http_1::Response<ResponseBody>;
+    type Error = http::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: http_1::Request<B>) -> Self::Future {
+        let dispatcher = self.dispatcher.clone();
+        async move {
+            let response = dispatcher.dispatch(from_request(req)?).await?;
+            Ok(to_response(response))
+        }
+        .boxed()
+    }
+}
+
+/// Converts a hyper 1.x request into the request type of the dispatcher. The body is streamed,
+/// so the limits of the dispatcher on the request size still apply. The connection info of the
+/// request is kept, but request trailers and any other extensions are dropped.
+pub fn from_request<B>(req: http_1::Request<B>) -> http::Result<http::Request<hyper::Body>>
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    let (parts, body) = req.into_parts();
+    let chunks = BodyStream::new(body).filter_map(|frame| {
+        let chunk = match frame {
+            Ok(frame) => frame.into_data().ok().map(Ok),
+            Err(err) => Some(Err(err.into())),
+        };
+        future::ready::<Option<Result<Bytes, Box<dyn Error + Send + Sync>>>>(chunk)
+    });
+    from_parts(&parts, hyper::Body::wrap_stream(chunks))
+}
+
+/// Converts the parts of an `http` 1.x request and its body into the request type of the
+/// dispatcher, keeping the connection info of the request
+pub(crate) fn from_parts(
+    parts: &http_1::request::Parts,
+    body: hyper::Body,
+) -> http::Result<http::Request<hyper::Body>> {
+    let mut builder = http::Request::builder()
+        .method(parts.method.as_str())
+        .uri(parts.uri.to_string())
+        .version(from_version(parts.version));
+    for (name, value) in &parts.headers {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    if let Some(info) = parts.extensions.get::<ConnectionInfo>() {
+        builder = builder.extension(info.clone());
+    }
+    builder.body(body)
+}
+
+/// Converts a response of the dispatcher into a hyper 1.x response
+pub fn to_response(response: http::Response<hyper::Body>) -> http_1::Response<ResponseBody> {
+    let (parts, body) = response.into_parts();
+    let mut response = http_1::Response::new(ResponseBody::new(body));
+    *response.status_mut() = http_1::StatusCode::from_u16(parts.status.as_u16())
+        .unwrap_or(http_1::StatusCode::INTERNAL_SERVER_ERROR);
+    *response.version_mut() = to_version(parts.version);
+    *response.headers_mut() = to_header_map(&parts.headers);
+    response
+}
+
+fn from_version(version: http_1::Version) -> http::Version {
+    match version {
+        http_1::Version::HTTP_09 => http::Version::HTTP_09,
+        http_1::Version::HTTP_10 => http::Version::HTTP_10,
+        http_1::Version::HTTP_2 => http::Version::HTTP_2,
+        http_1::Version::HTTP_3 => http::Version::HTTP_3,
+        _ => http::Version::HTTP_11,
+    }
+}
+
+fn to_version(version: http::Version) -> http_1::Version {
+    match version {
+        http::Version::HTTP_09 => http_1::Version::HTTP_09,
+        http::Version::HTTP_10 => http_1::Version::HTTP_10,
+        http::Version::HTTP_2 => http_1::Version::HTTP_2,
+        http::Version::HTTP_3 => http_1::Version::HTTP_3,
+        _ => http_1::Version::HTTP_11,
+    }
+}
+
+/// Body of a response converted to hyper 1.x. It streams the chunks of the dispatcher response,
+/// followed by its trailers (i.e. the `Content-Digest` trailer of streamed bodies).
+#[derive(Debug)]
+pub struct ResponseBody {
+    body: hyper::Body,
+    data_done: bool,
+}
+
+impl ResponseBody {
+    fn new(body: hyper::Body) -> ResponseBody {
+        ResponseBody {
+            body,
+            data_done: false,
+        }
+    }
+}
+
+impl HttpBody for ResponseBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        if !self.data_done {
+            match Pin::new(&mut self.body).poll_data(cx) {
+                Poll::Ready(Some(chunk)) => return Poll::Ready(Some(chunk.map(Frame::data))),
+                Poll::Ready(None) => self.data_done = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Pin::new(&mut self.body).poll_trailers(cx).map(|trailers| {
+            trailers
+                .transpose()
+                .map(|trailers| trailers.map(|trailers| Frame::trailers(to_header_map(&trailers))))
+        })
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let hint = self.body.size_hint();
+        let mut size_hint = SizeHint::new();
+        size_hint.set_lower(hint.lower());
+        if let Some(upper) = hint.upper() {
+            size_hint.set_upper(upper);
+        }
+        size_hint
+    }
+}
+
+pub(crate) fn to_header_map(headers: &http::HeaderMap) -> http_1::HeaderMap {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let name = http_1::HeaderName::from_bytes(name.as_str().as_bytes()).ok()?;
+            let value = http_1::HeaderValue::from_bytes(value.as_bytes()).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::StreamingBody, owned_callback, Resource};
+    use expectest::prelude::*;
+    use http_body_util::{BodyExt, Full};
+    use hyper_1::service::Service;
+
+    #[tokio::test]
+    async fn dispatches_hyper_1_requests() {
+        let service = DispatcherService::new(Dispatcher {
+            routes: btreemap! {
+                "/echo" => Resource {
+                    allowed_methods: vec!["POST"],
+                    process_post: owned_callback(|context, _| {
+                        context.response.body = context.request.body.clone();
+                        Box::pin(async { Ok(true) })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        });
+        let request = http_1::Request::post("/echo")
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from_static(b"{\"id\": 1}")))
+            .unwrap();
+
+        let response = service.call(request).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        expect!(body).to(be_equal_to(Bytes::from_static(b"{\"id\": 1}")));
+    }
+
+    #[tokio::test]
+    async fn streams_response_chunks_and_trailers() {
+        let service = DispatcherService::new(Dispatcher {
+            routes: btreemap! {
+                "/export" => Resource {
+                    render_response: owned_callback(|context, _| {
+                        let chunks = vec![Ok(b"1,2\n".to_vec()), Ok(b"3,4\n".to_vec())];
+                        let mut body = StreamingBody::new(futures::stream::iter(chunks));
+                        body.trailer_digests = vec![crate::DigestAlgorithm::Sha256];
+                        context.response.stream = Some(body);
+                        Box::pin(async { None })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        });
+        let request = http_1::Request::get("/export")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+
+        let response = service.call(request).await.unwrap();
+        let collected = response.into_body().collect().await.unwrap();
+        expect!(collected
+            .trailers()
+            .map(|trailers| trailers.contains_key("content-digest")))
+        .to(be_some().value(true));
+        expect!(collected.to_bytes()).to(be_equal_to(Bytes::from_static(b"1,2\n3,4\n")));
+    }
+}
@@ -0,0 +1,141 @@
+//! The `layer` module lets a dispatcher sit inside a tower stack. `WebmachineLayer` wraps an
+//! inner service: requests that match a route of the dispatcher are dispatched to its resources,
+//! and all other requests are passed through to the inner service.
+
+use futures::future::BoxFuture;
+use hyper::{body::HttpBody, service::Service, Body};
+use std::{
+    error::Error,
+    task::{Context, Poll},
+};
+use tower_layer::Layer;
+
+use crate::{dispatcher::into_hyper_body, Dispatcher};
+
+/// Tower layer that serves the routes of the dispatcher in front of the wrapped service
+#[derive(Clone)]
+pub struct WebmachineLayer {
+    /// Dispatcher that serves the requests that match its routes
+    pub dispatcher: Dispatcher<'static>,
+}
+
+impl WebmachineLayer {
+    /// Creates a layer that serves the routes of the dispatcher
+    pub fn new(dispatcher: Dispatcher<'static>) -> WebmachineLayer {
+        WebmachineLayer { dispatcher }
+    }
+}
+
+impl<S> Layer<S> for WebmachineLayer {
+    type Service = WebmachineService<S>;
+
+    fn layer(&self, inner: S) -> WebmachineService<S> {
+        WebmachineService {
+            dispatcher: self.dispatcher.clone(),
+            inner,
+        }
+    }
+}
+
+/// Service created by `WebmachineLayer`
+#[derive(Clone)]
+pub struct WebmachineService<S> {
+    /// Dispatcher that serves the requests that match its routes
+    pub dispatcher: Dispatcher<'static>,
+    /// Service that the other requests are passed to
+    pub inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for WebmachineService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<Body>>,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    S::Future: Send + 'static,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Response = http::Response<Body>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let (parts, body) = req.into_parts();
+        let request = self.dispatcher.request_from_http_parts(&parts);
+        if self.dispatcher.longest_matching_path(&request).is_some() {
+            let dispatcher = self.dispatcher.clone();
+            let req = http::Request::from_parts(parts, into_hyper_body(body));
+            Box::pin(async move { dispatcher.dispatch(req).await.map_err(Into::into) })
+        } else {
+            let response = self.inner.call(http::Request::from_parts(parts, body));
+            Box::pin(async move { response.await.map_err(Into::into) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{owned_callback, Resource};
+    use expectest::prelude::*;
+    use std::{convert::Infallible, time::Duration};
+    use tower::{ServiceBuilder, ServiceExt};
+
+    fn dispatcher() -> Dispatcher<'static> {
+        Dispatcher {
+            routes: btreemap! {
+                "/orders" => Resource {
+                    allowed_methods: vec!["POST"],
+                    process_post: owned_callback(|context, _| {
+                        context.response.body = context.request.body.clone();
+                        Box::pin(async { Ok(true) })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        }
+    }
+
+    fn request(path: &str) -> http::Request<String> {
+        http::Request::post(path)
+            .header("Content-Type", "application/json")
+            .body("{\"id\": 1}".to_string())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn dispatcher_can_be_wrapped_by_tower_layers() {
+        let service = ServiceBuilder::new()
+            .timeout(Duration::from_secs(5))
+            .service(dispatcher());
+        let response = service.oneshot(request("/orders")).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        expect!(body.as_ref()).to(be_equal_to(b"{\"id\": 1}".as_ref()));
+    }
+
+    #[tokio::test]
+    async fn layer_passes_requests_that_do_not_match_a_route_to_the_inner_service() {
+        let fallback = tower::service_fn(|_: http::Request<String>| async {
+            Ok::<_, Infallible>(
+                http::Response::builder()
+                    .status(418)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        });
+        let service = ServiceBuilder::new()
+            .layer(WebmachineLayer::new(dispatcher()))
+            .service(fallback);
+
+        let response = service.clone().oneshot(request("/orders")).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        let response = service.oneshot(request("/legacy")).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(418));
+    }
+}
@@ -81,7 +81,9 @@
 //!             // default everything else
 //!             .. Resource::default()
 //!           }
-//!       }
+//!       },
+//!       // default everything else
+//!       .. Dispatcher::default()
 //!    }
 //!  }
 //! 
@@ -121,22 +123,27 @@ extern crate maplit;
 #[macro_use]
 extern crate lazy_static;
 
+use auth::{Authentication, Authorization};
 use chrono::{DateTime, FixedOffset, Utc};
-use context::{Context, Request, Response};
+use context::{Context, DecisionStep, Request, Response};
 use futures::{lock::Mutex, TryStreamExt};
-use headers::HeaderValue;
+use headers::{EntityTag, HeaderValue};
 use http::request::Parts;
 use hyper::service::Service;
 use itertools::Itertools;
 use std::{
+    borrow::Cow,
     collections::{BTreeMap, HashMap},
     future::Future,
     ops::Deref,
     pin::Pin,
     sync::Arc,
     task::Poll,
+    time::Instant,
 };
 
+pub mod auth;
+pub mod builtin;
 pub mod cache;
 
 mod dispatcher;
@@ -144,6 +151,7 @@ pub use self::dispatcher::*;
 
 mod enums;
 use self::enums::*;
+pub use self::enums::{DecisionId, DecisionResult};
 
 #[macro_use]
 pub mod headers;
@@ -154,6 +162,140 @@ pub mod context;
 mod resource;
 pub use self::resource::*;
 
+mod registry;
+pub use self::registry::*;
+
+mod diagnostics;
+pub use self::diagnostics::*;
+
+mod docs;
+pub use self::docs::*;
+
+mod openapi;
+pub use self::openapi::*;
+
+mod routing;
+pub use self::routing::*;
+
+mod path_params;
+pub use self::path_params::*;
+
+mod query;
+pub use self::query::*;
+
+mod percent;
+pub use self::percent::*;
+
+mod blocking;
+pub use self::blocking::*;
+
+mod method;
+pub use self::method::*;
+
+mod cors;
+pub use self::cors::*;
+
+mod tls;
+pub use self::tls::*;
+
+mod forwarded;
+pub use self::forwarded::*;
+
+mod redirect;
+pub use self::redirect::*;
+
+mod header_requirements;
+pub use self::header_requirements::*;
+
+mod rate_limit;
+pub use self::rate_limit::*;
+
+mod decision_log;
+pub use self::decision_log::*;
+
+mod redaction;
+pub use self::redaction::*;
+
+mod error_renderer;
+pub use self::error_renderer::*;
+
+mod locale;
+pub use self::locale::*;
+
+mod debugger;
+pub use self::debugger::*;
+
+mod access_log;
+pub use self::access_log::*;
+
+mod replay;
+pub use self::replay::*;
+
+mod metrics;
+pub use self::metrics::*;
+
+mod slo;
+pub use self::slo::*;
+
+mod bot_policy;
+pub use self::bot_policy::*;
+
+mod timeout;
+pub use self::timeout::*;
+
+mod availability;
+pub use self::availability::*;
+
+mod caching;
+pub use self::caching::*;
+
+mod compression;
+pub use self::compression::*;
+
+mod deployment;
+pub use self::deployment::*;
+
+mod chaos;
+pub use self::chaos::*;
+
+mod readiness;
+pub use self::readiness::*;
+
+mod telemetry;
+pub use self::telemetry::TraceContext;
+
+mod digest;
+pub use self::digest::*;
+
+mod coalesce;
+pub use self::coalesce::RequestCoalescer;
+use self::coalesce::Coalesced;
+
+#[cfg(feature = "signatures")]
+mod signature;
+#[cfg(feature = "signatures")]
+pub use self::signature::*;
+
+pub mod codec;
+
+pub mod transport;
+
+pub mod server;
+
+mod layer;
+pub use self::layer::*;
+
+#[cfg(feature = "hyper1")]
+pub mod hyper1;
+
+#[cfg(feature = "http3")]
+pub mod http3;
+
+#[cfg(feature = "acme")]
+pub mod acme;
+
+pub mod discovery;
+
 pub mod wamp {
     //! Wamp(v2) support
     pub use wampire::*;
@@ -183,6 +325,18 @@ where
     Arc::new(Mutex::new(Box::new(cb)))
 }
 
+/// Wrap an owned callback (for instance, a closure that captures its configuration) in a
+/// structure that is safe to call between threads
+pub fn owned_callback<'a, T, RT>(cb: T) -> Callback<'a, RT>
+where
+    T: Fn(&mut Context, &Resource) -> Pin<Box<dyn Future<Output = RT> + Send>>
+        + Send
+        + Sync
+        + 'a,
+{
+    Arc::new(Mutex::new(Box::new(cb)))
+}
+
 fn sanitise_path(path: &str) -> Vec<String> {
     path.split("/")
         .filter(|p| !p.is_empty())
@@ -209,7 +363,8 @@ fn join_paths(base: &Vec<String>, path: &Vec<String>) -> String {
 lazy_static! {
     static ref TRANSITION_MAP: HashMap<Decision, Transition> = hashmap! {
         Decision::Start => Transition::To(Decision::B13Available),
-        Decision::B3Options => Transition::Branch(Decision::A3Options, Decision::C3AcceptExists),
+        Decision::B3Options => Transition::Branch(Decision::A3Options, Decision::C2VariantSelected),
+        Decision::C2VariantSelected => Transition::Branch(Decision::E5AcceptCharsetExists, Decision::C3AcceptExists),
         Decision::B4RequestEntityTooLarge => Transition::Branch(Decision::End(413), Decision::B3Options),
         Decision::B5UnknownContentType => Transition::Branch(Decision::End(415), Decision::B4RequestEntityTooLarge),
         Decision::B6UnsupportedContentHeader => Transition::Branch(Decision::End(501), Decision::B5UnknownContentType),
@@ -219,7 +374,10 @@ lazy_static! {
         Decision::B10MethodAllowed => Transition::Branch(Decision::B9MalformedRequest, Decision::End(405)),
         Decision::B11UriTooLong => Transition::Branch(Decision::End(414), Decision::B10MethodAllowed),
         Decision::B12KnownMethod => Transition::Branch(Decision::B11UriTooLong, Decision::End(501)),
-        Decision::B13Available => Transition::Branch(Decision::B12KnownMethod, Decision::End(503)),
+        Decision::B13Available => Transition::Branch(Decision::B13aMisdirectedRequest, Decision::End(503)),
+        Decision::B13aMisdirectedRequest => Transition::Branch(Decision::End(421), Decision::B13bUpgradeRequired),
+        Decision::B13bUpgradeRequired => Transition::Branch(Decision::End(426), Decision::B13cRateLimited),
+        Decision::B13cRateLimited => Transition::Branch(Decision::End(429), Decision::B12KnownMethod),
         Decision::C3AcceptExists => Transition::Branch(Decision::C4AcceptableMediaTypeAvailable, Decision::D4AcceptLanguageExists),
         Decision::C4AcceptableMediaTypeAvailable => Transition::Branch(Decision::D4AcceptLanguageExists, Decision::End(406)),
         Decision::D4AcceptLanguageExists => Transition::Branch(Decision::D5AcceptableLanguageAvailable, Decision::E5AcceptCharsetExists),
@@ -245,7 +403,8 @@ lazy_static! {
         Decision::K5HasMovedPermanently => Transition::Branch(Decision::End(301), Decision::L5HasMovedTemporarily),
         Decision::K7ResourcePreviouslyExisted => Transition::Branch(Decision::K5HasMovedPermanently, Decision::L7Post),
         Decision::L5HasMovedTemporarily => Transition::Branch(Decision::End(307), Decision::M5Post),
-        Decision::L7Post => Transition::Branch(Decision::M7PostToMissingResource, Decision::End(404)),
+        Decision::L7Post => Transition::Branch(Decision::M7PostToMissingResource, Decision::L7aOtherMethod),
+        Decision::L7aOtherMethod => Transition::Branch(Decision::P11NewResource, Decision::End(404)),
         Decision::L13IfModifiedSinceExists => Transition::Branch(Decision::L14IfModifiedSinceValid, Decision::M16Delete),
         Decision::L14IfModifiedSinceValid => Transition::Branch(Decision::L15IfModifiedSinceGreaterThanNow, Decision::M16Delete),
         Decision::L15IfModifiedSinceGreaterThanNow => Transition::Branch(Decision::M16Delete, Decision::L17IfLastModifiedGreaterThanMS),
@@ -258,7 +417,8 @@ lazy_static! {
         Decision::N11Redirect => Transition::Branch(Decision::End(303), Decision::P11NewResource),
         Decision::N16Post => Transition::Branch(Decision::N11Redirect, Decision::O16Put),
         Decision::O14Conflict => Transition::Branch(Decision::End(409), Decision::P11NewResource),
-        Decision::O16Put => Transition::Branch(Decision::O14Conflict, Decision::O18MultipleRepresentations),
+        Decision::O16Put => Transition::Branch(Decision::O14Conflict, Decision::O16aOtherMethod),
+        Decision::O16aOtherMethod => Transition::Branch(Decision::P11NewResource, Decision::O18MultipleRepresentations),
         Decision::P3Conflict => Transition::Branch(Decision::End(409), Decision::P11NewResource),
         Decision::P11NewResource => Transition::Branch(Decision::End(201), Decision::O20ResponseHasBody),
         Decision::O18MultipleRepresentations => Transition::Branch(Decision::End(300), Decision::End(200)),
@@ -270,25 +430,56 @@ async fn resource_etag_matches_header_values(
     resource: &Resource<'_>,
     context: &mut Context,
     header: &str,
+    compare: fn(&EntityTag, &EntityTag) -> bool,
 ) -> bool {
     let header_values = context.request.find_header(header);
-    let callback = resource.generate_etag.lock().await;
-
-    match callback.deref()(context, resource).await {
+    match representation_etag(context, resource).await {
         Some(etag) => header_values
             .iter()
-            .find(|val| {
-                if val.value.starts_with("W/") {
-                    val.weak_etag().unwrap() == etag
-                } else {
-                    val.value == etag
-                }
-            })
-            .is_some(),
+            .any(|val| compare(&etag, &EntityTag::from_header_value(val))),
         None => false,
     }
 }
 
+/// Returns the ETag of the selected representation. A selected variant with its own ETag
+/// callback is used as is, otherwise the ETag of the resource is suffixed with the negotiated
+/// media type, language, charset and encoding if the resource has more than one representation.
+async fn representation_etag(context: &mut Context, resource: &Resource<'_>) -> Option<EntityTag> {
+    let variant = selected_variant(context, resource);
+    if let Some(etag) = variant.and_then(|variant| variant.etag.as_ref()) {
+        let callback = etag.lock().await;
+        return callback.deref()(context, resource)
+            .await
+            .map(|etag| EntityTag::parse_string(&etag));
+    }
+    let callback = resource.generate_etag.lock().await;
+    let mut etag = EntityTag::parse_string(&callback.deref()(context, resource).await?);
+    if has_multiple_representations(context, resource) {
+        let representation = [
+            &context.selected_media_type,
+            &context.selected_language,
+            &context.selected_charset,
+            &context.selected_encoding,
+        ]
+        .iter()
+        .filter_map(|selected| selected.as_deref())
+        .map(|selected| selected.replace(|c: char| c == '"' || c.is_whitespace(), ""))
+        .join(";");
+        if !representation.is_empty() {
+            etag.tag = format!("{};{}", etag.tag, representation);
+        }
+    }
+    Some(etag)
+}
+
+fn has_multiple_representations(context: &Context, resource: &Resource<'_>) -> bool {
+    resource.variants.len() > 1
+        || resource.produces_for(&context.request.method).len() > 1
+        || resource.languages_provided.len() > 1
+        || resource.charsets_provided.len() > 1
+        || resource.encodings_provided.len() > 1
+}
+
 fn validate_header_date(
     request: &Request,
     header: &str,
@@ -296,7 +487,7 @@ fn validate_header_date(
 ) -> bool {
     let header_values = request.find_header(header);
     if let Some(date_value) = header_values.first() {
-        match DateTime::parse_from_rfc2822(&date_value.value) {
+        match headers::parse_http_date(&date_value.value) {
             Ok(datetime) => {
                 *context_meta = Some(datetime.clone());
                 true
@@ -314,6 +505,65 @@ fn validate_header_date(
     }
 }
 
+/// Returns the request to negotiate the media type with. This is the request with its Accept
+/// header replaced if the resource has a format override and the request has the query parameter,
+/// or with the media types preferred by the class of client if it has no Accept header.
+fn media_type_request<'r>(context: &'r Context, resource: &Resource<'_>) -> Cow<'r, Request> {
+    resource
+        .format_override
+        .as_ref()
+        .and_then(|format_override| format_override.apply(&context.request))
+        .or_else(|| {
+            let negotiation = resource.user_agent_negotiation.as_ref()?;
+            negotiation.apply(&context.request, context.user_agent_class?)
+        })
+        .map(Cow::Owned)
+        .unwrap_or(Cow::Borrowed(&context.request))
+}
+
+/// Adds an Allow header with the methods allowed on the resource
+fn add_allow_header(context: &mut Context, resource: &Resource<'_>) {
+    context.response.add_header(
+        "Allow",
+        resource
+            .allowed_method_names()
+            .into_iter()
+            .map(HeaderValue::basic)
+            .collect(),
+    );
+}
+
+/// Sets an RFC 7807 problem details body on the response, with any extension members
+fn set_problem_body(
+    context: &mut Context,
+    status: u16,
+    detail: &str,
+    extensions: serde_json::Value,
+) {
+    let mut problem = problem_document(status, Some(detail));
+    if let (Some(problem), serde_json::Value::Object(extensions)) =
+        (problem.as_object_mut(), extensions)
+    {
+        problem.extend(extensions);
+    }
+    context.response.add_header(
+        "Content-Type",
+        vec![HeaderValue::basic("application/problem+json")],
+    );
+    context.response.body = Some(problem.to_string().into_bytes());
+}
+
+/// Ends the request with a problem response if a resource callback failed to extract a path
+/// parameter with `Context::path_param`, returning the status of the response
+fn path_param_failure(context: &mut Context, resource: &Resource<'_>) -> Option<u16> {
+    let err = context.path_param_error.take()?;
+    let status = err.status(resource.invalid_path_param_status);
+    debug!("Ending the request with {} as {}", status, err);
+    context.response.status = status;
+    set_problem_body(context, status, &err.to_string(), serde_json::json!({}));
+    Some(status)
+}
+
 async fn execute_decision(
     decision: &Decision,
     context: &mut Context,
@@ -322,23 +572,15 @@ async fn execute_decision(
     match decision {
         Decision::B10MethodAllowed => {
             match resource
-                .allowed_methods
+                .allowed_known_methods()
                 .iter()
-                .find(|m| m.to_uppercase() == context.request.method.to_uppercase())
+                .find(|m| m.name.eq_ignore_ascii_case(&context.request.method))
             {
                 Some(_) => {
                     DecisionResult::True("method is in the list of allowed methods".to_string())
                 }
                 None => {
-                    context.response.add_header(
-                        "Allow",
-                        resource
-                            .allowed_methods
-                            .iter()
-                            .cloned()
-                            .map(HeaderValue::basic)
-                            .collect(),
-                    );
+                    add_allow_header(context, resource);
                     DecisionResult::False(
                         "method is not in the list of allowed methods".to_string(),
                     )
@@ -349,19 +591,76 @@ async fn execute_decision(
             let callback = resource.uri_too_long.lock().await;
             DecisionResult::wrap(callback.deref()(context, resource).await, "URI too long")
         }
-        Decision::B12KnownMethod => DecisionResult::wrap(
-            resource
-                .known_methods
-                .iter()
-                .find(|m| m.to_uppercase() == context.request.method.to_uppercase())
-                .is_some(),
-            "known method",
-        ),
+        Decision::B12KnownMethod => {
+            if resource.known_methods.contains(&context.request.method) {
+                return DecisionResult::wrap(true, "known method");
+            }
+            let status = resource.unknown_method_status;
+            add_allow_header(context, resource);
+            set_problem_body(
+                context,
+                status,
+                &format!("Method '{}' is not supported", context.request.method),
+                serde_json::json!({ "allowed_methods": resource.allowed_method_names() }),
+            );
+            if status == 501 {
+                DecisionResult::wrap(false, "known method")
+            } else {
+                DecisionResult::StatusCode(status)
+            }
+        }
         Decision::B13Available => {
             let callback = resource.available.lock().await;
             DecisionResult::wrap(callback.deref()(context, resource).await, "available")
         }
+        Decision::B13aMisdirectedRequest => {
+            let callback = resource.misdirected_request.lock().await;
+            DecisionResult::wrap(callback.deref()(context, resource).await, "misdirected request")
+        }
+        Decision::B13bUpgradeRequired => {
+            let callback = resource.upgrade_required.lock().await;
+            match callback.deref()(context, resource).await {
+                Some(protocols) => {
+                    context
+                        .response
+                        .add_header("Upgrade", HeaderValue::parse_list(&protocols));
+                    context.response.add_header("Connection", vec![h!("Upgrade")]);
+                    DecisionResult::True(format!("upgrade to {} required", protocols))
+                }
+                None => DecisionResult::False("upgrade not required".to_string()),
+            }
+        }
+        Decision::B13cRateLimited => match &resource.rate_limiter {
+            Some(rate_limiter) if !check_rate_limit(rate_limiter, context) => {
+                DecisionResult::True("rate limit exceeded".to_string())
+            }
+            _ => DecisionResult::False("within rate limit".to_string()),
+        },
         Decision::B9MalformedRequest => {
+            for requirement in &resource.required_headers {
+                if let Err((status, detail)) = requirement.check(&context.request) {
+                    debug!("Request failed a header requirement: {}", detail);
+                    set_problem_body(
+                        context,
+                        status,
+                        &detail,
+                        serde_json::json!({ "header": requirement.name }),
+                    );
+                    context.error = Some(detail.clone());
+                    return if status == 400 {
+                        DecisionResult::True(detail)
+                    } else {
+                        DecisionResult::StatusCode(status)
+                    };
+                }
+            }
+            if let Some(digest) = &resource.digest {
+                if let Err(reason) = digest.validate_request(&context.request) {
+                    debug!("Request failed digest validation: {}", reason);
+                    context.error = Some(reason.clone());
+                    return DecisionResult::True(reason);
+                }
+            }
             let callback = resource.malformed_request.lock().await;
             DecisionResult::wrap(
                 callback.deref()(context, resource).await,
@@ -369,6 +668,29 @@ async fn execute_decision(
             )
         }
         Decision::B8Authorized => {
+            if !resource.authenticators.is_empty() {
+                match authenticate(context, resource).await {
+                    Authentication::Authenticated(principal) => {
+                        // namespaced, so the claims of a token can not replace other metadata
+                        context.metadata.extend(principal.attributes.iter().map(
+                            |(name, value)| (format!("principal.{}", name), value.clone()),
+                        ));
+                        context.principal = Some(principal);
+                    }
+                    Authentication::Forbidden(reason) => {
+                        debug!("Credentials are not allowed to access the resource: {}", reason);
+                        return DecisionResult::StatusCode(403);
+                    }
+                    Authentication::Failed(reason) => {
+                        add_authentication_challenges(context, resource);
+                        return DecisionResult::False(reason);
+                    }
+                    Authentication::NoCredentials => {
+                        add_authentication_challenges(context, resource);
+                        return DecisionResult::False("no credentials were provided".to_string());
+                    }
+                }
+            }
             let callback = resource.not_authorized.lock().await;
             match callback.deref()(context, resource).await {
                 Some(realm) => {
@@ -382,6 +704,21 @@ async fn execute_decision(
             }
         }
         Decision::B7Forbidden => {
+            if let (Some(authorizer), false) =
+                (&resource.authorizer, resource.required_permissions.is_empty())
+            {
+                let authorization = authorizer.authorize(
+                    context.principal.as_ref(),
+                    &resource.required_permissions,
+                    &context.request,
+                );
+                if let Authorization::Denied(detail) = authorization.await {
+                    if let Some(detail) = detail {
+                        set_problem_body(context, 403, &detail, serde_json::json!({}));
+                    }
+                    return DecisionResult::True("principal is not authorized".to_string());
+                }
+            }
             let callback = resource.forbidden.lock().await;
             DecisionResult::wrap(callback.deref()(context, resource).await, "forbidden")
         }
@@ -395,13 +732,21 @@ async fn execute_decision(
         Decision::B5UnknownContentType => DecisionResult::wrap(
             context.request.is_put_or_post()
                 && resource
-                    .acceptable_content_types
+                    .acceptable_content_types_for(&context.request.method)
                     .iter()
                     .find(|ct| context.request.content_type().to_uppercase() == ct.to_uppercase())
                     .is_none(),
             "acceptable content types",
         ),
         Decision::B4RequestEntityTooLarge => {
+            if let Some(max_entity_length) = resource.max_entity_length {
+                let length = context.request.content_length().or_else(|| {
+                    context.request.body.as_ref().map(|body| body.len() as u64)
+                });
+                if length.unwrap_or(0) > max_entity_length {
+                    return DecisionResult::True("entity exceeds maximum length".to_string());
+                }
+            }
             let callback = resource.valid_entity_length.lock().await;
             DecisionResult::wrap(
                 context.request.is_put_or_post() && !callback.deref()(context, resource).await,
@@ -409,11 +754,36 @@ async fn execute_decision(
             )
         }
         Decision::B3Options => DecisionResult::wrap(context.request.is_options(), "options"),
-        Decision::C3AcceptExists => {
-            DecisionResult::wrap(context.request.has_accept_header(), "has accept header")
+        Decision::C2VariantSelected => {
+            if resource.variants.is_empty() {
+                return DecisionResult::False("no variants declared".to_string());
+            }
+            let request = media_type_request(context, resource);
+            if request.has_accept_header() && request.accept().is_empty() {
+                return DecisionResult::StatusCode(406);
+            }
+            match content_negotiation::matching_variant(resource, &request) {
+                Some(index) => {
+                    let variant = &resource.variants[index];
+                    context.selected_media_type = Some(variant.media_type.to_string());
+                    if let Some(language) = variant.language {
+                        context.selected_language = Some(language.to_string());
+                        context
+                            .response
+                            .add_header("Content-Language", vec![HeaderValue::basic(language)]);
+                    }
+                    DecisionResult::True(format!("variant {} selected", index))
+                }
+                None => DecisionResult::StatusCode(406),
+            }
         }
+        Decision::C3AcceptExists => DecisionResult::wrap(
+            media_type_request(context, resource).has_accept_header(),
+            "has accept header",
+        ),
         Decision::C4AcceptableMediaTypeAvailable => {
-            match content_negotiation::matching_content_type(resource, &context.request) {
+            let request = media_type_request(context, resource);
+            match content_negotiation::matching_content_type(resource, &request) {
                 Some(media_type) => {
                     context.selected_media_type = Some(media_type);
                     DecisionResult::True("acceptable media type is available".to_string())
@@ -486,7 +856,13 @@ async fn execute_decision(
             "match star exists",
         ),
         Decision::G11EtagInIfMatch => DecisionResult::wrap(
-            resource_etag_matches_header_values(resource, context, "If-Match").await,
+            resource_etag_matches_header_values(
+                resource,
+                context,
+                "If-Match",
+                EntityTag::strong_eq,
+            )
+            .await,
             "etag in if match",
         ),
         Decision::H10IfUnmodifiedSinceExists => DecisionResult::wrap(
@@ -532,9 +908,10 @@ async fn execute_decision(
             context.request.has_header_value("If-None-Match", "*"),
             "none match star exists",
         ),
-        Decision::J18GetHead => {
-            DecisionResult::wrap(context.request.is_get_or_head(), "is GET or HEAD request")
-        }
+        Decision::J18GetHead => DecisionResult::wrap(
+            resource.known_methods.is_cacheable(&context.request.method),
+            "cacheable method",
+        ),
         Decision::K7ResourcePreviouslyExisted => {
             let callback = resource.previously_existed.lock().await;
             DecisionResult::wrap(
@@ -543,7 +920,13 @@ async fn execute_decision(
             )
         }
         Decision::K13ETagInIfNoneMatch => DecisionResult::wrap(
-            resource_etag_matches_header_values(resource, context, "If-None-Match").await,
+            resource_etag_matches_header_values(
+                resource,
+                context,
+                "If-None-Match",
+                EntityTag::weak_eq,
+            )
+            .await,
             "ETag in if none match",
         ),
         Decision::L5HasMovedTemporarily => {
@@ -670,6 +1053,16 @@ async fn execute_decision(
             }
         }
         Decision::O16Put => DecisionResult::wrap(context.request.is_put(), "a PUT request"),
+        Decision::L7aOtherMethod | &Decision::O16aOtherMethod => {
+            let callback = resource.process_other_method.lock().await;
+            match callback.deref()(context, resource).await {
+                Ok(created) => {
+                    context.new_resource = created;
+                    DecisionResult::wrap(created, "new resource created by the method")
+                }
+                Err(status) => DecisionResult::StatusCode(status),
+            }
+        }
         Decision::O18MultipleRepresentations => {
             let callback = resource.multiple_choices.lock().await;
             DecisionResult::wrap(
@@ -684,27 +1077,179 @@ async fn execute_decision(
     }
 }
 
-async fn execute_state_machine(context: &mut Context, resource: &Resource<'_>) {
-    let mut state = Decision::Start;
-    let mut decisions: Vec<(Decision, bool, Decision)> = Vec::new();
-    let mut loop_count = 0;
-    while !state.is_terminal() {
-        loop_count += 1;
-        if loop_count >= MAX_STATE_MACHINE_TRANSITIONS {
-            panic!(
-                "State machine has not terminated within {} transitions!",
-                loop_count
+/// Authenticates the request with the authenticators of the resource. The result is from the
+/// first authenticator that accepts (or forbids) the credentials of the request, otherwise the
+/// first failure.
+async fn authenticate(context: &Context, resource: &Resource<'_>) -> Authentication {
+    let mut failure = None;
+    for authenticator in &resource.authenticators {
+        match authenticator.authenticate(&context.request).await {
+            Authentication::NoCredentials => (),
+            Authentication::Failed(reason) => {
+                failure.get_or_insert(Authentication::Failed(reason));
+            }
+            result => return result,
+        }
+    }
+    failure.unwrap_or(Authentication::NoCredentials)
+}
+
+fn add_authentication_challenges(context: &mut Context, resource: &Resource<'_>) {
+    let challenges = resource
+        .authenticators
+        .iter()
+        .map(|authenticator| HeaderValue::basic(authenticator.challenge()))
+        .collect();
+    context.response.add_header("WWW-Authenticate", challenges);
+}
+
+// decision, outcome, next state, reason and how long the decision took to execute
+type DecisionRecord = (Decision, bool, Decision, String, std::time::Duration);
+
+/// The state machine for a request against a resource, which can be driven one decision at a
+/// time. This allows embedders to inspect the current decision before it is executed, and to
+/// inject the outcome of a decision instead of executing it. Use `run_to_completion` to simply
+/// execute all the decisions.
+pub struct StateMachine<'c, 'r, 'a> {
+    context: &'c mut Context,
+    resource: &'r Resource<'a>,
+    state: Decision,
+    decisions: Vec<DecisionRecord>,
+    transitions: usize,
+    max_transitions: usize,
+    started: Instant,
+    complete: bool,
+}
+
+impl<'c, 'r, 'a> StateMachine<'c, 'r, 'a> {
+    /// Creates a state machine for the request in the context, starting at the start state
+    pub fn new(context: &'c mut Context, resource: &'r Resource<'a>) -> StateMachine<'c, 'r, 'a> {
+        StateMachine {
+            context,
+            resource,
+            state: Decision::Start,
+            decisions: Vec::new(),
+            transitions: 0,
+            max_transitions: MAX_STATE_MACHINE_TRANSITIONS,
+            started: Instant::now(),
+            complete: false,
+        }
+    }
+
+    /// Sets the maximum number of transitions the state machine may make before it is stopped
+    /// with a '500 Internal Server Error' response. Defaults to 100.
+    pub fn with_max_transitions(mut self, max_transitions: usize) -> StateMachine<'c, 'r, 'a> {
+        self.max_transitions = max_transitions;
+        self
+    }
+
+    /// Decision that the next step will execute, or None before the first step and once an end
+    /// state has been reached
+    pub fn current_decision(&self) -> Option<DecisionId> {
+        self.state.id()
+    }
+
+    /// If a terminal state has been reached and the response status set
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Context of the request the state machine is executing against
+    pub fn context(&self) -> &Context {
+        self.context
+    }
+
+    /// Mutable context of the request the state machine is executing against
+    pub fn context_mut(&mut self) -> &mut Context {
+        self.context
+    }
+
+    /// Executes the current decision and transitions to the next one. When a terminal state is
+    /// reached, the response status is set. Does nothing if the state machine is complete.
+    pub async fn step(&mut self) {
+        self.advance(None).await;
+    }
+
+    /// Transitions from the current decision using the outcome, instead of executing the
+    /// decision. Does nothing if the state machine is complete.
+    pub async fn step_with(&mut self, outcome: DecisionResult) {
+        self.advance(Some(outcome)).await;
+    }
+
+    /// Executes all the remaining decisions
+    pub async fn run_to_completion(mut self) {
+        while !self.complete {
+            self.advance(None).await;
+        }
+    }
+
+    /// Returns the decision to transition to. The decisions for methods the state machine does
+    /// not model are skipped for requests with a method it does, so their traces are unchanged.
+    fn next_decision(&self, decision: &Decision) -> Decision {
+        match (decision, TRANSITION_MAP.get(decision)) {
+            (
+                Decision::L7aOtherMethod | Decision::O16aOtherMethod,
+                Some(Transition::Branch(_, decision_false)),
+            ) if is_modelled_method(&self.context.request.method) => decision_false.clone(),
+            _ => decision.clone(),
+        }
+    }
+
+    async fn advance(&mut self, outcome: Option<DecisionResult>) {
+        if self.complete {
+            return;
+        }
+        let state = self.state.clone();
+        if self.transitions >= self.max_transitions {
+            let path = self
+                .decisions
+                .iter()
+                .rev()
+                .take(10)
+                .map(|(decision, result, ..)| format!("{:?}={}", decision, result))
+                .collect::<Vec<String>>();
+            error!(
+                "State machine for {} {} has not terminated within {} transitions, it is at \
+                 {:?} after the decisions (latest first) {}",
+                self.context.request.method,
+                self.context.request.request_path,
+                self.max_transitions,
+                state,
+                path.join(", ")
             );
+            let reason = "too many transitions".to_string();
+            let elapsed = std::time::Duration::ZERO;
+            self.decisions.push((state, false, Decision::End(500), reason, elapsed));
+            self.context.error = Some(format!(
+                "State machine has not terminated within {} transitions",
+                self.max_transitions
+            ));
+            self.state = Decision::End(500);
+            self.finish().await;
+            return;
         }
+        self.transitions += 1;
         trace!("state is {:?}", state);
-        state = match TRANSITION_MAP.get(&state) {
+        self.state = match TRANSITION_MAP.get(&state) {
             Some(transition) => match transition {
                 &Transition::To(ref decision) => {
                     trace!("Transitioning to {:?}", decision);
                     decision.clone()
                 }
                 &Transition::Branch(ref decision_true, ref decision_false) => {
-                    match execute_decision(&state, context, resource).await {
+                    let decision_true = self.next_decision(decision_true);
+                    let decision_false = self.next_decision(decision_false);
+                    let executed = Instant::now();
+                    let result = match outcome {
+                        Some(outcome) => outcome,
+                        None => execute_decision(&state, self.context, self.resource).await,
+                    };
+                    let result = match path_param_failure(self.context, self.resource) {
+                        Some(status) => DecisionResult::StatusCode(status),
+                        None => result,
+                    };
+                    let elapsed = executed.elapsed();
+                    match result {
                         DecisionResult::True(reason) => {
                             trace!(
                                 "Transitioning from {:?} to {:?} as decision is true -> {}",
@@ -712,7 +1257,8 @@ async fn execute_state_machine(context: &mut Context, resource: &Resource<'_>) {
                                 decision_true,
                                 reason
                             );
-                            decisions.push((state, true, decision_true.clone()));
+                            let next = decision_true.clone();
+                            self.decisions.push((state, true, next, reason, elapsed));
                             decision_true.clone()
                         }
                         DecisionResult::False(reason) => {
@@ -722,7 +1268,8 @@ async fn execute_state_machine(context: &mut Context, resource: &Resource<'_>) {
                                 decision_false,
                                 reason
                             );
-                            decisions.push((state, false, decision_false.clone()));
+                            let next = decision_false.clone();
+                            self.decisions.push((state, false, next, reason, elapsed));
                             decision_false.clone()
                         }
                         DecisionResult::StatusCode(code) => {
@@ -732,7 +1279,8 @@ async fn execute_state_machine(context: &mut Context, resource: &Resource<'_>) {
                                 state,
                                 decision
                             );
-                            decisions.push((state, false, decision.clone()));
+                            let reason = format!("status code {}", code);
+                            self.decisions.push((state, false, decision.clone(), reason, elapsed));
                             decision.clone()
                         }
                     }
@@ -743,31 +1291,91 @@ async fn execute_state_machine(context: &mut Context, resource: &Resource<'_>) {
                     "Error transitioning from {:?}, the TRANSITION_MAP is mis-configured",
                     state
                 );
-                decisions.push((state, false, Decision::End(500)));
+                let reason = "no transition".to_string();
+                let elapsed = std::time::Duration::ZERO;
+                self.decisions.push((state, false, Decision::End(500), reason, elapsed));
                 Decision::End(500)
             }
+        };
+        if self.state.is_terminal() {
+            self.finish().await;
         }
     }
-    trace!("Final state is {:?}", state);
-    match state {
-        Decision::End(status) => context.response.status = status,
-        Decision::A3Options => {
-            context.response.status = 204;
-            let callback = resource.options.lock().await;
-            match callback.deref()(context, resource).await {
-                Some(headers) => context.response.add_headers(headers),
-                None => (),
+
+    async fn finish(&mut self) {
+        let (context, resource) = (&mut *self.context, self.resource);
+        trace!("Final state is {:?}", self.state);
+        match self.state {
+            Decision::End(status) => context.response.status = status,
+            Decision::A3Options => {
+                context.response.status = 204;
+                let callback = resource.options.lock().await;
+                match callback.deref()(context, resource).await {
+                    Some(headers) => context.response.add_headers(headers),
+                    None => (),
+                }
             }
+            _ => (),
         }
-        _ => (),
+        context.terminal_decision = self.decisions.last().and_then(|(decision, ..)| decision.id());
+        context.decisions_executed = self.decisions.len();
+        context.decision_trace = self
+            .decisions
+            .iter()
+            .filter_map(|(decision, outcome, next, reason, duration)| {
+                Some(DecisionStep {
+                    decision: decision.id()?,
+                    outcome: *outcome,
+                    next: next.id(),
+                    reason: reason.clone(),
+                    duration: *duration,
+                })
+            })
+            .collect();
+        if let Some(config) = &resource.decision_log {
+            log_decisions(config, context, &self.decisions, self.started.elapsed());
+        }
+        self.complete = true;
+    }
+}
+
+/// Runs the state machine for the resource to completion, with the default transition limit
+pub async fn execute_state_machine(context: &mut Context, resource: &Resource<'_>) {
+    StateMachine::new(context, resource).run_to_completion().await;
+}
+
+fn log_decisions(
+    config: &DecisionLogConfig,
+    context: &Context,
+    decisions: &[DecisionRecord],
+    elapsed: std::time::Duration,
+) {
+    let reason = match config.should_log(context.response.status, elapsed) {
+        Some(reason) => reason,
+        None => return,
+    };
+    log!(
+        config.level,
+        "Decisions for {} {} -> {} in {:?} ({:?})",
+        context.request.method,
+        context.request.request_path,
+        context.response.status,
+        elapsed,
+        reason
+    );
+    for (decision, result, next, reason, _) in decisions {
+        let level = decision.id().map(|id| config.level_for(id)).unwrap_or(config.level);
+        log!(level, "  {:?} -> {} -> {:?} ({})", decision, result, next, reason);
     }
 }
 
 fn update_paths_for_resource(request: &mut Request, base_path: &str) {
     request.base_path = base_path.into();
+    // routes are matched against the decoded path, so they are decoded in the same way
+    let base_path = decode_path(base_path);
     if request.request_path.len() > base_path.len() {
         let request_path = request.request_path.clone();
-        let subpath = request_path.split_at(base_path.len()).1;
+        let subpath = request_path.get(base_path.len()..).unwrap_or_default();
         if subpath.starts_with("/") {
             request.request_path = subpath.to_string();
         } else {
@@ -778,70 +1386,12 @@ fn update_paths_for_resource(request: &mut Request, base_path: &str) {
     }
 }
 
-fn parse_header_values(value: &str) -> Vec<HeaderValue> {
-    if value.is_empty() {
-        Vec::new()
-    } else {
-        value
-            .split(',')
-            .map(|s| HeaderValue::parse_string(s.trim()))
-            .collect()
-    }
-}
-
 fn headers_from_http_request(req: &Parts) -> HashMap<String, Vec<HeaderValue>> {
-    req.headers
-        .iter()
-        .map(|(name, value)| {
-            (
-                name.to_string(),
-                parse_header_values(value.to_str().unwrap_or_default()),
-            )
-        })
-        .collect()
+    headers::from_header_map(&req.headers)
 }
 
 fn decode_query(query: &str) -> String {
-    let mut chars = query.chars();
-    let mut ch = chars.next();
-    let mut result = String::new();
-
-    while ch.is_some() {
-        let c = ch.unwrap();
-        if c == '%' {
-            let c1 = chars.next();
-            let c2 = chars.next();
-            match (c1, c2) {
-                (Some(v1), Some(v2)) => {
-                    let mut s = String::new();
-                    s.push(v1);
-                    s.push(v2);
-                    let decoded: Result<Vec<u8>, _> = hex::decode(s);
-                    match decoded {
-                        Ok(n) => result.push(n[0] as char),
-                        Err(_) => {
-                            result.push('%');
-                            result.push(v1);
-                            result.push(v2);
-                        }
-                    }
-                }
-                (Some(v1), None) => {
-                    result.push('%');
-                    result.push(v1);
-                }
-                _ => result.push('%'),
-            }
-        } else if c == '+' {
-            result.push(' ');
-        } else {
-            result.push(c);
-        }
-
-        ch = chars.next();
-    }
-
-    result
+    PercentDecoder::query().decode_lossy(query)
 }
 
 fn parse_query(query: &str) -> HashMap<String, Vec<String>> {
@@ -860,6 +1410,7 @@ fn parse_query(query: &str) -> HashMap<String, Vec<String>> {
             .fold(HashMap::new(), |mut map, name_value| {
                 if !name_value.is_empty() {
                     let name = decode_query(name_value[0]);
+                    let name = query::list_key(&name).to_string();
                     let value = if name_value.len() > 1 {
                         decode_query(name_value[1])
                     } else {
@@ -874,21 +1425,95 @@ fn parse_query(query: &str) -> HashMap<String, Vec<String>> {
     }
 }
 
+/// Counts the request against the rate limiter, adding the Retry-After header if the request is
+/// over the limit. Returns false if the request is over the limit.
+fn check_rate_limit(rate_limiter: &RateLimiter, context: &mut Context) -> bool {
+    match rate_limiter.check(&context.request) {
+        RateLimitDecision::Allowed(_) => true,
+        RateLimitDecision::Limited(retry_after) => {
+            context.response.add_header(
+                "Retry-After",
+                vec![HeaderValue::basic(retry_after_seconds(retry_after).to_string())],
+            );
+            false
+        }
+    }
+}
+
+/// Returns the declared variant of the resource that was selected by content negotiation
+fn selected_variant<'r, 'a>(
+    context: &Context,
+    resource: &'r Resource<'a>,
+) -> Option<&'r Variant<'a>> {
+    let media_type = context.selected_media_type.as_deref()?;
+    resource.variants.iter().find(|variant| {
+        variant.media_type == media_type
+            && variant.language.map(str::to_string) == context.selected_language
+    })
+}
+
+/// Returns the request headers that content negotiation for the resource depends on, which the
+/// response varies on in addition to the `variances` of the resource
+pub(crate) fn negotiated_headers(context: &Context, resource: &Resource<'_>) -> Vec<&'static str> {
+    let mut headers = Vec::new();
+    if resource.languages_provided.len() > 1 {
+        headers.push("Accept-Language");
+    }
+    if resource.charsets_provided.len() > 1 {
+        headers.push("Accept-Charset");
+    }
+    if resource.encodings_provided.len() > 1 {
+        headers.push("Accept-Encoding");
+    }
+    // A response selected with the format query parameter does not depend on the Accept header,
+    // and the parameter is part of the URL the response is cached against
+    let format_requested = resource
+        .format_override
+        .as_ref()
+        .and_then(|format_override| format_override.requested_format(&context.request))
+        .is_some();
+    if !format_requested
+        && (resource.produces_for(&context.request.method).len() > 1
+            || resource.variants.iter().map(|v| v.media_type).unique().count() > 1)
+    {
+        headers.push("Accept");
+        if resource.user_agent_negotiation.is_some() && context.user_agent_class.is_some() {
+            headers.push("User-Agent");
+        }
+    }
+    if resource.variants.iter().map(|v| v.language).unique().count() > 1 {
+        headers.push("Accept-Language");
+    }
+    headers.into_iter().unique().collect()
+}
+
 async fn finalise_response(context: &mut Context, resource: &Resource<'_>) {
     if !context.response.has_header("Content-Type") {
         let media_type = match &context.selected_media_type {
             &Some(ref media_type) => media_type.clone(),
             &None => "application/json".to_string(),
         };
-        let charset = match &context.selected_charset {
-            &Some(ref charset) => charset.clone(),
-            &None => "ISO-8859-1".to_string(),
-        };
-        let header = HeaderValue {
-            value: media_type,
-            params: hashmap! { "charset".to_string() => charset },
-            quote: false,
+        // the parameters of the media type (i.e. a version) are kept, along with any charset it
+        // declares if none was negotiated
+        let mut header = HeaderValue::parse_string(&media_type);
+        let declared_charset = header
+            .params
+            .keys()
+            .find(|name| name.eq_ignore_ascii_case("charset"))
+            .cloned();
+        let charset = match (&context.selected_charset, declared_charset) {
+            (Some(charset), declared) => {
+                if let Some(name) = declared {
+                    header.params.remove(&name);
+                }
+                Some(charset.clone())
+            }
+            (None, Some(_)) => None,
+            (None, None) => Some("ISO-8859-1".to_string()),
         };
+        if let Some(charset) = charset {
+            header.params.insert("charset".to_string(), charset);
+        }
         context.response.add_header("Content-Type", vec![header]);
     }
 
@@ -902,34 +1527,21 @@ async fn finalise_response(context: &mut Context, resource: &Resource<'_>) {
         Vec::new()
     };
 
-    if resource.languages_provided.len() > 1 {
-        vary_header.push(h!("Accept-Language"));
-    }
-    if resource.charsets_provided.len() > 1 {
-        vary_header.push(h!("Accept-Charset"));
-    }
-    if resource.encodings_provided.len() > 1 {
-        vary_header.push(h!("Accept-Encoding"));
-    }
-    if resource.produces.len() > 1 {
-        vary_header.push(h!("Accept"));
-    }
+    vary_header.extend(
+        negotiated_headers(context, resource)
+            .into_iter()
+            .map(HeaderValue::basic),
+    );
 
-    if vary_header.len() > 1 {
+    if !vary_header.is_empty() {
         context
             .response
             .add_header("Vary", vary_header.iter().cloned().unique().collect());
     }
 
-    if context.request.is_get_or_head() {
-        {
-            let callback = resource.generate_etag.lock().await;
-            match callback.deref()(context, resource).await {
-                Some(etag) => context
-                    .response
-                    .add_header("ETag", vec![HeaderValue::basic(&etag).quote()]),
-                None => (),
-            }
+    if resource.known_methods.is_cacheable(&context.request.method) {
+        if let Some(etag) = representation_etag(context, resource).await {
+            context.response.add_header("ETag", vec![etag.to_header_value()]);
         }
         {
             let callback = resource.expires.lock().await;
@@ -955,22 +1567,188 @@ async fn finalise_response(context: &mut Context, resource: &Resource<'_>) {
 
     if context.response.body.is_none() && context.response.status == 200 && context.request.is_get()
     {
-        let callback = resource.render_response.lock().await;
-        match callback.deref()(context, resource).await {
-            Some(body) => context.response.body = Some(body.into_bytes()),
+        let variant_render = selected_variant(context, resource)
+            .and_then(|variant| variant.render.as_ref());
+        let body = match (variant_render, &resource.render_value) {
+            (None, Some(render_value)) => encode_value(context, resource, render_value).await,
+            _ => {
+                let render = variant_render.unwrap_or(&resource.render_response);
+                let callback = render.lock().await;
+                callback.deref()(context, resource)
+                    .await
+                    .map(String::into_bytes)
+            }
+        };
+        match body {
+            Some(body) => {
+                if context.memory.allocate(body.len()) {
+                    context.response.body = Some(body);
+                } else {
+                    warn!(
+                        "Response body exceeds the request memory limit of {:?} bytes",
+                        context.memory.limit
+                    );
+                    context.response.status = 500;
+                    context.error =
+                        Some("Response body exceeds the request memory limit".to_string());
+                }
+            }
             None => (),
         }
+        path_param_failure(context, resource);
+    }
+
+    // Compressed before the ETag of the body is computed, so each encoding gets its own ETag, and
+    // before the digest headers are added, so Content-Digest covers the body as sent
+    if let Some(compression) = &resource.compression {
+        let negotiated_encoding = context.selected_encoding.clone();
+        compression.apply(context);
+        // the ETag of the resource was computed for the negotiated encoding, so if compression
+        // was skipped it is computed again for the identity encoding the body is sent with
+        if context.selected_encoding != negotiated_encoding
+            && context.response.remove_header("ETag").is_some()
+        {
+            if let Some(etag) = representation_etag(context, resource).await {
+                context.response.add_header("ETag", vec![etag.to_header_value()]);
+            }
+        }
     }
 
-    match &resource.finalise_response {
-        Some(callback) => {
-            let callback = callback.lock().await;
-            callback.deref()(context, resource);
+    if let Some(caching) = &resource.caching {
+        let cacheable = resource.known_methods.is_cacheable(&context.request.method);
+        caching.apply(context, cacheable);
+    }
+
+    // OPTIONS requests that end at A3 already have the preflight headers from the options
+    // callback, but ones that end with an error status still need them
+    if let Some(cors) = &resource.cors {
+        if !context.request.is_options()
+            || !context.response.has_header("Access-Control-Allow-Origin")
+        {
+            add_cors_headers(context, cors, &resource.allowed_method_names());
         }
-        None => (),
     }
 
-    debug!("Final response: {:?}", context.response);
+    if resource.strict_status_compliance {
+        apply_strict_status_compliance(context, resource).await;
+    }
+
+    if let Some(digest) = &resource.digest {
+        digest.add_response_headers(context);
+    }
+
+    #[cfg(feature = "signatures")]
+    if let Some(signer) = &resource.response_signer {
+        signer.sign(context);
+    }
+
+    // Invoked last, so the callbacks see the response as it will be sent
+    {
+        let callback = resource.finish_request.lock().await;
+        callback.deref()(context, resource).await;
+    }
+
+    if let Some(callback) = &resource.finalise_response {
+        let callback = callback.lock().await;
+        callback.deref()(context, resource).await;
+    }
+
+    if log_enabled!(log::Level::Debug) {
+        let description = match &resource.redaction {
+            Some(redaction) => redaction.describe_response(&context.response),
+            None => RedactionConfig::default().describe_response(&context.response),
+        };
+        debug!("Final response: {}", description);
+    }
+}
+
+/// Renders the value from the callback with the codec for the Content-Type of the response.
+/// Returns None and sets a '500 Internal Server Error' status if there is no codec for the
+/// content type or the value can not be encoded.
+async fn encode_value(
+    context: &mut Context,
+    resource: &Resource<'_>,
+    render_value: &Callback<'_, Option<serde_json::Value>>,
+) -> Option<Vec<u8>> {
+    let callback = render_value.lock().await;
+    let value = callback.deref()(context, resource).await?;
+    let content_type = context
+        .response
+        .headers
+        .get("Content-Type")
+        .and_then(|values| values.first())
+        .map(|value| value.value.clone())
+        .unwrap_or_else(|| "application/json".to_string());
+    let result = match resource.codecs.codec_for(&content_type) {
+        Some(codec) => codec.encode(&value),
+        None => Err(format!("No codec is registered for '{}'", content_type)),
+    };
+    match result {
+        Ok(body) => Some(body),
+        Err(err) => {
+            error!("Failed to render the response body: {}", err);
+            context.response.status = 500;
+            context.error = Some(err);
+            None
+        }
+    }
+}
+
+/// Headers that describe the content of a response, and so can not be sent on a 204
+const NO_CONTENT_EXCLUDED_HEADERS: [&str; 3] =
+    ["Content-Type", "Content-Length", "Transfer-Encoding"];
+
+/// Representation headers that should not be sent on a 304 as per RFC 9110 section 15.4.5
+const NOT_MODIFIED_EXCLUDED_HEADERS: [&str; 7] = [
+    "Content-Type",
+    "Content-Length",
+    "Content-Encoding",
+    "Content-Language",
+    "Content-Range",
+    "Content-Disposition",
+    "Transfer-Encoding",
+];
+
+fn add_cors_headers(context: &mut Context, cors: &CorsConfig, methods: &[&str]) {
+    let preflight = context.request.is_options();
+    let mut headers = cors.headers(&context.request, methods, preflight);
+    if let Some(vary) = headers.remove("Vary") {
+        let mut values = context.response.remove_header("Vary").unwrap_or_default();
+        values.extend(vary.iter().map(HeaderValue::basic));
+        context
+            .response
+            .add_header("Vary", values.iter().cloned().unique().collect());
+    }
+    context.response.add_headers(headers);
+}
+
+async fn apply_strict_status_compliance(context: &mut Context, resource: &Resource<'_>) {
+    match context.response.status {
+        204 => {
+            context.response.body = None;
+            context.response.stream = None;
+            for header in NO_CONTENT_EXCLUDED_HEADERS.iter() {
+                context.response.remove_header(header);
+            }
+        }
+        304 => {
+            context.response.body = None;
+            context.response.stream = None;
+            for header in NOT_MODIFIED_EXCLUDED_HEADERS.iter() {
+                context.response.remove_header(header);
+            }
+            if !context.response.has_header("ETag") {
+                if let Some(etag) = representation_etag(context, resource).await {
+                    context.response.add_header("ETag", vec![etag.to_header_value()]);
+                }
+            }
+            // Last-Modified is only useful to caches when there is no ETag
+            if context.response.has_header("ETag") {
+                context.response.remove_header("Last-Modified");
+            }
+        }
+        _ => (),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,290 @@
+//! The `locale` module provides small locale-aware formatting helpers for numbers and dates,
+//! keyed from the language selected by content negotiation, so localized HTML and CSV bodies
+//! are consistent with the Content-Language of the response. It also provides a catalog of
+//! translated messages, used to localize error responses.
+
+use chrono::{DateTime, TimeZone};
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::{
+    content_negotiation::{sort_media_languages, MediaLanguage},
+    context::Context,
+};
+
+/// Conventions for formatting numbers and dates in a language
+#[derive(Debug, Clone, PartialEq)]
+pub struct Locale {
+    /// Language tag of the locale (i.e. `de-CH`)
+    pub language: String,
+    /// Separator between the integer and fractional parts of a number
+    pub decimal_separator: char,
+    /// Separator between groups of thousands, or None if they are not grouped
+    pub group_separator: Option<char>,
+    /// Format of dates, as a `chrono` format string
+    pub date_format: String,
+    /// Format of times of day, as a `chrono` format string
+    pub time_format: String,
+}
+
+impl Default for Locale {
+    /// Creates the locale for English
+    fn default() -> Locale {
+        Locale::for_language("en")
+    }
+}
+
+// language, decimal separator, group separator, date format, time format
+const LOCALES: &[(&str, char, Option<char>, &str, &str)] = &[
+    ("en", '.', Some(','), "%m/%d/%Y", "%-I:%M %p"),
+    ("en-gb", '.', Some(','), "%d/%m/%Y", "%H:%M"),
+    ("en-au", '.', Some(','), "%d/%m/%Y", "%-I:%M %p"),
+    ("de", ',', Some('.'), "%d.%m.%Y", "%H:%M"),
+    ("de-ch", '.', Some('\''), "%d.%m.%Y", "%H:%M"),
+    ("fr", ',', Some('\u{202f}'), "%d/%m/%Y", "%H:%M"),
+    ("es", ',', Some('.'), "%d/%m/%Y", "%H:%M"),
+    ("it", ',', Some('.'), "%d/%m/%Y", "%H:%M"),
+    ("nl", ',', Some('.'), "%d-%m-%Y", "%H:%M"),
+    ("pt", ',', Some('.'), "%d/%m/%Y", "%H:%M"),
+    ("pl", ',', Some('\u{a0}'), "%d.%m.%Y", "%H:%M"),
+    ("ru", ',', Some('\u{a0}'), "%d.%m.%Y", "%H:%M"),
+    ("sv", ',', Some('\u{a0}'), "%Y-%m-%d", "%H:%M"),
+    ("ja", '.', Some(','), "%Y/%m/%d", "%H:%M"),
+    ("zh", '.', Some(','), "%Y/%m/%d", "%H:%M"),
+];
+
+impl Locale {
+    /// Returns the locale for the language tag. If there are no conventions for the tag, the
+    /// ones of its primary language are used (i.e. `de` for `de-AT`), falling back to English.
+    /// The returned locale keeps the requested tag.
+    pub fn for_language(language: &str) -> Locale {
+        let tag = language.trim().to_lowercase();
+        let primary = tag.split('-').next().unwrap_or_default();
+        let (_, decimal_separator, group_separator, date_format, time_format) = LOCALES
+            .iter()
+            .find(|(name, ..)| *name == tag)
+            .or_else(|| LOCALES.iter().find(|(name, ..)| *name == primary))
+            .unwrap_or(&LOCALES[0]);
+        Locale {
+            language: language.trim().to_string(),
+            decimal_separator: *decimal_separator,
+            group_separator: *group_separator,
+            date_format: date_format.to_string(),
+            time_format: time_format.to_string(),
+        }
+    }
+
+    /// Formats the number with the number of decimal places
+    pub fn format_number(&self, value: f64, decimals: usize) -> String {
+        if !value.is_finite() {
+            return value.to_string();
+        }
+        let formatted = format!("{:.*}", decimals, value.abs());
+        let (integer, fraction) = match formatted.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+        let mut result = String::new();
+        if value < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            result.push('-');
+        }
+        result.push_str(&self.group(integer));
+        if let Some(fraction) = fraction {
+            result.push(self.decimal_separator);
+            result.push_str(fraction);
+        }
+        result
+    }
+
+    /// Formats the integer with the digits grouped into thousands
+    pub fn format_integer(&self, value: i64) -> String {
+        let digits = value.unsigned_abs().to_string();
+        if value < 0 {
+            format!("-{}", self.group(&digits))
+        } else {
+            self.group(&digits)
+        }
+    }
+
+    /// Formats the date part of the date and time
+    pub fn format_date<Tz: TimeZone>(&self, date: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        date.format(&self.date_format).to_string()
+    }
+
+    /// Formats the date and the time of day of the date and time
+    pub fn format_date_time<Tz: TimeZone>(&self, date: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        date.format(&format!("{} {}", self.date_format, self.time_format))
+            .to_string()
+    }
+
+    fn group(&self, digits: &str) -> String {
+        match self.group_separator {
+            Some(separator) => {
+                let reversed = digits.chars().rev().collect::<Vec<char>>();
+                reversed
+                    .chunks(3)
+                    .rev()
+                    .map(|group| group.iter().rev().collect::<String>())
+                    .collect::<Vec<String>>()
+                    .join(&separator.to_string())
+            }
+            None => digits.to_string(),
+        }
+    }
+}
+
+/// Catalog of translations of messages (i.e. the titles and details of error responses). The
+/// messages are written in the default language of the catalog, and translations are looked up
+/// by the message in that language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageCatalog {
+    /// Language the messages are written in. Defaults to `en`.
+    pub default_language: String,
+    /// Translations of the messages, by language tag and then the message
+    pub translations: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for MessageCatalog {
+    fn default() -> MessageCatalog {
+        MessageCatalog {
+            default_language: "en".to_string(),
+            translations: HashMap::new(),
+        }
+    }
+}
+
+impl MessageCatalog {
+    /// Adds the translations of messages, as pairs of the message and its translation, to the
+    /// catalog for the language
+    pub fn with_language(
+        mut self,
+        language: &str,
+        translations: &[(&str, &str)],
+    ) -> MessageCatalog {
+        self.translations
+            .entry(language.to_string())
+            .or_default()
+            .extend(
+                translations
+                    .iter()
+                    .map(|(message, translation)| (message.to_string(), translation.to_string())),
+            );
+        self
+    }
+
+    /// Returns the language of the catalog to respond in: the language selected by content
+    /// negotiation if the catalog has it, otherwise the most acceptable language in the
+    /// Accept-Language header of the request. A more specific tag matches its primary language
+    /// (i.e. `de-AT` matches `de`). Falls back to the default language.
+    pub fn language_for(&self, context: &Context) -> String {
+        let candidates = context
+            .selected_language
+            .iter()
+            .map(|language| MediaLanguage::parse_string(language))
+            .chain(sort_media_languages(&context.request.accept_language()));
+        for candidate in candidates.filter(|candidate| candidate.main != "*") {
+            let candidate = MediaLanguage::parse_string(&candidate.to_string().to_lowercase());
+            let language = self
+                .languages()
+                .into_iter()
+                .filter(|language| {
+                    MediaLanguage::parse_string(&language.to_lowercase()).matches(&candidate)
+                })
+                .max_by_key(|language| language.len());
+            if let Some(language) = language {
+                return language.to_string();
+            }
+        }
+        self.default_language.clone()
+    }
+
+    /// Returns the translation of the message in the language, or the message if there is none
+    pub fn translate<'m>(&'m self, language: &str, message: &'m str) -> &'m str {
+        self.translations
+            .get(language)
+            .and_then(|translations| translations.get(message))
+            .map(|translation| translation.as_str())
+            .unwrap_or(message)
+    }
+
+    fn languages(&self) -> Vec<&str> {
+        std::iter::once(self.default_language.as_str())
+            .chain(self.translations.keys().map(|language| language.as_str()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::HeaderValue;
+    use expectest::prelude::*;
+
+    #[test]
+    fn locale_falls_back_to_the_primary_language_and_then_english() {
+        expect!(Locale::for_language("de-AT").date_format).to(be_equal_to("%d.%m.%Y"));
+        expect!(Locale::for_language("de-AT").language).to(be_equal_to("de-AT"));
+        expect!(Locale::for_language("en-GB").date_format).to(be_equal_to("%d/%m/%Y"));
+        expect!(Locale::for_language("*").decimal_separator).to(be_equal_to('.'));
+    }
+
+    #[test]
+    fn context_locale_is_for_the_selected_language() {
+        let context = crate::context::Context {
+            selected_language: Some("de".to_string()),
+            ..crate::context::Context::default()
+        };
+        expect!(context.locale().format_number(1500.25, 2)).to(be_equal_to("1.500,25"));
+        expect!(crate::context::Context::default().locale()).to(be_equal_to(Locale::default()));
+    }
+
+    #[test]
+    fn format_number_uses_the_separators_of_the_locale() {
+        expect!(Locale::for_language("en").format_number(1234567.891, 2))
+            .to(be_equal_to("1,234,567.89"));
+        expect!(Locale::for_language("de").format_number(-1234.5, 1)).to(be_equal_to("-1.234,5"));
+        expect!(Locale::for_language("de-CH").format_number(999.0, 0)).to(be_equal_to("999"));
+        expect!(Locale::for_language("fr").format_number(-0.001, 2)).to(be_equal_to("0,00"));
+        expect!(Locale::for_language("ru").format_integer(-1000000))
+            .to(be_equal_to("-1\u{a0}000\u{a0}000"));
+    }
+
+    #[test]
+    fn format_date_uses_the_date_format_of_the_locale() {
+        let date = DateTime::parse_from_rfc3339("2021-03-07T14:05:00Z").unwrap();
+        expect!(Locale::for_language("en-US").format_date_time(&date))
+            .to(be_equal_to("03/07/2021 2:05 PM"));
+        expect!(Locale::for_language("de").format_date(&date)).to(be_equal_to("07.03.2021"));
+        expect!(Locale::for_language("ja").format_date_time(&date))
+            .to(be_equal_to("2021/03/07 14:05"));
+    }
+
+    #[test]
+    fn message_catalog_negotiates_the_language_with_fallback() {
+        let catalog = MessageCatalog::default()
+            .with_language("de", &[("Not Found", "Nicht gefunden")])
+            .with_language("de-CH", &[("Not Found", "Nöd gfunde")]);
+        let context = |accept_language: &[&str]| crate::context::Context {
+            request: crate::context::Request {
+                headers: hashmap! {
+                    "Accept-Language".to_string() =>
+                        accept_language.iter().map(|language| h!(*language)).collect()
+                },
+                ..crate::context::Request::default()
+            },
+            ..crate::context::Context::default()
+        };
+        expect!(catalog.language_for(&context(&["fr;q=0.9", "de-AT;q=0.8"]))).to(be_equal_to("de"));
+        expect!(catalog.language_for(&context(&["de-ch"]))).to(be_equal_to("de-CH"));
+        expect!(catalog.language_for(&context(&["fr", "*;q=0.5"]))).to(be_equal_to("en"));
+        expect!(catalog.translate("de", "Not Found")).to(be_equal_to("Nicht gefunden"));
+        expect!(catalog.translate("de", "Gone")).to(be_equal_to("Gone"));
+        expect!(catalog.translate("en", "Not Found")).to(be_equal_to("Not Found"));
+    }
+}
@@ -0,0 +1,199 @@
+//! The `method` module provides a registry of the HTTP methods known to a resource, with the
+//! properties (RFC 9110 section 9.2) that decide how requests with them are handled.
+
+/// Methods the state machine has branches for. Requests with any other known method are
+/// processed by the `process_other_method` callback of the resource.
+const MODELLED_METHODS: [&str; 6] = ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS"];
+
+/// If the state machine has branches for the method
+pub(crate) fn is_modelled_method(name: &str) -> bool {
+    MODELLED_METHODS
+        .iter()
+        .any(|method| method.eq_ignore_ascii_case(name))
+}
+
+/// An HTTP method and its properties
+#[derive(Debug, Clone, PartialEq)]
+pub struct Method {
+    /// Name of the method (i.e. `GET`)
+    pub name: String,
+    /// If the method is read-only. Safe methods can be prefetched and retried freely.
+    pub safe: bool,
+    /// If repeating a request with the method has the same effect as making it once, so it can
+    /// be retried after a failure
+    pub idempotent: bool,
+    /// If responses to the method can be cached. Cacheable responses get validators (ETag and
+    /// Last-Modified) and an Expires header, and a failed If-None-Match condition results in a
+    /// '304 Not Modified' instead of a '412 Precondition Failed'.
+    pub cacheable: bool,
+}
+
+impl Method {
+    /// Creates a method that is not safe, idempotent or cacheable, which are the properties to
+    /// assume for an unknown method
+    pub fn new(name: &str) -> Method {
+        Method {
+            name: name.to_uppercase(),
+            safe: false,
+            idempotent: false,
+            cacheable: false,
+        }
+    }
+
+    fn standard(name: &str, safe: bool, idempotent: bool, cacheable: bool) -> Method {
+        Method {
+            name: name.to_string(),
+            safe,
+            idempotent,
+            cacheable,
+        }
+    }
+}
+
+/// Registry of the HTTP methods known to a resource
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodRegistry {
+    /// Known methods
+    pub methods: Vec<Method>,
+}
+
+impl Default for MethodRegistry {
+    /// Creates a registry with the standard HTTP methods
+    fn default() -> MethodRegistry {
+        MethodRegistry {
+            methods: vec![
+                Method::standard("OPTIONS", true, true, false),
+                Method::standard("GET", true, true, true),
+                Method::standard("POST", false, false, false),
+                Method::standard("PUT", false, true, false),
+                Method::standard("DELETE", false, true, false),
+                Method::standard("HEAD", true, true, true),
+                Method::standard("TRACE", true, true, false),
+                Method::standard("CONNECT", false, false, false),
+                Method::standard("PATCH", false, false, false),
+            ],
+        }
+    }
+}
+
+impl MethodRegistry {
+    /// Creates a registry with the standard HTTP methods and the WebDAV methods (RFC 4918),
+    /// which are processed by the `process_other_method` callback of a resource
+    pub fn webdav() -> MethodRegistry {
+        let mut registry = MethodRegistry::default();
+        for method in [
+            Method::standard("PROPFIND", true, true, false),
+            Method::standard("PROPPATCH", false, true, false),
+            Method::standard("MKCOL", false, true, false),
+            Method::standard("COPY", false, true, false),
+            Method::standard("MOVE", false, true, false),
+            Method::standard("LOCK", false, false, false),
+            Method::standard("UNLOCK", false, true, false),
+        ] {
+            registry.register(method);
+        }
+        registry
+    }
+
+    /// Registers a method, replacing any existing method with the same name
+    pub fn register(&mut self, method: Method) {
+        self.methods
+            .retain(|existing| !existing.name.eq_ignore_ascii_case(&method.name));
+        self.methods.push(method);
+    }
+
+    /// Returns the method with the name (case-insensitive)
+    pub fn get(&self, name: &str) -> Option<&Method> {
+        self.methods
+            .iter()
+            .find(|method| method.name.eq_ignore_ascii_case(name))
+    }
+
+    /// If the method is known
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Returns the known methods with the names, in the order of the names. Names of methods
+    /// that are not known are left out, which is how the allowed methods of a resource are
+    /// validated against its known methods.
+    pub fn resolve(&self, names: &[&str]) -> Vec<&Method> {
+        names.iter().filter_map(|name| self.get(name)).collect()
+    }
+
+    /// Returns the names of the known methods
+    pub fn names(&self) -> Vec<&str> {
+        self.methods.iter().map(|method| method.name.as_str()).collect()
+    }
+
+    /// If the method is known and safe
+    pub fn is_safe(&self, name: &str) -> bool {
+        self.get(name).map(|method| method.safe).unwrap_or(false)
+    }
+
+    /// If the method is known and idempotent
+    pub fn is_idempotent(&self, name: &str) -> bool {
+        self.get(name).map(|method| method.idempotent).unwrap_or(false)
+    }
+
+    /// If the method is known and cacheable
+    pub fn is_cacheable(&self, name: &str) -> bool {
+        self.get(name).map(|method| method.cacheable).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn registry_has_the_standard_methods() {
+        let registry = MethodRegistry::default();
+        expect!(registry.is_safe("get")).to(be_true());
+        expect!(registry.is_cacheable("HEAD")).to(be_true());
+        expect!(registry.is_idempotent("PUT")).to(be_true());
+        expect!(registry.is_safe("PUT")).to(be_false());
+        expect!(registry.is_idempotent("POST")).to(be_false());
+        expect!(registry.contains("PROPFIND")).to(be_false());
+    }
+
+    #[test]
+    fn registry_registers_custom_methods() {
+        let mut registry = MethodRegistry::default();
+        registry.register(Method {
+            safe: true,
+            idempotent: true,
+            ..Method::new("propfind")
+        });
+        registry.register(Method::new("POST"));
+        expect!(registry.is_safe("PROPFIND")).to(be_true());
+        expect!(registry.get("post")).to(be_some().value(&Method::new("POST")));
+        expect!(registry.names().len()).to(be_equal_to(10));
+    }
+
+    #[test]
+    fn registry_resolves_only_the_known_methods() {
+        let registry = MethodRegistry::default();
+        let names = |methods: Vec<&Method>| {
+            methods
+                .iter()
+                .map(|method| method.name.clone())
+                .collect::<Vec<_>>()
+        };
+        expect!(names(registry.resolve(&["post", "PURGE", "GET"])))
+            .to(be_equal_to(vec!["POST", "GET"]));
+        expect!(names(registry.resolve(&[]))).to(be_equal_to(Vec::<String>::new()));
+    }
+
+    #[test]
+    fn webdav_registry_has_the_webdav_methods() {
+        let registry = MethodRegistry::webdav();
+        expect!(registry.is_safe("PROPFIND")).to(be_true());
+        expect!(registry.is_idempotent("MKCOL")).to(be_true());
+        expect!(registry.is_idempotent("LOCK")).to(be_false());
+        expect!(registry.contains("GET")).to(be_true());
+        expect!(is_modelled_method("delete")).to(be_true());
+        expect!(is_modelled_method("PROPFIND")).to(be_false());
+    }
+}
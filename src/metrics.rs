@@ -0,0 +1,587 @@
+//! The `metrics` module collects request metrics for Prometheus. The dispatcher counts the
+//! requests and their response status classes, and records their latency in histograms,
+//! labelled by the route template that matched the request and the method. The metrics resource
+//! renders them in the Prometheus text exposition format, along with the gauges of the service
+//! level objectives (see the `slo` module) of the routes.
+//!
+//! Responses with a streamed body are also recorded once the body has been sent, with how long
+//! the body waited for the client to accept its chunks, how many chunks were queued for the
+//! client at most, and if the body was aborted by the write timeout. These show the slow clients
+//! that hold on to the resources of the server.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    context::Context, headers::HeaderValue, owned_callback, slo::SloWindow, Resource, Slo,
+    SloAlertCallback, SloStatus,
+};
+
+/// Default upper bounds in seconds of the buckets of the latency histograms
+pub const DEFAULT_LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Content type of the Prometheus text exposition format
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds of the buckets of the histograms of the send-queue depths of streamed bodies,
+/// up to the number of chunks that are read ahead of the client
+const QUEUE_DEPTH_BUCKETS: [f64; 4] = [1.0, 2.0, 4.0, 8.0];
+
+/// Route label of requests that did not match a route
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Methods that are used as a label as is. Any other method is labelled `OTHER`, so clients
+/// can not create an unbounded number of series.
+const LABELLED_METHODS: [&str; 9] = [
+    "CONNECT", "DELETE", "GET", "HEAD", "OPTIONS", "PATCH", "POST", "PUT", "TRACE",
+];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, bounds: &[f64], value: f64) {
+        if self.counts.len() != bounds.len() {
+            self.counts = vec![0; bounds.len()];
+        }
+        for (count, bound) in self.counts.iter_mut().zip(bounds) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str, bounds: &[f64]) {
+        for (count, bound) in self.counts.iter().zip(bounds) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    requests: BTreeMap<(String, String, String), u64>,
+    latencies: BTreeMap<(String, String), Histogram>,
+    stream_stalls: BTreeMap<(String, String), Histogram>,
+    stream_queue_depths: BTreeMap<(String, String), Histogram>,
+    stream_write_timeouts: BTreeMap<(String, String), u64>,
+    slo_windows: Vec<SloWindow>,
+}
+
+/// How a streamed response body was sent to the client
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamStats {
+    /// Largest number of chunks of the body that were queued to be sent to the client
+    pub max_queue_depth: usize,
+    /// Total time the chunks of the body waited for the client to accept them
+    pub stall: Duration,
+    /// If the body was aborted because the client did not accept a chunk within the write
+    /// timeout
+    pub timed_out: bool,
+}
+
+/// Request metrics of a dispatcher. Clones share the same metrics, so the metrics set on the
+/// dispatcher and the ones the metrics resource is created with should be clones of each other.
+#[derive(Clone)]
+pub struct Metrics {
+    /// Upper bounds in seconds of the buckets of the latency histograms, in increasing order.
+    /// Defaults to `DEFAULT_LATENCY_BUCKETS`.
+    pub latency_buckets: Vec<f64>,
+    /// Service level objectives of the routes. Defaults to empty.
+    pub slos: Vec<Slo>,
+    /// Callback invoked when the burn rate of an SLO crosses its threshold. Defaults to None.
+    pub slo_alert: Option<SloAlertCallback>,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new(DEFAULT_LATENCY_BUCKETS.to_vec())
+    }
+}
+
+impl Metrics {
+    /// Creates metrics with latency histograms with the bucket upper bounds (in seconds)
+    pub fn new(latency_buckets: Vec<f64>) -> Metrics {
+        Metrics {
+            latency_buckets,
+            slos: Vec::new(),
+            slo_alert: None,
+            registry: Arc::new(Mutex::new(Registry::default())),
+        }
+    }
+
+    /// Records a request that matched the route (None if it did not match one), and the time
+    /// taken to generate its response
+    pub fn record(&self, context: &Context, route: Option<&str>, latency: Duration) {
+        let (route, method) = labels(route, &context.request.method);
+        let status = format!("{}xx", context.response.status / 100);
+        let seconds = latency.as_secs_f64();
+
+        let mut registry = self.registry();
+        let now = Instant::now();
+        let mut alerts = vec![];
+        for (index, slo) in self.slos.iter().enumerate() {
+            if slo.route == route {
+                let window = registry.slo_window(index, now);
+                alerts.extend(window.observe(slo, now, context.response.status, latency));
+            }
+        }
+        *registry
+            .requests
+            .entry((route.clone(), method.clone(), status))
+            .or_insert(0) += 1;
+        registry
+            .latencies
+            .entry((route, method))
+            .or_default()
+            .observe(&self.latency_buckets, seconds);
+        drop(registry);
+
+        if let Some(callback) = &self.slo_alert {
+            for alert in alerts {
+                callback(&alert);
+            }
+        }
+    }
+
+    /// Records how the streamed body of a response to a request with the method, that matched
+    /// the route (None if it did not match one), was sent to the client. The stall durations
+    /// are recorded in histograms with the buckets of the latency histograms.
+    pub fn record_stream(&self, route: Option<&str>, method: &str, stats: &StreamStats) {
+        let key = labels(route, method);
+        let mut registry = self.registry();
+        registry
+            .stream_stalls
+            .entry(key.clone())
+            .or_default()
+            .observe(&self.latency_buckets, stats.stall.as_secs_f64());
+        registry
+            .stream_queue_depths
+            .entry(key.clone())
+            .or_default()
+            .observe(&QUEUE_DEPTH_BUCKETS, stats.max_queue_depth as f64);
+        if stats.timed_out {
+            *registry.stream_write_timeouts.entry(key).or_insert(0) += 1;
+        }
+    }
+
+    /// Returns the status of the SLOs over their rolling windows
+    pub fn slo_statuses(&self) -> Vec<SloStatus> {
+        let mut registry = self.registry();
+        let now = Instant::now();
+        self.slos
+            .iter()
+            .enumerate()
+            .map(|(index, slo)| registry.slo_window(index, now).status(slo, now))
+            .collect()
+    }
+
+    fn registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let slo_statuses = self.slo_statuses();
+        let registry = self.registry();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# HELP webmachine_requests_total Requests handled by the dispatcher."
+        );
+        let _ = writeln!(out, "# TYPE webmachine_requests_total counter");
+        for ((route, method, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "webmachine_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape_label(route),
+                method,
+                status,
+                count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP webmachine_request_duration_seconds Time taken to generate the responses."
+        );
+        let _ = writeln!(out, "# TYPE webmachine_request_duration_seconds histogram");
+        for ((route, method), histogram) in &registry.latencies {
+            histogram.render(
+                &mut out,
+                "webmachine_request_duration_seconds",
+                &route_labels(route, method),
+                &self.latency_buckets,
+            );
+        }
+        if !registry.stream_stalls.is_empty() {
+            self.render_streams(&mut out, &registry);
+        }
+        if !slo_statuses.is_empty() {
+            render_slo_gauges(&mut out, &slo_statuses);
+        }
+        out
+    }
+
+    fn render_streams(&self, out: &mut String, registry: &Registry) {
+        let _ = writeln!(
+            out,
+            "# HELP webmachine_response_stream_stall_seconds Time streamed response bodies \
+             waited for the client to accept their chunks."
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE webmachine_response_stream_stall_seconds histogram"
+        );
+        for ((route, method), histogram) in &registry.stream_stalls {
+            histogram.render(
+                out,
+                "webmachine_response_stream_stall_seconds",
+                &route_labels(route, method),
+                &self.latency_buckets,
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP webmachine_response_stream_queue_depth Largest number of chunks of streamed \
+             response bodies queued to be sent to the client."
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE webmachine_response_stream_queue_depth histogram"
+        );
+        for ((route, method), histogram) in &registry.stream_queue_depths {
+            histogram.render(
+                out,
+                "webmachine_response_stream_queue_depth",
+                &route_labels(route, method),
+                &QUEUE_DEPTH_BUCKETS,
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP webmachine_response_stream_write_timeouts_total Streamed response bodies \
+             aborted as the client did not accept a chunk within the write timeout."
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE webmachine_response_stream_write_timeouts_total counter"
+        );
+        for ((route, method), count) in &registry.stream_write_timeouts {
+            let _ = writeln!(
+                out,
+                "webmachine_response_stream_write_timeouts_total{{{}}} {}",
+                route_labels(route, method),
+                count
+            );
+        }
+    }
+
+    /// Builds the resource that serves the metrics for Prometheus to scrape (i.e. on `/metrics`)
+    pub fn resource(&self) -> Resource<'static> {
+        let metrics = self.clone();
+        Resource {
+            produces: vec!["text/plain"],
+            render_response: owned_callback(move |context, _| {
+                context.response.add_header(
+                    "Content-Type",
+                    vec![HeaderValue::basic(PROMETHEUS_CONTENT_TYPE)],
+                );
+                context
+                    .response
+                    .add_header("Cache-Control", vec![HeaderValue::basic("no-store")]);
+                let body = metrics.render();
+                Box::pin(async move { Some(body) })
+            }),
+            ..Resource::default()
+        }
+    }
+}
+
+impl Registry {
+    fn slo_window(&mut self, index: usize, now: Instant) -> &mut SloWindow {
+        while self.slo_windows.len() <= index {
+            self.slo_windows.push(SloWindow::new(now));
+        }
+        &mut self.slo_windows[index]
+    }
+}
+
+/// Name, help and value of a gauge of an SLO
+type Gauge = (&'static str, &'static str, fn(&SloStatus) -> f64);
+
+fn render_slo_gauges(out: &mut String, statuses: &[SloStatus]) {
+    let gauges: [Gauge; 3] = [
+        (
+            "webmachine_slo_objective",
+            "Fraction of requests that must be good.",
+            |status| status.slo.objective,
+        ),
+        (
+            "webmachine_slo_compliance",
+            "Fraction of the requests in the SLO window that were good.",
+            |status| status.compliance,
+        ),
+        (
+            "webmachine_slo_burn_rate",
+            "Rate the error budget is being used up at over the SLO window.",
+            |status| status.burn_rate,
+        ),
+    ];
+    for (name, help, value) in &gauges {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for status in statuses {
+            let _ = writeln!(
+                out,
+                "{}{{route=\"{}\",slo=\"{}\"}} {}",
+                name,
+                escape_label(&status.slo.route),
+                escape_label(&status.slo.name),
+                gauge_value(value(status))
+            );
+        }
+    }
+}
+
+fn gauge_value(value: f64) -> String {
+    if value.is_infinite() {
+        "+Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Returns the route and method labels of a request. Methods that are not labelled as is are
+/// labelled `OTHER`.
+fn labels(route: Option<&str>, method: &str) -> (String, String) {
+    let method = method.to_uppercase();
+    let method = if LABELLED_METHODS.contains(&method.as_str()) {
+        method
+    } else {
+        "OTHER".to_string()
+    };
+    (route.unwrap_or(UNMATCHED_ROUTE).to_string(), method)
+}
+
+fn route_labels(route: &str, method: &str) -> String {
+    format!("route=\"{}\",method=\"{}\"", escape_label(route), method)
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Request, Dispatcher};
+    use expectest::prelude::*;
+
+    #[test]
+    fn renders_counters_and_histograms_by_route_and_method() {
+        let metrics = Metrics::new(vec![0.1, 1.0]);
+        let mut context = Context::default();
+        metrics.record(&context, Some("/orders"), Duration::from_millis(50));
+        metrics.record(&context, Some("/orders"), Duration::from_millis(500));
+        context.request = Request {
+            method: "BREW".to_string(),
+            ..Request::default()
+        };
+        context.response.status = 404;
+        metrics.record(&context, None, Duration::from_millis(5));
+
+        let rendered = metrics.render();
+        let orders = "route=\"/orders\",method=\"GET\"";
+        for line in &[
+            format!("webmachine_requests_total{{{},status=\"2xx\"}} 2", orders),
+            "webmachine_requests_total{route=\"unmatched\",method=\"OTHER\",status=\"4xx\"} 1"
+                .to_string(),
+            format!(
+                "webmachine_request_duration_seconds_bucket{{{},le=\"0.1\"}} 1",
+                orders
+            ),
+            format!(
+                "webmachine_request_duration_seconds_bucket{{{},le=\"1\"}} 2",
+                orders
+            ),
+            format!(
+                "webmachine_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 2",
+                orders
+            ),
+            format!("webmachine_request_duration_seconds_count{{{}}} 2", orders),
+        ] {
+            expect!(rendered.lines().any(|rendered| rendered == *line)).to(be_true());
+        }
+    }
+
+    #[test]
+    fn tracks_slos_and_calls_the_alert_callback_when_the_burn_rate_crosses_its_threshold() {
+        let alerts = Arc::new(Mutex::new(vec![]));
+        let raised = alerts.clone();
+        let metrics = Metrics {
+            slos: vec![
+                Slo {
+                    burn_rate_threshold: 2.0,
+                    ..Slo::availability("/orders", 0.9)
+                },
+                Slo::latency("/orders", Duration::from_millis(100), 0.5),
+            ],
+            slo_alert: Some(Arc::new(move |status: &SloStatus| {
+                let alert = (status.slo.name.clone(), status.alerting);
+                raised.lock().unwrap().push(alert);
+            })),
+            ..Metrics::default()
+        };
+        let mut context = Context::default();
+        for _ in 0..3 {
+            metrics.record(&context, Some("/orders"), Duration::from_millis(10));
+        }
+        metrics.record(&context, Some("/reports"), Duration::from_millis(10));
+        context.response.status = 500;
+        metrics.record(&context, Some("/orders"), Duration::from_millis(10));
+
+        expect!(alerts.lock().unwrap().clone())
+            .to(be_equal_to(vec![("availability".to_string(), true)]));
+        let statuses = metrics.slo_statuses();
+        expect!(statuses[0].requests).to(be_equal_to(4));
+        expect!(statuses[0].compliance).to(be_equal_to(0.75));
+        expect!(statuses[1].burn_rate).to(be_equal_to(0.5));
+        let rendered = metrics.render();
+        expect!(rendered
+            .contains("webmachine_slo_compliance{route=\"/orders\",slo=\"availability\"} 0.75"))
+        .to(be_true());
+        expect!(
+            rendered.contains("webmachine_slo_burn_rate{route=\"/orders\",slo=\"latency\"} 0.5")
+        )
+        .to(be_true());
+    }
+
+    #[test]
+    fn renders_the_stream_histograms_and_write_timeouts() {
+        let metrics = Metrics::new(vec![0.1, 1.0]);
+        expect!(metrics.render().contains("webmachine_response_stream")).to(be_false());
+        metrics.record_stream(
+            Some("/exports"),
+            "get",
+            &StreamStats {
+                max_queue_depth: 3,
+                stall: Duration::from_millis(500),
+                timed_out: false,
+            },
+        );
+        metrics.record_stream(
+            Some("/exports"),
+            "GET",
+            &StreamStats {
+                max_queue_depth: 8,
+                stall: Duration::from_secs(2),
+                timed_out: true,
+            },
+        );
+
+        let rendered = metrics.render();
+        let exports = "route=\"/exports\",method=\"GET\"";
+        for line in &[
+            format!(
+                "webmachine_response_stream_stall_seconds_bucket{{{},le=\"1\"}} 1",
+                exports
+            ),
+            format!(
+                "webmachine_response_stream_stall_seconds_sum{{{}}} 2.5",
+                exports
+            ),
+            format!(
+                "webmachine_response_stream_queue_depth_bucket{{{},le=\"2\"}} 0",
+                exports
+            ),
+            format!(
+                "webmachine_response_stream_queue_depth_bucket{{{},le=\"4\"}} 1",
+                exports
+            ),
+            format!(
+                "webmachine_response_stream_queue_depth_count{{{}}} 2",
+                exports
+            ),
+            format!(
+                "webmachine_response_stream_write_timeouts_total{{{}}} 1",
+                exports
+            ),
+        ] {
+            expect!(rendered.lines().any(|rendered| rendered == *line)).to(be_true());
+        }
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        expect!(escape_label("/a\"b\\c\nd")).to(be_equal_to("/a\\\"b\\\\c\\nd"));
+    }
+
+    #[tokio::test]
+    async fn dispatcher_records_metrics_served_by_the_metrics_resource() {
+        let metrics = Metrics::default();
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/orders" => Resource::default(),
+                "/metrics" => metrics.resource()
+            },
+            metrics: Some(metrics.clone()),
+            ..Dispatcher::default()
+        };
+        for path in &["/orders/1", "/orders/2", "/metrics"] {
+            let request = http::Request::builder()
+                .uri(*path)
+                .body(hyper::Body::empty())
+                .unwrap();
+            dispatcher.clone().dispatch(request).await.unwrap();
+        }
+
+        let request = http::Request::builder()
+            .uri("/metrics")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = dispatcher.dispatch(request).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        expect!(response.headers()["Content-Type"].to_str().unwrap())
+            .to(be_equal_to(PROMETHEUS_CONTENT_TYPE));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        expect!(body.contains(
+            "webmachine_requests_total{route=\"/orders\",method=\"GET\",status=\"2xx\"} 2"
+        ))
+        .to(be_true());
+        expect!(body.contains(
+            "webmachine_requests_total{route=\"/metrics\",method=\"GET\",status=\"2xx\"} 1"
+        ))
+        .to(be_true());
+    }
+}
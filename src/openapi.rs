@@ -0,0 +1,461 @@
+//! The `openapi` module generates an OpenAPI 3 document describing the routes of a dispatcher,
+//! from what their resources declare: the allowed methods, the media types they produce and
+//! accept, the headers they require and whether requests are authenticated. The declarations
+//! do not describe the structure of the bodies, so the schemas are left open, and a resource
+//! can enrich its operations with an `openapi` hook (i.e. to add schemas and examples).
+//!
+//! Routes match requests by prefix, so each route is one path of the document, joined with the
+//! path template of its resource if it has one (i.e. `/orders/{id}`).
+//!
+//! The document can be served by an `ApiDocsResource`, which responds with the JSON document or
+//! an HTML page rendering it with Swagger UI or Redoc, depending on the Accept header:
+//!
+//! ```
+//! # use webmachine::*;
+//! let mut dispatcher = Dispatcher {
+//!     routes: routes! { "/orders" => Resource::default() },
+//!     ..Dispatcher::default()
+//! };
+//! let document = dispatcher.openapi(&OpenApiInfo::new("Shop", "1.0"));
+//! dispatcher
+//!     .routes
+//!     .insert("/openapi.json", ApiDocsResource::new(document).resource());
+//! ```
+
+use std::sync::Arc;
+
+use itertools::Itertools;
+use serde_json::{json, Map, Value};
+
+use crate::{error_renderer::escape_html, headers::HeaderValue, owned_callback, Resource};
+
+/// Type of a hook that enriches the operation of a resource for a method (i.e. `GET`) in the
+/// generated OpenAPI document
+pub type OpenApiHook<'a> = Arc<dyn Fn(&str, &mut Value) + Send + Sync + 'a>;
+
+/// Methods that OpenAPI 3 has operations for
+const OPERATION_METHODS: [&str; 8] = [
+    "GET", "PUT", "POST", "DELETE", "OPTIONS", "HEAD", "PATCH", "TRACE",
+];
+
+/// Information about an API, for the `info` and `servers` of its OpenAPI document
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenApiInfo {
+    /// Title of the API
+    pub title: String,
+    /// Version of the API (not of the OpenAPI specification)
+    pub version: String,
+    /// Description of the API. Defaults to None.
+    pub description: Option<String>,
+    /// URLs of the servers the API is served from. Defaults to empty, in which case the paths
+    /// are relative to the server the document is served from.
+    pub servers: Vec<String>,
+}
+
+impl OpenApiInfo {
+    /// Creates the information of the API with the title and version
+    pub fn new(title: &str, version: &str) -> OpenApiInfo {
+        OpenApiInfo {
+            title: title.to_string(),
+            version: version.to_string(),
+            description: None,
+            servers: Vec::new(),
+        }
+    }
+}
+
+/// Generates the OpenAPI document of the routes and their resources
+pub fn openapi_document(info: &OpenApiInfo, routes: &[(&str, &Resource)]) -> Value {
+    let mut document = json!({
+        "openapi": "3.0.3",
+        "info": { "title": info.title, "version": info.version },
+        "paths": {}
+    });
+    if let Some(description) = &info.description {
+        document["info"]["description"] = json!(description);
+    }
+    if !info.servers.is_empty() {
+        document["servers"] = info
+            .servers
+            .iter()
+            .map(|url| json!({ "url": url }))
+            .collect();
+    }
+    for (route, resource) in routes {
+        let path_item: Map<String, Value> = resource
+            .allowed_methods
+            .iter()
+            .map(|method| method.to_uppercase())
+            .filter(|method| OPERATION_METHODS.contains(&method.as_str()))
+            .unique()
+            .map(|method| {
+                let mut operation = operation(&method, resource);
+                if let Some(hook) = &resource.openapi {
+                    hook(&method, &mut operation);
+                }
+                (method.to_lowercase(), operation)
+            })
+            .collect();
+        if !path_item.is_empty() {
+            document["paths"][document_path(route, resource)] = Value::Object(path_item);
+        }
+    }
+    document
+}
+
+/// Returns the path of the resource in the document, which is the route joined with the path
+/// template of the resource
+fn document_path(route: &str, resource: &Resource) -> String {
+    match resource.path_template.map(|template| template.trim_matches('/')) {
+        Some(template) if !template.is_empty() => {
+            format!("{}/{}", route.trim_end_matches('/'), template)
+        }
+        _ => route.to_string(),
+    }
+}
+
+/// Returns the operation of the resource for the method
+fn operation(method: &str, resource: &Resource) -> Value {
+    let mut operation = Map::new();
+    if let Some(description) = resource.description {
+        operation.insert("summary".to_string(), json!(description));
+    }
+
+    let path_params = resource
+        .path_template
+        .unwrap_or_default()
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" }
+            })
+        });
+    let header_params = resource
+        .required_headers
+        .iter()
+        .filter(|requirement| {
+            requirement.methods.is_empty() || requirement.methods.iter().any(|m| m == method)
+        })
+        .map(|requirement| {
+            json!({
+                "name": requirement.name,
+                "in": "header",
+                "required": true,
+                "schema": { "type": "string" }
+            })
+        });
+    let parameters: Vec<Value> = path_params.chain(header_params).collect();
+    if !parameters.is_empty() {
+        operation.insert("parameters".to_string(), Value::Array(parameters));
+    }
+
+    if matches!(method, "POST" | "PUT" | "PATCH") {
+        operation.insert(
+            "requestBody".to_string(),
+            json!({
+                "required": true,
+                "content": content(resource.acceptable_content_types_for(method).iter().cloned())
+            }),
+        );
+    }
+
+    let mut responses = Map::new();
+    if method == "DELETE" {
+        responses.insert("204".to_string(), json!({ "description": "Deleted" }));
+    } else {
+        let produces = resource
+            .produces_for(method)
+            .iter()
+            .cloned()
+            .chain(resource.variants.iter().map(|variant| variant.media_type))
+            .unique();
+        responses.insert(
+            "200".to_string(),
+            json!({ "description": "Success", "content": content(produces) }),
+        );
+    }
+    if method == "POST" || method == "PUT" {
+        responses.insert("201".to_string(), json!({ "description": "Created" }));
+    }
+    if !resource.authenticators.is_empty() {
+        responses.insert(
+            "401".to_string(),
+            json!({ "description": "Not authenticated" }),
+        );
+    }
+    if !resource.required_permissions.is_empty() {
+        responses.insert("403".to_string(), json!({ "description": "Forbidden" }));
+    }
+    operation.insert("responses".to_string(), Value::Object(responses));
+    Value::Object(operation)
+}
+
+fn content<'a>(media_types: impl Iterator<Item = &'a str>) -> Value {
+    Value::Object(
+        media_types
+            .map(|media_type| (media_type.to_string(), json!({ "schema": {} })))
+            .collect(),
+    )
+}
+
+/// UI that the API docs page renders the OpenAPI document with. The page loads the UI from the
+/// jsDelivr CDN.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiDocsUi {
+    /// Swagger UI, which lets the operations be tried out from the page
+    SwaggerUi,
+    /// Redoc, which renders a read-only reference
+    Redoc,
+}
+
+/// Resource that serves an OpenAPI document, as JSON to clients that accept it (i.e. on
+/// `/openapi.json`) and as an HTML page rendering it to browsers
+#[derive(Debug, Clone)]
+pub struct ApiDocsResource {
+    /// The OpenAPI document (see `Dispatcher::openapi`)
+    pub document: Arc<Value>,
+    /// UI of the HTML page. Defaults to Swagger UI.
+    pub ui: ApiDocsUi,
+}
+
+impl ApiDocsResource {
+    /// Creates the resource serving the document with Swagger UI
+    pub fn new(document: Value) -> ApiDocsResource {
+        ApiDocsResource {
+            document: Arc::new(document),
+            ui: ApiDocsUi::SwaggerUi,
+        }
+    }
+
+    /// Renders the HTML page with the UI
+    pub fn with_ui(self, ui: ApiDocsUi) -> ApiDocsResource {
+        ApiDocsResource { ui, ..self }
+    }
+
+    /// Builds the webmachine resource that serves the document. JSON is produced unless the
+    /// request prefers `text/html`.
+    pub fn resource(&self) -> Resource<'static> {
+        let json = self.document.to_string();
+        let page = docs_page(&self.document, self.ui);
+        Resource {
+            produces: vec!["application/json", "text/html"],
+            render_response: owned_callback(move |context, _| {
+                let html = context
+                    .selected_media_type
+                    .as_deref()
+                    .map(|media_type| media_type.starts_with("text/html"))
+                    .unwrap_or(false);
+                context
+                    .response
+                    .add_header("Cache-Control", vec![HeaderValue::basic("no-cache")]);
+                let body = if html { page.clone() } else { json.clone() };
+                Box::pin(async move { Some(body) })
+            }),
+            ..Resource::default()
+        }
+    }
+}
+
+/// Renders the page showing the document with the UI. The document is embedded in the page, so
+/// it does not depend on where the JSON is served from.
+fn docs_page(document: &Value, ui: ApiDocsUi) -> String {
+    let title = escape_html(document["info"]["title"].as_str().unwrap_or("API"));
+    // a `</script>` in a string of the document would end the script early
+    let spec = document.to_string().replace("</", "<\\/");
+    match ui {
+        ApiDocsUi::SwaggerUi => format!(
+            "<!DOCTYPE html><html><head><title>{}</title><meta charset=\"utf-8\">\
+             <link rel=\"stylesheet\" \
+             href=\"https://cdn.jsdelivr.net/npm/swagger-ui-dist@5/swagger-ui.css\"></head>\
+             <body><div id=\"swagger-ui\"></div>\
+             <script src=\"https://cdn.jsdelivr.net/npm/swagger-ui-dist@5/swagger-ui-bundle.js\">\
+             </script><script>SwaggerUIBundle({{spec: {}, dom_id: \"#swagger-ui\"}});</script>\
+             </body></html>",
+            title, spec
+        ),
+        ApiDocsUi::Redoc => format!(
+            "<!DOCTYPE html><html><head><title>{}</title><meta charset=\"utf-8\"></head>\
+             <body><div id=\"redoc\"></div>\
+             <script src=\"https://cdn.jsdelivr.net/npm/redoc@2/bundles/redoc.standalone.js\">\
+             </script><script>Redoc.init({}, {{}}, document.getElementById(\"redoc\"));\
+             </script></body></html>",
+            title, spec
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dispatcher, HeaderRequirement};
+    use expectest::prelude::*;
+
+    #[test]
+    fn documents_the_declared_methods_and_media_types() {
+        let orders = Resource {
+            description: Some("Orders"),
+            allowed_methods: vec!["GET", "POST", "PROPFIND"],
+            produces: vec!["application/json"],
+            acceptable_content_types: vec!["application/json", "text/csv"],
+            required_headers: vec![
+                HeaderRequirement::new("Idempotency-Key").for_methods(&["POST"])
+            ],
+            ..Resource::default()
+        };
+        let document = openapi_document(&OpenApiInfo::new("Shop", "1.2"), &[("/orders", &orders)]);
+        expect!(document).to(be_equal_to(json!({
+            "openapi": "3.0.3",
+            "info": { "title": "Shop", "version": "1.2" },
+            "paths": {
+                "/orders": {
+                    "get": {
+                        "summary": "Orders",
+                        "responses": {
+                            "200": {
+                                "description": "Success",
+                                "content": { "application/json": { "schema": {} } }
+                            }
+                        }
+                    },
+                    "post": {
+                        "summary": "Orders",
+                        "parameters": [{
+                            "name": "Idempotency-Key",
+                            "in": "header",
+                            "required": true,
+                            "schema": { "type": "string" }
+                        }],
+                        "requestBody": {
+                            "required": true,
+                            "content": {
+                                "application/json": { "schema": {} },
+                                "text/csv": { "schema": {} }
+                            }
+                        },
+                        "responses": {
+                            "200": {
+                                "description": "Success",
+                                "content": { "application/json": { "schema": {} } }
+                            },
+                            "201": { "description": "Created" }
+                        }
+                    }
+                }
+            }
+        })));
+    }
+
+    #[test]
+    fn resources_can_enrich_their_operations() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/orders" => Resource {
+                    openapi: Some(Arc::new(|method: &str, operation: &mut Value| {
+                        let id = format!("{}Orders", method.to_lowercase());
+                        operation["operationId"] = json!(id);
+                        operation["responses"]["200"]["content"]["application/json"]["schema"] =
+                            json!({ "type": "array" });
+                    })),
+                    ..Resource::default()
+                },
+                "/internal" => Resource {
+                    allowed_methods: vec![],
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        };
+        let document = dispatcher.openapi(&OpenApiInfo {
+            servers: vec!["https://api.example.com".to_string()],
+            ..OpenApiInfo::new("Shop", "1")
+        });
+        expect!(document["servers"].clone())
+            .to(be_equal_to(json!([{ "url": "https://api.example.com" }])));
+        let get = &document["paths"]["/orders"]["get"];
+        expect!(get["operationId"].clone()).to(be_equal_to(json!("getOrders")));
+        expect!(get["responses"]["200"]["content"]["application/json"]["schema"].clone())
+            .to(be_equal_to(json!({ "type": "array" })));
+        expect!(document["paths"]["/orders"]["head"]["operationId"].clone())
+            .to(be_equal_to(json!("headOrders")));
+        expect!(document["paths"].get("/internal")).to(be_none());
+    }
+
+    #[test]
+    fn path_templates_are_documented_as_path_parameters() {
+        let order = Resource {
+            allowed_methods: vec!["DELETE"],
+            path_template: Some("/{id}/"),
+            ..Resource::default()
+        };
+        let document = openapi_document(&OpenApiInfo::new("Shop", "1"), &[("/orders/", &order)]);
+        expect!(document["paths"]["/orders/{id}"]["delete"]["parameters"].clone()).to(
+            be_equal_to(json!([{
+                "name": "id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" }
+            }])),
+        );
+    }
+
+    #[tokio::test]
+    async fn api_docs_resource_negotiates_json_or_the_docs_page() {
+        let document = json!({
+            "openapi": "3.0.3",
+            "info": { "title": "Shop </script>", "version": "1" },
+            "paths": {}
+        });
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/openapi.json" => ApiDocsResource::new(document.clone()).resource(),
+                "/redoc" => ApiDocsResource::new(document.clone())
+                    .with_ui(ApiDocsUi::Redoc)
+                    .resource()
+            },
+            ..Dispatcher::default()
+        };
+        let request = |path: &str, accept: &str| {
+            http::Request::get(path)
+                .header("Accept", accept)
+                .body(hyper::Body::empty())
+                .unwrap()
+        };
+
+        let response = dispatcher
+            .clone()
+            .dispatch(request("/openapi.json", "application/json, */*"))
+            .await
+            .unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let served: Value = serde_json::from_slice(&body).unwrap();
+        expect!(served).to(be_equal_to(document));
+
+        let response = dispatcher
+            .clone()
+            .dispatch(request("/openapi.json", "text/html,*/*;q=0.8"))
+            .await
+            .unwrap();
+        expect!(response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"))
+        .to(be_true());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let page = String::from_utf8_lossy(&body);
+        expect!(page.contains("<title>Shop &lt;/script&gt;</title>")).to(be_true());
+        expect!(page.contains("SwaggerUIBundle({spec: {")).to(be_true());
+        expect!(page.contains("Shop <\\/script>")).to(be_true());
+
+        let response = dispatcher
+            .dispatch(request("/redoc", "text/html"))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        expect!(String::from_utf8_lossy(&body).contains("Redoc.init(")).to(be_true());
+    }
+}
@@ -0,0 +1,147 @@
+//! The `path_params` module matches the path of a request against the path template of the
+//! resource it was routed to, and extracts the path parameters from it. Templates are relative
+//! to the route (i.e. `/{id}` or `/{id}/items/{item}` for a resource routed at `/orders`), where
+//! each `{name}` parameter matches a single non-empty segment of the path and the other segments
+//! must match literally. Resources then read typed parameters with `Context::path_param`, which
+//! ends the request with a '400 Bad Request' (or the status configured on the resource) if the
+//! value can not be parsed.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{context::Context, Resource};
+
+/// Error extracting a typed path parameter from the path of a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathParamError {
+    /// The path template of the resource has no parameter with the name
+    Missing(String),
+    /// The value of the parameter could not be parsed as the requested type
+    Invalid {
+        /// Name of the parameter
+        name: String,
+        /// Value of the parameter in the path of the request
+        value: String,
+        /// Why the value could not be parsed
+        reason: String,
+    },
+}
+
+impl PathParamError {
+    /// Status of the response to a request the error occurred for. Invalid values get the
+    /// configured status, while a missing parameter is an error in the resource (500).
+    pub fn status(&self, invalid_status: u16) -> u16 {
+        match self {
+            PathParamError::Missing(_) => 500,
+            PathParamError::Invalid { .. } => invalid_status,
+        }
+    }
+}
+
+impl fmt::Display for PathParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathParamError::Missing(name) => {
+                write!(f, "The path template has no parameter '{}'", name)
+            }
+            PathParamError::Invalid {
+                name,
+                value,
+                reason,
+            } => write!(
+                f,
+                "Path parameter '{}' of '{}' is invalid - {}",
+                name, value, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PathParamError {}
+
+/// Matches the path against the template, returning the values of the parameters of the
+/// template. Returns None if the path does not match. Empty segments (i.e. from a trailing
+/// slash) are ignored.
+pub fn match_path_template(template: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut template_segments = template.split('/').filter(|segment| !segment.is_empty());
+    let mut path_segments = path.split('/').filter(|segment| !segment.is_empty());
+    let mut params = HashMap::new();
+    loop {
+        match (template_segments.next(), path_segments.next()) {
+            (None, None) => return Some(params),
+            (Some(expected), Some(segment)) => match template_param(expected) {
+                Some(name) => {
+                    params.insert(name.to_string(), segment.to_string());
+                }
+                None if expected == segment => (),
+                None => return None,
+            },
+            _ => return None,
+        }
+    }
+}
+
+/// Sets the path parameters of the context from the path template of the resource. If the path
+/// of the request does not match the template, the response is set to a 404 and false returned.
+pub(crate) fn bind_path_params(context: &mut Context, resource: &Resource) -> bool {
+    let template = match resource.path_template {
+        Some(template) => template,
+        None => return true,
+    };
+    match match_path_template(template, &context.request.request_path) {
+        Some(params) => {
+            context.path_params = params;
+            true
+        }
+        None => {
+            debug!(
+                "Path '{}' does not match the template '{}' of route '{}'",
+                context.request.request_path, template, context.request.base_path
+            );
+            context.response.status = 404;
+            false
+        }
+    }
+}
+
+fn template_param(segment: &str) -> Option<&str> {
+    segment
+        .strip_prefix('{')
+        .and_then(|segment| segment.strip_suffix('}'))
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn matches_parameters_and_literal_segments() {
+        expect!(match_path_template("/{id}/items/{item}", "/100/items/abc")).to(be_some().value(
+            hashmap! {
+                "id".to_string() => "100".to_string(),
+                "item".to_string() => "abc".to_string()
+            },
+        ));
+        expect!(match_path_template("/{id}", "/100/"))
+            .to(be_some().value(hashmap! { "id".to_string() => "100".to_string() }));
+        expect!(match_path_template("/", "/")).to(be_some().value(HashMap::new()));
+        expect!(match_path_template("/{id}", "/")).to(be_none());
+        expect!(match_path_template("/{id}", "/100/items")).to(be_none());
+        expect!(match_path_template("/{id}/items", "/100/lines")).to(be_none());
+    }
+
+    #[test]
+    fn missing_parameters_are_server_errors() {
+        let invalid = PathParamError::Invalid {
+            name: "id".to_string(),
+            value: "abc".to_string(),
+            reason: "invalid digit found in string".to_string(),
+        };
+        expect!(invalid.status(404)).to(be_equal_to(404));
+        expect!(invalid.to_string()).to(be_equal_to(
+            "Path parameter 'id' of 'abc' is invalid - invalid digit found in string",
+        ));
+        expect!(PathParamError::Missing("id".to_string()).status(400)).to(be_equal_to(500));
+    }
+}
@@ -0,0 +1,240 @@
+//! The `percent` module is a percent-encoding codec (RFC 3986 section 2.1) for the components
+//! of URIs. Decoding assembles the escaped bytes before decoding them as UTF-8, so multi-byte
+//! characters (i.e. `%C3%A9` for `é`) are decoded correctly. In lossy mode malformed escapes are
+//! kept as they are and invalid UTF-8 is replaced with U+FFFD, while in strict mode either is an
+//! error. The query strings of requests are decoded in lossy mode, and their paths with
+//! `decode_path`.
+//!
+//! ```
+//! # use webmachine::*;
+//! let decoded = PercentDecoder::query().decode("caf%C3%A9+au+lait");
+//! assert_eq!(decoded, Ok("café au lait".to_string()));
+//! assert!(PercentDecoder::path().strict().decode("%FF").is_err());
+//! assert_eq!(percent_encode("café au lait"), "caf%C3%A9%20au%20lait");
+//! ```
+
+use std::fmt;
+
+use itertools::Itertools;
+
+/// How bytes that are not valid UTF-8, or malformed escapes, are decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeMode {
+    /// Keep malformed escapes as they are, and replace invalid UTF-8 with U+FFFD
+    Lossy,
+    /// Fail to decode malformed escapes and invalid UTF-8
+    Strict,
+}
+
+/// Error decoding a percent-encoded string in strict mode
+#[derive(Debug, Clone, PartialEq)]
+pub enum PercentDecodeError {
+    /// The escape at the byte offset is not a '%' followed by two hex digits
+    InvalidEscape(usize),
+    /// The decoded bytes are not valid UTF-8, from the byte offset of the decoded bytes
+    InvalidUtf8(usize),
+}
+
+impl fmt::Display for PercentDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PercentDecodeError::InvalidEscape(offset) => {
+                write!(f, "Invalid percent escape at offset {}", offset)
+            }
+            PercentDecodeError::InvalidUtf8(offset) => {
+                write!(f, "Decoded value is not valid UTF-8 from offset {}", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PercentDecodeError {}
+
+/// Decoder of percent-encoded URI components
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PercentDecoder {
+    /// If '+' decodes to a space, as in `application/x-www-form-urlencoded` query strings
+    pub plus_as_space: bool,
+    /// How malformed escapes and invalid UTF-8 are decoded
+    pub mode: DecodeMode,
+}
+
+impl PercentDecoder {
+    /// Lossy decoder of the keys and values of query strings, where '+' is a space
+    pub fn query() -> PercentDecoder {
+        PercentDecoder {
+            plus_as_space: true,
+            mode: DecodeMode::Lossy,
+        }
+    }
+
+    /// Lossy decoder of path segments, where '+' is itself
+    pub fn path() -> PercentDecoder {
+        PercentDecoder {
+            plus_as_space: false,
+            mode: DecodeMode::Lossy,
+        }
+    }
+
+    /// Returns the decoder in strict mode
+    pub fn strict(self) -> PercentDecoder {
+        PercentDecoder {
+            mode: DecodeMode::Strict,
+            ..self
+        }
+    }
+
+    /// Decodes the input to bytes. In strict mode malformed escapes are an error.
+    pub fn decode_bytes(&self, input: &str) -> Result<Vec<u8>, PercentDecodeError> {
+        let bytes = input.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut index = 0;
+        while index < bytes.len() {
+            match bytes[index] {
+                b'%' => match escaped_byte(bytes, index) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        index += 3;
+                        continue;
+                    }
+                    None if self.mode == DecodeMode::Strict => {
+                        return Err(PercentDecodeError::InvalidEscape(index));
+                    }
+                    None => decoded.push(b'%'),
+                },
+                b'+' if self.plus_as_space => decoded.push(b' '),
+                byte => decoded.push(byte),
+            }
+            index += 1;
+        }
+        Ok(decoded)
+    }
+
+    /// Decodes the input to a string
+    pub fn decode(&self, input: &str) -> Result<String, PercentDecodeError> {
+        let decoded = self.decode_bytes(input)?;
+        match self.mode {
+            DecodeMode::Lossy => Ok(String::from_utf8_lossy(&decoded).into_owned()),
+            DecodeMode::Strict => String::from_utf8(decoded)
+                .map_err(|err| PercentDecodeError::InvalidUtf8(err.utf8_error().valid_up_to())),
+        }
+    }
+
+    /// Decodes the input to a string, which can not fail in lossy mode
+    pub(crate) fn decode_lossy(&self, input: &str) -> String {
+        PercentDecoder {
+            mode: DecodeMode::Lossy,
+            ..*self
+        }
+        .decode(input)
+        .unwrap_or_default()
+    }
+}
+
+fn escaped_byte(bytes: &[u8], index: usize) -> Option<u8> {
+    let hex = bytes.get(index + 1..index + 3)?;
+    let hex = std::str::from_utf8(hex).ok()?;
+    if hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        u8::from_str_radix(hex, 16).ok()
+    } else {
+        None
+    }
+}
+
+/// Percent-decodes the segments of a path. Escaped slashes (`%2F`) and percent signs (`%25`)
+/// are kept encoded (in upper case), so decoding does not split a segment and the result can not
+/// be decoded again into a different path; resources that want them decoded can decode the
+/// segment with `PercentDecoder::path`. Segments that do not decode to valid UTF-8 are kept as
+/// they are.
+pub fn decode_path(path: &str) -> String {
+    path.split('/').map(decode_path_segment).join("/")
+}
+
+fn decode_path_segment(segment: &str) -> String {
+    if !segment.contains('%') {
+        return segment.to_string();
+    }
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match escaped_byte(bytes, index).filter(|_| bytes[index] == b'%') {
+            Some(byte @ (b'/' | b'%')) => {
+                decoded.extend_from_slice(format!("%{:02X}", byte).as_bytes());
+                index += 3;
+            }
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap_or_else(|_| segment.to_string())
+}
+
+/// Percent-encodes all the bytes of the input except the unreserved characters of RFC 3986
+/// (letters, digits, '-', '.', '_' and '~'), so the result is safe in any URI component
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn decodes_multi_byte_utf8_sequences() {
+        expect!(PercentDecoder::query().decode("%C3%A9t%C3%A9+%E2%82%AC"))
+            .to(be_ok().value("été €".to_string()));
+        expect!(PercentDecoder::path().decode("a+b%2Bc")).to(be_ok().value("a+b+c".to_string()));
+        expect!(PercentDecoder::query().decode("%f0%9f%98%80"))
+            .to(be_ok().value("\u{1F600}".to_string()));
+    }
+
+    #[test]
+    fn lossy_mode_keeps_malformed_escapes_and_replaces_invalid_utf8() {
+        let decoder = PercentDecoder::query();
+        expect!(decoder.decode("100%+%zz%4")).to(be_ok().value("100% %zz%4".to_string()));
+        expect!(decoder.decode("a%FFb")).to(be_ok().value("a\u{FFFD}b".to_string()));
+        expect!(decoder.decode("%C3")).to(be_ok().value("\u{FFFD}".to_string()));
+    }
+
+    #[test]
+    fn strict_mode_rejects_malformed_escapes_and_invalid_utf8() {
+        let decoder = PercentDecoder::query().strict();
+        expect!(decoder.decode("ab%zz")).to(be_err().value(PercentDecodeError::InvalidEscape(2)));
+        expect!(decoder.decode("ab%4")).to(be_err().value(PercentDecodeError::InvalidEscape(2)));
+        expect!(decoder.decode("ab%C3")).to(be_err().value(PercentDecodeError::InvalidUtf8(2)));
+        expect!(decoder.decode("%C3%A9")).to(be_ok().value("é".to_string()));
+    }
+
+    #[test]
+    fn decodes_path_segments_keeping_escaped_slashes() {
+        expect!(decode_path("/files/my%20doc%C3%A9")).to(be_equal_to("/files/my docé"));
+        expect!(decode_path("/files/a%2fb/100%25")).to(be_equal_to("/files/a%2Fb/100%25"));
+        expect!(decode_path("/files/a+b%zz")).to(be_equal_to("/files/a+b%zz"));
+        expect!(decode_path("/files/%FF%20")).to(be_equal_to("/files/%FF%20"));
+        expect!(decode_path(&decode_path("/a%252F"))).to(be_equal_to("/a%252F"));
+    }
+
+    #[test]
+    fn encodes_all_but_unreserved_characters() {
+        expect!(percent_encode("a-b.c_d~e")).to(be_equal_to("a-b.c_d~e"));
+        expect!(percent_encode("a b/c?d=é")).to(be_equal_to("a%20b%2Fc%3Fd%3D%C3%A9"));
+        let decoded = PercentDecoder::path().decode(&percent_encode("été/€ 100%"));
+        expect!(decoded).to(be_ok().value("été/€ 100%".to_string()));
+    }
+}
@@ -0,0 +1,149 @@
+//! The `query` module understands the bracket conventions that many JavaScript clients (i.e.
+//! `qs` and jQuery) use to encode lists and nested objects in query strings. Keys ending with
+//! `[]` (`tags[]=a&tags[]=b`) are lists, and are added to the parsed query of the request under
+//! the key without the brackets (`tags`). Keys with bracketed segments (`filter[status]=open`)
+//! are kept as they are in the parsed query, and are available as nested maps from
+//! `Request::nested_query`.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// Value of a key of a query string with nested keys
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryValue {
+    /// Values of the key, in the order they appear in the query string
+    Values(Vec<String>),
+    /// Nested keys of the key (i.e. `status` of `filter[status]`)
+    Map(BTreeMap<String, QueryValue>),
+}
+
+impl QueryValue {
+    /// Returns the values, if this is not a map
+    pub fn values(&self) -> Option<&[String]> {
+        match self {
+            QueryValue::Values(values) => Some(values),
+            QueryValue::Map(_) => None,
+        }
+    }
+
+    /// Returns the first value, if this is not a map
+    pub fn value(&self) -> Option<&str> {
+        self.values()?.first().map(String::as_str)
+    }
+
+    /// Returns the nested key, if this is a map
+    pub fn get(&self, key: &str) -> Option<&QueryValue> {
+        match self {
+            QueryValue::Values(_) => None,
+            QueryValue::Map(map) => map.get(key),
+        }
+    }
+}
+
+/// Returns the name of a list key (`tags` of `tags[]`)
+pub(crate) fn list_key(key: &str) -> &str {
+    match key.strip_suffix("[]") {
+        Some(name) if !name.is_empty() => name,
+        _ => key,
+    }
+}
+
+/// Nests the keys of the parsed query with bracketed segments. Keys that are not well formed
+/// (i.e. `filter[status`) are kept as they are. If a key has both values and nested keys (i.e.
+/// `filter=x&filter[status]=open`), the nested keys are kept.
+pub fn nest_query(query: &HashMap<String, Vec<String>>) -> BTreeMap<String, QueryValue> {
+    let mut nested = BTreeMap::new();
+    // sorted, so the result does not depend on the order of the hash map
+    let mut keys = query.keys().collect::<Vec<&String>>();
+    keys.sort();
+    for key in keys {
+        let path = key_path(key).unwrap_or_else(|| vec![key.as_str()]);
+        insert(&mut nested, &path, &query[key]);
+    }
+    nested
+}
+
+/// Splits a key into its segments (`filter[status][eq]` into `filter`, `status` and `eq`)
+fn key_path(key: &str) -> Option<Vec<&str>> {
+    let open = key.find('[').filter(|open| *open > 0)?;
+    let mut path = vec![&key[..open]];
+    let mut rest = &key[open..];
+    while !rest.is_empty() {
+        let close = rest.find(']')?;
+        let segment = rest.strip_prefix('[')?.get(..close - 1)?;
+        if segment.is_empty() || segment.contains('[') {
+            return None;
+        }
+        path.push(segment);
+        rest = &rest[close + 1..];
+    }
+    Some(path)
+}
+
+fn insert(map: &mut BTreeMap<String, QueryValue>, path: &[&str], values: &[String]) {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    if rest.is_empty() {
+        match map.get_mut(*key) {
+            Some(QueryValue::Values(existing)) => existing.extend(values.iter().cloned()),
+            Some(QueryValue::Map(_)) => (),
+            None => {
+                map.insert(key.to_string(), QueryValue::Values(values.to_vec()));
+            }
+        }
+    } else {
+        let entry = map
+            .entry(key.to_string())
+            .or_insert_with(|| QueryValue::Map(BTreeMap::new()));
+        if let QueryValue::Values(_) = entry {
+            *entry = QueryValue::Map(BTreeMap::new());
+        }
+        if let QueryValue::Map(nested) = entry {
+            insert(nested, rest, values);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    fn values(values: &[&str]) -> QueryValue {
+        QueryValue::Values(values.iter().map(|value| value.to_string()).collect())
+    }
+
+    #[test]
+    fn nests_keys_with_bracketed_segments() {
+        let query = hashmap! {
+            "filter[status]".to_string() => vec!["open".to_string()],
+            "filter[created][gte]".to_string() => vec!["2024-01-01".to_string()],
+            "filter".to_string() => vec!["ignored".to_string()],
+            "page".to_string() => vec!["2".to_string()],
+            "broken[key".to_string() => vec!["1".to_string()],
+            "[]".to_string() => vec!["2".to_string()]
+        };
+        let nested = nest_query(&query);
+        expect!(nested.keys().collect::<Vec<_>>()).to(be_equal_to(vec![
+            "[]",
+            "broken[key",
+            "filter",
+            "page",
+        ]));
+        let filter = &nested["filter"];
+        expect!(filter.get("status")).to(be_some().value(&values(&["open"])));
+        expect!(filter.get("created").and_then(|created| created.get("gte")))
+            .to(be_some().value(&values(&["2024-01-01"])));
+        expect!(filter.values()).to(be_none());
+        expect!(nested["page"].value()).to(be_some().value("2"));
+    }
+
+    #[test]
+    fn list_keys_are_the_name_without_brackets() {
+        expect!(list_key("tags[]")).to(be_equal_to("tags"));
+        expect!(list_key("filter[tags][]")).to(be_equal_to("filter[tags]"));
+        expect!(list_key("[]")).to(be_equal_to("[]"));
+        expect!(list_key("tags")).to(be_equal_to("tags"));
+    }
+}
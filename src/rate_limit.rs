@@ -0,0 +1,296 @@
+//! The `rate_limit` module provides rate limiting of requests, keyed by the client address, a
+//! request header (like an API key) or a custom extractor. Requests over the limit receive a
+//! '429 Too Many Requests' response with a Retry-After header.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::context::Request;
+
+/// Number of keys tracked before idle counters are pruned
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Type of a function that extracts the rate limit key from a request
+pub type RateLimitKeyExtractor = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+/// What requests are counted against. Requests that have no key are not rate limited.
+#[derive(Clone)]
+pub enum RateLimitKey {
+    /// The IP address of the client, which is the forwarded address for requests from trusted
+    /// proxies (see `Request::client_ip`). This is only known if the dispatcher is served with
+    /// `server::serve`.
+    ClientIp,
+    /// The value of a request header, like an API key
+    Header(String),
+    /// A custom function of the request
+    Custom(RateLimitKeyExtractor),
+}
+
+impl fmt::Debug for RateLimitKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateLimitKey::ClientIp => write!(f, "ClientIp"),
+            RateLimitKey::Header(header) => write!(f, "Header({:?})", header),
+            RateLimitKey::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl RateLimitKey {
+    /// Returns the key of the request
+    pub fn key(&self, request: &Request) -> Option<String> {
+        match self {
+            RateLimitKey::ClientIp => request.client_ip().map(|addr| addr.to_string()),
+            RateLimitKey::Header(header) => request
+                .find_header(header)
+                .first()
+                .map(|value| value.to_string()),
+            RateLimitKey::Custom(extractor) => extractor(request),
+        }
+    }
+}
+
+/// How requests are counted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitAlgorithm {
+    /// Allows bursts of up to `capacity` requests, refilled at a steady rate
+    TokenBucket {
+        /// Maximum number of tokens in the bucket
+        capacity: u32,
+        /// Number of tokens added to the bucket each second
+        refill_per_second: f64,
+    },
+    /// Allows `limit` requests in each consecutive window
+    FixedWindow {
+        /// Number of requests allowed in a window
+        limit: u32,
+        /// Length of the window
+        window: Duration,
+    },
+}
+
+/// Outcome of checking a request against a rate limiter
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitDecision {
+    /// The request is allowed, with the number of requests remaining
+    Allowed(u32),
+    /// The request is over the limit, and may be retried after the duration
+    Limited(Duration),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Counter {
+    Bucket { tokens: f64, updated: Instant },
+    Window { count: u32, started: Instant },
+}
+
+/// Rate limiter that can be attached to the dispatcher or individual resources. Clones share
+/// the same counters.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    /// What requests are counted against
+    pub key: RateLimitKey,
+    /// How requests are counted
+    pub algorithm: RateLimitAlgorithm,
+    counters: Arc<Mutex<HashMap<String, Counter>>>,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter with the key and algorithm
+    pub fn new(key: RateLimitKey, algorithm: RateLimitAlgorithm) -> RateLimiter {
+        RateLimiter {
+            key,
+            algorithm,
+            counters: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Creates a token bucket rate limiter
+    pub fn token_bucket(key: RateLimitKey, capacity: u32, refill_per_second: f64) -> RateLimiter {
+        RateLimiter::new(
+            key,
+            RateLimitAlgorithm::TokenBucket {
+                capacity,
+                refill_per_second,
+            },
+        )
+    }
+
+    /// Creates a fixed window rate limiter
+    pub fn fixed_window(key: RateLimitKey, limit: u32, window: Duration) -> RateLimiter {
+        RateLimiter::new(key, RateLimitAlgorithm::FixedWindow { limit, window })
+    }
+
+    /// Counts the request, returning if it is allowed
+    pub fn check(&self, request: &Request) -> RateLimitDecision {
+        self.check_at(request, Instant::now())
+    }
+
+    fn check_at(&self, request: &Request, now: Instant) -> RateLimitDecision {
+        let key = match self.key.key(request) {
+            Some(key) => key,
+            None => return RateLimitDecision::Allowed(u32::MAX),
+        };
+        let mut counters = self.counters.lock().unwrap_or_else(|err| err.into_inner());
+        if counters.len() >= PRUNE_THRESHOLD {
+            let algorithm = self.algorithm;
+            counters.retain(|_, counter| !is_idle(&algorithm, counter, now));
+        }
+        let counter = counters
+            .entry(key)
+            .or_insert_with(|| initial_counter(&self.algorithm, now));
+        take(&self.algorithm, counter, now)
+    }
+}
+
+fn initial_counter(algorithm: &RateLimitAlgorithm, now: Instant) -> Counter {
+    match algorithm {
+        RateLimitAlgorithm::TokenBucket { capacity, .. } => Counter::Bucket {
+            tokens: *capacity as f64,
+            updated: now,
+        },
+        RateLimitAlgorithm::FixedWindow { .. } => Counter::Window {
+            count: 0,
+            started: now,
+        },
+    }
+}
+
+fn refill(algorithm: &RateLimitAlgorithm, counter: &mut Counter, now: Instant) {
+    match (algorithm, counter) {
+        (
+            RateLimitAlgorithm::TokenBucket {
+                capacity,
+                refill_per_second,
+            },
+            Counter::Bucket { tokens, updated },
+        ) => {
+            let elapsed = now.saturating_duration_since(*updated).as_secs_f64();
+            *tokens = (*tokens + elapsed * refill_per_second).min(*capacity as f64);
+            *updated = now;
+        }
+        (RateLimitAlgorithm::FixedWindow { window, .. }, Counter::Window { count, started }) => {
+            if now.saturating_duration_since(*started) >= *window {
+                *count = 0;
+                *started = now;
+            }
+        }
+        (algorithm, counter) => *counter = initial_counter(algorithm, now),
+    }
+}
+
+fn is_idle(algorithm: &RateLimitAlgorithm, counter: &Counter, now: Instant) -> bool {
+    let mut counter = *counter;
+    refill(algorithm, &mut counter, now);
+    match (algorithm, counter) {
+        (RateLimitAlgorithm::TokenBucket { capacity, .. }, Counter::Bucket { tokens, .. }) => {
+            tokens >= *capacity as f64
+        }
+        (_, Counter::Window { count, .. }) => count == 0,
+        _ => true,
+    }
+}
+
+fn take(algorithm: &RateLimitAlgorithm, counter: &mut Counter, now: Instant) -> RateLimitDecision {
+    refill(algorithm, counter, now);
+    match (algorithm, counter) {
+        (
+            RateLimitAlgorithm::TokenBucket {
+                refill_per_second, ..
+            },
+            Counter::Bucket { tokens, .. },
+        ) => {
+            if *tokens >= 1.0 {
+                *tokens -= 1.0;
+                RateLimitDecision::Allowed(*tokens as u32)
+            } else if *refill_per_second > 0.0 {
+                RateLimitDecision::Limited(Duration::from_secs_f64(
+                    (1.0 - *tokens) / refill_per_second,
+                ))
+            } else {
+                RateLimitDecision::Limited(Duration::MAX)
+            }
+        }
+        (
+            RateLimitAlgorithm::FixedWindow { limit, window },
+            Counter::Window { count, started },
+        ) => {
+            if *count < *limit {
+                *count += 1;
+                RateLimitDecision::Allowed(limit - *count)
+            } else {
+                RateLimitDecision::Limited(
+                    window.saturating_sub(now.saturating_duration_since(*started)),
+                )
+            }
+        }
+        _ => RateLimitDecision::Allowed(0),
+    }
+}
+
+/// Returns the value of the Retry-After header for the duration, in whole seconds rounded up
+pub fn retry_after_seconds(duration: Duration) -> u64 {
+    let seconds = duration.as_secs();
+    if duration.subsec_nanos() > 0 {
+        seconds.saturating_add(1)
+    } else {
+        seconds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::HeaderValue;
+    use expectest::prelude::*;
+
+    fn request(api_key: &str) -> Request {
+        Request {
+            headers: hashmap! {
+                "X-API-Key".to_string() => vec![HeaderValue::basic(api_key)]
+            },
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn token_bucket_allows_bursts_and_refills() {
+        let limiter = RateLimiter::token_bucket(RateLimitKey::Header("X-API-Key".into()), 2, 0.5);
+        let now = Instant::now();
+        expect!(limiter.check_at(&request("a"), now)).to(be_equal_to(RateLimitDecision::Allowed(1)));
+        expect!(limiter.check_at(&request("a"), now)).to(be_equal_to(RateLimitDecision::Allowed(0)));
+        expect!(limiter.check_at(&request("a"), now))
+            .to(be_equal_to(RateLimitDecision::Limited(Duration::from_secs(2))));
+        expect!(limiter.check_at(&request("b"), now)).to(be_equal_to(RateLimitDecision::Allowed(1)));
+        expect!(limiter.check_at(&request("a"), now + Duration::from_secs(2)))
+            .to(be_equal_to(RateLimitDecision::Allowed(0)));
+    }
+
+    #[test]
+    fn fixed_window_resets_the_count_each_window() {
+        let limiter =
+            RateLimiter::fixed_window(RateLimitKey::ClientIp, 1, Duration::from_secs(60));
+        let request = Request {
+            remote_addr: Some("10.0.0.1:4000".parse().unwrap()),
+            ..Request::default()
+        };
+        let now = Instant::now();
+        expect!(limiter.check_at(&request, now)).to(be_equal_to(RateLimitDecision::Allowed(0)));
+        expect!(limiter.check_at(&request, now + Duration::from_secs(15)))
+            .to(be_equal_to(RateLimitDecision::Limited(Duration::from_secs(45))));
+        expect!(limiter.check_at(&request, now + Duration::from_secs(60)))
+            .to(be_equal_to(RateLimitDecision::Allowed(0)));
+        expect!(limiter.check_at(&Request::default(), now))
+            .to(be_equal_to(RateLimitDecision::Allowed(u32::MAX)));
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        expect!(retry_after_seconds(Duration::from_secs(2))).to(be_equal_to(2));
+        expect!(retry_after_seconds(Duration::from_millis(2001))).to(be_equal_to(3));
+    }
+}
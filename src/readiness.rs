@@ -0,0 +1,159 @@
+//! The `readiness` module gates the readiness of a dispatcher on warm-up work. The dispatcher
+//! is not ready until the `on_start` hooks of its resources and the declared warm-up tasks
+//! (cache priming, connecting to dependencies) have completed, and the readiness resource
+//! returns '503 Service Unavailable' until then. The liveness resource always returns
+//! '200 OK', so an orchestrator does not restart a server that is still warming up.
+
+use futures::{future, Future};
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{headers::HeaderValue, owned_callback, Resource};
+
+/// Async task that prepares a dependency of the server before it is ready for traffic
+pub type WarmUpTask =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> + Send + Sync>;
+
+/// Readiness state of a dispatcher, along with the warm-up tasks that must complete before it
+/// is ready. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct Readiness {
+    /// Tasks that must all succeed before the dispatcher is ready. They are run concurrently
+    /// once the `on_start` hooks of the resources have completed.
+    pub warm_up_tasks: Vec<WarmUpTask>,
+    ready: Arc<AtomicBool>,
+}
+
+impl Readiness {
+    /// Creates a readiness gate with the warm-up tasks
+    pub fn new(warm_up_tasks: Vec<WarmUpTask>) -> Readiness {
+        Readiness {
+            warm_up_tasks,
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// If the dispatcher is ready for traffic
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Marks the dispatcher as ready or not ready (i.e. while a dependency is unavailable)
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    /// Runs the warm-up tasks concurrently, marking the dispatcher as ready if they all
+    /// succeed. If any fail, the failures are logged and the dispatcher stays not ready.
+    pub async fn warm_up(&self) {
+        let results = future::join_all(self.warm_up_tasks.iter().map(|task| task())).await;
+        let failures = results
+            .into_iter()
+            .filter_map(|result| result.err())
+            .collect::<Vec<String>>();
+        if failures.is_empty() {
+            info!("Warm-up is complete, the dispatcher is ready");
+            self.set_ready(true);
+        } else {
+            for failure in failures {
+                error!(
+                    "Warm-up task failed, the dispatcher is not ready - {}",
+                    failure
+                );
+            }
+        }
+    }
+
+    /// Builds the readiness resource, which returns '200 OK' when the dispatcher is ready and
+    /// '503 Service Unavailable' when it is not
+    pub fn readiness_resource(&self) -> Resource<'static> {
+        let readiness = self.clone();
+        probe_resource(move || readiness.is_ready())
+    }
+
+    /// Builds the liveness resource, which always returns '200 OK' while the server is running
+    pub fn liveness_resource(&self) -> Resource<'static> {
+        probe_resource(|| true)
+    }
+}
+
+fn probe_resource<F>(check: F) -> Resource<'static>
+where
+    F: Fn() -> bool + Send + Sync + 'static,
+{
+    Resource {
+        produces: vec!["text/plain"],
+        available: owned_callback(move |context, _| {
+            context
+                .response
+                .add_header("Cache-Control", vec![HeaderValue::basic("no-store")]);
+            let available = check();
+            Box::pin(async move { available })
+        }),
+        render_response: owned_callback(|_, _| Box::pin(async { Some("OK".to_string()) })),
+        ..Resource::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dispatcher;
+    use expectest::prelude::*;
+
+    async fn status(dispatcher: &Dispatcher<'static>, path: &str) -> u16 {
+        let request = http::Request::builder()
+            .uri(path)
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = dispatcher.clone().dispatch(request).await.unwrap();
+        response.status().as_u16()
+    }
+
+    #[tokio::test]
+    async fn dispatcher_is_not_ready_until_the_warm_up_is_complete() {
+        let (sender, receiver) = futures::channel::oneshot::channel::<Result<(), String>>();
+        let receiver = futures::FutureExt::shared(receiver);
+        let task: WarmUpTask = Arc::new(move || {
+            let receiver = receiver.clone();
+            Box::pin(async move { receiver.await.unwrap_or(Err("cancelled".to_string())) })
+        });
+        let readiness = Readiness::new(vec![task]);
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/ready" => readiness.readiness_resource(),
+                "/live" => readiness.liveness_resource()
+            },
+            readiness: Some(readiness.clone()),
+            ..Dispatcher::default()
+        };
+
+        let starting = tokio::spawn({
+            let dispatcher = dispatcher.clone();
+            async move { dispatcher.start().await }
+        });
+        expect!(status(&dispatcher, "/ready").await).to(be_equal_to(503));
+        expect!(status(&dispatcher, "/live").await).to(be_equal_to(200));
+
+        sender.send(Ok(())).unwrap();
+        starting.await.unwrap();
+        expect!(readiness.is_ready()).to(be_true());
+        expect!(status(&dispatcher, "/ready").await).to(be_equal_to(200));
+
+        dispatcher.stop().await;
+        expect!(status(&dispatcher, "/ready").await).to(be_equal_to(503));
+    }
+
+    #[tokio::test]
+    async fn failed_warm_up_leaves_the_dispatcher_not_ready() {
+        let task: WarmUpTask = Arc::new(|| Box::pin(async { Err("cache is down".to_string()) }));
+        let readiness = Readiness::new(vec![task]);
+        readiness.warm_up().await;
+        expect!(readiness.is_ready()).to(be_false());
+    }
+}
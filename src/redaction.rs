@@ -0,0 +1,116 @@
+//! The `redaction` module controls what is written to the logs about requests and responses,
+//! so that credentials and other personal data in headers and bodies are not leaked.
+
+use itertools::Itertools;
+
+use crate::context::Response;
+
+/// Configuration of redacting headers and bodies in log output
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedactionConfig {
+    /// Headers (case-insensitive) whose values are replaced with `[redacted]`. Defaults to the
+    /// headers that carry credentials or session cookies.
+    pub headers: Vec<String>,
+    /// Development mode flag to log bodies. Bodies can contain personal data, so only enable
+    /// this for local development. Defaults to false, in which case only the body length is
+    /// logged.
+    pub dump_bodies: bool,
+    /// Maximum number of bytes of a body to log when `dump_bodies` is set. Defaults to 1024.
+    pub max_body_length: usize,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> RedactionConfig {
+        RedactionConfig {
+            headers: vec![
+                "Authorization".to_string(),
+                "Proxy-Authorization".to_string(),
+                "Cookie".to_string(),
+                "Set-Cookie".to_string(),
+                "X-API-Key".to_string(),
+            ],
+            dump_bodies: false,
+            max_body_length: 1024,
+        }
+    }
+}
+
+impl RedactionConfig {
+    /// If the values of the header should be redacted
+    pub fn is_redacted(&self, header: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|redacted| redacted.eq_ignore_ascii_case(header))
+    }
+
+    /// Returns a single line description of the response, with redacted header values and the
+    /// body only included in development mode
+    pub fn describe_response(&self, response: &Response) -> String {
+        let headers = response
+            .headers
+            .iter()
+            .map(|(header, values)| {
+                if self.is_redacted(header) {
+                    format!("{}: [redacted]", header)
+                } else {
+                    format!("{}: {}", header, values.iter().map(|v| v.to_string()).join(", "))
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("; ");
+        format!(
+            "status={} headers={{{}}} body={}",
+            response.status,
+            headers,
+            self.describe_body(response.body.as_deref())
+        )
+    }
+
+    fn describe_body(&self, body: Option<&[u8]>) -> String {
+        match body {
+            None => "none".to_string(),
+            Some(body) if !self.dump_bodies => format!("<{} bytes>", body.len()),
+            Some(body) if body.len() > self.max_body_length => format!(
+                "{:?}... <{} bytes>",
+                String::from_utf8_lossy(&body[..self.max_body_length]),
+                body.len()
+            ),
+            Some(body) => format!("{:?}", String::from_utf8_lossy(body)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::HeaderValue;
+    use expectest::prelude::*;
+
+    fn response() -> Response {
+        let mut response = Response::default();
+        response.add_header("Set-Cookie", vec![HeaderValue::basic("session=secret")]);
+        response.add_header("Content-Type", vec![HeaderValue::basic("text/plain")]);
+        response.body = Some(b"hello world".to_vec());
+        response
+    }
+
+    #[test]
+    fn describe_response_redacts_headers_and_bodies() {
+        expect!(RedactionConfig::default().describe_response(&response())).to(be_equal_to(
+            "status=200 headers={Content-Type: text/plain; Set-Cookie: [redacted]} body=<11 bytes>",
+        ));
+    }
+
+    #[test]
+    fn describe_response_dumps_bodies_in_development_mode() {
+        let config = RedactionConfig {
+            dump_bodies: true,
+            max_body_length: 5,
+            ..RedactionConfig::default()
+        };
+        expect!(config.describe_response(&response())).to(be_equal_to(
+            "status=200 headers={Content-Type: text/plain; Set-Cookie: [redacted]} \
+             body=\"hello\"... <11 bytes>",
+        ));
+    }
+}
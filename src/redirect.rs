@@ -0,0 +1,176 @@
+//! The `redirect` module redirects legacy URL trees in bulk, for site migrations. The redirect
+//! rules of the dispatcher are evaluated before routing, and a matching request gets a permanent
+//! redirect to the rewritten path, with the query string of the request. Rules either replace a
+//! path prefix (i.e. `/blog` with `/articles`), or match a regular expression and expand its
+//! captures into the target (i.e. `^/products/(\d+)$` to `/shop/items/$1`).
+
+use regex::Regex;
+
+use crate::{context::Context, headers::HeaderValue, replay::encode_query_component};
+
+/// How a redirect rule matches the request path
+#[derive(Debug, Clone)]
+pub enum RedirectMatch {
+    /// Matches the path if it starts with the prefix, by whole segments (`/blog` matches
+    /// `/blog` and `/blog/2020`, but not `/blogs`). The rest of the path is appended to the
+    /// target.
+    Prefix(String),
+    /// Matches the path against the regular expression. The captures are expanded into the
+    /// target (i.e. `$1` or `${name}`).
+    Regex(Regex),
+}
+
+/// A rule redirecting requests to paths of a legacy URL tree
+#[derive(Debug, Clone)]
+pub struct RedirectRule {
+    /// How the rule matches the request path
+    pub matches: RedirectMatch,
+    /// Path, or URL, that requests are redirected to
+    pub target: String,
+    /// Status of the redirect. Defaults to '301 Moved Permanently'; use
+    /// '308 Permanent Redirect' if clients must repeat the method and body (i.e. for a POST).
+    pub status: u16,
+}
+
+impl RedirectRule {
+    /// Creates a rule that replaces the path prefix with the target
+    pub fn prefix(prefix: &str, target: &str) -> RedirectRule {
+        RedirectRule {
+            matches: RedirectMatch::Prefix(prefix.trim_end_matches('/').to_string()),
+            target: target.trim_end_matches('/').to_string(),
+            status: 301,
+        }
+    }
+
+    /// Creates a rule that matches the path against the regular expression, and expands its
+    /// captures into the target. Returns an error if the regular expression is not valid.
+    pub fn regex(pattern: &str, target: &str) -> Result<RedirectRule, regex::Error> {
+        Ok(RedirectRule {
+            matches: RedirectMatch::Regex(Regex::new(pattern)?),
+            target: target.to_string(),
+            status: 301,
+        })
+    }
+
+    /// Returns the path the request path is redirected to, if the rule matches it
+    pub fn rewrite(&self, path: &str) -> Option<String> {
+        match &self.matches {
+            RedirectMatch::Prefix(prefix) => {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                if !rest.is_empty() && !rest.starts_with('/') {
+                    return None;
+                }
+                let rewritten = format!("{}{}", self.target, rest);
+                Some(if rewritten.is_empty() {
+                    "/".to_string()
+                } else {
+                    rewritten
+                })
+            }
+            RedirectMatch::Regex(regex) => {
+                let captures = regex.captures(path)?;
+                let mut rewritten = String::new();
+                captures.expand(&self.target, &mut rewritten);
+                Some(rewritten)
+            }
+        }
+    }
+}
+
+/// Redirects the request with the first of the rules that matches its path
+pub(crate) fn apply_redirects(rules: &[RedirectRule], context: &mut Context) {
+    let path = context.request.request_path.clone();
+    let (rule, location) = match rules
+        .iter()
+        .find_map(|rule| rule.rewrite(&path).map(|location| (rule, location)))
+    {
+        Some(redirect) => redirect,
+        None => return,
+    };
+    let mut query: Vec<_> = context.request.query.iter().collect();
+    query.sort();
+    let query: Vec<String> = query
+        .into_iter()
+        .flat_map(|(name, values)| {
+            values.iter().map(move |value| {
+                format!(
+                    "{}={}",
+                    encode_query_component(name),
+                    encode_query_component(value)
+                )
+            })
+        })
+        .collect();
+    let location = if query.is_empty() {
+        location
+    } else {
+        format!("{}?{}", location, query.join("&"))
+    };
+    debug!("Redirecting legacy path '{}' to '{}'", path, location);
+    context.response.status = rule.status;
+    context
+        .response
+        .add_header("Location", vec![HeaderValue::basic(location)]);
+    context.error = Some("Legacy path redirected".to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dispatcher, Resource};
+    use expectest::prelude::*;
+
+    #[test]
+    fn prefix_rules_match_whole_segments() {
+        let rule = RedirectRule::prefix("/blog/", "/articles");
+        expect!(rule.rewrite("/blog")).to(be_some().value("/articles"));
+        expect!(rule.rewrite("/blog/2020/hello")).to(be_some().value("/articles/2020/hello"));
+        expect!(rule.rewrite("/blogs")).to(be_none());
+        expect!(RedirectRule::prefix("/old", "/").rewrite("/old")).to(be_some().value("/"));
+    }
+
+    #[test]
+    fn regex_rules_expand_captures() {
+        let rule =
+            RedirectRule::regex(r"^/products/(?P<id>\d+)\.html$", "/shop/items/${id}").unwrap();
+        expect!(rule.rewrite("/products/42.html")).to(be_some().value("/shop/items/42"));
+        expect!(rule.rewrite("/products/shoes.html")).to(be_none());
+    }
+
+    #[tokio::test]
+    async fn dispatcher_redirects_legacy_paths_before_routing() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/articles" => Resource::default(),
+                "/blog/feed" => Resource::default()
+            },
+            redirects: vec![
+                RedirectRule {
+                    status: 308,
+                    ..RedirectRule::prefix("/blog", "/articles")
+                },
+                RedirectRule::regex(r"^/p/(\d+)$", "/articles/$1").unwrap(),
+            ],
+            ..Dispatcher::default()
+        };
+        let get = |uri: &str| {
+            let request = http::Request::get(uri).body(hyper::Body::empty()).unwrap();
+            let dispatcher = dispatcher.clone();
+            async move {
+                let response = dispatcher.dispatch(request).await.unwrap();
+                let location = response
+                    .headers()
+                    .get("Location")
+                    .map(|value| value.to_str().unwrap().to_string());
+                (response.status().as_u16(), location)
+            }
+        };
+
+        expect!(get("/blog/feed?page=2&lang=en").await).to(be_equal_to((
+            308,
+            Some("/articles/feed?lang=en&page=2".to_string()),
+        )));
+        expect!(get("/p/7").await).to(be_equal_to((301, Some("/articles/7".to_string()))));
+        expect!(get("/articles").await).to(be_equal_to((200, None)));
+    }
+}
@@ -0,0 +1,108 @@
+//! The `registry` module provides a registry of named resources, so that routes can reference
+//! resources by name instead of constructing them in place.
+
+use std::collections::BTreeMap;
+
+use super::{Dispatcher, Resource};
+
+/// Registry of webmachine resources keyed by name
+#[derive(Clone, Default)]
+pub struct ResourceRegistry<'a> {
+    resources: BTreeMap<String, Resource<'a>>,
+}
+
+impl<'a> ResourceRegistry<'a> {
+    /// Creates an empty registry
+    pub fn new() -> ResourceRegistry<'a> {
+        ResourceRegistry {
+            resources: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a resource under the given name. If a resource was already registered with
+    /// that name, it is replaced and returned.
+    pub fn register<S: Into<String>>(
+        &mut self,
+        name: S,
+        resource: Resource<'a>,
+    ) -> Option<Resource<'a>> {
+        self.resources.insert(name.into(), resource)
+    }
+
+    /// Removes the named resource from the registry, returning it if it was registered
+    pub fn unregister(&mut self, name: &str) -> Option<Resource<'a>> {
+        self.resources.remove(name)
+    }
+
+    /// Returns the resource registered with the given name
+    pub fn get(&self, name: &str) -> Option<&Resource<'a>> {
+        self.resources.get(name)
+    }
+
+    /// If a resource is registered with the given name
+    pub fn contains(&self, name: &str) -> bool {
+        self.resources.contains_key(name)
+    }
+
+    /// Returns the names of all the registered resources, in sorted order
+    pub fn names(&self) -> Vec<&str> {
+        self.resources.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Creates a dispatcher from a map of routes to resource names. Returns an error naming the
+    /// first route that references a resource that is not registered.
+    pub fn dispatcher(&self, routes: &BTreeMap<&'a str, &str>) -> Result<Dispatcher<'a>, String> {
+        let mut resolved = BTreeMap::new();
+        for (path, name) in routes {
+            match self.get(name) {
+                Some(resource) => {
+                    resolved.insert(*path, resource.clone());
+                }
+                None => {
+                    return Err(format!(
+                        "Route '{}' refers to resource '{}' which is not registered",
+                        path, name
+                    ))
+                }
+            }
+        }
+        Ok(Dispatcher {
+            routes: resolved,
+            ..Dispatcher::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn registry_resolves_routes_by_name() {
+        let mut registry = ResourceRegistry::new();
+        registry.register(
+            "users",
+            Resource {
+                allowed_methods: vec!["GET", "POST"],
+                ..Resource::default()
+            },
+        );
+        registry.register("health", Resource::default());
+        expect!(registry.names()).to(be_equal_to(vec!["health", "users"]));
+
+        let dispatcher = registry
+            .dispatcher(&btreemap! { "/users" => "users", "/status" => "health" })
+            .unwrap();
+        expect!(dispatcher.routes.keys().cloned().collect::<Vec<_>>())
+            .to(be_equal_to(vec!["/status", "/users"]));
+        expect!(dispatcher.routes["/users"].allowed_methods.clone())
+            .to(be_equal_to(vec!["GET", "POST"]));
+    }
+
+    #[test]
+    fn registry_returns_an_error_for_unknown_resource_names() {
+        let registry = ResourceRegistry::new();
+        expect!(registry.dispatcher(&btreemap! { "/users" => "users" }).is_err()).to(be_true());
+    }
+}
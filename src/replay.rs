@@ -0,0 +1,371 @@
+//! The `replay` module replays recorded traffic against a dispatcher in-process, bypassing the
+//! network, for capacity testing of the callbacks of resources. Requests are recorded by the
+//! `TraceRecorder` of the dispatcher (without the headers and bodies its redaction configuration
+//! excludes), sent at a fixed rate, and the latency of the responses is reported per route.
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use crate::{context::Request, debugger::request_path, Dispatcher, RedactionConfig};
+
+/// Route of the report for requests that did not match a route
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Request captured by a `TraceRecorder`, which can be replayed against a dispatcher
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    /// Method of the request
+    pub method: String,
+    /// Path of the request
+    pub path: String,
+    /// Query parameters of the request
+    pub query: BTreeMap<String, Vec<String>>,
+    /// Headers of the request, as name and value pairs, without the redacted headers
+    pub headers: Vec<(String, String)>,
+    /// Body of the request, if bodies are captured
+    pub body: Option<Vec<u8>>,
+}
+
+impl RecordedRequest {
+    /// Captures the request so it can be replayed. The headers redacted by the configuration
+    /// (i.e. Authorization and Cookie) are not captured, and the body is only captured if the
+    /// configuration dumps bodies, so credentials and personal data are not kept.
+    pub fn from_request(request: &Request, redaction: &RedactionConfig) -> RecordedRequest {
+        let mut headers = request
+            .headers
+            .iter()
+            .filter(|(name, _)| !redaction.is_redacted(name))
+            .flat_map(|(name, values)| {
+                values
+                    .iter()
+                    .map(move |value| (name.clone(), value.to_string()))
+            })
+            .collect::<Vec<(String, String)>>();
+        headers.sort();
+        RecordedRequest {
+            method: request.method.clone(),
+            path: request_path(request),
+            query: request
+                .query
+                .iter()
+                .map(|(name, values)| (name.clone(), values.clone()))
+                .collect(),
+            headers,
+            body: request.body.clone().filter(|_| redaction.dump_bodies),
+        }
+    }
+
+    /// Returns the request target (the path and the encoded query string)
+    pub fn target(&self) -> String {
+        let mut target = self.path.clone();
+        let mut separator = '?';
+        for (name, values) in &self.query {
+            for value in values {
+                target.push(separator);
+                target.push_str(&encode_query_component(name));
+                target.push('=');
+                target.push_str(&encode_query_component(value));
+                separator = '&';
+            }
+        }
+        target
+    }
+
+    /// Builds the HTTP request to dispatch
+    pub fn to_http_request(&self) -> http::Result<http::Request<hyper::Body>> {
+        let mut builder = http::Request::builder()
+            .method(self.method.as_str())
+            .uri(self.target());
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        let body = match &self.body {
+            Some(body) => hyper::Body::from(body.clone()),
+            None => hyper::Body::empty(),
+        };
+        builder.body(body)
+    }
+}
+
+pub(crate) fn encode_query_component(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+/// Configuration of a replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayConfig {
+    /// Number of requests sent per second. Requests are sent on schedule regardless of how long
+    /// earlier requests are taking, so a slow resource results in more concurrent requests.
+    /// Zero sends all the requests at once. Defaults to 100.
+    pub rate: f64,
+    /// Number of times the recorded requests are replayed. Defaults to 1.
+    pub repeat: usize,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> ReplayConfig {
+        ReplayConfig {
+            rate: 100.0,
+            repeat: 1,
+        }
+    }
+}
+
+/// Latencies of the replayed requests to a route
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RouteLatencies {
+    /// Number of requests to the route
+    pub requests: usize,
+    /// Number of requests that failed, with a 5xx status or an error streaming the body
+    pub errors: usize,
+    /// Median latency
+    pub p50: Duration,
+    /// 90th percentile latency
+    pub p90: Duration,
+    /// 99th percentile latency
+    pub p99: Duration,
+    /// Maximum latency
+    pub max: Duration,
+}
+
+impl RouteLatencies {
+    fn from_samples(mut latencies: Vec<Duration>, errors: usize) -> RouteLatencies {
+        latencies.sort();
+        RouteLatencies {
+            requests: latencies.len(),
+            errors,
+            p50: percentile(&latencies, 50.0),
+            p90: percentile(&latencies, 90.0),
+            p99: percentile(&latencies, 99.0),
+            max: latencies.last().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted latencies
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Report of a replay
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ReplayReport {
+    /// Number of requests sent
+    pub requests: usize,
+    /// Time taken by the replay
+    pub elapsed: Duration,
+    /// Latencies by the route that the requests matched. Requests that did not match a route
+    /// are reported under `unmatched`.
+    pub routes: BTreeMap<String, RouteLatencies>,
+}
+
+/// Replays the requests against the dispatcher, at the rate of the configuration. The latency
+/// of each request is measured from when it is dispatched until its response body has been
+/// read, and reported by the route that matched the request.
+pub async fn replay(
+    dispatcher: &Dispatcher<'_>,
+    requests: &[RecordedRequest],
+    config: &ReplayConfig,
+) -> ReplayReport {
+    let started = tokio::time::Instant::now();
+    let total = requests.len() * config.repeat;
+    let mut in_flight = requests
+        .iter()
+        .cycle()
+        .take(total)
+        .enumerate()
+        .map(|(index, request)| {
+            let dispatcher = dispatcher.clone();
+            let send_at = if config.rate > 0.0 {
+                started + Duration::from_secs_f64(index as f64 / config.rate)
+            } else {
+                started
+            };
+            async move {
+                tokio::time::sleep_until(send_at).await;
+                match request.to_http_request() {
+                    Ok(http_request) => {
+                        let target = Request {
+                            request_path: request.path.clone(),
+                            ..Request::default()
+                        };
+                        let route = dispatcher.longest_matching_path(&target);
+                        (route, send(dispatcher, http_request).await)
+                    }
+                    Err(err) => {
+                        warn!(
+                            "Recorded request to '{}' is invalid - {}",
+                            request.path, err
+                        );
+                        (None, (Duration::ZERO, false))
+                    }
+                }
+            }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut samples: BTreeMap<String, (Vec<Duration>, usize)> = BTreeMap::new();
+    while let Some((route, (latency, ok))) = in_flight.next().await {
+        let route = route.unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+        let (latencies, errors) = samples.entry(route).or_default();
+        latencies.push(latency);
+        if !ok {
+            *errors += 1;
+        }
+    }
+    ReplayReport {
+        requests: total,
+        elapsed: started.elapsed(),
+        routes: samples
+            .into_iter()
+            .map(|(route, (latencies, errors))| {
+                (route, RouteLatencies::from_samples(latencies, errors))
+            })
+            .collect(),
+    }
+}
+
+/// Dispatches the request and reads the response body, returning the latency and if the request
+/// succeeded
+async fn send(dispatcher: Dispatcher<'_>, request: http::Request<hyper::Body>) -> (Duration, bool) {
+    let started = Instant::now();
+    let ok = match dispatcher.dispatch(request).await {
+        Ok(response) => {
+            let success = !response.status().is_server_error();
+            hyper::body::to_bytes(response.into_body()).await.is_ok() && success
+        }
+        Err(_) => false,
+    };
+    (started.elapsed(), ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{headers::HeaderValue, owned_callback, Resource, TraceRecorder};
+    use expectest::prelude::*;
+
+    #[test]
+    fn recorded_requests_are_rebuilt_with_their_query_and_headers() {
+        let request = RecordedRequest {
+            method: "POST".to_string(),
+            path: "/orders".to_string(),
+            query: btreemap! {
+                "q".to_string() => vec!["a b&c".to_string()],
+                "page".to_string() => vec!["2".to_string()]
+            },
+            headers: vec![("X-Test".to_string(), "1".to_string())],
+            body: Some(b"{}".to_vec()),
+        };
+        let http_request = request.to_http_request().unwrap();
+        expect!(http_request.method().as_str()).to(be_equal_to("POST"));
+        expect!(http_request.uri().to_string()).to(be_equal_to("/orders?page=2&q=a%20b%26c"));
+        expect!(http_request.headers()["X-Test"].to_str().unwrap()).to(be_equal_to("1"));
+    }
+
+    #[test]
+    fn recorded_requests_do_not_capture_credentials_or_bodies_unless_configured() {
+        let request = Request {
+            method: "POST".to_string(),
+            request_path: "/orders".to_string(),
+            headers: hashmap! {
+                "authorization".to_string() => vec![HeaderValue::basic("Bearer secret")],
+                "Cookie".to_string() => vec![HeaderValue::basic("session=1")],
+                "Accept".to_string() => vec![HeaderValue::basic("application/json")]
+            },
+            body: Some(b"{\"card\": \"4111\"}".to_vec()),
+            ..Request::default()
+        };
+        let recorded = RecordedRequest::from_request(&request, &RedactionConfig::default());
+        expect!(recorded.headers).to(be_equal_to(vec![(
+            "Accept".to_string(),
+            "application/json".to_string(),
+        )]));
+        expect!(recorded.body).to(be_none());
+
+        let redaction = RedactionConfig {
+            dump_bodies: true,
+            ..RedactionConfig::default()
+        };
+        let recorded = RecordedRequest::from_request(&request, &redaction);
+        expect!(recorded.body).to(be_equal_to(request.body));
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let latencies = (1..=10)
+            .map(Duration::from_millis)
+            .collect::<Vec<Duration>>();
+        let report = RouteLatencies::from_samples(latencies, 0);
+        expect!(report.p50).to(be_equal_to(Duration::from_millis(5)));
+        expect!(report.p90).to(be_equal_to(Duration::from_millis(9)));
+        expect!(report.p99).to(be_equal_to(Duration::from_millis(10)));
+        expect!(report.max).to(be_equal_to(Duration::from_millis(10)));
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_traffic_and_reports_latencies_by_route() {
+        let recorder = TraceRecorder::default();
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/orders" => Resource {
+                    allowed_methods: vec!["GET", "POST"],
+                    ..Resource::default()
+                },
+                "/reports" => Resource {
+                    render_response: owned_callback(|context, _| {
+                        context.response.status = 500;
+                        Box::pin(async { None })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            debugger: Some(recorder.clone()),
+            ..Dispatcher::default()
+        };
+        for (method, path) in &[
+            ("GET", "/orders/1?full=true"),
+            ("POST", "/orders"),
+            ("GET", "/reports"),
+        ] {
+            let request = http::Request::builder()
+                .method(*method)
+                .uri(*path)
+                .body(hyper::Body::empty())
+                .unwrap();
+            dispatcher.clone().dispatch(request).await.unwrap();
+        }
+        let requests = recorder.requests();
+        expect!(requests[0].target()).to(be_equal_to("/orders/1?full=true"));
+
+        let config = ReplayConfig {
+            rate: 0.0,
+            repeat: 2,
+        };
+        let report = replay(&dispatcher, &requests, &config).await;
+        expect!(report.requests).to(be_equal_to(6));
+        expect!(report.routes.keys().cloned().collect::<Vec<String>>()).to(be_equal_to(vec![
+            "/orders".to_string(),
+            "/reports".to_string(),
+        ]));
+        expect!(report.routes["/orders"].requests).to(be_equal_to(4));
+        expect!(report.routes["/orders"].errors).to(be_equal_to(0));
+        expect!(report.routes["/reports"].errors).to(be_equal_to(2));
+    }
+}
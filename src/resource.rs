@@ -1,36 +1,120 @@
 use chrono::{DateTime, FixedOffset};
 use futures::Future;
-use std::{collections::HashMap, pin::Pin};
+use serde_json::Value;
+use std::{collections::HashMap, pin::Pin, sync::Arc};
 
-use super::{callback, Callback, Context, Response};
+use super::{
+    auth::{Authenticator, Authorizer},
+    callback,
+    codec::CodecRegistry,
+    content_negotiation::{FormatOverride, UserAgentNegotiation},
+    Availability, CachingProfile, Callback, CompressionConfig, Context, CorsConfig,
+    DecisionLogConfig, DigestConfig, FaultInjector, HeaderRequirement, Method, MethodRegistry,
+    OpenApiHook, RateLimiter, RedactionConfig, RequestCoalescer, RequestTimeout,
+};
+
+/// A complete representation of a resource, declared so that the media type and language are
+/// negotiated together
+#[derive(Clone)]
+pub struct Variant<'a> {
+    /// Media type of the representation
+    pub media_type: &'a str,
+    /// Language of the representation, if it has one
+    pub language: Option<&'a str>,
+    /// Callback to render the representation. If this is None, the `render_response` callback
+    /// of the resource is used.
+    pub render: Option<Callback<'a, Option<String>>>,
+    /// Callback to generate the ETag of the representation. If this is None, the ETag from the
+    /// `generate_etag` callback of the resource is suffixed with the media type and language.
+    pub etag: Option<Callback<'a, Option<String>>>,
+}
+
+impl<'a> Variant<'a> {
+    /// Creates a variant with the media type and language, rendered by the resource
+    pub fn new(media_type: &'a str, language: Option<&'a str>) -> Variant<'a> {
+        Variant {
+            media_type,
+            language,
+            render: None,
+            etag: None,
+        }
+    }
+}
+
+/// Type of a hook called when a resource starts or stops being served
+pub type LifecycleHook<'a> =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync + 'a>;
 
 /// Struct to represent a resource in webmachine
 #[derive(Clone)]
 pub struct Resource<'a> {
-    /// This is called just before the final response is constructed and sent. It allows the resource
-    /// an opportunity to modify the response after the webmachine has executed.
+    /// This is called just before the final response is constructed and sent, after
+    /// `finish_request`. It allows the resource an opportunity to modify the response after the
+    /// webmachine has executed, and sees the response as it will be sent (i.e. with the
+    /// compressed body and the digest headers). The returned future is awaited before the
+    /// response is sent, so it can do I/O like audit logging.
     pub finalise_response: Option<Callback<'a, ()>>,
     /// This is invoked to render the response for the resource
     pub render_response: Callback<'a, Option<String>>,
+    /// This is invoked to render the response for the resource as a value, which is encoded
+    /// with the codec for the negotiated media type. If this is set, it is used instead of
+    /// `render_response`. Defaults to None.
+    pub render_value: Option<Callback<'a, Option<Value>>>,
+    /// Codecs used to encode the values from `render_value`, keyed by media type. Defaults to
+    /// the JSON codec (and the CBOR codec with the `cbor` feature).
+    pub codecs: CodecRegistry,
     /// Is the resource available? Returning false will result in a '503 Service Not Available'
     /// response. Defaults to true. If the resource is only temporarily not available,
     /// add a 'Retry-After' response header.
     pub available: Callback<'a, bool>,
-    /// HTTP methods that are known to the resource. Default includes all standard HTTP methods.
-    /// One could override this to allow additional methods
-    pub known_methods: Vec<&'a str>,
+    /// Is the request directed at a resource that this server is not able to produce a response
+    /// for (for instance, a request for another host)? Returning true will result in a
+    /// '421 Misdirected Request' response. Default is false.
+    pub misdirected_request: Callback<'a, bool>,
+    /// If the client must switch to a different protocol to access the resource, this should
+    /// return the protocols to upgrade to (i.e. "TLS/1.2, HTTP/1.1" or "websocket"), which
+    /// will result in a '426 Upgrade Required' response with an Upgrade header. Default is None.
+    pub upgrade_required: Callback<'a, Option<String>>,
+    /// HTTP methods that are known to the resource, with their properties. Default includes all
+    /// standard HTTP methods. Additional methods can be registered, and their properties decide
+    /// if responses to them are cacheable.
+    pub known_methods: MethodRegistry,
+    /// Status of the response to a request with a method that is not in `known_methods`. The
+    /// response has an Allow header and a problem details body listing the allowed methods.
+    /// Defaults to 501 (Not Implemented), but many APIs prefer 405 (Method Not Allowed) for any
+    /// method that is not allowed.
+    pub unknown_method_status: u16,
     /// If the URI is too long to be processed, this should return true, which will result in a
     /// '414 Request URI Too Long' response. Defaults to false.
     pub uri_too_long: Callback<'a, bool>,
-    /// HTTP methods that are allowed on this resource. Defaults to GET','HEAD and 'OPTIONS'.
+    /// HTTP methods that are allowed on this resource. Methods that are not in `known_methods`
+    /// are left out of the Allow and CORS headers (see `Resource::allowed_known_methods`), and
+    /// reported by `Dispatcher::diagnostics`. Defaults to GET','HEAD and 'OPTIONS'.
     pub allowed_methods: Vec<&'a str>,
     /// If the request is malformed, this should return true, which will result in a
     /// '400 Malformed Request' response. Defaults to false.
     pub malformed_request: Callback<'a, bool>,
+    /// Request headers the resource requires, with validators of their values. A missing or
+    /// invalid header results in a '400 Bad Request' (or the missing status of the requirement)
+    /// response with a problem details body naming the header, before `malformed_request` is
+    /// called. Defaults to empty.
+    pub required_headers: Vec<HeaderRequirement>,
     /// Is the client or request not authorized? Returning a Some<String>
     /// will result in a '401 Unauthorized' response.  Defaults to None. If a Some(String) is
     /// returned, the string will be used as the value in the WWW-Authenticate header.
     pub not_authorized: Callback<'a, Option<String>>,
+    /// Authenticators that are consulted in order before `not_authorized`. The principal of the
+    /// first authenticator that accepts the credentials of the request is stored in the
+    /// context, and its attributes (i.e. token claims) are added to the context metadata. If
+    /// none of them do, the response is a '401 Unauthorized' with the challenges of all the
+    /// authenticators. Defaults to empty (no authentication).
+    pub authenticators: Vec<Arc<dyn Authenticator>>,
+    /// Permissions (or roles) the principal of the request must have to access the resource.
+    /// These are checked by the authorizer before `forbidden`. Defaults to empty.
+    pub required_permissions: Vec<&'a str>,
+    /// Authorizer that checks the required permissions. If this is None, the authorizer of the
+    /// dispatcher is used. If neither is set, the required permissions are not checked.
+    pub authorizer: Option<Arc<dyn Authorizer>>,
     /// Is the request or client forbidden? Returning true will result in a '403 Forbidden' response.
     /// Defaults to false.
     pub forbidden: Callback<'a, bool>,
@@ -40,35 +124,66 @@ pub struct Resource<'a> {
     /// The list of acceptable content types. Defaults to 'application/json'. If the content type
     /// of the request is not in this list, a '415 Unsupported Media Type' response is returned.
     pub acceptable_content_types: Vec<&'a str>,
+    /// Per-method overrides of `acceptable_content_types`, keyed by HTTP method. If the request
+    /// method has an entry in this map, it is used instead of `acceptable_content_types`.
+    /// Defaults to an empty map.
+    pub method_acceptable_content_types: HashMap<&'a str, Vec<&'a str>>,
     /// If the entity length on PUT or POST is invalid, this should return false, which will result
     /// in a '413 Request Entity Too Large' response. Defaults to true.
     pub valid_entity_length: Callback<'a, bool>,
-    /// This is called just before the final response is constructed and sent. This allows the
-    /// response to be modified. The default implementation adds CORS headers to the response
+    /// Maximum length in bytes of a request body. Requests with a larger Content-Length will
+    /// result in a '413 Request Entity Too Large' response before the body is read, and reading
+    /// a body without a Content-Length is aborted once it exceeds the limit. Defaults to None, in
+    /// which case the maximum of the dispatcher is used if it has one.
+    pub max_entity_length: Option<u64>,
+    /// This is called once the state machine has reached a terminal state, the body has been
+    /// rendered and the headers of the response (compression, caching, CORS, digest and
+    /// signature headers) have been added, before `finalise_response`. This allows the response
+    /// to be modified, and any resources held for the request to be released. Changes to the
+    /// body are not reflected in the digest and signature headers. The default implementation
+    /// does nothing.
     pub finish_request: Callback<'a, ()>,
     /// If the OPTIONS method is supported and is used, this returns a HashMap of headers that
-    /// should appear in the response. Defaults to CORS headers.
+    /// should appear in the response. Defaults to the preflight headers of `cors`, and no
+    /// headers if the resource has no CORS configuration.
     pub options: Callback<'a, Option<HashMap<String, Vec<String>>>>,
     /// The list of content types that this resource produces. Defaults to 'application/json'. If
     /// more than one is provided, and the client does not supply an Accept header, the first one
     /// will be selected.
     pub produces: Vec<&'a str>,
+    /// Per-method overrides of `produces`, keyed by HTTP method. If the request method has an
+    /// entry in this map, it is used for content negotiation instead of `produces`. Defaults to
+    /// an empty map.
+    pub method_produces: HashMap<&'a str, Vec<&'a str>>,
     /// The list of content languages that this resource provides. Defaults to an empty list,
     /// which represents all languages. If more than one is provided, and the client does not
     /// supply an Accept-Language header, the first one will be selected.
     pub languages_provided: Vec<&'a str>,
+    /// The complete representations of the resource. If any are declared, the media type and
+    /// language are negotiated together across the variants (instead of independently using
+    /// `produces` and `languages_provided`), and the selected variant is rendered. Defaults to
+    /// an empty list.
+    pub variants: Vec<Variant<'a>>,
     /// The list of charsets that this resource provides. Defaults to an empty list,
     /// which represents all charsets with ISO-8859-1 as the default. If more than one is provided,
     /// and the client does not supply an Accept-Charset header, the first one will be selected.
     pub charsets_provided: Vec<&'a str>,
-    /// The list of encodings your resource wants to provide. The encoding will be applied to the
-    /// response body automatically by Webmachine. Default includes only the 'identity' encoding.
+    /// The list of encodings your resource wants to provide. The gzip and deflate encodings are
+    /// applied to the response body by Webmachine if `compression` is set, otherwise the resource
+    /// must encode the body itself. Default includes only the 'identity' encoding.
     pub encodings_provided: Vec<&'a str>,
     /// The list of header names that should be included in the response's Vary header. The standard
     /// content negotiation headers (Accept, Accept-Encoding, Accept-Charset, Accept-Language) do
     /// not need to be specified here as Webmachine will add the correct elements of those
     /// automatically depending on resource behavior. Default is an empty list.
     pub variances: Vec<&'a str>,
+    /// Query parameter that overrides the Accept header when negotiating the media type (i.e.
+    /// `?format=csv`). Requests for a format that is not mapped to a media type result in a
+    /// '406 Not Acceptable' response. Defaults to None.
+    pub format_override: Option<FormatOverride>,
+    /// Media types preferred by each class of client (i.e. HTML for browsers) when a request has
+    /// no Accept header. Defaults to None (the first of `produces` is selected).
+    pub user_agent_negotiation: Option<UserAgentNegotiation>,
     /// Does the resource exist? Returning a false value will result in a '404 Not Found' response
     /// unless it is a PUT or POST. Defaults to true.
     pub resource_exists: Callback<'a, bool>,
@@ -86,7 +201,9 @@ pub struct Resource<'a> {
     /// Return true if the resource accepts POST requests to nonexistent resources. Defaults to false.
     pub allow_missing_post: Callback<'a, bool>,
     /// If this returns a value, it will be used as the value of the ETag header and for
-    /// comparison in conditional requests. Default is None.
+    /// comparison in conditional requests. If the resource has more than one representation,
+    /// the selected media type, language, charset and encoding are appended to the tag so each
+    /// representation has a distinct ETag. Default is None.
     pub generate_etag: Callback<'a, Option<String>>,
     /// Returns the last modified date and time of the resource which will be added as the
     /// Last-Modified header in the response and used in negotiating conditional requests.
@@ -122,12 +239,170 @@ pub struct Resource<'a> {
     /// `Ok(false)` otherwise. If it fails for any reason, return an Err with the status code
     /// you wish returned (e.g., a 500 status makes sense). Default is `Ok(true)`
     pub process_put: Callback<'a, Result<bool, u16>>,
+    /// This will be called to process requests with a known method that the state machine has
+    /// no branches for (any method other than GET, HEAD, POST, PUT, DELETE and OPTIONS, i.e. the
+    /// WebDAV methods PROPFIND, MKCOL, COPY, MOVE and LOCK), once the preconditions have been
+    /// checked. It is called for missing resources as well as existing ones. Return `Ok(true)`
+    /// if the request created a new resource (i.e. MKCOL), which will result in a
+    /// '201 Created' response, and `Ok(false)` otherwise, which results in a '404 Not Found'
+    /// for a missing resource and the response body set by the callback for an existing one.
+    /// To respond with any other status (i.e. '207 Multi-Status' or '423 Locked'), return an
+    /// Err with it. Default is `Ok(false)`.
+    pub process_other_method: Callback<'a, Result<bool, u16>>,
     /// If this returns true, then it is assumed that multiple representations of the response are
     /// possible and a single one cannot be automatically chosen, so a 300 Multiple Choices will
     /// be sent instead of a 200. Default is false.
     pub multiple_choices: Callback<'a, bool>,
     /// If the resource expires, this should return the date/time it expires. Default is None.
     pub expires: Callback<'a, Option<DateTime<FixedOffset>>>,
+    /// If this is true, 204 and 304 responses are made compliant with RFC 9110 before they are
+    /// sent: any body is removed along with the representation headers that do not apply, and a
+    /// 304 response will include the ETag of the resource. Defaults to false.
+    pub strict_status_compliance: bool,
+    /// CORS configuration for the resource. If this is set, it is used for the CORS headers of
+    /// normal and preflight responses instead of the defaults (any origin is allowed). Defaults
+    /// to None, in which case the CORS configuration of the dispatcher is used if it has one.
+    pub cors: Option<CorsConfig>,
+    /// Rate limiter for requests to the resource. Requests over the limit will result in a
+    /// '429 Too Many Requests' response with a Retry-After header. Defaults to None.
+    pub rate_limiter: Option<RateLimiter>,
+    /// Configuration of logging the decisions made for requests to the resource. Defaults to
+    /// None, in which case the configuration of the dispatcher is used if it has one.
+    pub decision_log: Option<DecisionLogConfig>,
+    /// Configuration of redacting headers and bodies when the response is logged. Defaults to
+    /// None, in which case the configuration of the dispatcher is used if it has one, otherwise
+    /// the default redaction.
+    pub redaction: Option<RedactionConfig>,
+    /// Names of the server listeners the resource is served on (i.e. an admin resource only on
+    /// a localhost listener). Requests accepted by any other listener, or not accepted by a
+    /// listener of the `server` module, are not routed to the resource. Defaults to empty, which
+    /// serves the resource on all listeners.
+    pub listeners: Vec<&'a str>,
+    /// Configuration of the integrity digests (RFC 9530) of the resource. When set, responses
+    /// get a digest of their final body, and the `Content-Digest` of request bodies is validated
+    /// as part of the malformed request decision. Defaults to None.
+    pub digest: Option<DigestConfig>,
+    /// Coalesces bursts of identical requests with an idempotent, unsafe method (i.e. retried
+    /// PUTs), so the resource is only executed once for them. Defaults to None.
+    pub coalescer: Option<RequestCoalescer>,
+    /// Called when the dispatcher begins serving, before any requests are accepted (i.e. to warm
+    /// caches or open connections). Defaults to None.
+    pub on_start: Option<LifecycleHook<'a>>,
+    /// Called when the dispatcher shuts down, after its listeners have stopped accepting
+    /// connections (i.e. to close connections or deregister from service discovery). Defaults
+    /// to None.
+    pub on_stop: Option<LifecycleHook<'a>>,
+    /// Injects faults (latency, errors, dropped connections and truncated bodies) into requests
+    /// to the resource, for resilience testing. This should not be set in production. Defaults
+    /// to None.
+    pub fault_injector: Option<FaultInjector>,
+    /// Deadline for the state machine to handle a request to the resource, after which the
+    /// remaining callbacks are abandoned and a '503 Service Unavailable' or '504 Gateway Timeout'
+    /// response is returned. Defaults to None (the deadline of the dispatcher, if any).
+    pub request_timeout: Option<RequestTimeout>,
+    /// Caching profile of the responses of the resource, which sets their Cache-Control header
+    /// and how their ETags are generated (see the presets of `CachingProfile`). Defaults to None
+    /// (the caching profile of the dispatcher, if any).
+    pub caching: Option<CachingProfile>,
+    /// Compresses the response bodies of the resource with the negotiated gzip or deflate
+    /// encoding, unless the heuristics of the configuration skip it. Defaults to None (the
+    /// compression configuration of the dispatcher, if any).
+    pub compression: Option<CompressionConfig>,
+    /// Availability windows and scheduled maintenance of the resource, outside of which
+    /// requests get a '503 Service Unavailable' response with a Retry-After header of when it
+    /// is next available. Defaults to None (always available).
+    pub availability: Option<Availability>,
+    /// Human readable description of the resource, shown on its documentation page (see
+    /// `DocsConfig`). Defaults to None.
+    pub description: Option<&'a str>,
+    /// Hook that enriches the operations of the resource in the generated OpenAPI document (see
+    /// `Dispatcher::openapi`), i.e. with the schemas of its bodies. It is called with the method
+    /// and the operation object. Defaults to None.
+    pub openapi: Option<OpenApiHook<'a>>,
+    /// Template of the paths of the resource relative to its route, with `{name}` parameters
+    /// (i.e. `/{id}`). Requests with a path that does not match it get a '404 Not Found'
+    /// response, and the values of its parameters are available with `Context::path_param`.
+    /// Defaults to None (any path under the route).
+    pub path_template: Option<&'a str>,
+    /// Status of the response if a path parameter can not be parsed as the type requested with
+    /// `Context::path_param`. Defaults to 400, but 404 suits identifiers of resources.
+    pub invalid_path_param_status: u16,
+    /// Signs the responses of the resource with an HTTP message signature (RFC 9421), after any
+    /// digest headers are added. Enabled with the `signatures` feature. Defaults to None.
+    #[cfg(feature = "signatures")]
+    pub response_signer: Option<crate::ResponseSigner>,
+}
+
+impl<'a> Resource<'a> {
+    /// Creates a new resource from a base resource, applying the overrides to a copy of it. This
+    /// allows a common base resource (for authentication, CORS, error rendering, etc.) to be
+    /// defined once and derived from by concrete resources. Callbacks are shared with the base
+    /// resource unless they are replaced.
+    ///
+    /// ```
+    /// # use webmachine::*;
+    /// let base = Resource {
+    ///   forbidden: callback(&|_, _| Box::pin(async { false })),
+    ///   .. Resource::default()
+    /// };
+    /// let resource = Resource::extend(&base, |resource| {
+    ///   resource.allowed_methods = vec!["OPTIONS", "GET", "HEAD", "POST"];
+    /// });
+    /// ```
+    pub fn extend<F>(base: &Resource<'a>, overrides: F) -> Resource<'a>
+    where
+        F: FnOnce(&mut Resource<'a>),
+    {
+        let mut resource = base.clone();
+        overrides(&mut resource);
+        resource
+    }
+
+    /// If requests accepted by the named server listener are routed to the resource
+    pub fn is_served_on(&self, listener: Option<&str>) -> bool {
+        self.listeners.is_empty()
+            || listener
+                .map(|listener| self.listeners.contains(&listener))
+                .unwrap_or(false)
+    }
+
+    /// Returns the allowed methods of the resource that are in its `known_methods`, with their
+    /// properties
+    pub fn allowed_known_methods(&self) -> Vec<&Method> {
+        self.known_methods.resolve(&self.allowed_methods)
+    }
+
+    /// Returns the names of the allowed methods of the resource that are in its
+    /// `known_methods`, as they are listed in the Allow header
+    pub fn allowed_method_names(&self) -> Vec<&str> {
+        self.allowed_known_methods()
+            .into_iter()
+            .map(|method| method.name.as_str())
+            .collect()
+    }
+
+    /// Returns the content types produced by the resource for the given request method. This
+    /// will be the entry from `method_produces` if there is one, otherwise `produces`.
+    pub fn produces_for(&self, method: &str) -> &Vec<&'a str> {
+        lookup_by_method(&self.method_produces, method).unwrap_or(&self.produces)
+    }
+
+    /// Returns the content types acceptable to the resource for the given request method. This
+    /// will be the entry from `method_acceptable_content_types` if there is one, otherwise
+    /// `acceptable_content_types`.
+    pub fn acceptable_content_types_for(&self, method: &str) -> &Vec<&'a str> {
+        lookup_by_method(&self.method_acceptable_content_types, method)
+            .unwrap_or(&self.acceptable_content_types)
+    }
+}
+
+fn lookup_by_method<'m, 'a>(
+    map: &'m HashMap<&'a str, Vec<&'a str>>,
+    method: &str,
+) -> Option<&'m Vec<&'a str>> {
+    map.iter()
+        .find(|(m, _)| m.to_uppercase() == method.to_uppercase())
+        .map(|(_, v)| v)
 }
 
 fn true_fn(
@@ -156,32 +431,42 @@ impl<'a> Default for Resource<'a> {
         Resource {
             finalise_response: None,
             available: callback(&true_fn),
-            known_methods: vec![
-                "OPTIONS", "GET", "POST", "PUT", "DELETE", "HEAD", "TRACE", "CONNECT", "PATCH",
-            ],
+            misdirected_request: callback(&false_fn),
+            upgrade_required: callback(&none_fn),
+            known_methods: MethodRegistry::default(),
+            unknown_method_status: 501,
             uri_too_long: callback(&false_fn),
             allowed_methods: vec!["OPTIONS", "GET", "HEAD"],
             malformed_request: callback(&false_fn),
+            required_headers: Vec::new(),
             not_authorized: callback(&none_fn),
+            authenticators: Vec::new(),
+            required_permissions: Vec::new(),
+            authorizer: None,
             forbidden: callback(&false_fn),
             unsupported_content_headers: callback(&false_fn),
             acceptable_content_types: vec!["application/json"],
+            method_acceptable_content_types: HashMap::new(),
             valid_entity_length: callback(&true_fn),
-            finish_request: callback(&|context, resource| {
-                context.response.add_cors_headers(&resource.allowed_methods);
-                Box::pin(async {})
-            }),
-            options: callback(&|_, resource| {
-                let res = Response::cors_headers(&resource.allowed_methods);
-                Box::pin(async {
-                    Some(res)
-                })
+            max_entity_length: None,
+            finish_request: callback(&|_, _| Box::pin(async {})),
+            options: callback(&|context, resource| {
+                let methods = resource.allowed_method_names();
+                let res = resource
+                    .cors
+                    .as_ref()
+                    .map(|cors| cors.headers(&context.request, &methods, true));
+                Box::pin(async { res })
             }),
             produces: vec!["application/json"],
+            method_produces: HashMap::new(),
             languages_provided: Vec::new(),
+            variants: Vec::new(),
             charsets_provided: Vec::new(),
             encodings_provided: vec!["identity"],
             variances: Vec::new(),
+            format_override: None,
+            user_agent_negotiation: None,
             resource_exists: callback(&true_fn),
             previously_existed: callback(&false_fn),
             moved_permanently: callback(&none_fn),
@@ -194,6 +479,7 @@ impl<'a> Default for Resource<'a> {
             post_is_create: callback(&false_fn),
             process_post: callback(&|_, _| Box::pin(async { Ok(false) })),
             process_put: callback(&|_, _| Box::pin(async { Ok(true) })),
+            process_other_method: callback(&|_, _| Box::pin(async { Ok(false) })),
             multiple_choices: callback(&false_fn),
             create_path: callback(&|context, _| {
                 let path = context.request.request_path.clone();
@@ -201,6 +487,29 @@ impl<'a> Default for Resource<'a> {
             }),
             expires: callback(&none_fn),
             render_response: callback(&none_fn),
+            render_value: None,
+            codecs: CodecRegistry::default(),
+            strict_status_compliance: false,
+            cors: None,
+            rate_limiter: None,
+            decision_log: None,
+            redaction: None,
+            listeners: Vec::new(),
+            digest: None,
+            coalescer: None,
+            on_start: None,
+            on_stop: None,
+            fault_injector: None,
+            request_timeout: None,
+            caching: None,
+            compression: None,
+            availability: None,
+            description: None,
+            openapi: None,
+            path_template: None,
+            invalid_path_param_status: 400,
+            #[cfg(feature = "signatures")]
+            response_signer: None,
         }
     }
 }
@@ -0,0 +1,317 @@
+//! The `routing` module provides the `routes!` macro, which builds the route table of a
+//! dispatcher and checks it when the crate using it is compiled. Each route must be a path
+//! starting with a '/', and no two routes may match the same paths (i.e. `/users` and
+//! `/users/`). Routes match the request path by prefix. A route may end with path parameters
+//! (i.e. `/users/{id}`), in which case the resource is routed at the part of the route before
+//! the first parameter, and the rest becomes its `path_template`. Routes are percent-decoded in the same way as the paths of requests before they are matched, so
+//! `/my%20files` matches requests for `/my%20files` and `/my files`, and an encoded slash
+//! (`%2F`) never matches a '/' of a route.
+
+use crate::Resource;
+
+/// Builds the route table of a dispatcher, which is the same `BTreeMap` that can be built by
+/// hand. Routes may be prefixed with the methods that the resource allows, which replace its
+/// `allowed_methods`, and may have path parameters, which replace its `path_template`. The
+/// routes are checked at compile time, so a malformed or duplicate route fails the build.
+///
+/// ```
+/// # use webmachine::{routes, Dispatcher, Resource};
+/// let users = Resource::default();
+/// let dispatcher = Dispatcher {
+///     routes: routes! {
+///         GET | POST "/users" => users,
+///         GET "/orders/{id}" => Resource::default(),
+///         "/health" => Resource::default(),
+///     },
+///     ..Dispatcher::default()
+/// };
+/// assert_eq!(dispatcher.routes["/users"].allowed_methods, vec!["GET", "POST"]);
+/// assert_eq!(dispatcher.routes["/orders"].path_template, Some("/{id}"));
+/// ```
+///
+/// ```compile_fail
+/// # use webmachine::{routes, Resource};
+/// let routes = routes! {
+///     "/users" => Resource::default(),
+///     "/users/" => Resource::default(),
+/// };
+/// ```
+///
+/// ```compile_fail
+/// # use webmachine::{routes, Resource};
+/// let routes = routes! {
+///     "/users" => Resource::default(),
+///     "/users/{id}" => Resource::default(),
+/// };
+/// ```
+#[macro_export]
+macro_rules! routes {
+    ($($($method:ident)|* $route:literal => $resource:expr),* $(,)?) => {{
+        const _: () = $crate::__check_routes(&[$($route),*]);
+        let mut routes = ::std::collections::BTreeMap::new();
+        $(
+            let (route, resource) = $crate::__with_template($route, $resource);
+            routes.insert(
+                route,
+                $crate::__with_methods(resource, &[$(stringify!($method)),*]),
+            );
+        )*
+        routes
+    }};
+}
+
+/// Removes the `.` and `..` segments of a path, as per RFC 3986 section 5.2.4, so
+/// `/files/./a/../b` is `/files/b`. Returns None if a `..` segment would escape the root of the
+/// path (i.e. `/files/../../etc`). The dispatcher normalises the paths of requests before they
+/// are routed, and rejects the requests with paths that escape the root, so resources that
+/// serve files from the path can not be tricked into serving files outside of their directory.
+pub fn remove_dot_segments(path: &str) -> Option<String> {
+    let relative = match path.strip_prefix('/') {
+        Some(relative) => relative,
+        // i.e. the `*` target of OPTIONS requests
+        None => return Some(path.to_string()),
+    };
+    let mut segments = Vec::new();
+    let mut input = relative.split('/').peekable();
+    while let Some(segment) = input.next() {
+        match segment {
+            "." => (),
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+        // a path ending with a dot segment refers to a directory
+        if input.peek().is_none() && matches!(segment, "." | "..") {
+            segments.push("");
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+#[doc(hidden)]
+pub fn __with_methods<'a>(resource: Resource<'a>, methods: &[&'a str]) -> Resource<'a> {
+    if methods.is_empty() {
+        resource
+    } else {
+        Resource {
+            allowed_methods: methods.to_vec(),
+            ..resource
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn __with_template<'a>(route: &'a str, resource: Resource<'a>) -> (&'a str, Resource<'a>) {
+    match route.find('{') {
+        Some(param) => {
+            // the parameter starts a segment, so this is the '/' before it
+            let start = param - 1;
+            let prefix = if start == 0 { "/" } else { &route[..start] };
+            let resource = Resource {
+                path_template: Some(&route[start..]),
+                ..resource
+            };
+            (prefix, resource)
+        }
+        None => (route, resource),
+    }
+}
+
+#[doc(hidden)]
+pub const fn __check_routes(routes: &[&str]) {
+    let mut i = 0;
+    while i < routes.len() {
+        check_route(routes[i].as_bytes());
+        let mut j = 0;
+        while j < i {
+            if same_paths(
+                static_prefix(routes[i].as_bytes()),
+                static_prefix(routes[j].as_bytes()),
+            ) {
+                panic!("two routes match the same paths");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+const fn check_route(route: &[u8]) {
+    if route.is_empty() || route[0] != b'/' {
+        panic!("routes must start with a '/'");
+    }
+    let mut i = 0;
+    while i < route.len() {
+        let c = route[i];
+        let path_char = c.is_ascii_alphanumeric()
+            || matches!(
+                c,
+                b'/' | b'-'
+                    | b'.'
+                    | b'_'
+                    | b'~'
+                    | b'%'
+                    | b'!'
+                    | b'$'
+                    | b'&'
+                    | b'\''
+                    | b'('
+                    | b')'
+                    | b'*'
+                    | b'+'
+                    | b','
+                    | b';'
+                    | b'='
+                    | b':'
+                    | b'@'
+            );
+        if c == b'{' {
+            i = check_param(route, i);
+            continue;
+        }
+        if c == b'}' {
+            panic!("path parameters must be a whole segment of the route");
+        }
+        if !path_char {
+            panic!("routes may only contain the characters of a URL path");
+        }
+        i += 1;
+    }
+}
+
+/// Checks the path parameter starting at the index of the route, returning the index after it
+const fn check_param(route: &[u8], start: usize) -> usize {
+    if route[start - 1] != b'/' {
+        panic!("path parameters must be a whole segment of the route");
+    }
+    let mut i = start + 1;
+    while i < route.len() && (route[i].is_ascii_alphanumeric() || route[i] == b'_') {
+        i += 1;
+    }
+    if i == start + 1 || i == route.len() || route[i] != b'}' {
+        panic!("path parameters must be a name in braces");
+    }
+    if i + 1 < route.len() && route[i + 1] != b'/' {
+        panic!("path parameters must be a whole segment of the route");
+    }
+    i + 1
+}
+
+/// Part of the route that the resource is routed at, which is before its first path parameter
+const fn static_prefix(route: &[u8]) -> &[u8] {
+    let mut i = 0;
+    while i < route.len() {
+        if route[i] == b'{' {
+            return route.split_at(i - 1).0;
+        }
+        i += 1;
+    }
+    route
+}
+
+/// If the routes have the same path segments, ignoring empty ones, as the dispatcher does
+const fn same_paths(a: &[u8], b: &[u8]) -> bool {
+    let (mut i, mut j) = (0, 0);
+    loop {
+        while i < a.len() && a[i] == b'/' {
+            i += 1;
+        }
+        while j < b.len() && b[j] == b'/' {
+            j += 1;
+        }
+        if i == a.len() || j == b.len() {
+            return i == a.len() && j == b.len();
+        }
+        while i < a.len() && a[i] != b'/' && j < b.len() && b[j] != b'/' {
+            if a[i] != b[j] {
+                return false;
+            }
+            i += 1;
+            j += 1;
+        }
+        let a_ended = i == a.len() || a[i] == b'/';
+        let b_ended = j == b.len() || b[j] == b'/';
+        if !(a_ended && b_ended) {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn routes_macro_builds_the_route_table() {
+        let routes = routes! {
+            GET | PUT | DELETE "/orders/item" => Resource::default(),
+            "/orders" => Resource {
+                allowed_methods: vec!["POST"],
+                ..Resource::default()
+            },
+        };
+        expect!(routes.keys().cloned().collect::<Vec<_>>())
+            .to(be_equal_to(vec!["/orders", "/orders/item"]));
+        expect!(routes["/orders/item"].allowed_methods.clone())
+            .to(be_equal_to(vec!["GET", "PUT", "DELETE"]));
+        expect!(routes["/orders"].allowed_methods.clone()).to(be_equal_to(vec!["POST"]));
+    }
+
+    #[test]
+    fn dot_segments_are_removed_without_escaping_the_root() {
+        let normalised = remove_dot_segments;
+        expect!(normalised("/files/./a/../b")).to(be_some().value("/files/b"));
+        expect!(normalised("/files/a/..")).to(be_some().value("/files/"));
+        expect!(normalised("/files/.")).to(be_some().value("/files/"));
+        expect!(normalised("/files/a/")).to(be_some().value("/files/a/"));
+        expect!(normalised("/a/..")).to(be_some().value("/"));
+        expect!(normalised("/files/..%2F..")).to(be_some().value("/files/..%2F.."));
+        expect!(normalised("*")).to(be_some().value("*"));
+        expect!(normalised("/..")).to(be_none());
+        expect!(normalised("/files/../../etc/passwd")).to(be_none());
+    }
+
+    #[test]
+    fn routes_match_the_same_paths_if_their_segments_are_the_same() {
+        expect!(same_paths(b"/users", b"/users/")).to(be_true());
+        expect!(same_paths(b"/users//admin", b"/users/admin")).to(be_true());
+        expect!(same_paths(b"/", b"")).to(be_true());
+        expect!(same_paths(b"/users", b"/user")).to(be_false());
+        expect!(same_paths(b"/users", b"/users/admin")).to(be_false());
+    }
+
+    #[test]
+    fn routes_with_path_parameters_set_the_path_template() {
+        let routes = routes! {
+            GET "/users/{id}/items/{item}" => Resource::default(),
+            "/{tenant}" => Resource::default(),
+            "/orders" => Resource::default(),
+        };
+        expect!(routes.keys().cloned().collect::<Vec<_>>())
+            .to(be_equal_to(vec!["/", "/orders", "/users"]));
+        expect!(routes["/users"].path_template).to(be_some().value("/{id}/items/{item}"));
+        expect!(routes["/users"].allowed_methods.clone()).to(be_equal_to(vec!["GET"]));
+        expect!(routes["/"].path_template).to(be_some().value("/{tenant}"));
+        expect!(routes["/orders"].path_template).to(be_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "two routes match the same paths")]
+    fn routes_with_the_same_prefix_before_their_parameters_are_rejected() {
+        __check_routes(&["/users/{id}", "/users/{name}/"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "whole segment")]
+    fn path_parameters_within_a_segment_are_rejected() {
+        __check_routes(&["/users/id-{id}"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "a name in braces")]
+    fn path_parameters_without_a_name_are_rejected() {
+        __check_routes(&["/users/{}"]);
+    }
+}
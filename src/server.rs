@@ -0,0 +1,857 @@
+//! The `server` module provides helpers to serve a dispatcher with Hyper, with hooks for
+//! connection level events. A dispatcher can be served on several listeners at once (i.e. TLS on
+//! 443, plaintext on localhost for health checks and a Unix domain socket for a sidecar), each
+//! with its own configuration. On shutdown, the listeners stop accepting connections and the
+//! open connections are drained: requests in flight are completed before the server exits, or
+//! closed once the drain timeout of the dispatcher has passed.
+
+use futures::{
+    future::{self, try_join_all},
+    FutureExt,
+};
+use hyper::{server::conn::Http, service::service_fn, Body};
+use std::{future::Future, io, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use super::{ClientCertificate, Dispatcher};
+
+/// Information about a client connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    /// Name of the listener that accepted the connection
+    pub listener: String,
+    /// Address of the connected peer. None for Unix domain socket connections.
+    pub remote_addr: Option<SocketAddr>,
+    /// Local address that the connection was accepted on. None for Unix domain socket
+    /// connections.
+    pub local_addr: Option<SocketAddr>,
+    /// If the connection was wrapped by the acceptor of the listener (i.e. TLS was terminated)
+    pub secure: bool,
+    /// Certificate the client authenticated with, if the acceptor of the listener used TLS
+    /// client authentication. This is only known once the acceptor has completed, so it is
+    /// None in the `on_open` hook.
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+/// Type of a hook called for a connection event
+pub type ConnectionHook = Arc<dyn Fn(&ConnectionInfo) + Send + Sync>;
+
+/// Type of a hook called when a connection fails, with the error of the acceptor of the listener
+/// (i.e. a failed TLS handshake) or of serving HTTP over the connection. The error can be
+/// downcast to the `io::Error` of the acceptor or the `hyper::Error`.
+pub type ConnectionErrorHook =
+    Arc<dyn Fn(&ConnectionInfo, &(dyn std::error::Error + Send + Sync + 'static)) + Send + Sync>;
+
+/// Hooks that are called for connection level events
+#[derive(Clone, Default)]
+pub struct ConnectionHooks {
+    /// Called when a connection has been accepted, before any requests are read from it
+    pub on_open: Option<ConnectionHook>,
+    /// Called when a connection has been closed, including after an error
+    pub on_close: Option<ConnectionHook>,
+    /// Called when a connection terminates with an error, including when the acceptor of the
+    /// listener fails (i.e. the TLS handshake or the verification of a client certificate)
+    pub on_error: Option<ConnectionErrorHook>,
+}
+
+/// A stream that HTTP can be served over
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Connection for T {}
+
+/// A connection wrapped by the acceptor of a listener
+pub struct Accepted {
+    /// Stream to serve HTTP over
+    pub stream: Box<dyn Connection>,
+    /// Certificate the client authenticated with, if TLS client authentication was used.
+    /// Defaults to None.
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+impl Accepted {
+    /// Creates an accepted connection for the stream
+    pub fn new<S: Connection>(stream: S) -> Accepted {
+        Accepted {
+            stream: Box::new(stream),
+            client_certificate: None,
+        }
+    }
+
+    /// Sets the certificate the client authenticated with
+    pub fn with_client_certificate(self, certificate: ClientCertificate) -> Accepted {
+        Accepted {
+            client_certificate: Some(certificate),
+            ..self
+        }
+    }
+}
+
+/// Future returned by a stream acceptor
+pub type AcceptFuture = Pin<Box<dyn Future<Output = io::Result<Accepted>> + Send>>;
+
+/// Type of a function that wraps accepted TCP connections before they are served. This is how
+/// TLS is terminated, using the TLS library of your choice. If the client authenticated with a
+/// certificate, the acceptor returns it with the connection so requests can be authorized by
+/// it.
+pub type StreamAcceptor = Arc<dyn Fn(TcpStream) -> AcceptFuture + Send + Sync>;
+
+/// Socket that a listener accepts connections on
+#[derive(Debug)]
+pub enum ListenerSocket {
+    /// TCP socket
+    Tcp(TcpListener),
+    /// Unix domain socket
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// A listener that a dispatcher is served on, with its own configuration
+pub struct Listener {
+    /// Name of the listener, used in logs and the connection info (i.e. `https`)
+    pub name: String,
+    /// Socket to accept connections on
+    pub socket: ListenerSocket,
+    /// Acceptor to wrap accepted TCP connections with (i.e. to terminate TLS). Defaults to None,
+    /// in which case connections are served as plaintext.
+    pub acceptor: Option<StreamAcceptor>,
+    /// Hooks to call for the connections accepted by the listener
+    pub hooks: ConnectionHooks,
+}
+
+impl Listener {
+    /// Creates a plaintext listener on the TCP socket
+    pub fn tcp(name: &str, listener: TcpListener) -> Listener {
+        Listener {
+            name: name.to_string(),
+            socket: ListenerSocket::Tcp(listener),
+            acceptor: None,
+            hooks: ConnectionHooks::default(),
+        }
+    }
+
+    /// Creates a listener on the Unix domain socket
+    #[cfg(unix)]
+    pub fn unix(name: &str, listener: UnixListener) -> Listener {
+        Listener {
+            name: name.to_string(),
+            socket: ListenerSocket::Unix(listener),
+            acceptor: None,
+            hooks: ConnectionHooks::default(),
+        }
+    }
+
+    /// Accepts the next connection, returning it with the info about it
+    async fn accept(&self) -> io::Result<(AcceptFuture, ConnectionInfo)> {
+        match &self.socket {
+            ListenerSocket::Tcp(listener) => {
+                let (stream, remote_addr) = listener.accept().await?;
+                let info = ConnectionInfo {
+                    listener: self.name.clone(),
+                    remote_addr: Some(remote_addr),
+                    local_addr: listener.local_addr().ok(),
+                    secure: self.acceptor.is_some(),
+                    client_certificate: None,
+                };
+                let connection = match &self.acceptor {
+                    Some(acceptor) => acceptor(stream),
+                    None => plaintext(stream),
+                };
+                Ok((connection, info))
+            }
+            #[cfg(unix)]
+            ListenerSocket::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                let info = ConnectionInfo {
+                    listener: self.name.clone(),
+                    remote_addr: None,
+                    local_addr: None,
+                    secure: false,
+                    client_certificate: None,
+                };
+                Ok((plaintext(stream), info))
+            }
+        }
+    }
+}
+
+fn plaintext<S: Connection>(stream: S) -> AcceptFuture {
+    Box::pin(future::ready(Ok(Accepted::new(stream))))
+}
+
+/// Serves the dispatcher on the listener, calling the connection hooks as connections are
+/// opened and closed. Each connection is served on its own task. The `on_start` hooks of the
+/// resources are called before the first connection is accepted. Only returns if the listener
+/// fails in a way it can not recover from.
+pub async fn serve(
+    listener: TcpListener,
+    dispatcher: Dispatcher<'static>,
+    hooks: ConnectionHooks,
+) -> io::Result<()> {
+    let listener = Listener {
+        hooks,
+        ..Listener::tcp("default", listener)
+    };
+    serve_listeners(vec![listener], dispatcher, future::pending()).await
+}
+
+/// Serves the dispatcher on the listener until the process receives SIGTERM or ctrl-c, then
+/// drains the open connections before returning. This is the glue most servers need:
+///
+/// ```no_run
+/// # use webmachine::{server, Dispatcher};
+/// # async fn run(dispatcher: Dispatcher<'static>) -> std::io::Result<()> {
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+/// server::serve_until_shutdown(listener, dispatcher).await
+/// # }
+/// ```
+pub async fn serve_until_shutdown(
+    listener: TcpListener,
+    dispatcher: Dispatcher<'static>,
+) -> io::Result<()> {
+    serve_listeners(
+        vec![Listener::tcp("default", listener)],
+        dispatcher,
+        shutdown_signal(),
+    )
+    .await
+}
+
+/// Completes when the process receives SIGTERM (on Unix) or ctrl-c, for use as the shutdown
+/// future of `serve_listeners`
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for ctrl-c - {}", err);
+            future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                error!("Failed to listen for SIGTERM - {}", err);
+                future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => info!("Received ctrl-c, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+}
+
+/// Serves the dispatcher on all the listeners concurrently. Each listener stops accepting
+/// connections when the shutdown future completes, after which the open connections are
+/// drained: they finish the requests in flight (including streaming the response bodies) and
+/// are then closed, with idle keep-alive connections closed straight away. Connections that have
+/// not finished when the `drain_timeout` of the dispatcher passes (or are still completing the
+/// acceptor, i.e. the TLS handshake) are closed without waiting for them. Returns once the
+/// connections have been drained, or with the first fatal error of any of the listeners.
+/// Errors accepting a single connection (i.e. the client reset it, or the process ran out of
+/// file descriptors) are logged, and the listener keeps accepting connections, backing off
+/// while the errors persist.
+///
+/// The `on_start` hooks of the resources are called before any connections are accepted, and
+/// the `on_stop` hooks once the connections have been drained (including when one of the
+/// listeners fails). If the dispatcher has a readiness gate, connections are accepted while
+/// the `on_start` hooks and warm-up tasks run instead, so that probes can be answered.
+pub async fn serve_listeners<F>(
+    listeners: Vec<Listener>,
+    dispatcher: Dispatcher<'static>,
+    shutdown: F,
+) -> io::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let shutdown = shutdown.boxed().shared();
+    let (drain_tx, drain_rx) = watch::channel(ServerState::Serving);
+    let accepting = try_join_all(listeners.into_iter().map(|listener| {
+        accept_connections(
+            listener,
+            dispatcher.clone(),
+            shutdown.clone(),
+            drain_rx.clone(),
+        )
+    }));
+    drop(drain_rx);
+    let result = if dispatcher.readiness.is_some() {
+        future::join(dispatcher.start(), accepting).await.1
+    } else {
+        dispatcher.start().await;
+        accepting.await
+    };
+    // Every connection holds a receiver, so the channel is closed once they have all finished
+    debug!("Draining open connections");
+    drain_tx.send_replace(ServerState::Draining);
+    match dispatcher.drain_timeout {
+        Some(drain_timeout) => {
+            if tokio::time::timeout(drain_timeout, drain_tx.closed())
+                .await
+                .is_err()
+            {
+                warn!(
+                    "Connections did not drain within {:?}, closing them",
+                    drain_timeout
+                );
+                drain_tx.send_replace(ServerState::Closing);
+                drain_tx.closed().await;
+            }
+        }
+        None => drain_tx.closed().await,
+    }
+    dispatcher.stop().await;
+    result.map(|_| ())
+}
+
+async fn accept_connections<F>(
+    listener: Listener,
+    dispatcher: Dispatcher<'static>,
+    mut shutdown: F,
+    drain: watch::Receiver<ServerState>,
+) -> io::Result<()>
+where
+    F: Future<Output = ()> + Unpin,
+{
+    let mut backoff = MIN_ACCEPT_BACKOFF;
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => {
+                debug!("Listener '{}' is shutting down", listener.name);
+                return Ok(());
+            }
+            accepted = listener.accept() => accepted,
+        };
+        let (connection, info) = match accepted {
+            Ok(accepted) => {
+                backoff = MIN_ACCEPT_BACKOFF;
+                accepted
+            }
+            Err(err) if is_connection_error(&err) => {
+                debug!(
+                    "Failed to accept connection on '{}': {}",
+                    listener.name, err
+                );
+                continue;
+            }
+            Err(err) if is_fatal_accept_error(&err) => {
+                error!("Listener '{}' failed: {}", listener.name, err);
+                return Err(err);
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to accept connection on '{}', retrying in {:?}: {}",
+                    listener.name, backoff, err
+                );
+                tokio::select! {
+                    _ = &mut shutdown => {
+                        debug!("Listener '{}' is shutting down", listener.name);
+                        return Ok(());
+                    }
+                    _ = tokio::time::sleep(backoff) => {}
+                }
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        if let Some(on_open) = &listener.hooks.on_open {
+            on_open(&info);
+        }
+        let dispatcher = dispatcher.clone();
+        let hooks = listener.hooks.clone();
+        let mut drain = drain.clone();
+        let mut info = info;
+        tokio::spawn(async move {
+            // A connection that has not completed the acceptor when draining starts has no
+            // requests in flight, so it is closed instead of holding up the drain
+            let accepted = tokio::select! {
+                accepted = connection => Some(accepted),
+                _ = drain.wait_for(|state| *state != ServerState::Serving) => None,
+            };
+            match accepted {
+                Some(Ok(accepted)) => {
+                    info.client_certificate = accepted.client_certificate;
+                    return serve_connection(accepted.stream, dispatcher, info, hooks, drain).await;
+                }
+                Some(Err(err)) => {
+                    debug!(
+                        "Failed to accept connection on '{}': {}",
+                        info.listener, err
+                    );
+                    if let Some(on_error) = &hooks.on_error {
+                        on_error(&info, &err);
+                    }
+                }
+                None => debug!(
+                    "Closing connection on '{}' that had not been accepted",
+                    info.listener
+                ),
+            }
+            if let Some(on_close) = &hooks.on_close {
+                on_close(&info);
+            }
+        });
+    }
+}
+
+/// Delay before accepting again after the first transient error accepting a connection. It is
+/// doubled for each error in a row, up to `MAX_ACCEPT_BACKOFF`.
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Maximum delay before accepting again after a transient error accepting a connection
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// If the error accepting a connection only affects that connection, so the next one can be
+/// accepted straight away
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// If the error accepting a connection means the socket can not accept any more connections.
+/// Other errors (i.e. running out of file descriptors) are transient.
+fn is_fatal_accept_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::NotConnected | io::ErrorKind::Unsupported
+    )
+}
+
+/// State of the server, sent to the open connections
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ServerState {
+    /// Connections are being served
+    Serving,
+    /// Connections finish the requests in flight and are then closed
+    Draining,
+    /// The drain timeout has passed, so connections are closed straight away
+    Closing,
+}
+
+fn serve_connection(
+    stream: Box<dyn Connection>,
+    dispatcher: Dispatcher<'static>,
+    info: ConnectionInfo,
+    hooks: ConnectionHooks,
+    mut drain: watch::Receiver<ServerState>,
+) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    // The service is built outside of an async block, so that the connection is checked to be
+    // Send against the concrete 'static dispatcher type. The connection info is added to the
+    // request extensions so the dispatcher knows the address of the client.
+    let connection = info.clone();
+    let service = service_fn(move |mut req: http::Request<Body>| {
+        req.extensions_mut().insert(connection.clone());
+        dispatcher.clone().dispatch(req)
+    });
+    let connection = Http::new().serve_connection(stream, service);
+    let listener = info.listener.clone();
+    // Once draining starts, the connection stops reading new requests and is closed after the
+    // response in flight. The receiver is held until then, so the server waits for it, unless
+    // the drain timeout passes and the connection is dropped.
+    let drained = async move {
+        tokio::pin!(connection);
+        tokio::select! {
+            result = &mut connection => result,
+            _ = drain.wait_for(|state| *state != ServerState::Serving).map(|_| ()) => {
+                connection.as_mut().graceful_shutdown();
+                tokio::select! {
+                    result = &mut connection => result,
+                    _ = drain.wait_for(|state| *state == ServerState::Closing).map(|_| ()) => {
+                        debug!("Closing connection on '{}' that did not drain", listener);
+                        Ok(())
+                    }
+                }
+            }
+        }
+    };
+    Box::pin(drained.map(move |result| {
+        if let Err(err) = result {
+            debug!("Connection on '{}' failed: {}", info.listener, err);
+            if let Some(on_error) = &hooks.on_error {
+                on_error(&info, &err);
+            }
+        }
+        if let Some(on_close) = &hooks.on_close {
+            on_close(&info);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Resource;
+    use expectest::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::{mpsc, oneshot},
+    };
+
+    type ConnectionError = dyn std::error::Error + Send + Sync;
+
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+    fn dispatcher() -> Dispatcher<'static> {
+        Dispatcher {
+            routes: btreemap! { "/" => Resource::default() },
+            ..Dispatcher::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_calls_the_connection_hooks() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let opened = Arc::new(AtomicUsize::new(0));
+        let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+        let open_count = opened.clone();
+        let hooks = ConnectionHooks {
+            on_open: Some(Arc::new(move |info: &ConnectionInfo| {
+                open_count.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(info.local_addr, Some(addr));
+            })),
+            on_close: Some(Arc::new(move |info: &ConnectionInfo| {
+                closed_tx.send(info.remote_addr).unwrap();
+            })),
+            ..ConnectionHooks::default()
+        };
+        tokio::spawn(serve(listener, dispatcher(), hooks));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let client_addr = stream.local_addr().unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        expect!(closed_rx.recv().await).to(be_some().value(Some(client_addr)));
+        expect!(opened.load(Ordering::SeqCst)).to(be_equal_to(1));
+    }
+
+    #[tokio::test]
+    async fn requests_have_the_client_certificate_returned_by_the_acceptor() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/" => Resource {
+                    render_response: crate::owned_callback(|context, _| {
+                        let certificate = context.request.client_certificate.clone();
+                        Box::pin(async move {
+                            certificate.and_then(|certificate| {
+                                certificate.common_name().map(|name| name.to_string())
+                            })
+                        })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        };
+        // stands in for a TLS acceptor that verified the certificate of the client
+        let acceptor: StreamAcceptor = Arc::new(|stream| {
+            let certificate = ClientCertificate {
+                subject: Some("CN=orders".to_string()),
+                ..ClientCertificate::new(vec![b"client".to_vec()])
+            };
+            Box::pin(async move { Ok(Accepted::new(stream).with_client_certificate(certificate)) })
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = Listener {
+            acceptor: Some(acceptor),
+            ..Listener::tcp("mtls", listener)
+        };
+        tokio::spawn(serve_listeners(
+            vec![listener],
+            dispatcher,
+            future::pending(),
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        expect!(response.ends_with("\r\n\r\norders")).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn acceptor_failures_are_passed_to_the_error_hook() {
+        let (errors_tx, mut errors_rx) = mpsc::unbounded_channel();
+        let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+        let hooks = ConnectionHooks {
+            on_error: Some(Arc::new(
+                move |info: &ConnectionInfo, err: &ConnectionError| {
+                    errors_tx
+                        .send((info.listener.clone(), err.to_string()))
+                        .unwrap();
+                },
+            )),
+            on_close: Some(Arc::new(move |info: &ConnectionInfo| {
+                closed_tx.send(info.listener.clone()).unwrap();
+            })),
+            ..ConnectionHooks::default()
+        };
+        // stands in for a TLS acceptor whose handshake fails
+        let acceptor: StreamAcceptor = Arc::new(|_stream| {
+            Box::pin(async {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "client certificate is not trusted",
+                ))
+            })
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = Listener {
+            acceptor: Some(acceptor),
+            hooks,
+            ..Listener::tcp("mtls", listener)
+        };
+        tokio::spawn(serve_listeners(
+            vec![listener],
+            dispatcher(),
+            future::pending(),
+        ));
+
+        let _stream = TcpStream::connect(addr).await.unwrap();
+        expect!(errors_rx.recv().await).to(be_some().value((
+            "mtls".to_string(),
+            "client certificate is not trusted".to_string(),
+        )));
+        expect!(closed_rx.recv().await).to(be_some().value("mtls".to_string()));
+    }
+
+    #[tokio::test]
+    async fn http_errors_are_passed_to_the_error_hook() {
+        let (errors_tx, mut errors_rx) = mpsc::unbounded_channel();
+        let hooks = ConnectionHooks {
+            on_error: Some(Arc::new(
+                move |_info: &ConnectionInfo, err: &ConnectionError| {
+                    errors_tx.send(err.to_string()).unwrap();
+                },
+            )),
+            ..ConnectionHooks::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, dispatcher(), hooks));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"NOT HTTP\r\n\r\n").await.unwrap();
+        expect!(errors_rx.recv().await.is_some()).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn serve_listeners_serves_every_listener_until_shutdown() {
+        let (opened_tx, mut opened_rx) = mpsc::unbounded_channel();
+        let hooks = ConnectionHooks {
+            on_open: Some(Arc::new(move |info: &ConnectionInfo| {
+                opened_tx.send(info.listener.clone()).unwrap();
+            })),
+            ..ConnectionHooks::default()
+        };
+        let mut addrs = vec![];
+        let mut listeners = vec![];
+        for name in ["public", "admin"] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap());
+            listeners.push(Listener {
+                hooks: hooks.clone(),
+                ..Listener::tcp(name, listener)
+            });
+        }
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_listeners(
+            listeners,
+            dispatcher(),
+            shutdown_rx.map(|_| ()),
+        ));
+
+        for addr in &addrs {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(REQUEST).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            expect!(response.starts_with("HTTP/1.1 200 OK")).to(be_true());
+        }
+        expect!(opened_rx.recv().await).to(be_some().value("public".to_string()));
+        expect!(opened_rx.recv().await).to(be_some().value("admin".to_string()));
+
+        shutdown_tx.send(()).unwrap();
+        expect!(server.await.unwrap().is_ok()).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn serve_listeners_calls_the_resource_lifecycle_hooks() {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel();
+        let hook = |event: &'static str| -> crate::LifecycleHook<'static> {
+            let events = events_tx.clone();
+            Arc::new(move || {
+                events.send(event).unwrap();
+                Box::pin(async {})
+            })
+        };
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/" => Resource {
+                    on_start: Some(hook("start")),
+                    on_stop: Some(hook("stop")),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_listeners(
+            vec![Listener::tcp("public", listener)],
+            dispatcher,
+            shutdown_rx.map(|_| ()),
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        expect!(events_rx.recv().await).to(be_some().value("start"));
+        expect!(events_rx.try_recv().is_err()).to(be_true());
+
+        shutdown_tx.send(()).unwrap();
+        expect!(server.await.unwrap().is_ok()).to(be_true());
+        expect!(events_rx.recv().await).to(be_some().value("stop"));
+    }
+
+    #[tokio::test]
+    async fn serve_listeners_drains_requests_in_flight_on_shutdown() {
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/" => Resource {
+                    render_response: crate::owned_callback(move |_, _| {
+                        started_tx.send(()).unwrap();
+                        Box::pin(async {
+                            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                            Some("done".to_string())
+                        })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            ..Dispatcher::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_listeners(
+            vec![Listener::tcp("public", listener)],
+            dispatcher,
+            shutdown_rx.map(|_| ()),
+        ));
+
+        // A keep-alive request, so the connection is only closed because of the shutdown
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        started_rx.recv().await.unwrap();
+        shutdown_tx.send(()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        expect!(response.starts_with("HTTP/1.1 200 OK")).to(be_true());
+        expect!(response.ends_with("done")).to(be_true());
+        expect!(server.await.unwrap().is_ok()).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn serve_listeners_closes_connections_that_do_not_drain_in_time() {
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/" => Resource {
+                    render_response: crate::owned_callback(move |_, _| {
+                        started_tx.send(()).unwrap();
+                        Box::pin(future::pending())
+                    }),
+                    ..Resource::default()
+                }
+            },
+            drain_timeout: Some(std::time::Duration::from_millis(50)),
+            ..Dispatcher::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_listeners(
+            vec![Listener::tcp("public", listener)],
+            dispatcher,
+            shutdown_rx.map(|_| ()),
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(REQUEST).await.unwrap();
+        started_rx.recv().await.unwrap();
+        shutdown_tx.send(()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        expect!(response.is_empty()).to(be_true());
+        expect!(server.await.unwrap().is_ok()).to(be_true());
+    }
+
+    #[tokio::test]
+    async fn serve_listeners_does_not_wait_for_connections_completing_the_acceptor() {
+        let (accepted_tx, mut accepted_rx) = mpsc::unbounded_channel();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = Listener {
+            acceptor: Some(Arc::new(move |_stream: TcpStream| -> AcceptFuture {
+                accepted_tx.send(()).unwrap();
+                Box::pin(future::pending())
+            })),
+            ..Listener::tcp("https", listener)
+        };
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_listeners(
+            vec![listener],
+            dispatcher(),
+            shutdown_rx.map(|_| ()),
+        ));
+
+        let _stream = TcpStream::connect(addr).await.unwrap();
+        accepted_rx.recv().await.unwrap();
+        shutdown_tx.send(()).unwrap();
+
+        let result = tokio::time::timeout(std::time::Duration::from_secs(1), server).await;
+        expect!(result.map(|server| server.unwrap().is_ok())).to(be_ok().value(true));
+    }
+
+    #[test]
+    fn only_errors_of_the_socket_end_the_listener() {
+        let aborted = io::Error::from(io::ErrorKind::ConnectionAborted);
+        expect!(is_connection_error(&aborted)).to(be_true());
+        expect!(is_fatal_accept_error(&aborted)).to(be_false());
+
+        // EMFILE, the process has run out of file descriptors
+        let too_many_files = io::Error::from_raw_os_error(24);
+        expect!(is_connection_error(&too_many_files)).to(be_false());
+        expect!(is_fatal_accept_error(&too_many_files)).to(be_false());
+
+        let invalid = io::Error::from(io::ErrorKind::InvalidInput);
+        expect!(is_fatal_accept_error(&invalid)).to(be_true());
+    }
+}
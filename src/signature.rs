@@ -0,0 +1,431 @@
+//! HTTP message signatures (RFC 9421), enabled with the `signatures` feature. Responses can be
+//! signed with a `ResponseSigner`, and the signatures of requests verified with a
+//! `SignatureAuthenticator`. Keys are looked up by their key ID with a `KeyResolver`, so they
+//! can be rotated or fetched from a key store.
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+
+use crate::{
+    auth::{Authentication, AuthenticationFuture, Authenticator, Principal},
+    context::{Context, Request},
+    headers::HeaderValue,
+};
+
+/// Key that creates and verifies signatures with an algorithm
+pub trait SignatureKey: Send + Sync {
+    /// Name of the algorithm in the HTTP Signature Algorithms registry (i.e. `hmac-sha256`)
+    fn algorithm(&self) -> &str;
+
+    /// Signs the signature base
+    fn sign(&self, base: &[u8]) -> Vec<u8>;
+
+    /// Verifies the signature of the signature base
+    fn verify(&self, base: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Function that returns the key for a key ID, or None if the key ID is not known
+pub type KeyResolver = Arc<dyn Fn(&str) -> Option<Arc<dyn SignatureKey>> + Send + Sync>;
+
+/// HMAC using SHA-256 with a shared secret (the `hmac-sha256` algorithm)
+#[derive(Clone)]
+pub struct HmacSha256Key {
+    /// Shared secret
+    pub secret: Vec<u8>,
+}
+
+impl HmacSha256Key {
+    /// Creates a key with the shared secret
+    pub fn new(secret: &[u8]) -> HmacSha256Key {
+        HmacSha256Key {
+            secret: secret.to_vec(),
+        }
+    }
+
+    fn mac(&self, base: &[u8]) -> Hmac<Sha256> {
+        // HMAC accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC key");
+        mac.update(base);
+        mac
+    }
+}
+
+impl SignatureKey for HmacSha256Key {
+    fn algorithm(&self) -> &str {
+        "hmac-sha256"
+    }
+
+    fn sign(&self, base: &[u8]) -> Vec<u8> {
+        self.mac(base).finalize().into_bytes().to_vec()
+    }
+
+    fn verify(&self, base: &[u8], signature: &[u8]) -> bool {
+        self.mac(base).verify_slice(signature).is_ok()
+    }
+}
+
+/// Signs responses, adding the `Signature-Input` and `Signature` headers
+#[derive(Clone)]
+pub struct ResponseSigner {
+    /// ID of the key, sent as the `keyid` parameter
+    pub key_id: String,
+    /// Key to sign with
+    pub key: Arc<dyn SignatureKey>,
+    /// Label of the signature in the headers. Defaults to `sig`.
+    pub label: String,
+    /// Components covered by the signature (derived components like `@status`, and lowercase
+    /// header names). Headers the response does not have are not covered. Defaults to
+    /// `@status`, `content-type` and `content-digest`.
+    pub components: Vec<String>,
+}
+
+impl ResponseSigner {
+    /// Creates a signer with the key and the default components
+    pub fn new<S: Into<String>>(key_id: S, key: Arc<dyn SignatureKey>) -> ResponseSigner {
+        ResponseSigner {
+            key_id: key_id.into(),
+            key,
+            label: "sig".to_string(),
+            components: vec![
+                "@status".to_string(),
+                "content-type".to_string(),
+                "content-digest".to_string(),
+            ],
+        }
+    }
+
+    /// Signs the response of the context
+    pub fn sign(&self, context: &mut Context) {
+        let mut lines = vec![];
+        let mut covered = vec![];
+        for component in &self.components {
+            let component = component.to_lowercase();
+            let value = if component == "@status" {
+                Some(context.response.status.to_string())
+            } else {
+                context
+                    .response
+                    .headers
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&component))
+                    .map(|(_, values)| field_value(values))
+            };
+            if let Some(value) = value {
+                lines.push(format!("\"{}\": {}", component, value));
+                covered.push(format!("\"{}\"", component));
+            }
+        }
+        let params = format!(
+            "({});created={};keyid=\"{}\";alg=\"{}\"",
+            covered.join(" "),
+            Utc::now().timestamp(),
+            self.key_id,
+            self.key.algorithm()
+        );
+        let signature = self.key.sign(signature_base(&lines, &params).as_bytes());
+        context.response.add_header(
+            "Signature-Input",
+            vec![HeaderValue::basic(format!("{}={}", self.label, params))],
+        );
+        context.response.add_header(
+            "Signature",
+            vec![HeaderValue::basic(format!(
+                "{}=:{}:",
+                self.label,
+                base64::encode(signature)
+            ))],
+        );
+    }
+}
+
+/// Authenticator that verifies the HTTP message signatures of requests. The key ID of a valid
+/// signature is used as the name of the principal. The path of a request is reconstructed from
+/// the route it was matched to, and the `@query` and `@target-uri` components are not
+/// supported.
+#[derive(Clone)]
+pub struct SignatureAuthenticator {
+    /// Looks up the keys of signatures
+    pub keys: KeyResolver,
+    /// Components that signatures must cover. Defaults to `@method`, `@authority` and `@path`.
+    pub required_components: Vec<String>,
+    /// Maximum age in seconds of a signature, from its `created` parameter. Signatures without
+    /// the parameter are rejected when set. Defaults to 300 seconds.
+    pub max_age: Option<i64>,
+}
+
+impl SignatureAuthenticator {
+    /// Creates an authenticator that looks up keys with the resolver
+    pub fn new(keys: KeyResolver) -> SignatureAuthenticator {
+        SignatureAuthenticator {
+            keys,
+            required_components: vec![
+                "@method".to_string(),
+                "@authority".to_string(),
+                "@path".to_string(),
+            ],
+            max_age: Some(300),
+        }
+    }
+
+    fn verify(&self, request: &Request) -> Authentication {
+        let input = request.find_header("Signature-Input");
+        let (label, params) = match input.first().and_then(|input| input.value.split_once('=')) {
+            Some((label, params)) => (label.trim(), params.trim()),
+            None => return Authentication::NoCredentials,
+        };
+        let signature = request
+            .find_header("Signature")
+            .iter()
+            .filter_map(|member| {
+                let (member_label, value) = member.value.split_once('=')?;
+                if member_label.trim() == label {
+                    base64::decode(value.trim().trim_matches(':')).ok()
+                } else {
+                    None
+                }
+            })
+            .next();
+        let signature = match signature {
+            Some(signature) => signature,
+            None => return Authentication::Failed(format!("No valid '{}' signature", label)),
+        };
+        let (components, parameters) = match parse_signature_params(params) {
+            Some(parsed) => parsed,
+            None => return Authentication::Failed("Malformed Signature-Input".to_string()),
+        };
+        if let Some(missing) = self
+            .required_components
+            .iter()
+            .find(|required| !components.contains(&required.to_lowercase()))
+        {
+            return Authentication::Failed(format!("Signature does not cover {}", missing));
+        }
+        let now = Utc::now().timestamp();
+        let created = parameter(&parameters, "created")
+            .and_then(|value| value.parse::<i64>().ok());
+        if let Some(max_age) = self.max_age {
+            match created {
+                Some(created) if now - created <= max_age => (),
+                _ => return Authentication::Failed("Signature has expired".to_string()),
+            }
+        }
+        let expires = parameter(&parameters, "expires")
+            .and_then(|value| value.parse::<i64>().ok());
+        if expires.map(|expires| expires < now).unwrap_or(false) {
+            return Authentication::Failed("Signature has expired".to_string());
+        }
+        let key_id = match parameter(&parameters, "keyid") {
+            Some(key_id) => key_id,
+            None => return Authentication::Failed("Signature does not have a key ID".to_string()),
+        };
+        let key = match (self.keys)(&key_id) {
+            Some(key) => key,
+            None => return Authentication::Failed(format!("Unknown key ID '{}'", key_id)),
+        };
+        if let Some(alg) = parameter(&parameters, "alg") {
+            if alg != key.algorithm() {
+                return Authentication::Failed(format!("Unexpected algorithm '{}'", alg));
+            }
+        }
+        let mut lines = vec![];
+        for component in &components {
+            match request_component(request, component) {
+                Some(value) => lines.push(format!("\"{}\": {}", component, value)),
+                None => {
+                    return Authentication::Failed(format!(
+                        "Signed component {} is not available",
+                        component
+                    ))
+                }
+            }
+        }
+        if key.verify(signature_base(&lines, params).as_bytes(), &signature) {
+            Authentication::Authenticated(Principal::new(key_id, "HTTP-Signature"))
+        } else {
+            Authentication::Failed("Invalid signature".to_string())
+        }
+    }
+}
+
+impl Authenticator for SignatureAuthenticator {
+    fn authenticate(&self, request: &Request) -> AuthenticationFuture {
+        let authentication = self.verify(request);
+        Box::pin(async { authentication })
+    }
+
+    fn challenge(&self) -> String {
+        "Signature".to_string()
+    }
+}
+
+fn signature_base(lines: &[String], params: &str) -> String {
+    let mut base = String::new();
+    for line in lines {
+        base.push_str(line);
+        base.push('\n');
+    }
+    base.push_str("\"@signature-params\": ");
+    base.push_str(params);
+    base
+}
+
+fn field_value(values: &[HeaderValue]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string().trim().to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn request_component(request: &Request, component: &str) -> Option<String> {
+    match component {
+        "@method" => Some(request.method.to_uppercase()),
+        "@authority" => request.host().map(|host| host.to_lowercase()),
+        "@path" => Some(if let Some(raw_path) = &request.raw_path {
+            raw_path.clone()
+        } else if request.base_path == "/" || request.base_path.is_empty() {
+            request.request_path.clone()
+        } else if request.request_path == "/" {
+            request.base_path.clone()
+        } else {
+            format!("{}{}", request.base_path.trim_end_matches('/'), request.request_path)
+        }),
+        _ if component.starts_with('@') => None,
+        _ => {
+            let values = request.find_header(component);
+            if values.is_empty() {
+                None
+            } else {
+                Some(field_value(&values))
+            }
+        }
+    }
+}
+
+// covered components and parameters of a signature
+type SignatureParams = (Vec<String>, Vec<(String, String)>);
+
+// signature-params -> "(" *( component ) ")" *( ";" key "=" value )
+fn parse_signature_params(params: &str) -> Option<SignatureParams> {
+    let rest = params.strip_prefix('(')?;
+    let (components, parameters) = rest.split_once(')')?;
+    let components = components
+        .split_whitespace()
+        .map(|component| component.trim_matches('"').to_lowercase())
+        .collect();
+    let parameters = parameters
+        .split(';')
+        .filter_map(|parameter| {
+            let (key, value) = parameter.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().trim_matches('"').to_string()))
+        })
+        .collect();
+    Some((components, parameters))
+}
+
+fn parameter(parameters: &[(String, String)], key: &str) -> Option<String> {
+    parameters
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Response;
+    use expectest::prelude::*;
+
+    fn resolver() -> KeyResolver {
+        Arc::new(|key_id| {
+            if key_id == "test-shared-secret" {
+                Some(Arc::new(HmacSha256Key::new(b"secret")) as Arc<dyn SignatureKey>)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn signed_request(params: &str, signature: &[u8]) -> Request {
+        Request {
+            method: "POST".to_string(),
+            base_path: "/foo".to_string(),
+            request_path: "/".to_string(),
+            headers: hashmap! {
+                "Host".to_string() => vec![h!("example.com")],
+                "Signature-Input".to_string() => {
+                    vec![HeaderValue::basic(format!("sig-b25={}", params))]
+                },
+                "Signature".to_string() => {
+                    vec![HeaderValue::basic(format!("sig-b25=:{}:", base64::encode(signature)))]
+                }
+            },
+            ..Request::default()
+        }
+    }
+
+    #[test]
+    fn signature_authenticator_verifies_request_signatures() {
+        let params = format!(
+            "(\"@method\" \"@authority\" \"@path\");created={};keyid=\"test-shared-secret\"",
+            Utc::now().timestamp()
+        );
+        let base = format!(
+            "\"@method\": POST\n\"@authority\": example.com\n\"@path\": /foo\n\
+             \"@signature-params\": {}",
+            params
+        );
+        let key = HmacSha256Key::new(b"secret");
+        let authenticator = SignatureAuthenticator::new(resolver());
+        expect!(authenticator.verify(&signed_request(&params, &key.sign(base.as_bytes()))))
+            .to(be_equal_to(Authentication::Authenticated(Principal::new(
+                "test-shared-secret",
+                "HTTP-Signature"
+            ))));
+        expect!(authenticator.verify(&signed_request(&params, b"forged")))
+            .to(be_equal_to(Authentication::Failed("Invalid signature".to_string())));
+        expect!(authenticator.verify(&Request::default()))
+            .to(be_equal_to(Authentication::NoCredentials));
+    }
+
+    #[test]
+    fn signature_authenticator_rejects_old_and_partial_signatures() {
+        let authenticator = SignatureAuthenticator::new(resolver());
+        let old = "(\"@method\" \"@authority\" \"@path\");created=1618884473;keyid=\"k\"";
+        expect!(authenticator.verify(&signed_request(old, b"")))
+            .to(be_equal_to(Authentication::Failed("Signature has expired".to_string())));
+        let partial = "(\"@method\");keyid=\"test-shared-secret\"";
+        expect!(authenticator.verify(&signed_request(partial, b""))).to(be_equal_to(
+            Authentication::Failed("Signature does not cover @authority".to_string()),
+        ));
+    }
+
+    #[test]
+    fn response_signer_signs_the_covered_components() {
+        let mut context = Context {
+            response: Response {
+                status: 200,
+                headers: btreemap! {
+                    "Content-Type".to_string() => vec![h!("application/json")]
+                },
+                ..Response::default()
+            },
+            ..Context::default()
+        };
+        let key = Arc::new(HmacSha256Key::new(b"secret"));
+        ResponseSigner::new("server-key", key.clone()).sign(&mut context);
+        let input = context.response.headers.get("Signature-Input").unwrap()[0].value.clone();
+        let params = input.strip_prefix("sig=").unwrap();
+        expect!(params.starts_with("(\"@status\" \"content-type\");created=")).to(be_true());
+        expect!(params.ends_with(";keyid=\"server-key\";alg=\"hmac-sha256\"")).to(be_true());
+        let base = format!(
+            "\"@status\": 200\n\"content-type\": application/json\n\"@signature-params\": {}",
+            params
+        );
+        expect!(context.response.headers.get("Signature").cloned()).to(be_some().value(vec![
+            HeaderValue::basic(format!("sig=:{}:", base64::encode(key.sign(base.as_bytes())))),
+        ]));
+    }
+}
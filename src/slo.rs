@@ -0,0 +1,213 @@
+//! The `slo` module tracks service level objectives (SLOs) of routes. Each SLO declares the
+//! fraction of requests to a route that must be good, where a request is bad if it fails with a
+//! 5xx status or, for latency SLOs, takes longer than a threshold. The compliance and burn rate
+//! of each SLO are computed over a rolling window, exposed as gauges by the `Metrics` that track
+//! them, and an alert callback is invoked when the burn rate crosses its threshold.
+
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Number of slots the rolling window of an SLO is divided into
+const WINDOW_SLOTS: u32 = 60;
+
+/// Callback invoked when the burn rate of an SLO rises above its threshold, or falls back
+/// below it
+pub type SloAlertCallback = Arc<dyn Fn(&SloStatus) + Send + Sync>;
+
+/// Service level objective of a route
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slo {
+    /// Name of the SLO, used as the `slo` label of its gauges (i.e. `availability`)
+    pub name: String,
+    /// Route template that the SLO applies to
+    pub route: String,
+    /// Fraction of requests that must be good (i.e. 0.999)
+    pub objective: f64,
+    /// Requests that take longer than this are bad. Defaults to None (only failed requests are
+    /// bad).
+    pub latency_threshold: Option<Duration>,
+    /// Rolling window that compliance is computed over. Defaults to one hour.
+    pub window: Duration,
+    /// Burn rate that triggers an alert. A burn rate of 1 uses up the error budget exactly over
+    /// the window. Defaults to 14.4, which uses up a 30 day budget in two days.
+    pub burn_rate_threshold: f64,
+}
+
+impl Slo {
+    /// SLO that the fraction of requests to the route that do not fail with a 5xx status meets
+    /// the objective
+    pub fn availability<S: Into<String>>(route: S, objective: f64) -> Slo {
+        Slo {
+            name: "availability".to_string(),
+            route: route.into(),
+            objective,
+            latency_threshold: None,
+            window: Duration::from_secs(3600),
+            burn_rate_threshold: 14.4,
+        }
+    }
+
+    /// SLO that the fraction of requests to the route that succeed within the threshold meets
+    /// the objective
+    pub fn latency<S: Into<String>>(route: S, threshold: Duration, objective: f64) -> Slo {
+        Slo {
+            name: "latency".to_string(),
+            latency_threshold: Some(threshold),
+            ..Slo::availability(route, objective)
+        }
+    }
+
+    fn is_good(&self, status: u16, latency: Duration) -> bool {
+        status < 500 && !matches!(self.latency_threshold, Some(threshold) if latency > threshold)
+    }
+}
+
+/// Compliance of an SLO over its rolling window
+#[derive(Debug, Clone, PartialEq)]
+pub struct SloStatus {
+    /// The SLO
+    pub slo: Slo,
+    /// Number of requests in the window
+    pub requests: u64,
+    /// Number of bad requests in the window
+    pub bad_requests: u64,
+    /// Fraction of the requests in the window that were good. This is 1 if there were none.
+    pub compliance: f64,
+    /// Rate the error budget is being used up at, which is the fraction of bad requests divided
+    /// by the fraction allowed by the objective
+    pub burn_rate: f64,
+    /// If the burn rate is over its threshold
+    pub alerting: bool,
+}
+
+/// Rolling window of the requests of an SLO
+#[derive(Debug)]
+pub(crate) struct SloWindow {
+    started: Instant,
+    /// Index, request count and bad request count of the slots, oldest first
+    slots: VecDeque<(u64, u64, u64)>,
+    alerting: bool,
+}
+
+impl SloWindow {
+    pub(crate) fn new(started: Instant) -> SloWindow {
+        SloWindow {
+            started,
+            slots: VecDeque::new(),
+            alerting: false,
+        }
+    }
+
+    fn slot(&self, slo: &Slo, now: Instant) -> u64 {
+        let slot_length = (slo.window / WINDOW_SLOTS).max(Duration::from_millis(1));
+        (now.saturating_duration_since(self.started).as_nanos() / slot_length.as_nanos()) as u64
+    }
+
+    fn expire(&mut self, slo: &Slo, now: Instant) {
+        let oldest = self
+            .slot(slo, now)
+            .saturating_sub(u64::from(WINDOW_SLOTS) - 1);
+        while matches!(self.slots.front(), Some((slot, _, _)) if *slot < oldest) {
+            self.slots.pop_front();
+        }
+    }
+
+    /// Records a request, returning the status of the SLO if it crossed its burn rate threshold
+    pub(crate) fn observe(
+        &mut self,
+        slo: &Slo,
+        now: Instant,
+        status: u16,
+        latency: Duration,
+    ) -> Option<SloStatus> {
+        self.expire(slo, now);
+        let slot = self.slot(slo, now);
+        let bad = u64::from(!slo.is_good(status, latency));
+        match self.slots.back_mut() {
+            Some((index, requests, bad_requests)) if *index == slot => {
+                *requests += 1;
+                *bad_requests += bad;
+            }
+            _ => self.slots.push_back((slot, 1, bad)),
+        }
+        let status = self.status(slo, now);
+        if status.alerting != self.alerting {
+            self.alerting = status.alerting;
+            Some(status)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the status of the SLO over the window ending now
+    pub(crate) fn status(&mut self, slo: &Slo, now: Instant) -> SloStatus {
+        self.expire(slo, now);
+        let (requests, bad_requests) = self.slots.iter().fold((0, 0), |(requests, bad), slot| {
+            (requests + slot.1, bad + slot.2)
+        });
+        let bad_fraction = if requests == 0 {
+            0.0
+        } else {
+            bad_requests as f64 / requests as f64
+        };
+        let budget = 1.0 - slo.objective;
+        let burn_rate = if budget > 0.0 {
+            bad_fraction / budget
+        } else if bad_requests > 0 {
+            f64::INFINITY
+        } else {
+            0.0
+        };
+        SloStatus {
+            slo: slo.clone(),
+            requests,
+            bad_requests,
+            compliance: 1.0 - bad_fraction,
+            burn_rate,
+            alerting: burn_rate > slo.burn_rate_threshold,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn slo_compliance_and_burn_rate_are_computed_over_the_window() {
+        let slo = Slo {
+            window: Duration::from_secs(60),
+            burn_rate_threshold: 5.0,
+            ..Slo::latency("/orders", Duration::from_millis(100), 0.99)
+        };
+        let start = Instant::now();
+        let mut window = SloWindow::new(start);
+        let fast = Duration::from_millis(10);
+        for _ in 0..95 {
+            expect!(window.observe(&slo, start, 200, fast)).to(be_none());
+        }
+        window.observe(&slo, start, 200, Duration::from_millis(150));
+        window.observe(&slo, start, 200, Duration::from_millis(150));
+        for _ in 0..3 {
+            expect!(window.observe(&slo, start, 503, fast)).to(be_none());
+        }
+        let status = window.status(&slo, start);
+        expect!(status.requests).to(be_equal_to(100));
+        expect!(status.bad_requests).to(be_equal_to(5));
+        expect!((status.compliance - 0.95).abs() < 1e-9).to(be_true());
+        expect!((status.burn_rate - 5.0).abs() < 1e-6).to(be_true());
+        expect!(status.alerting).to(be_false());
+
+        let crossed = window.observe(&slo, start, 500, fast).unwrap();
+        expect!(crossed.alerting).to(be_true());
+
+        let later = start + Duration::from_secs(61);
+        let recovered = window.observe(&slo, later, 200, fast).unwrap();
+        expect!(recovered.requests).to(be_equal_to(1));
+        expect!(recovered.alerting).to(be_false());
+    }
+}
@@ -0,0 +1,279 @@
+//! The `telemetry` module supports distributed tracing. The W3C trace context of a request (the
+//! `traceparent` and `tracestate` headers) is parsed into the context, so calls that resources
+//! make to other services can continue the trace. With the `otel` feature, the dispatcher also
+//! emits an OpenTelemetry HTTP server span for each request, following the semantic
+//! conventions for HTTP servers.
+
+use crate::context::Request;
+
+/// W3C trace context (https://www.w3.org/TR/trace-context/) of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    /// Trace ID, as 32 lowercase hex digits
+    pub trace_id: String,
+    /// ID of the parent span, as 16 lowercase hex digits. When the dispatcher emits a span for
+    /// the request, this is the ID of that span.
+    pub span_id: String,
+    /// Trace flags
+    pub flags: u8,
+    /// Vendor specific trace state, from the `tracestate` header
+    pub trace_state: Option<String>,
+}
+
+impl TraceContext {
+    /// Returns the trace context from the `traceparent` and `tracestate` headers of the request,
+    /// or None if the request does not have a valid `traceparent`
+    pub fn from_request(request: &Request) -> Option<TraceContext> {
+        let traceparent = request.find_header("traceparent");
+        if traceparent.len() != 1 {
+            return None;
+        }
+        let trace_state = request
+            .find_header("tracestate")
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        let trace_state = Some(trace_state).filter(|state| !state.is_empty());
+        TraceContext::parse(&traceparent[0].to_string(), trace_state)
+    }
+
+    /// Parses a `traceparent` header value (i.e.
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`). Later versions of the format
+    /// are parsed as version 00, as the specification requires.
+    pub fn parse(traceparent: &str, trace_state: Option<String>) -> Option<TraceContext> {
+        let fields = traceparent.trim().split('-').collect::<Vec<&str>>();
+        if fields.len() < 4 {
+            return None;
+        }
+        let (version, trace_id, span_id, flags) = (fields[0], fields[1], fields[2], fields[3]);
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && fields.len() != 4) {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+        if trace_id.bytes().all(|b| b == b'0') || span_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id: trace_id.to_string(),
+            span_id: span_id.to_string(),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            trace_state,
+        })
+    }
+
+    /// If the trace is sampled
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// Returns the `traceparent` header value to continue the trace in a downstream call
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(feature = "otel")]
+pub(crate) use self::otel::*;
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::{
+        global::{BoxedSpan, BoxedTracer},
+        trace::{
+            Span, SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId,
+            TraceState, Tracer,
+        },
+        KeyValue,
+    };
+    use std::str::FromStr;
+
+    use super::TraceContext;
+    use crate::context::Context;
+
+    /// Starts the server span of the request, as a child of the trace context of the request.
+    /// The trace context of the request is updated to the span, so downstream calls are its
+    /// children.
+    pub(crate) fn start_server_span(tracer: &BoxedTracer, context: &mut Context) -> BoxedSpan {
+        let mut parent = opentelemetry::Context::new();
+        if let Some(trace_context) = &context.trace_context {
+            let span_context = SpanContext::new(
+                TraceId::from_hex(&trace_context.trace_id).unwrap_or(TraceId::INVALID),
+                SpanId::from_hex(&trace_context.span_id).unwrap_or(SpanId::INVALID),
+                TraceFlags::new(trace_context.flags),
+                true,
+                trace_context
+                    .trace_state
+                    .as_deref()
+                    .and_then(|state| TraceState::from_str(state).ok())
+                    .unwrap_or_default(),
+            );
+            parent = parent.with_remote_span_context(span_context);
+        }
+        let request = &context.request;
+        let mut attributes = vec![
+            KeyValue::new("http.request.method", request.method.to_uppercase()),
+            KeyValue::new("url.path", request.request_path.clone()),
+            KeyValue::new(
+                "network.protocol.version",
+                request.http_version.trim_start_matches("HTTP/").to_string(),
+            ),
+        ];
+        if let Some(user_agent) = request.find_header("User-Agent").first() {
+            attributes.push(KeyValue::new("user_agent.original", user_agent.to_string()));
+        }
+        match (&request.forwarded, request.remote_addr) {
+            (Some(forwarded), _) => {
+                if let Some(addr) = forwarded.client_ip {
+                    attributes.push(KeyValue::new("client.address", addr.to_string()));
+                }
+            }
+            (None, Some(addr)) => {
+                attributes.push(KeyValue::new("client.address", addr.ip().to_string()));
+                attributes.push(KeyValue::new("client.port", i64::from(addr.port())));
+            }
+            (None, None) => (),
+        }
+        let span = tracer
+            .span_builder(request.method.to_uppercase())
+            .with_kind(SpanKind::Server)
+            .with_attributes(attributes)
+            .start_with_context(tracer, &parent);
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            let trace_state = span_context.trace_state().header();
+            context.trace_context = Some(TraceContext {
+                trace_id: span_context.trace_id().to_string(),
+                span_id: span_context.span_id().to_string(),
+                flags: span_context.trace_flags().to_u8(),
+                trace_state: Some(trace_state).filter(|state| !state.is_empty()),
+            });
+        }
+        span
+    }
+
+    /// Ends the server span of the request, naming it with the route template that matched the
+    /// request
+    pub(crate) fn end_server_span(mut span: BoxedSpan, context: &Context, route: Option<&str>) {
+        let method = context.request.method.to_uppercase();
+        let status = context.response.status;
+        if let Some(route) = route {
+            span.update_name(format!("{} {}", method, route));
+            span.set_attribute(KeyValue::new("http.route", route.to_string()));
+        }
+        span.set_attribute(KeyValue::new(
+            "http.response.status_code",
+            i64::from(status),
+        ));
+        if status >= 500 {
+            span.set_attribute(KeyValue::new("error.type", status.to_string()));
+            span.set_status(Status::error(context.error.clone().unwrap_or_default()));
+        }
+        span.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    #[test]
+    fn parse_accepts_only_valid_traceparents() {
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let trace_context = TraceContext::parse(parent, Some("rojo=00f067aa0ba902b7".into()));
+        expect!(trace_context.clone().map(|context| context.traceparent()))
+            .to(be_some().value(parent));
+        expect!(trace_context.map(|context| context.is_sampled())).to(be_some().value(true));
+        expect!(TraceContext::parse(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-future",
+            None
+        ))
+        .to(be_some());
+        for invalid in &[
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+        ] {
+            expect!(TraceContext::parse(invalid, None)).to(be_none());
+        }
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn dispatcher_emits_a_server_span_continuing_the_trace() {
+        use crate::{owned_callback, Dispatcher, Resource};
+        use opentelemetry::{
+            global::BoxedTracer,
+            trace::{SpanKind, TracerProvider},
+            KeyValue,
+        };
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use std::sync::{Arc, Mutex};
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let downstream = Arc::new(Mutex::new(None));
+        let seen = downstream.clone();
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/orders" => Resource {
+                    resource_exists: owned_callback(move |context, _| {
+                        *seen.lock().unwrap() = context.trace_context.clone();
+                        Box::pin(async { true })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            tracer: Some(Arc::new(BoxedTracer::new(Box::new(
+                provider.tracer("test"),
+            )))),
+            ..Dispatcher::default()
+        };
+        let request = http::Request::builder()
+            .uri("/orders/1")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body(hyper::Body::empty())
+            .unwrap();
+        let response = dispatcher.dispatch(request).await.unwrap();
+        expect!(response.status().as_u16()).to(be_equal_to(200));
+
+        let spans = exporter.get_finished_spans().unwrap();
+        expect!(spans.len()).to(be_equal_to(1));
+        let span = &spans[0];
+        expect!(span.name.to_string()).to(be_equal_to("GET /orders"));
+        expect!(span.span_kind.clone()).to(be_equal_to(SpanKind::Server));
+        expect!(span.span_context.trace_id().to_string())
+            .to(be_equal_to("4bf92f3577b34da6a3ce929d0e0e4736"));
+        expect!(span.parent_span_id.to_string()).to(be_equal_to("00f067aa0ba902b7"));
+        expect!(span
+            .attributes
+            .contains(&KeyValue::new("http.response.status_code", 200)))
+        .to(be_true());
+        expect!(span
+            .attributes
+            .contains(&KeyValue::new("http.route", "/orders")))
+        .to(be_true());
+
+        let downstream = downstream.lock().unwrap().clone().unwrap();
+        expect!(downstream.trace_id).to(be_equal_to("4bf92f3577b34da6a3ce929d0e0e4736"));
+        expect!(downstream.span_id).to(be_equal_to(span.span_context.span_id().to_string()));
+    }
+}
@@ -7,10 +7,18 @@ fn resource(path: &str) -> Request {
     Request {
         request_path: path.to_string(),
         base_path: "/".to_string(),
+        raw_path: None,
         method: "GET".to_string(),
         headers: HashMap::new(),
         body: None,
         query: HashMap::new(),
+        remote_addr: None,
+        listener: None,
+        http_version: "HTTP/1.1".to_string(),
+        local_addr: None,
+        secure_connection: false,
+        client_certificate: None,
+        forwarded: None,
     }
 }
 
@@ -23,6 +31,7 @@ fn path_matcher_test() {
           "/path2" => Resource::default(),
           "/path1/path3" => Resource::default()
         },
+        ..Dispatcher::default()
     };
     expect!(dispatcher.match_paths(&resource("/path1"))).to(be_equal_to(vec!["/", "/path1"]));
     expect!(dispatcher.match_paths(&resource("/path1/"))).to(be_equal_to(vec!["/", "/path1"]));
@@ -41,6 +50,31 @@ fn path_matcher_test() {
     expect!(dispatcher.match_paths(&resource("/"))).to(be_equal_to(vec!["/"]));
 }
 
+#[test]
+fn path_matcher_excludes_resources_not_served_on_the_listener() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+          "/" => Resource::default(),
+          "/admin" => Resource {
+            listeners: vec!["localhost"],
+            ..Resource::default()
+          }
+        },
+        ..Dispatcher::default()
+    };
+    expect!(dispatcher.match_paths(&resource("/admin"))).to(be_equal_to(vec!["/"]));
+    let request = Request {
+        listener: Some("public".to_string()),
+        ..resource("/admin")
+    };
+    expect!(dispatcher.match_paths(&request)).to(be_equal_to(vec!["/"]));
+    let request = Request {
+        listener: Some("localhost".to_string()),
+        ..resource("/admin")
+    };
+    expect!(dispatcher.match_paths(&request)).to(be_equal_to(vec!["/", "/admin"]));
+}
+
 #[test]
 fn sanitise_path_test() {
     expect!(sanitise_path(&"/".to_string()).iter()).to(be_empty());
@@ -55,11 +89,202 @@ async fn dispatcher_returns_404_if_there_is_no_matching_resource() {
     let mut context = Context::default();
     let displatcher = Dispatcher {
         routes: btreemap! { "/some/path" => Resource::default() },
+        ..Dispatcher::default()
     };
     displatcher.dispatch_to_resource(&mut context).await;
     expect(context.response.status).to(be_equal_to(404));
 }
 
+#[tokio::test]
+async fn dispatcher_returns_400_if_the_request_body_can_not_be_read() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        ..Dispatcher::default()
+    };
+    let chunks: Vec<Result<&str, std::io::Error>> = vec![
+        Ok("partial"),
+        Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset")),
+    ];
+    let request = http::Request::builder()
+        .method("POST")
+        .uri("/")
+        .body(hyper::Body::wrap_stream(futures::stream::iter(chunks)))
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.response.status).to(be_equal_to(400));
+    expect!(context.error.is_some()).to(be_true());
+    expect!(context.request.body).to(be_none());
+}
+
+#[tokio::test]
+async fn dispatcher_returns_413_if_the_request_body_exceeds_the_memory_limit() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        max_request_memory: Some(4),
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .method("POST")
+        .uri("/")
+        .body(hyper::Body::from("too large"))
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.response.status).to(be_equal_to(413));
+    expect!(context.request.body).to(be_none());
+}
+
+#[tokio::test]
+async fn dispatcher_returns_413_if_the_request_body_exceeds_the_maximum_entity_length() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/" => Resource::default(),
+            "/upload" => Resource {
+                max_entity_length: Some(16),
+                ..Resource::default()
+            }
+        },
+        max_entity_length: Some(4),
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .method("POST")
+        .uri("/")
+        .header("Content-Length", "9")
+        .body(hyper::Body::from("too large"))
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.response.status).to(be_equal_to(413));
+    expect!(context.request.body).to(be_none());
+
+    let (mut sender, body) = hyper::Body::channel();
+    tokio::spawn(async move {
+        sender.send_data("too ".into()).await.unwrap();
+        sender.send_data("large".into()).await.unwrap();
+    });
+    let request = http::Request::builder().method("POST").uri("/").body(body).unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.response.status).to(be_equal_to(413));
+
+    let request = http::Request::builder()
+        .method("POST")
+        .uri("/upload")
+        .header("Content-Length", "9")
+        .body(hyper::Body::from("too large"))
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.error).to(be_none());
+    expect!(context.request.body).to(be_some().value(b"too large".to_vec()));
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_413_if_the_entity_exceeds_the_maximum_length() {
+    let mut context = Context {
+        request: Request {
+            method: "POST".to_string(),
+            body: Some(b"too large".to_vec()),
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        allowed_methods: vec!["POST"],
+        max_entity_length: Some(4),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(413));
+}
+
+#[tokio::test]
+async fn finalise_response_returns_500_if_the_rendered_body_exceeds_the_memory_limit() {
+    let mut context = Context {
+        memory: MemoryAccount::new(Some(4)),
+        ..Context::default()
+    };
+    let resource = Resource {
+        render_response: callback(&|_, _| Box::pin(async { Some("too large".to_string()) })),
+        ..Resource::default()
+    };
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(500));
+    expect!(context.response.body).to(be_none());
+}
+
+#[tokio::test]
+async fn finalise_response_invokes_finish_request_after_the_body_is_rendered() {
+    let mut context = Context::default();
+    let resource = Resource {
+        render_response: callback(&|_, _| Box::pin(async { Some("body".to_string()) })),
+        finish_request: callback(&|context, _| {
+            let length = context.response.body.as_ref().map(Vec::len).unwrap_or_default();
+            context.metadata.insert("rendered".to_string(), length.to_string());
+            Box::pin(async {})
+        }),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.metadata.get("rendered").cloned()).to(be_none());
+    finalise_response(&mut context, &resource).await;
+    expect!(context.metadata.get("rendered").cloned()).to(be_some().value("4"));
+}
+
+#[tokio::test]
+async fn finalise_response_awaits_the_finalise_response_callback() {
+    let audited = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let flag = audited.clone();
+    let resource = Resource {
+        finalise_response: Some(Arc::new(Mutex::new(Box::new(move |context, _| {
+            context.response.add_header("X-Audited", vec![h!("true")]);
+            let flag = flag.clone();
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                flag.store(true, std::sync::atomic::Ordering::SeqCst);
+            })
+        })))),
+        ..Resource::default()
+    };
+    let mut context = Context::default();
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.has_header("X-Audited")).to(be_true());
+    expect!(audited.load(std::sync::atomic::Ordering::SeqCst)).to(be_true());
+}
+
+#[tokio::test]
+async fn finalise_response_callback_sees_the_response_as_it_will_be_sent() {
+    let observed = Arc::new(std::sync::Mutex::new(None));
+    let recorded = observed.clone();
+    let resource = Resource {
+        encodings_provided: vec!["gzip", "identity"],
+        render_response: callback(&|_, _| {
+            Box::pin(async { Some("{\"total\": 1}".repeat(200)) })
+        }),
+        compression: Some(CompressionConfig::default()),
+        digest: Some(DigestConfig::default()),
+        finalise_response: Some(Arc::new(Mutex::new(Box::new(move |context, _| {
+            let body = context.response.body.clone().unwrap_or_default();
+            let digest = context.response.headers.get("Content-Digest").cloned();
+            let recorded = recorded.clone();
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                *recorded.lock().unwrap() = Some((body, digest));
+            })
+        })))),
+        ..Resource::default()
+    };
+    let mut context = Context::default();
+    context.request.headers = hashmap! { "Accept-Encoding".to_string() => vec![h!("gzip")] };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+
+    let (body, digest) = observed.lock().unwrap().take().unwrap();
+    expect!(context.response.headers.get("Content-Encoding").cloned())
+        .to(be_some().value(vec![h!("gzip")]));
+    expect!(body.starts_with(&[0x1f, 0x8b])).to(be_true());
+    expect!(Some(body)).to(be_equal_to(context.response.body.clone()));
+    expect!(digest.is_some()).to(be_true());
+    expect!(digest).to(be_equal_to(context.response.headers.get("Content-Digest").cloned()));
+}
+
 #[tokio::test]
 async fn execute_state_machine_returns_503_if_resource_indicates_not_available() {
     let mut context = Context::default();
@@ -124,6 +349,39 @@ async fn execute_state_machine_returns_501_if_method_is_not_in_known_list() {
     let resource = Resource::default();
     execute_state_machine(&mut context, &resource).await;
     expect(context.response.status).to(be_equal_to(501));
+    expect(context.response.headers.get("Allow").unwrap().clone()).to(be_equal_to(vec![
+        HeaderValue::basic("OPTIONS"),
+        HeaderValue::basic("GET"),
+        HeaderValue::basic("HEAD"),
+    ]));
+    let problem: serde_json::Value =
+        serde_json::from_slice(&context.response.body.unwrap()).unwrap();
+    expect!(problem).to(be_equal_to(serde_json::json!({
+        "type": "about:blank",
+        "title": "Not Implemented",
+        "status": 501,
+        "detail": "Method 'Blah' is not supported",
+        "allowed_methods": ["OPTIONS", "GET", "HEAD"]
+    })));
+}
+
+#[tokio::test]
+async fn execute_state_machine_can_return_405_for_unknown_methods() {
+    let mut context = Context {
+        request: Request {
+            method: "Blah".to_string(),
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        unknown_method_status: 405,
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(405));
+    expect!(context.response.has_header("Allow")).to(be_true());
+    expect!(context.terminal_decision).to(be_some().value(DecisionId::B12KnownMethod));
 }
 
 #[tokio::test]
@@ -156,6 +414,25 @@ async fn execute_state_machine_returns_405_if_method_is_not_allowed() {
     ]));
 }
 
+#[tokio::test]
+async fn execute_state_machine_only_allows_methods_that_are_known() {
+    let mut context = Context {
+        request: Request {
+            method: "PATCH".to_string(),
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        allowed_methods: vec!["get", "PURGE"],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(405));
+    expect(context.response.headers.get("Allow").unwrap().clone())
+        .to(be_equal_to(vec![HeaderValue::basic("GET")]));
+}
+
 #[tokio::test]
 async fn execute_state_machine_returns_400_if_malformed_request() {
     let mut context = Context::default();
@@ -234,6 +511,40 @@ async fn execute_state_machine_returns_415_if_the_content_type_is_unknown() {
     expect(context.response.status).to(be_equal_to(415));
 }
 
+#[tokio::test]
+async fn execute_state_machine_uses_the_method_specific_acceptable_content_types() {
+    let mut context = Context {
+        request: Request {
+            method: "PUT".to_string(),
+            headers: hashmap! {
+              "Content-type".to_string() => vec![HeaderValue::basic(&"multipart/form-data".to_string())]
+            },
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        acceptable_content_types: vec!["application/json"],
+        method_acceptable_content_types: hashmap! {
+          "POST" => vec!["multipart/form-data"]
+        },
+        allowed_methods: vec!["POST", "PUT"],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(415));
+
+    let mut context = Context {
+        request: Request {
+            method: "POST".to_string(),
+            ..context.request.clone()
+        },
+        ..Context::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to_not(be_equal_to(415));
+}
+
 #[tokio::test]
 async fn execute_state_machine_returns_does_not_return_415_if_not_a_put_or_post() {
     let mut context = Context {
@@ -254,13 +565,13 @@ async fn execute_state_machine_returns_does_not_return_415_if_not_a_put_or_post(
 
 #[test]
 fn parse_header_test() {
-    expect(parse_header_values("").iter()).to(be_empty());
-    expect(parse_header_values("HEADER A")).to(be_equal_to(vec!["HEADER A".to_string()]));
-    expect(parse_header_values("HEADER A, header B")).to(be_equal_to(vec![
+    expect(HeaderValue::parse_list("").iter()).to(be_empty());
+    expect(HeaderValue::parse_list("HEADER A")).to(be_equal_to(vec!["HEADER A".to_string()]));
+    expect(HeaderValue::parse_list("HEADER A, header B")).to(be_equal_to(vec![
         "HEADER A".to_string(),
         "header B".to_string(),
     ]));
-    expect(parse_header_values(
+    expect(HeaderValue::parse_list(
         "text/plain;  q=0.5,   text/html,text/x-dvi; q=0.8, text/x-c",
     ))
     .to(be_equal_to(vec![
@@ -285,6 +596,59 @@ fn parse_header_test() {
             quote: false,
         },
     ]));
+    expect(HeaderValue::parse_list("A; title=\"a, b\", B")).to(be_equal_to(vec![
+        HeaderValue {
+            value: "A".to_string(),
+            params: hashmap! {"title".to_string() => "a, b".to_string()},
+            quote: false,
+        },
+        h!("B"),
+    ]));
+}
+
+#[test]
+fn headers_from_http_request_splits_entity_tag_lists_respecting_quotes() {
+    let (parts, _) = http::Request::builder()
+        .header("If-None-Match", "\"a,b\", W/\"c\"")
+        .body(())
+        .unwrap()
+        .into_parts();
+    let headers = headers_from_http_request(&parts);
+    expect!(headers.get("if-none-match").cloned()).to(be_some().value(vec![
+        HeaderValue::basic("a,b"),
+        HeaderValue::basic("W/\"c\""),
+    ]));
+}
+
+#[test]
+fn headers_from_http_request_merges_repeated_header_lines() {
+    let (parts, _) = http::Request::builder()
+        .header("Accept", "application/json")
+        .header("Accept", "text/html, text/plain")
+        .header("Content-Type", "text/plain")
+        .body(())
+        .unwrap()
+        .into_parts();
+    let headers = headers_from_http_request(&parts);
+    expect!(headers.get("accept").cloned()).to(be_some().value(vec![
+        h!("application/json"),
+        h!("text/html"),
+        h!("text/plain"),
+    ]));
+    expect!(headers.get("content-type").cloned()).to(be_some().value(vec![h!("text/plain")]));
+}
+
+#[test]
+fn headers_from_http_request_does_not_split_date_headers() {
+    let (parts, _) = http::Request::builder()
+        .header("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")
+        .body(())
+        .unwrap()
+        .into_parts();
+    let headers = headers_from_http_request(&parts);
+    expect!(headers.get("if-modified-since").cloned()).to(be_some().value(vec![
+        HeaderValue::basic("Sun, 06 Nov 1994 08:49:37 GMT"),
+    ]));
 }
 
 #[tokio::test]
@@ -350,6 +714,20 @@ async fn execute_state_machine_returns_headers_for_option_request() {
         .to(be_equal_to(vec!["D;E=F".to_string()]));
 }
 
+#[tokio::test]
+async fn execute_state_machine_returns_no_cors_headers_for_option_request_without_cors_config() {
+    let mut context = Context::default();
+    context.request.method = "OPTIONS".to_string();
+    context.request.headers = hashmap! {
+        "Origin".to_string() => vec![h!("https://evil.com")]
+    };
+    let resource = Resource::default();
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(204));
+    expect!(context.response.has_header("Access-Control-Allow-Origin")).to(be_false());
+    expect!(context.response.has_header("Access-Control-Allow-Methods")).to(be_false());
+}
+
 #[tokio::test]
 async fn execute_state_machine_returns_406_if_the_request_does_not_have_an_acceptable_content_type()
 {
@@ -834,6 +1212,35 @@ async fn execute_state_machine_returns_304_if_non_match_star_exists_and_is_a_hea
     expect(context.response.status).to(be_equal_to(304));
 }
 
+#[tokio::test]
+async fn execute_state_machine_returns_304_for_a_registered_cacheable_method() {
+    let mut context = Context {
+        request: Request {
+            method: "QUERY".to_string(),
+            headers: hashmap! {
+              "If-None-Match".to_string() => vec![h!("*")]
+            },
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let mut known_methods = MethodRegistry::default();
+    known_methods.register(Method {
+        safe: true,
+        idempotent: true,
+        cacheable: true,
+        ..Method::new("QUERY")
+    });
+    let resource = Resource {
+        resource_exists: callback(&|_, _| Box::pin(async { true })),
+        known_methods,
+        allowed_methods: vec!["QUERY"],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(304));
+}
+
 #[tokio::test]
 async fn execute_state_machine_returns_412_if_resource_etag_in_if_non_match_and_is_not_a_head_or_get() {
     let mut context = Context {
@@ -876,6 +1283,46 @@ async fn execute_state_machine_returns_304_if_resource_etag_in_if_non_match_and_
     expect(context.response.status).to(be_equal_to(304));
 }
 
+#[tokio::test]
+async fn execute_state_machine_uses_weak_comparison_for_if_none_match() {
+    let mut context = Context {
+        request: Request {
+            headers: hashmap! {
+              "If-None-Match".to_string() => vec![h!("W/\"1234567890\"")]
+            },
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        resource_exists: callback(&|_, _| Box::pin(async { true })),
+        generate_etag: callback(&|_, _| Box::pin(async { Some("1234567890".to_string()) })),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(304));
+}
+
+#[tokio::test]
+async fn execute_state_machine_uses_strong_comparison_for_if_match() {
+    let mut context = Context {
+        request: Request {
+            headers: hashmap! {
+              "If-Match".to_string() => vec![h!("W/\"1234567890\"")]
+            },
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        resource_exists: callback(&|_, _| Box::pin(async { true })),
+        generate_etag: callback(&|_, _| Box::pin(async { Some("W/\"1234567890\"".to_string()) })),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(412));
+}
+
 #[tokio::test]
 async fn execute_state_machine_returns_304_if_the_resource_last_modified_gt_modified_since() {
     let datetime =
@@ -1189,65 +1636,133 @@ async fn execute_state_machine_returns_204_if_put_request_to_existing_resource_w
 }
 
 #[tokio::test]
-async fn execute_state_machine_returns_300_if_multiple_choices_is_true() {
+async fn execute_state_machine_processes_other_methods_of_existing_resources() {
     let mut context = Context {
         request: Request {
+            method: "PROPFIND".to_string(),
             ..Request::default()
         },
         ..Context::default()
     };
     let resource = Resource {
+        allowed_methods: vec!["GET", "PROPFIND"],
+        known_methods: MethodRegistry::webdav(),
         resource_exists: callback(&|_, _| Box::pin(async { true })),
-        multiple_choices: callback(&|_, _| Box::pin(async { true })),
+        process_other_method: callback(&|context, _| {
+            context.response.body = Some("<multistatus/>".as_bytes().to_vec());
+            Box::pin(async { Err(207) })
+        }),
         ..Resource::default()
     };
     execute_state_machine(&mut context, &resource).await;
-    expect(context.response.status).to(be_equal_to(300));
-}
+    expect(context.response.status).to(be_equal_to(207));
+    expect(context.response.body).to(be_some().value("<multistatus/>".as_bytes().to_vec()));
 
-#[tokio::test]
-async fn execute_state_machine_returns_204_if_delete_was_enacted_and_response_has_no_body() {
     let mut context = Context {
         request: Request {
-            method: "DELETE".to_string(),
+            method: "GET".to_string(),
             ..Request::default()
         },
         ..Context::default()
     };
-    let resource = Resource {
-        resource_exists: callback(&|_, _| Box::pin(async { true })),
-        delete_resource: callback(&|_, _| Box::pin(async { Ok(true) })),
-        allowed_methods: vec!["DELETE"],
-        ..Resource::default()
-    };
     execute_state_machine(&mut context, &resource).await;
-    expect(context.response.status).to(be_equal_to(204));
+    expect(context.response.status).to(be_equal_to(200));
+    expect(context.response.body).to(be_none());
 }
 
 #[tokio::test]
-async fn execute_state_machine_returns_200_if_delete_was_enacted_and_response_has_a_body() {
+async fn execute_state_machine_processes_other_methods_of_missing_resources() {
+    let resource = Resource {
+        allowed_methods: vec!["MKCOL", "PROPFIND"],
+        known_methods: MethodRegistry::webdav(),
+        resource_exists: callback(&|_, _| Box::pin(async { false })),
+        process_other_method: callback(&|context, _| {
+            let created = context.request.method == "MKCOL";
+            Box::pin(async move { Ok(created) })
+        }),
+        ..Resource::default()
+    };
     let mut context = Context {
         request: Request {
-            method: "DELETE".to_string(),
+            method: "MKCOL".to_string(),
             ..Request::default()
         },
         ..Context::default()
     };
-    let resource = Resource {
-        resource_exists: callback(&|_, _| Box::pin(async { true })),
-        delete_resource: callback(&|context, _| {
-            context.response.body = Some("body".as_bytes().to_vec());
-            Box::pin(async { Ok(true) })
-        }),
-        allowed_methods: vec!["DELETE"],
-        ..Resource::default()
-    };
     execute_state_machine(&mut context, &resource).await;
-    expect(context.response.status).to(be_equal_to(200));
-}
+    expect(context.response.status).to(be_equal_to(201));
 
-#[test]
-fn parse_query_string_test() {
+    let mut context = Context {
+        request: Request {
+            method: "PROPFIND".to_string(),
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(404));
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_300_if_multiple_choices_is_true() {
+    let mut context = Context {
+        request: Request {
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        resource_exists: callback(&|_, _| Box::pin(async { true })),
+        multiple_choices: callback(&|_, _| Box::pin(async { true })),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(300));
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_204_if_delete_was_enacted_and_response_has_no_body() {
+    let mut context = Context {
+        request: Request {
+            method: "DELETE".to_string(),
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        resource_exists: callback(&|_, _| Box::pin(async { true })),
+        delete_resource: callback(&|_, _| Box::pin(async { Ok(true) })),
+        allowed_methods: vec!["DELETE"],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(204));
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_200_if_delete_was_enacted_and_response_has_a_body() {
+    let mut context = Context {
+        request: Request {
+            method: "DELETE".to_string(),
+            ..Request::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        resource_exists: callback(&|_, _| Box::pin(async { true })),
+        delete_resource: callback(&|context, _| {
+            context.response.body = Some("body".as_bytes().to_vec());
+            Box::pin(async { Ok(true) })
+        }),
+        allowed_methods: vec!["DELETE"],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(200));
+}
+
+#[test]
+fn parse_query_string_test() {
     let query = "a=b&c=d".to_string();
     let expected = hashmap! {
       "a".to_string() => vec!["b".to_string()],
@@ -1290,3 +1805,1634 @@ fn parse_query_string_decodes_values() {
     };
     expect!(parse_query(&query)).to(be_equal_to(expected));
 }
+
+#[test]
+fn parse_query_string_decodes_utf8_values() {
+    let query = "name=Ren%C3%A9e&city=Z%C3%BCrich+%E2%82%AC&bad=%FF".to_string();
+    let expected = hashmap! {
+      "name".to_string() => vec!["Renée".to_string()],
+      "city".to_string() => vec!["Zürich €".to_string()],
+      "bad".to_string() => vec!["\u{FFFD}".to_string()]
+    };
+    expect!(parse_query(&query)).to(be_equal_to(expected));
+}
+
+#[test]
+fn parse_query_string_collects_list_keys() {
+    let query = "tags[]=a&tags[]=b&tags=c&filter[status]=open&filter[ids][]=1".to_string();
+    let expected = hashmap! {
+      "tags".to_string() => vec!["a".to_string(), "b".to_string(), "c".to_string()],
+      "filter[status]".to_string() => vec!["open".to_string()],
+      "filter[ids]".to_string() => vec!["1".to_string()]
+    };
+    let request = Request {
+        query: parse_query(&query),
+        ..Request::default()
+    };
+    expect!(request.query.clone()).to(be_equal_to(expected));
+    let nested = request.nested_query();
+    let filter = &nested["filter"];
+    expect!(filter.get("status").and_then(QueryValue::value)).to(be_some().value("open"));
+    expect!(filter.get("ids").and_then(QueryValue::values))
+        .to(be_some().value(&["1".to_string()][..]));
+}
+
+#[tokio::test]
+async fn finalise_response_strips_content_from_304_in_strict_mode() {
+    let mut context = Context {
+        request: Request {
+            method: "POST".to_string(),
+            ..Request::default()
+        },
+        response: Response {
+            status: 304,
+            body: Some("body".as_bytes().to_vec()),
+            headers: btreemap! {
+              "Content-Language".to_string() => vec![h!("en")],
+              "Cache-Control".to_string() => vec![h!("max-age=60")]
+            },
+            ..Response::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        generate_etag: callback(&|_, _| Box::pin(async { Some("1234567890".to_string()) })),
+        strict_status_compliance: true,
+        ..Resource::default()
+    };
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.body.clone()).to(be_none());
+    expect!(context.response.has_header("Content-Type")).to(be_false());
+    expect!(context.response.has_header("Content-Language")).to(be_false());
+    expect!(context.response.has_header("Cache-Control")).to(be_true());
+    expect!(context.response.headers.get("ETag").cloned())
+        .to(be_some().value(vec![h!("1234567890").quote()]));
+}
+
+#[tokio::test]
+async fn finalise_response_strips_content_from_204_in_strict_mode() {
+    let mut context = Context {
+        response: Response {
+            status: 204,
+            body: Some("body".as_bytes().to_vec()),
+            ..Response::default()
+        },
+        ..Context::default()
+    };
+    let resource = Resource {
+        strict_status_compliance: true,
+        ..Resource::default()
+    };
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.body.clone()).to(be_none());
+    expect!(context.response.has_header("Content-Type")).to(be_false());
+}
+
+#[tokio::test]
+async fn finalise_response_does_not_modify_204_if_not_in_strict_mode() {
+    let mut context = Context {
+        response: Response {
+            status: 204,
+            body: Some("body".as_bytes().to_vec()),
+            ..Response::default()
+        },
+        ..Context::default()
+    };
+    finalise_response(&mut context, &Resource::default()).await;
+    expect!(context.response.body.clone()).to(be_some());
+    expect!(context.response.has_header("Content-Type")).to(be_true());
+}
+
+#[tokio::test]
+async fn extended_resource_overrides_the_base_resource() {
+    let base = Resource {
+        forbidden: callback(&|_, _| Box::pin(async { true })),
+        allowed_methods: vec!["GET"],
+        ..Resource::default()
+    };
+    let resource = Resource::extend(&base, |resource| {
+        resource.allowed_methods = vec!["GET", "POST"];
+    });
+    expect!(resource.allowed_methods.clone()).to(be_equal_to(vec!["GET", "POST"]));
+    expect!(base.allowed_methods).to(be_equal_to(vec!["GET"]));
+
+    let mut context = Context::default();
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(403));
+}
+
+#[tokio::test]
+async fn configured_cors_headers_are_added_to_normal_responses() {
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Origin".to_string() => vec![h!("https://app.example.com")]
+    };
+    let resource = Resource {
+        variances: vec!["Accept-Encoding", "Accept-Language"],
+        cors: Some(CorsConfig::for_origins(vec!["https://*.example.com"])),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.headers.get("Access-Control-Allow-Origin").cloned())
+        .to(be_some().value(vec![h!("https://app.example.com")]));
+    expect!(context.response.headers.get("Vary").cloned())
+        .to(be_some().value(vec![h!("Accept-Encoding"), h!("Accept-Language"), h!("Origin")]));
+    expect!(context.response.has_header("Access-Control-Allow-Methods")).to(be_false());
+}
+
+#[tokio::test]
+async fn configured_cors_headers_are_added_to_preflight_responses() {
+    let mut context = Context::default();
+    context.request.method = "OPTIONS".to_string();
+    context.request.headers = hashmap! {
+        "Origin".to_string() => vec![h!("https://evil.com")]
+    };
+    let resource = Resource {
+        cors: Some(CorsConfig::for_origins(vec!["https://*.example.com"])),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(204));
+    expect!(context.response.has_header("Access-Control-Allow-Origin")).to(be_false());
+
+    let mut context = Context::default();
+    context.request.method = "OPTIONS".to_string();
+    context.request.headers = hashmap! {
+        "Origin".to_string() => vec![h!("https://app.example.com")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.headers.get("Access-Control-Allow-Methods").cloned())
+        .to(be_some().value(vec![h!("OPTIONS"), h!("GET"), h!("HEAD")]));
+}
+
+#[tokio::test]
+async fn dispatcher_cors_config_applies_to_resources_without_their_own() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/default" => Resource::default(),
+            "/own" => Resource {
+                cors: Some(CorsConfig::for_origins(vec!["https://own.example.com"])),
+                ..Resource::default()
+            }
+        },
+        cors: Some(CorsConfig::for_origins(vec!["https://app.example.com"])),
+        ..Dispatcher::default()
+    };
+    let mut context = Context::default();
+    context.request.request_path = "/default".to_string();
+    context.request.headers = hashmap! {
+        "Origin".to_string() => vec![h!("https://app.example.com")]
+    };
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.headers.get("Access-Control-Allow-Origin").cloned())
+        .to(be_some().value(vec![h!("https://app.example.com")]));
+
+    let mut context = Context::default();
+    context.request.request_path = "/own".to_string();
+    context.request.headers = hashmap! {
+        "Origin".to_string() => vec![h!("https://app.example.com")]
+    };
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.has_header("Access-Control-Allow-Origin")).to(be_false());
+}
+
+#[tokio::test]
+async fn dispatcher_validates_the_host_header_against_the_allowed_hosts() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        allowed_hosts: vec!["example.com".to_string(), "*.example.org".to_string()],
+        ..Dispatcher::default()
+    };
+    let request = |host: Option<&str>| {
+        let builder = http::Request::builder().uri("/");
+        match host {
+            Some(host) => builder.header("Host", host),
+            None => builder,
+        }
+        .body(hyper::Body::empty())
+        .unwrap()
+    };
+
+    let context = dispatcher.context_from_http_request(request(Some("example.com:8080"))).await;
+    expect!(context.error).to(be_none());
+    let context = dispatcher.context_from_http_request(request(Some("api.example.org"))).await;
+    expect!(context.error).to(be_none());
+    let context = dispatcher.context_from_http_request(request(Some("evil.com"))).await;
+    expect!(context.response.status).to(be_equal_to(421));
+    let context = dispatcher.context_from_http_request(request(Some("example.org"))).await;
+    expect!(context.response.status).to(be_equal_to(421));
+    let context = dispatcher.context_from_http_request(request(None)).await;
+    expect!(context.response.status).to(be_equal_to(400));
+}
+
+#[tokio::test]
+async fn dispatcher_uses_the_authority_of_absolute_form_request_targets() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        allowed_hosts: vec!["example.com".to_string()],
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("http://evil.com/path")
+        .header("Host", "example.com")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.request.host()).to(be_some().value("evil.com"));
+    expect!(context.request.request_path).to(be_equal_to("/path"));
+    expect!(context.response.status).to(be_equal_to(421));
+}
+
+#[tokio::test]
+async fn dispatcher_sets_the_http_version_and_connection_of_the_request() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        ..Dispatcher::default()
+    };
+    let mut request = http::Request::builder()
+        .uri("/path")
+        .version(http::Version::HTTP_10)
+        .body(hyper::Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(server::ConnectionInfo {
+        listener: "https".to_string(),
+        remote_addr: Some("10.0.0.1:4000".parse().unwrap()),
+        local_addr: Some("10.0.0.2:443".parse().unwrap()),
+        secure: true,
+        client_certificate: None,
+    });
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.request.http_version.as_str()).to(be_equal_to("HTTP/1.0"));
+    expect!(context.request.is_legacy_http()).to(be_true());
+    expect!(context.request.local_addr).to(be_some().value("10.0.0.2:443".parse().unwrap()));
+    expect!(context.request.secure_connection).to(be_true());
+
+    let request = http::Request::builder()
+        .uri("/path")
+        .version(http::Version::HTTP_2)
+        .body(hyper::Body::empty())
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.request.http_version.as_str()).to(be_equal_to("HTTP/2.0"));
+    expect!(context.request.is_legacy_http()).to(be_false());
+    expect!(context.request.secure_connection).to(be_false());
+}
+
+#[tokio::test]
+async fn dispatcher_only_trusts_forwarding_headers_from_trusted_proxies() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        require_tls: Some(TlsPolicy {
+            canonical_host: Some("api.example.com".to_string()),
+            ..TlsPolicy::default()
+        }),
+        forwarded: Some(ForwardedConfig::trusting(&["10.0.0.0/8"]).unwrap()),
+        ..Dispatcher::default()
+    };
+    let request = |remote_addr: &str| {
+        let mut request = http::Request::builder()
+            .uri("/orders/1")
+            .header("Host", "backend:8080")
+            .header("X-Forwarded-For", "203.0.113.7")
+            .header("X-Forwarded-Proto", "https")
+            .header("X-Forwarded-Host", "api.example.com")
+            .body(hyper::Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(server::ConnectionInfo {
+            listener: "http".to_string(),
+            remote_addr: Some(remote_addr.parse().unwrap()),
+            local_addr: None,
+            secure: false,
+            client_certificate: None,
+        });
+        request
+    };
+    let ip = |addr: &str| addr.parse::<std::net::IpAddr>().unwrap();
+
+    let context = dispatcher
+        .context_from_http_request(request("10.0.0.1:4000"))
+        .await;
+    expect!(context.request.client_ip()).to(be_some().value(ip("203.0.113.7")));
+    expect!(context.request.absolute_url("/orders/1"))
+        .to(be_some().value("https://api.example.com/orders/1"));
+    expect!(context.response.status).to(be_equal_to(200));
+
+    let context = dispatcher
+        .context_from_http_request(request("198.51.100.2:4000"))
+        .await;
+    expect!(context.request.forwarded.is_none()).to(be_true());
+    expect!(context.request.client_ip()).to(be_some().value(ip("198.51.100.2")));
+    expect!(context.request.absolute_url("/orders/1"))
+        .to(be_some().value("http://backend:8080/orders/1"));
+    expect!(context.response.status).to(be_equal_to(308));
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_421_if_the_request_is_misdirected() {
+    let mut context = Context::default();
+    let resource = Resource {
+        misdirected_request: callback(&|_, _| Box::pin(async { true })),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(421));
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_426_if_an_upgrade_is_required() {
+    let mut context = Context::default();
+    let resource = Resource {
+        upgrade_required: callback(&|_, _| {
+            Box::pin(async { Some("TLS/1.2, HTTP/1.1".to_string()) })
+        }),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(426));
+    expect!(context.response.headers.get("Upgrade").cloned())
+        .to(be_some().value(vec![h!("TLS/1.2"), h!("HTTP/1.1")]));
+    expect!(context.response.headers.get("Connection").cloned())
+        .to(be_some().value(vec![h!("Upgrade")]));
+}
+
+#[tokio::test]
+async fn dispatcher_returns_505_if_the_http_version_is_not_supported() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        minimum_http_version: Some(http::Version::HTTP_11),
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("/")
+        .version(http::Version::HTTP_10)
+        .body(hyper::Body::empty())
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.response.status).to(be_equal_to(505));
+
+    let request = http::Request::builder()
+        .uri("/")
+        .version(http::Version::HTTP_2)
+        .body(hyper::Body::empty())
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.error).to(be_none());
+}
+
+#[tokio::test]
+async fn execute_state_machine_stores_the_principal_from_the_authenticators() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/" => Resource {
+                authenticators: vec![
+                    Arc::new(auth::BearerAuthenticator::new(
+                        "api",
+                        Arc::new(|_| Box::pin(async { None })),
+                    )),
+                    Arc::new(auth::BasicAuthenticator::new(
+                        "api",
+                        Arc::new(|user, password| {
+                            Box::pin(async move { user == "bob" && password == "secret" })
+                        }),
+                    )),
+                ],
+                ..Resource::default()
+            }
+        },
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("/")
+        .header("Authorization", "Basic Ym9iOnNlY3JldA==")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let mut context = dispatcher.context_from_http_request(request).await;
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.status).to(be_equal_to(200));
+    expect!(context.principal).to(be_some().value(auth::Principal::new("bob", "Basic")));
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_401_with_challenges_if_not_authenticated() {
+    let mut context = Context::default();
+    let resource = Resource {
+        authenticators: vec![
+            Arc::new(auth::BearerAuthenticator::new(
+                "api",
+                Arc::new(|_| Box::pin(async { None })),
+            )),
+            Arc::new(auth::BasicAuthenticator::new(
+                "api",
+                Arc::new(|_, _| Box::pin(async { true })),
+            )),
+        ],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(401));
+    expect!(context.response.headers.get("WWW-Authenticate").cloned()).to(be_some().value(vec![
+        HeaderValue::basic("Bearer realm=\"api\""),
+        HeaderValue::basic("Basic realm=\"api\", charset=\"UTF-8\""),
+    ]));
+    expect!(context.principal).to(be_none());
+}
+
+#[tokio::test]
+async fn dispatcher_redirects_plaintext_requests_if_tls_is_required() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        require_tls: Some(TlsPolicy::default()),
+        allowed_hosts: vec!["example.com".to_string()],
+        forwarded: Some(ForwardedConfig::trusting(&["10.0.0.0/8"]).unwrap()),
+        ..Dispatcher::default()
+    };
+    let request = |forwarded_proto: Option<&str>, remote_addr: &str| {
+        let mut request = http::Request::builder()
+            .uri("/path?a=b")
+            .header("Host", "example.com");
+        if let Some(proto) = forwarded_proto {
+            request = request
+                .header("X-Forwarded-For", "203.0.113.7")
+                .header("X-Forwarded-Proto", proto);
+        }
+        let mut request = request.body(hyper::Body::empty()).unwrap();
+        request.extensions_mut().insert(server::ConnectionInfo {
+            listener: "http".to_string(),
+            remote_addr: Some(remote_addr.parse().unwrap()),
+            local_addr: None,
+            secure: false,
+            client_certificate: None,
+        });
+        request
+    };
+
+    let context = dispatcher
+        .context_from_http_request(request(None, "203.0.113.7:4000"))
+        .await;
+    expect!(context.response.status).to(be_equal_to(308));
+    expect!(context.response.headers.get("Location").cloned())
+        .to(be_some().value(vec![h!("https://example.com/path?a=b")]));
+
+    let mut context = dispatcher
+        .context_from_http_request(request(Some("https"), "10.0.0.1:4000"))
+        .await;
+    expect!(context.error.clone()).to(be_none());
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.status).to(be_equal_to(200));
+    expect!(context.response.headers.get("Strict-Transport-Security").cloned())
+        .to(be_some().value(vec![h!("max-age=31536000")]));
+}
+
+#[tokio::test]
+async fn dispatcher_does_not_trust_a_forwarded_proto_the_client_set() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        require_tls: Some(TlsPolicy::default()),
+        allowed_hosts: vec!["example.com".to_string()],
+        forwarded: Some(ForwardedConfig::trusting(&["10.0.0.0/8"]).unwrap()),
+        ..Dispatcher::default()
+    };
+    let request = |forwarded_proto: &str, remote_addr: &str| {
+        let mut request = http::Request::builder()
+            .uri("/path")
+            .header("Host", "example.com")
+            .header("X-Forwarded-For", "203.0.113.7")
+            .header("X-Forwarded-Proto", forwarded_proto)
+            .body(hyper::Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(server::ConnectionInfo {
+            listener: "http".to_string(),
+            remote_addr: Some(remote_addr.parse().unwrap()),
+            local_addr: None,
+            secure: false,
+            client_certificate: None,
+        });
+        request
+    };
+
+    // the client sent https, and the trusted proxy appended the http it received the request with
+    let context = dispatcher
+        .context_from_http_request(request("https, http", "10.0.0.1:4000"))
+        .await;
+    expect!(context.response.status).to(be_equal_to(308));
+
+    // the client connected directly, so none of its forwarding headers are trusted
+    let context = dispatcher
+        .context_from_http_request(request("https", "203.0.113.7:4000"))
+        .await;
+    expect!(context.response.status).to(be_equal_to(308));
+}
+
+#[tokio::test]
+async fn dispatcher_redirects_plaintext_requests_to_the_https_port() {
+    let request = || {
+        http::Request::builder()
+            .uri("/path")
+            .header("Host", "example.com:8080")
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        require_tls: Some(TlsPolicy::default()),
+        allowed_hosts: vec!["example.com".to_string()],
+        ..Dispatcher::default()
+    };
+    let context = dispatcher.context_from_http_request(request()).await;
+    expect!(context.response.status).to(be_equal_to(308));
+    expect!(context.response.headers.get("Location").cloned())
+        .to(be_some().value(vec![h!("https://example.com/path")]));
+
+    let dispatcher = Dispatcher {
+        require_tls: Some(TlsPolicy {
+            https_port: Some(8443),
+            ..TlsPolicy::default()
+        }),
+        ..dispatcher
+    };
+    let context = dispatcher.context_from_http_request(request()).await;
+    expect!(context.response.headers.get("Location").cloned())
+        .to(be_some().value(vec![h!("https://example.com:8443/path")]));
+}
+
+#[tokio::test]
+async fn dispatcher_only_redirects_plaintext_requests_to_trusted_hosts() {
+    let request = || {
+        http::Request::builder()
+            .uri("https://example.com/path")
+            .header("Host", "evil.example.org")
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        require_tls: Some(TlsPolicy::default()),
+        ..Dispatcher::default()
+    };
+    let context = dispatcher.context_from_http_request(request()).await;
+    expect!(context.response.status).to(be_equal_to(403));
+    expect!(context.response.headers.get("Location")).to(be_none());
+
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        require_tls: Some(TlsPolicy {
+            canonical_host: Some("www.example.com".to_string()),
+            ..TlsPolicy::default()
+        }),
+        ..Dispatcher::default()
+    };
+    let context = dispatcher.context_from_http_request(request()).await;
+    expect!(context.response.status).to(be_equal_to(308));
+    expect!(context.response.headers.get("Location").cloned())
+        .to(be_some().value(vec![h!("https://www.example.com/path")]));
+}
+
+#[tokio::test]
+async fn dispatcher_rejects_plaintext_requests_if_tls_is_required() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        require_tls: Some(TlsPolicy {
+            plaintext_action: PlaintextAction::Reject(426),
+            ..TlsPolicy::default()
+        }),
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("/path")
+        .header("Host", "example.com")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let context = dispatcher.context_from_http_request(request).await;
+    expect!(context.response.status).to(be_equal_to(426));
+    expect!(context.response.headers.get("Upgrade").cloned())
+        .to(be_some().value(vec![h!("TLS/1.2"), h!("HTTP/1.1")]));
+}
+
+#[tokio::test]
+async fn execute_state_machine_adds_the_principal_attributes_to_the_metadata_namespaced() {
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Authorization".to_string() => vec![h!("Bearer abc")]
+    };
+    let resource = Resource {
+        authenticators: vec![Arc::new(auth::BearerAuthenticator::new(
+            "api",
+            Arc::new(|_| {
+                Box::pin(async {
+                    Some(auth::Principal {
+                        attributes: hashmap! {
+                            "tenant".to_string() => "acme".to_string(),
+                            "webmachine.debugger".to_string() => "true".to_string()
+                        },
+                        ..auth::Principal::new("client", "Bearer")
+                    })
+                })
+            }),
+        ))],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(200));
+    expect!(context.metadata.get("principal.tenant").cloned()).to(be_some().value("acme"));
+    expect!(context.metadata.get("tenant")).to(be_none());
+    expect!(context.metadata.get("webmachine.debugger")).to(be_none());
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_403_if_the_credentials_are_forbidden() {
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "X-API-Key".to_string() => vec![h!("disabled")]
+    };
+    let resource = Resource {
+        authenticators: vec![Arc::new(auth::ApiKeyAuthenticator::new(
+            "api",
+            Arc::new(|key| {
+                Box::pin(async move {
+                    Some(auth::ApiKey {
+                        key,
+                        principal: auth::Principal::new("client", "ApiKey"),
+                        enabled: false,
+                    })
+                })
+            }),
+        ))],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(403));
+    expect!(context.response.has_header("WWW-Authenticate")).to(be_false());
+}
+
+#[tokio::test]
+async fn execute_state_machine_selects_and_renders_a_declared_variant() {
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("application/json"), h!("text/html;q=0.5")],
+        "Accept-Language".to_string() => vec![h!("de")]
+    };
+    let resource = Resource {
+        variants: vec![
+            Variant::new("application/json", Some("en")),
+            Variant {
+                render: Some(callback(&|_, _| {
+                    Box::pin(async { Some("<p>Hallo</p>".to_string()) })
+                })),
+                ..Variant::new("text/html", Some("de"))
+            },
+        ],
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(200));
+    expect!(context.response.body.clone()).to(be_some().value(b"<p>Hallo</p>".to_vec()));
+    expect!(context.response.headers.get("Content-Language").cloned())
+        .to(be_some().value(vec![h!("de")]));
+    expect!(context.response.headers.get("Vary").cloned())
+        .to(be_some().value(vec![h!("Accept"), h!("Accept-Language")]));
+
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("application/json")],
+        "Accept-Language".to_string() => vec![h!("de")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(406));
+}
+
+#[tokio::test]
+async fn dispatcher_authorizes_the_required_permissions_of_the_resource() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/orders" => Resource {
+                authenticators: vec![Arc::new(auth::BearerAuthenticator::new(
+                    "api",
+                    Arc::new(|token| {
+                        Box::pin(async move {
+                            Some(auth::Principal {
+                                attributes: hashmap! { "scope".to_string() => token },
+                                ..auth::Principal::new("client", "Bearer")
+                            })
+                        })
+                    }),
+                ))],
+                required_permissions: vec!["read:orders"],
+                ..Resource::default()
+            }
+        },
+        authorizer: Some(Arc::new(auth::AttributeAuthorizer::new("scope"))),
+        ..Dispatcher::default()
+    };
+
+    let request = http::Request::builder()
+        .uri("/orders")
+        .header("Authorization", "Bearer read:orders")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let mut context = dispatcher.context_from_http_request(request).await;
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.status).to(be_equal_to(200));
+
+    let request = http::Request::builder()
+        .uri("/orders")
+        .header("Authorization", "Bearer write:orders")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let mut context = dispatcher.context_from_http_request(request).await;
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.status).to(be_equal_to(403));
+    expect!(context.response.headers.get("Content-Type").cloned())
+        .to(be_some().value(vec![h!("application/problem+json")]));
+    let body: serde_json::Value =
+        serde_json::from_slice(&context.response.body.unwrap_or_default()).unwrap();
+    expect!(body["detail"].as_str()).to(be_some().value("Missing permissions: read:orders"));
+}
+
+#[tokio::test]
+async fn execute_state_machine_generates_a_distinct_etag_for_each_representation() {
+    let resource = Resource {
+        produces: vec!["application/json", "text/html"],
+        generate_etag: callback(&|_, _| Box::pin(async { Some("1234567890".to_string()) })),
+        ..Resource::default()
+    };
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("text/html")],
+        "If-None-Match".to_string() => vec![h!("\"1234567890;application/json\"")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(200));
+    expect!(context.response.headers.get("ETag").cloned())
+        .to(be_some().value(vec![h!("\"1234567890;text/html\"")]));
+
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("text/html")],
+        "If-None-Match".to_string() => vec![h!("\"1234567890;text/html\"")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(304));
+}
+
+#[tokio::test]
+async fn execute_state_machine_uses_the_etag_of_the_selected_variant() {
+    let resource = Resource {
+        variants: vec![
+            Variant::new("application/json", None),
+            Variant {
+                etag: Some(callback(&|_, _| Box::pin(async { Some("W/\"html-v2\"".to_string()) }))),
+                ..Variant::new("text/html", None)
+            },
+        ],
+        generate_etag: callback(&|_, _| Box::pin(async { Some("v2".to_string()) })),
+        ..Resource::default()
+    };
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("text/html")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.headers.get("ETag").cloned())
+        .to(be_some().value(vec![h!("W/\"html-v2\"")]));
+
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("application/json")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.headers.get("ETag").cloned())
+        .to(be_some().value(vec![h!("\"v2;application/json\"")]));
+}
+
+#[tokio::test]
+async fn execute_state_machine_returns_429_if_the_resource_rate_limit_is_exceeded() {
+    let resource = Resource {
+        rate_limiter: Some(RateLimiter::fixed_window(
+            RateLimitKey::Header("X-API-Key".to_string()),
+            1,
+            std::time::Duration::from_secs(60),
+        )),
+        ..Resource::default()
+    };
+    let request = Request {
+        headers: hashmap! { "X-API-Key".to_string() => vec![h!("abc")] },
+        ..Request::default()
+    };
+    let mut context = Context {
+        request: request.clone(),
+        ..Context::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(200));
+
+    let mut context = Context {
+        request,
+        ..Context::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(429));
+    expect!(context.response.headers.get("Retry-After").cloned())
+        .to(be_some().value(vec![h!("60")]));
+}
+
+#[tokio::test]
+async fn dispatcher_returns_429_if_the_rate_limit_is_exceeded() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        rate_limiter: Some(RateLimiter::token_bucket(
+            RateLimitKey::Custom(Arc::new(|_| Some("everyone".to_string()))),
+            1,
+            0.25,
+        )),
+        ..Dispatcher::default()
+    };
+    let request = || http::Request::builder().uri("/").body(hyper::Body::empty()).unwrap();
+    let mut context = dispatcher.context_from_http_request(request()).await;
+    expect!(context.error.clone()).to(be_none());
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.status).to(be_equal_to(200));
+
+    let context = dispatcher.context_from_http_request(request()).await;
+    expect!(context.response.status).to(be_equal_to(429));
+    expect!(context.response.headers.get("Retry-After").cloned())
+        .to(be_some().value(vec![h!("4")]));
+}
+
+#[tokio::test]
+async fn dispatcher_returns_500_if_a_resource_callback_panics() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/panics" => Resource {
+                resource_exists: callback(&|_, _| Box::pin(async { panic!("boom") })),
+                ..Resource::default()
+            },
+            "/ok" => Resource::default()
+        },
+        ..Dispatcher::default()
+    };
+    let request = |path: &str| {
+        http::Request::builder()
+            .uri(path)
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+    let mut context = dispatcher.context_from_http_request(request("/panics")).await;
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.status).to(be_equal_to(500));
+    expect!(context.error).to(be_some().value("Resource panicked: boom"));
+
+    let response = dispatcher.dispatch(request("/ok")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(200));
+}
+
+#[tokio::test]
+async fn dispatcher_returns_500_if_the_state_machine_exceeds_the_transition_limit() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/" => Resource::default() },
+        max_state_machine_transitions: Some(3),
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder().uri("/").body(hyper::Body::empty()).unwrap();
+    let mut context = dispatcher.context_from_http_request(request).await;
+    dispatcher.dispatch_to_resource(&mut context).await;
+    expect!(context.response.status).to(be_equal_to(500));
+    expect!(context.error)
+        .to(be_some().value("State machine has not terminated within 3 transitions"));
+    expect!(context.decisions_executed).to(be_equal_to(3));
+}
+
+#[tokio::test]
+async fn resource_with_a_digest_config_validates_and_adds_content_digests() {
+    let resource = Resource {
+        allowed_methods: vec!["GET", "PUT"],
+        render_response: callback(&|_, _| {
+            Box::pin(async { Some("{\"hello\": \"world\"}".to_string()) })
+        }),
+        digest: Some(DigestConfig::default()),
+        ..Resource::default()
+    };
+    let mut context = Context::default();
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.headers.get("Content-Digest").cloned()).to(be_some().value(vec![
+        h!("sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:"),
+    ]));
+
+    let mut context = Context::default();
+    context.request.method = "PUT".to_string();
+    context.request.body = Some(b"tampered".to_vec());
+    let digest = "sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:";
+    context.request.headers = hashmap! { "Content-Digest".to_string() => vec![h!(digest)] };
+    execute_state_machine(&mut context, &resource).await;
+    expect!(context.response.status).to(be_equal_to(400));
+    expect!(context.terminal_decision).to(be_some().value(DecisionId::B9MalformedRequest));
+}
+
+#[tokio::test]
+async fn dispatcher_streams_bodies_with_a_content_digest_trailer() {
+    use hyper::body::HttpBody;
+
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/report" => Resource {
+                render_response: callback(&|context, _| {
+                    let chunks = vec![Ok(b"{\"hello\": ".to_vec()), Ok(b"\"world\"}".to_vec())];
+                    context.response.stream =
+                        Some(StreamingBody::new(futures::stream::iter(chunks)));
+                    Box::pin(async { None })
+                }),
+                digest: Some(DigestConfig::default()),
+                ..Resource::default()
+            }
+        },
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("/report")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let response = dispatcher.dispatch(request).await.unwrap();
+    expect!(response.headers().get("Trailer").cloned()).to(be_some().value("Content-Digest"));
+    expect!(response.headers().contains_key("Content-Digest")).to(be_false());
+    let mut body = response.into_body();
+    let mut data = vec![];
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    expect!(data).to(be_equal_to(b"{\"hello\": \"world\"}".to_vec()));
+    let trailers = body.trailers().await.unwrap().unwrap();
+    expect!(trailers.get("content-digest").cloned()).to(be_some().value(
+        "sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:",
+    ));
+}
+
+#[tokio::test]
+async fn dispatcher_aborts_streamed_bodies_the_client_does_not_read_within_the_write_timeout() {
+    let metrics = Metrics::default();
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/export" => Resource {
+                render_response: callback(&|context, _| {
+                    let chunks = futures::stream::repeat_with(|| Ok(vec![0; 1024]));
+                    context.response.stream = Some(StreamingBody::new(chunks));
+                    Box::pin(async { None })
+                }),
+                ..Resource::default()
+            }
+        },
+        metrics: Some(metrics.clone()),
+        stream_write_timeout: Some(std::time::Duration::from_millis(50)),
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("/export")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let response = dispatcher.dispatch(request).await.unwrap();
+    // the body is not read, so the send queue fills up until the write timeout passes
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    expect!(hyper::body::to_bytes(response.into_body()).await.is_err()).to(be_true());
+
+    let rendered = metrics.render();
+    let labels = "route=\"/export\",method=\"GET\"";
+    for line in &[
+        format!("webmachine_response_stream_write_timeouts_total{{{}}} 1", labels),
+        format!(
+            "webmachine_response_stream_queue_depth_bucket{{{},le=\"4\"}} 0",
+            labels
+        ),
+        format!(
+            "webmachine_response_stream_queue_depth_bucket{{{},le=\"8\"}} 1",
+            labels
+        ),
+    ] {
+        expect!(rendered.lines().any(|rendered| rendered == *line)).to(be_true());
+    }
+}
+
+#[tokio::test]
+async fn dispatcher_signals_a_client_disconnect_when_the_request_is_dropped() {
+    let seen: Arc<std::sync::Mutex<Option<ClientDisconnect>>> = Arc::default();
+    let slot = seen.clone();
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/slow" => Resource {
+                resource_exists: Arc::new(Mutex::new(Box::new(move |context, _| {
+                    *slot.lock().unwrap() = Some(context.client_disconnect.clone());
+                    Box::pin(futures::future::pending())
+                }))),
+                ..Resource::default()
+            }
+        },
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("/slow")
+        .body(hyper::Body::empty())
+        .unwrap();
+    // polls the request until it is waiting on the callback, then drops it like hyper does when
+    // the connection is closed
+    let response = futures::FutureExt::now_or_never(dispatcher.dispatch(request));
+    expect!(response.is_none()).to(be_true());
+    let disconnect = seen.lock().unwrap().clone().unwrap();
+    expect!(disconnect.is_disconnected()).to(be_true());
+}
+
+#[tokio::test]
+async fn dispatcher_returns_the_decision_trace_when_enabled() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/missing" => Resource {
+                resource_exists: callback(&|_, _| Box::pin(async { false })),
+                ..Resource::default()
+            }
+        },
+        decision_trace: true,
+        ..Dispatcher::default()
+    };
+    let request = |accept: &str| {
+        http::Request::builder()
+            .uri("/missing")
+            .header("Accept", accept)
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+    let response = dispatcher.clone().dispatch(request("application/json")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(404));
+    let trace = response.headers().get("X-Webmachine-Trace").unwrap().to_str().unwrap();
+    expect!(trace.starts_with("B13=true, B13a=false, ")).to(be_true());
+    expect!(trace.ends_with(", G7=false, H7=false, I7=false, K7=false, L7=false")).to(be_true());
+
+    let response = dispatcher.dispatch(request(TRACE_MEDIA_TYPE)).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(404));
+    expect!(response.headers().get("Content-Type").cloned()).to(be_some().value(TRACE_MEDIA_TYPE));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let trace: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    expect!(trace["status"].clone()).to(be_equal_to(serde_json::json!(404)));
+    let last = trace["decisions"].as_array().unwrap().last().unwrap().clone();
+    expect!(last["name"].clone()).to(be_equal_to(serde_json::json!("L7Post")));
+    expect!(last["next"].clone()).to(be_equal_to(serde_json::Value::Null));
+}
+
+#[tokio::test]
+async fn dispatcher_records_traces_for_the_debugger() {
+    let recorder = TraceRecorder::default();
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/_debug" => DebuggerResource::new(recorder.clone()).resource(),
+            "/orders" => Resource::default()
+        },
+        debugger: Some(recorder.clone()),
+        ..Dispatcher::default()
+    };
+    let request = |path: &str| {
+        http::Request::builder()
+            .uri(path)
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+    dispatcher.clone().dispatch(request("/orders/1")).await.unwrap();
+    let response = dispatcher.clone().dispatch(request("/_debug")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(200));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let page = String::from_utf8(body.to_vec()).unwrap();
+    expect!(page.contains("<a href=\"?trace=1\">GET /orders/1 200</a>")).to(be_true());
+    expect!(recorder.traces().len()).to(be_equal_to(1));
+
+    let response = dispatcher.clone().dispatch(request("/_debug?trace=2")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(404));
+    let response = dispatcher.dispatch(request("/_debug?trace=x")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(400));
+}
+
+#[tokio::test]
+async fn dispatcher_coalesces_identical_idempotent_writes() {
+    let executions = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = executions.clone();
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/orders" => Resource {
+                allowed_methods: vec!["PUT"],
+                process_put: Arc::new(Mutex::new(Box::new(move |_, _| {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    Box::pin(async {
+                        tokio::task::yield_now().await;
+                        Ok(true)
+                    })
+                }))),
+                coalescer: Some(RequestCoalescer::new(std::time::Duration::from_secs(60))),
+                ..Resource::default()
+            }
+        },
+        ..Dispatcher::default()
+    };
+    let request = |body: &'static str| {
+        http::Request::builder()
+            .method("PUT")
+            .uri("/orders/1")
+            .header("Content-Type", "application/json")
+            .body(hyper::Body::from(body))
+            .unwrap()
+    };
+    let (first, second) = futures::join!(
+        dispatcher.clone().dispatch(request("{}")),
+        dispatcher.clone().dispatch(request("{}"))
+    );
+    expect!(first.unwrap().status().as_u16()).to(be_equal_to(204));
+    expect!(second.unwrap().status().as_u16()).to(be_equal_to(204));
+    expect!(executions.load(std::sync::atomic::Ordering::SeqCst)).to(be_equal_to(1));
+
+    let response = dispatcher.dispatch(request("{\"changed\": true}")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(204));
+    expect!(executions.load(std::sync::atomic::Ordering::SeqCst)).to(be_equal_to(2));
+}
+
+#[tokio::test]
+async fn dispatcher_does_not_share_streamed_responses_of_coalesced_writes() {
+    let executions = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = executions.clone();
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/orders" => Resource {
+                allowed_methods: vec!["PUT"],
+                process_put: Arc::new(Mutex::new(Box::new(move |context, _| {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let chunks: Vec<Result<Vec<u8>, String>> = vec![Ok(b"saved".to_vec())];
+                    context.response.stream =
+                        Some(StreamingBody::new(futures::stream::iter(chunks)));
+                    Box::pin(async {
+                        tokio::task::yield_now().await;
+                        Ok(true)
+                    })
+                }))),
+                coalescer: Some(RequestCoalescer::new(std::time::Duration::from_secs(60))),
+                ..Resource::default()
+            }
+        },
+        ..Dispatcher::default()
+    };
+    let request = || {
+        http::Request::builder()
+            .method("PUT")
+            .uri("/orders/1")
+            .body(hyper::Body::from("{}"))
+            .unwrap()
+    };
+    let (first, second) = futures::join!(
+        dispatcher.clone().dispatch(request()),
+        dispatcher.clone().dispatch(request())
+    );
+    for response in [first.unwrap(), second.unwrap()] {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        expect!(body.to_vec()).to(be_equal_to(b"saved".to_vec()));
+    }
+    expect!(executions.load(std::sync::atomic::Ordering::SeqCst)).to(be_equal_to(2));
+}
+
+#[tokio::test]
+async fn dispatcher_adds_cors_headers_to_error_responses() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/resource" => Resource {
+                allowed_methods: vec!["GET"],
+                ..Resource::default()
+            }
+        },
+        cors: Some(CorsConfig::for_origins(vec!["https://app.example.com"])),
+        allowed_hosts: vec!["example.com".to_string()],
+        ..Dispatcher::default()
+    };
+    let request = |method: &str, path: &str, host: &str| {
+        http::Request::builder()
+            .method(method)
+            .uri(path)
+            .header("Host", host)
+            .header("Origin", "https://app.example.com")
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+
+    let response = dispatcher.clone().dispatch(request("GET", "/missing", "example.com")).await;
+    let response = response.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(404));
+    expect!(response.headers().get("Access-Control-Allow-Origin").cloned())
+        .to(be_some().value("https://app.example.com"));
+
+    let response = dispatcher.clone().dispatch(request("GET", "/resource", "other.com")).await;
+    let response = response.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(421));
+    expect!(response.headers().get("Access-Control-Allow-Origin").cloned())
+        .to(be_some().value("https://app.example.com"));
+
+    let response = dispatcher.dispatch(request("OPTIONS", "/resource", "example.com")).await;
+    let response = response.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(405));
+    expect!(response.headers().get("Access-Control-Allow-Origin").cloned())
+        .to(be_some().value("https://app.example.com"));
+    expect!(response.headers().get("Access-Control-Allow-Methods").cloned())
+        .to(be_some().value("GET"));
+}
+
+#[tokio::test]
+async fn dispatcher_renders_error_bodies_with_the_error_renderer() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/resource" => Resource::default() },
+        error_renderer: Some(ErrorRenderer::new(&["text/plain"], |context, _| {
+            Some(format!("Error {}", context.response.status))
+        })),
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("/missing")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let response = dispatcher.dispatch(request).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(404));
+    expect!(response.headers().get("Content-Type").cloned())
+        .to(be_some().value("text/plain; charset=UTF-8"));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    expect!(body.to_vec()).to(be_equal_to(b"Error 404".to_vec()));
+}
+
+#[tokio::test]
+async fn execute_state_machine_records_the_decision_trace_on_the_context() {
+    let mut context = Context::default();
+    let resource = Resource {
+        resource_exists: callback(&|_, _| Box::pin(async { false })),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    let trace = &context.decision_trace;
+    expect!(trace.len()).to(be_equal_to(context.decisions_executed));
+    expect!(trace.first().map(|step| step.decision)).to(be_some().value(DecisionId::B13Available));
+    let last = trace.last().unwrap();
+    expect!(last.decision).to(be_equal_to(DecisionId::L7Post));
+    expect!(last.outcome).to(be_false());
+    expect!(last.next).to(be_none());
+    let g7 = trace.iter().find(|step| step.decision == DecisionId::G7ResourceExists).unwrap();
+    expect!(g7.outcome).to(be_false());
+    expect!(g7.next).to(be_some().value(DecisionId::H7IfMatchStarExists));
+}
+
+#[tokio::test]
+async fn context_summary_is_a_single_logfmt_line() {
+    let mut context = Context::default();
+    context.request.base_path = "/orders".to_string();
+    context.request.request_path = "/my order".to_string();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("application/json")]
+    };
+    let resource = Resource {
+        resource_exists: callback(&|_, _| Box::pin(async { false })),
+        ..Resource::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    let summary = context.summary();
+    expect!(summary.starts_with(
+        "method=GET route=/orders path=\"/my order\" status=404 decision=L7 \
+         media_type=application/json language=- charset=- encoding=- decisions="
+    ))
+    .to(be_true());
+    expect!(summary.contains(" latency_ms=")).to(be_true());
+    expect!(summary.contains('\n')).to(be_false());
+}
+
+#[tokio::test]
+async fn state_machine_can_be_stepped_and_have_outcomes_injected() {
+    let mut context = Context::default();
+    let resource = Resource::default();
+    let mut state_machine = StateMachine::new(&mut context, &resource);
+    expect!(state_machine.current_decision()).to(be_none());
+    state_machine.step().await;
+    expect!(state_machine.current_decision()).to(be_some().value(DecisionId::B13Available));
+
+    while state_machine.current_decision() != Some(DecisionId::G7ResourceExists) {
+        state_machine.step().await;
+    }
+    state_machine
+        .step_with(DecisionResult::False("injected".to_string()))
+        .await;
+    expect!(state_machine.current_decision()).to(be_some().value(DecisionId::H7IfMatchStarExists));
+    expect!(state_machine.is_complete()).to(be_false());
+
+    state_machine.run_to_completion().await;
+    expect!(context.response.status).to(be_equal_to(404));
+    expect!(context.terminal_decision).to(be_some().value(DecisionId::L7Post));
+}
+
+#[test]
+fn response_set_json_sets_the_body_and_content_type() {
+    let mut response = Response::default();
+    response.add_header("content-type", vec![h!("text/plain")]);
+    expect!(response.set_json(&btreemap! { "id" => 1 })).to(be_ok());
+    expect!(response.body.clone()).to(be_some().value(b"{\"id\":1}".to_vec()));
+    expect!(response.headers.clone()).to(be_equal_to(btreemap! {
+        "Content-Type".to_string() => vec![h!("application/json;charset=UTF-8")]
+    }));
+}
+
+#[tokio::test]
+async fn format_query_parameter_overrides_the_accept_header() {
+    let resource = Resource {
+        produces: vec!["application/json", "text/csv"],
+        languages_provided: vec!["en", "de"],
+        charsets_provided: vec!["UTF-8", "ISO-8859-1"],
+        format_override: Some(content_negotiation::FormatOverride::default()),
+        ..Resource::default()
+    };
+    let request = Request {
+        headers: hashmap! { "Accept".to_string() => vec![h!("application/json")] },
+        query: hashmap! { "format".to_string() => vec!["CSV".to_string()] },
+        ..Request::default()
+    };
+    let mut context = Context {
+        request: request.clone(),
+        ..Context::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(200));
+    expect!(context.selected_media_type).to(be_some().value("text/csv"));
+    expect!(context.response.headers.get("Vary").cloned()).to(be_some().value(vec![
+        h!("Accept-Language"),
+        h!("Accept-Charset"),
+    ]));
+
+    let mut context = Context {
+        request: Request {
+            query: hashmap! { "format".to_string() => vec!["pdf".to_string()] },
+            ..request.clone()
+        },
+        ..Context::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    expect(context.response.status).to(be_equal_to(406));
+
+    let mut context = Context {
+        request: Request {
+            query: HashMap::new(),
+            ..request
+        },
+        ..Context::default()
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.selected_media_type).to(be_some().value("application/json"));
+    expect!(context.response.headers.get("Vary").cloned()).to(be_some().value(vec![
+        h!("Accept-Language"),
+        h!("Accept-Charset"),
+        h!("Accept"),
+    ]));
+}
+
+#[tokio::test]
+async fn user_agent_class_selects_the_media_type_when_there_is_no_accept_header() {
+    let resource = Resource {
+        produces: vec!["application/json", "text/html"],
+        user_agent_negotiation: Some(content_negotiation::UserAgentNegotiation::default()),
+        ..Resource::default()
+    };
+    let dispatcher = Dispatcher {
+        routes: btreemap! { "/orders" => resource },
+        user_agent_classifier: Some(content_negotiation::UserAgentClassifier::default()),
+        ..Dispatcher::default()
+    };
+    let negotiate = |user_agent: &str, accept: Option<&str>| {
+        let mut request = http::Request::builder()
+            .uri("/orders")
+            .header("User-Agent", user_agent);
+        if let Some(accept) = accept {
+            request = request.header("Accept", accept);
+        }
+        let request = request.body(hyper::Body::empty()).unwrap();
+        let dispatcher = dispatcher.clone();
+        async move {
+            let response = dispatcher.dispatch(request).await.unwrap();
+            let content_type = response.headers()["Content-Type"].to_str().unwrap().to_string();
+            let vary = response.headers()["Vary"].to_str().unwrap().to_string();
+            (content_type, vary)
+        }
+    };
+
+    let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+    let (content_type, vary) = negotiate(firefox, None).await;
+    expect!(content_type.starts_with("text/html")).to(be_true());
+    expect!(vary).to(be_equal_to("Accept, User-Agent"));
+    let (content_type, _) = negotiate("curl/8.4.0", None).await;
+    expect!(content_type.starts_with("application/json")).to(be_true());
+    let (content_type, _) = negotiate(firefox, Some("application/json")).await;
+    expect!(content_type.starts_with("application/json")).to(be_true());
+}
+
+#[test]
+fn decision_id_displays_and_parses_the_diagram_code() {
+    expect!(DecisionId::B13aMisdirectedRequest.to_string()).to(be_equal_to("B13a"));
+    expect!(DecisionId::B13aMisdirectedRequest.name()).to(be_equal_to("B13aMisdirectedRequest"));
+    expect!("b13a".parse::<DecisionId>()).to(be_ok().value(DecisionId::B13aMisdirectedRequest));
+    expect!("G7ResourceExists".parse::<DecisionId>())
+        .to(be_ok().value(DecisionId::G7ResourceExists));
+    expect!("Z1".parse::<DecisionId>()).to(be_err());
+    expect!(DecisionId::ALL.len()).to(be_equal_to(61));
+}
+
+#[tokio::test]
+async fn finalise_response_encodes_the_rendered_value_with_the_negotiated_codec() {
+    let mut codecs = codec::CodecRegistry::default();
+    codecs.register(Arc::new(TextCodec));
+    let resource = Resource {
+        produces: vec!["application/json", "text/plain"],
+        render_value: Some(callback(&|_, _| {
+            Box::pin(async { Some(serde_json::json!({ "id": 1 })) })
+        })),
+        codecs,
+        ..Resource::default()
+    };
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("text/plain")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.body.clone()).to(be_some().value(b"id=1".to_vec()));
+
+    let mut context = Context::default();
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.response.body).to(be_some().value(b"{\"id\":1}".to_vec()));
+}
+
+#[tokio::test]
+async fn dispatcher_extracts_typed_path_parameters() {
+    let order = Resource {
+        path_template: Some("/{id}"),
+        resource_exists: callback(&|context, _| {
+            let exists = context.path_param::<u64>("id").map(|id| id < 100).unwrap_or(false);
+            Box::pin(async move { exists })
+        }),
+        render_response: callback(&|context, _| {
+            let id = context.path_param::<u64>("id").unwrap_or_default();
+            Box::pin(async move { Some(format!("{{\"id\": {}}}", id)) })
+        }),
+        ..Resource::default()
+    };
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/orders" => order.clone(),
+            "/items" => Resource {
+                invalid_path_param_status: 404,
+                ..order
+            }
+        },
+        ..Dispatcher::default()
+    };
+    let request = |path: &str| {
+        http::Request::builder()
+            .uri(path)
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+
+    let response = dispatcher.clone().dispatch(request("/orders/42")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(200));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    expect!(body.as_ref()).to(be_equal_to(b"{\"id\": 42}".as_ref()));
+
+    let response = dispatcher.clone().dispatch(request("/orders/abc")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(400));
+    expect!(response.headers()["Content-Type"].to_str().unwrap())
+        .to(be_equal_to("application/problem+json"));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    expect!(problem["detail"].as_str()).to(be_some().value(
+        "Path parameter 'id' of 'abc' is invalid - invalid digit found in string",
+    ));
+
+    let response = dispatcher.clone().dispatch(request("/items/abc")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(404));
+
+    let response = dispatcher.clone().dispatch(request("/orders/100")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(404));
+
+    let response = dispatcher.dispatch(request("/orders/42/lines")).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(404));
+}
+
+#[tokio::test]
+async fn dispatcher_matches_routes_with_the_decoded_path() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/files/my%20doc" => Resource {
+                render_response: callback(&|context, _| {
+                    let request = &context.request;
+                    let body = format!("{} {:?}", request.request_path, request.raw_path);
+                    Box::pin(async move { Some(body) })
+                }),
+                ..Resource::default()
+            }
+        },
+        ..Dispatcher::default()
+    };
+    let request = http::Request::builder()
+        .uri("/files/my%20doc/a%2Fb%C3%A9")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let response = dispatcher.dispatch(request).await.unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(200));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    expect!(String::from_utf8_lossy(&body))
+        .to(be_equal_to("/a%2Fbé Some(\"/files/my%20doc/a%2Fb%C3%A9\")"));
+}
+
+#[tokio::test]
+async fn dispatcher_normalises_dot_segments_before_routing() {
+    let dispatcher = Dispatcher {
+        routes: btreemap! {
+            "/files" => Resource {
+                render_response: callback(&|context, _| {
+                    let path = context.request.request_path.clone();
+                    Box::pin(async move { Some(path) })
+                }),
+                ..Resource::default()
+            }
+        },
+        ..Dispatcher::default()
+    };
+    let request = |path: &str| {
+        http::Request::builder()
+            .uri(path)
+            .body(hyper::Body::empty())
+            .unwrap()
+    };
+
+    let response = dispatcher
+        .clone()
+        .dispatch(request("/static/../files/./a/b/../c"))
+        .await
+        .unwrap();
+    expect!(response.status().as_u16()).to(be_equal_to(200));
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    expect!(body.as_ref()).to(be_equal_to(b"/a/c".as_ref()));
+
+    let response = dispatcher.clone().dispatch(request("/files/../../etc/passwd")).await;
+    expect!(response.unwrap().status().as_u16()).to(be_equal_to(400));
+
+    let response = dispatcher.dispatch(request("/files/%2E%2E/%2e%2e/etc/passwd")).await;
+    expect!(response.unwrap().status().as_u16()).to(be_equal_to(400));
+}
+
+#[tokio::test]
+async fn finalise_response_keeps_the_parameters_of_the_negotiated_media_type() {
+    let resource = Resource {
+        produces: vec!["application/vnd.orders+json;v=3", "text/plain;charset=utf-8"],
+        ..Resource::default()
+    };
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("application/vnd.orders+json")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    expect!(context.selected_media_type.clone())
+        .to(be_some().value("application/vnd.orders+json;v=3"));
+    let content_type = context.response.headers["Content-Type"][0].clone();
+    expect!(content_type.value).to(be_equal_to("application/vnd.orders+json"));
+    expect!(content_type.params).to(be_equal_to(hashmap! {
+        "v".to_string() => "3".to_string(),
+        "charset".to_string() => "ISO-8859-1".to_string()
+    }));
+
+    let mut context = Context::default();
+    context.request.headers = hashmap! {
+        "Accept".to_string() => vec![h!("text/plain")]
+    };
+    execute_state_machine(&mut context, &resource).await;
+    finalise_response(&mut context, &resource).await;
+    let content_type = context.response.headers["Content-Type"][0].clone();
+    expect!(content_type.to_string()).to(be_equal_to("text/plain; charset=utf-8"));
+}
+
+struct TextCodec;
+
+impl codec::BodyCodec for TextCodec {
+    fn media_type(&self) -> &str {
+        "text/plain"
+    }
+
+    fn encode(&self, value: &serde_json::Value) -> Result<Vec<u8>, String> {
+        let fields = value.as_object().ok_or("Expected an object")?;
+        Ok(fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .join("&")
+            .into_bytes())
+    }
+
+    fn decode(&self, _body: &[u8]) -> Result<serde_json::Value, String> {
+        Err("Not supported".to_string())
+    }
+}
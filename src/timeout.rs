@@ -0,0 +1,129 @@
+//! The `timeout` module bounds how long the state machine (and so the callbacks of a resource)
+//! may take to handle a request. Once the deadline passes, the remaining work is dropped and the
+//! request fails with a '503 Service Unavailable' or '504 Gateway Timeout' response, so a slow
+//! dependency does not hold the connection open indefinitely.
+
+use std::time::Duration;
+
+use crate::{context::Context, headers::HeaderValue, retry_after_seconds, Response};
+
+/// Deadline for handling a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestTimeout {
+    /// How long the state machine may run for
+    pub duration: Duration,
+    /// Status of the response when the deadline passes (503 or 504)
+    pub status: u16,
+    /// Value of the Retry-After header of '503 Service Unavailable' responses, if any
+    pub retry_after: Option<Duration>,
+}
+
+impl RequestTimeout {
+    /// Requests that take longer than the duration fail with a '503 Service Unavailable'
+    /// response, with a Retry-After header if `retry_after` is given
+    pub fn service_unavailable(
+        duration: Duration,
+        retry_after: Option<Duration>,
+    ) -> RequestTimeout {
+        RequestTimeout {
+            duration,
+            status: 503,
+            retry_after,
+        }
+    }
+
+    /// Requests that take longer than the duration fail with a '504 Gateway Timeout' response,
+    /// for resources that are waiting on an upstream service
+    pub fn gateway_timeout(duration: Duration) -> RequestTimeout {
+        RequestTimeout {
+            duration,
+            status: 504,
+            retry_after: None,
+        }
+    }
+
+    /// Replaces the response with the timeout response
+    pub(crate) fn timed_out(&self, context: &mut Context) {
+        warn!(
+            "Request to '{}' timed out after {:?}",
+            context.request.request_path, self.duration
+        );
+        context.response = Response {
+            status: self.status,
+            ..Response::default()
+        };
+        if let (503, Some(retry_after)) = (self.status, self.retry_after) {
+            context.response.add_header(
+                "Retry-After",
+                vec![HeaderValue::basic(
+                    retry_after_seconds(retry_after).to_string(),
+                )],
+            );
+        }
+        context.error = Some(format!("Request timed out after {:?}", self.duration));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{owned_callback, Dispatcher, Resource};
+    use expectest::prelude::*;
+
+    fn slow_resource() -> Resource<'static> {
+        Resource {
+            resource_exists: owned_callback(|_, _| {
+                Box::pin(async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    true
+                })
+            }),
+            ..Resource::default()
+        }
+    }
+
+    async fn dispatch(dispatcher: Dispatcher<'static>) -> http::Response<hyper::Body> {
+        let request = http::Request::builder()
+            .uri("/slow")
+            .body(hyper::Body::empty())
+            .unwrap();
+        dispatcher.dispatch(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_that_exceed_the_deadline_fail_with_a_503() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! { "/slow" => slow_resource() },
+            request_timeout: Some(RequestTimeout::service_unavailable(
+                Duration::from_millis(20),
+                Some(Duration::from_millis(1500)),
+            )),
+            ..Dispatcher::default()
+        };
+        let response = dispatch(dispatcher).await;
+        expect!(response.status().as_u16()).to(be_equal_to(503));
+        expect!(response.headers()["Retry-After"].to_str().unwrap()).to(be_equal_to("2"));
+    }
+
+    #[tokio::test]
+    async fn resource_deadline_overrides_the_dispatcher_deadline() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/slow" => Resource {
+                    request_timeout: Some(RequestTimeout::gateway_timeout(
+                        Duration::from_millis(20)
+                    )),
+                    ..slow_resource()
+                }
+            },
+            request_timeout: Some(RequestTimeout::service_unavailable(
+                Duration::from_secs(60),
+                None,
+            )),
+            ..Dispatcher::default()
+        };
+        let response = dispatch(dispatcher).await;
+        expect!(response.status().as_u16()).to(be_equal_to(504));
+        expect!(response.headers().contains_key("Retry-After")).to(be_false());
+    }
+}
@@ -0,0 +1,219 @@
+//! The `tls` module provides a policy that requires requests to be made over TLS, redirecting
+//! or rejecting plaintext requests and adding the Strict-Transport-Security header to secure
+//! responses. It also describes the certificates clients authenticate with when TLS client
+//! authentication (mTLS) is used.
+
+use http::request::Parts;
+use sha2::{Digest, Sha256};
+
+use crate::server::ConnectionInfo;
+
+/// What to do with requests that are not made over TLS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaintextAction {
+    /// Redirect to the https URL with the given status (301 or 308)
+    Redirect(u16),
+    /// Reject the request with the given status (403 or 426)
+    Reject(u16),
+}
+
+/// Configuration of the Strict-Transport-Security (HSTS) header
+#[derive(Debug, Clone, PartialEq)]
+pub struct HstsConfig {
+    /// Number of seconds clients should only access the host over TLS for. Defaults to a year.
+    pub max_age: u64,
+    /// If the policy also applies to subdomains of the host. Defaults to false.
+    pub include_subdomains: bool,
+    /// If the host consents to being preloaded into browsers. Defaults to false.
+    pub preload: bool,
+}
+
+impl Default for HstsConfig {
+    fn default() -> HstsConfig {
+        HstsConfig {
+            max_age: 31_536_000,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+}
+
+impl HstsConfig {
+    /// Returns the value of the Strict-Transport-Security header
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// Certificate a client authenticated with using TLS client authentication. The acceptor of
+/// the listener that terminated TLS provides it (see `server::Accepted`), filling in the details
+/// parsed by its TLS library, and it is then available on the requests of the connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertificate {
+    /// Certificate chain presented by the client, DER encoded, starting with the certificate of
+    /// the client
+    pub chain: Vec<Vec<u8>>,
+    /// Subject distinguished name of the certificate of the client (i.e. `CN=orders,O=Example`)
+    pub subject: Option<String>,
+    /// Issuer distinguished name of the certificate of the client
+    pub issuer: Option<String>,
+    /// Subject alternative names of the certificate of the client (i.e. DNS names, URIs)
+    pub subject_alt_names: Vec<String>,
+    /// Lowercase hex SHA-256 fingerprint of the certificate of the client
+    pub fingerprint: String,
+}
+
+impl ClientCertificate {
+    /// Creates the certificate of the chain, with its fingerprint. The subject, issuer and
+    /// subject alternative names are left for the acceptor to fill in.
+    pub fn new(chain: Vec<Vec<u8>>) -> ClientCertificate {
+        let fingerprint = chain
+            .first()
+            .map(|certificate| hex::encode(Sha256::digest(certificate)))
+            .unwrap_or_default();
+        ClientCertificate {
+            chain,
+            subject: None,
+            issuer: None,
+            subject_alt_names: Vec::new(),
+            fingerprint,
+        }
+    }
+
+    /// Returns the common name (CN) of the subject
+    pub fn common_name(&self) -> Option<&str> {
+        self.subject.as_deref()?.split(',').find_map(|attribute| {
+            let (name, value) = attribute.trim().split_once('=')?;
+            if name.trim().eq_ignore_ascii_case("CN") {
+                Some(value.trim())
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Policy that requires requests to be made over TLS. A request is considered secure if it was
+/// accepted by a `server::Listener` with an acceptor, or if it came through a TLS terminating
+/// proxy trusted by the `forwarded` config of the dispatcher that forwarded the https scheme.
+/// The X-Forwarded-Proto header of other requests and the scheme of an absolute-form request
+/// target are chosen by the client, so they are not trusted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsPolicy {
+    /// What to do with plaintext requests. Defaults to a 308 redirect. Plaintext requests are
+    /// only redirected to the `canonical_host`, or to the host they were made to if it is one of
+    /// the `allowed_hosts` of the dispatcher; otherwise they are rejected with a '403 Forbidden'.
+    pub plaintext_action: PlaintextAction,
+    /// Host (and port, if needed) plaintext requests are redirected to, regardless of the host
+    /// they were made to. Defaults to None.
+    pub canonical_host: Option<String>,
+    /// Port plaintext requests are redirected to when they are redirected to the host they were
+    /// made to, replacing the plaintext port of the request. Defaults to None (the default https
+    /// port).
+    pub https_port: Option<u16>,
+    /// Strict-Transport-Security header to add to secure responses. Defaults to the default
+    /// HSTS configuration.
+    pub hsts: Option<HstsConfig>,
+}
+
+impl Default for TlsPolicy {
+    fn default() -> TlsPolicy {
+        TlsPolicy {
+            plaintext_action: PlaintextAction::Redirect(308),
+            canonical_host: None,
+            https_port: None,
+            hsts: Some(HstsConfig::default()),
+        }
+    }
+}
+
+impl TlsPolicy {
+    /// If the request was made on a connection accepted by a secure listener. Requests that
+    /// came through a trusted proxy are checked with the scheme of the request instead (see
+    /// `Request::scheme`).
+    pub fn is_secure(&self, parts: &Parts) -> bool {
+        parts
+            .extensions
+            .get::<ConnectionInfo>()
+            .map(|info| info.secure)
+            .unwrap_or(false)
+    }
+
+    /// Returns the https URL on the host to redirect a plaintext request to. Returns None if
+    /// there is no host to redirect to. The host must have been checked by the caller.
+    pub fn redirect_url(&self, parts: &Parts, host: Option<&str>) -> Option<String> {
+        let host = host.filter(|host| !host.is_empty())?;
+        let path = parts
+            .uri
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+        Some(format!("https://{}{}", host, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expectest::prelude::*;
+
+    fn parts(uri: &str, forwarded_proto: Option<&str>) -> Parts {
+        let mut builder = http::Request::builder().uri(uri);
+        if let Some(proto) = forwarded_proto {
+            builder = builder.header("X-Forwarded-Proto", proto);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn is_secure_does_not_trust_the_headers_or_target_of_the_request() {
+        let policy = TlsPolicy::default();
+        expect!(policy.is_secure(&parts("/path", Some("https")))).to(be_false());
+        expect!(policy.is_secure(&parts("/path", Some("https, http")))).to(be_false());
+        expect!(policy.is_secure(&parts("https://example.com/path", None))).to(be_false());
+    }
+
+    #[test]
+    fn is_secure_if_the_connection_was_accepted_by_a_secure_listener() {
+        let mut parts = parts("/path", None);
+        parts.extensions.insert(ConnectionInfo {
+            listener: "https".to_string(),
+            remote_addr: None,
+            local_addr: None,
+            secure: true,
+            client_certificate: None,
+        });
+        expect!(TlsPolicy::default().is_secure(&parts)).to(be_true());
+    }
+
+    #[test]
+    fn hsts_header_value() {
+        expect!(HstsConfig::default().header_value()).to(be_equal_to("max-age=31536000"));
+        let hsts = HstsConfig {
+            max_age: 60,
+            include_subdomains: true,
+            preload: true,
+        };
+        expect!(hsts.header_value()).to(be_equal_to("max-age=60; includeSubDomains; preload"));
+    }
+
+    #[test]
+    fn client_certificate_has_the_fingerprint_and_common_name() {
+        let certificate = ClientCertificate {
+            subject: Some("O=Example, CN=orders".to_string()),
+            ..ClientCertificate::new(vec![b"client".to_vec(), b"ca".to_vec()])
+        };
+        expect!(certificate.fingerprint.as_str()).to(be_equal_to(
+            "948fe603f61dc036b5c596dc09fe3ce3f3d30dc90f024c85f3c82db2ccab679d",
+        ));
+        expect!(certificate.common_name()).to(be_some().value("orders"));
+        expect!(ClientCertificate::new(vec![]).common_name()).to(be_none());
+    }
+}
@@ -0,0 +1,100 @@
+//! The `transport` module separates the dispatcher from the transport its requests arrive over.
+//! The state machine, content negotiation and the context types do not depend on hyper; only
+//! `Dispatcher::dispatch` and the `server` module do. Other transports (i.e. CGI, another web
+//! framework or a test harness) implement `Adapter` to convert their requests and responses,
+//! and dispatch them with `Dispatcher::dispatch_with`.
+
+use futures::future::BoxFuture;
+
+use crate::context::{Request, Response};
+
+/// Converts the requests and responses of a transport to and from those of the dispatcher
+pub trait Adapter: Send + Sync {
+    /// Request type of the transport
+    type Request: Send;
+    /// Response type of the transport
+    type Response;
+
+    /// Reads the request, including its body. Returns an Err with the status code to respond
+    /// with if the request can not be read (i.e. 400 for a malformed request, or 413 for a body
+    /// over a limit of the transport).
+    fn read_request(&self, request: Self::Request) -> BoxFuture<'_, Result<Request, u16>>;
+
+    /// Converts the response of the dispatcher. A streamed body can be taken from
+    /// `response.stream`.
+    fn write_response(&self, response: Response) -> Self::Response;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{headers::HeaderValue, owned_callback, Dispatcher, Resource};
+    use expectest::prelude::*;
+
+    /// Transport of a test harness, with requests as a method, path and body, and responses as
+    /// a status and body
+    struct LineAdapter;
+
+    impl Adapter for LineAdapter {
+        type Request = (&'static str, &'static str, &'static str);
+        type Response = (u16, String);
+
+        fn read_request(&self, request: Self::Request) -> BoxFuture<'_, Result<Request, u16>> {
+            let (method, path, body) = request;
+            Box::pin(async move {
+                if !path.starts_with('/') {
+                    return Err(400);
+                }
+                Ok(Request {
+                    method: method.to_string(),
+                    request_path: path.to_string(),
+                    headers: hashmap! {
+                        "Content-Type".to_string() => vec![HeaderValue::basic("application/json")]
+                    },
+                    body: Some(body.as_bytes().to_vec()).filter(|body| !body.is_empty()),
+                    ..Request::default()
+                })
+            })
+        }
+
+        fn write_response(&self, response: Response) -> Self::Response {
+            let body = response.body.unwrap_or_default();
+            (response.status, String::from_utf8_lossy(&body).to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatches_requests_of_other_transports_through_an_adapter() {
+        let dispatcher = Dispatcher {
+            routes: btreemap! {
+                "/echo" => Resource {
+                    allowed_methods: vec!["POST"],
+                    process_post: owned_callback(|context, _| {
+                        context.response.body = context.request.body.clone();
+                        Box::pin(async { Ok(true) })
+                    }),
+                    ..Resource::default()
+                }
+            },
+            max_entity_length: Some(16),
+            ..Dispatcher::default()
+        };
+
+        let response = dispatcher
+            .dispatch_with(&LineAdapter, ("POST", "/echo", "{\"id\": 1}"))
+            .await;
+        expect!(response).to(be_equal_to((200, "{\"id\": 1}".to_string())));
+        let response = dispatcher
+            .dispatch_with(&LineAdapter, ("GET", "/other", ""))
+            .await;
+        expect!(response.0).to(be_equal_to(404));
+        let response = dispatcher
+            .dispatch_with(&LineAdapter, ("POST", "/echo", "{\"id\": 1000000000}"))
+            .await;
+        expect!(response.0).to(be_equal_to(413));
+        let response = dispatcher
+            .dispatch_with(&LineAdapter, ("GET", "echo", ""))
+            .await;
+        expect!(response.0).to(be_equal_to(400));
+    }
+}
@@ -1,5 +1,6 @@
 use expectest::prelude::*;
 use maplit::*;
+use std::collections::HashMap;
 
 use webmachine::{content_negotiation::*, context::*, headers::*, *};
 
@@ -108,6 +109,53 @@ fn matches_most_specific() {
     expect!(matching_content_type(&resource5, &request)).to(be_some().value("application/pdf"));
 }
 
+#[test]
+fn matches_the_media_types_produced_for_the_request_method() {
+    let resource = Resource {
+        produces: vec!["application/json"],
+        method_produces: hashmap! {
+          "GET" => vec!["text/html", "application/json"]
+        },
+        ..Resource::default()
+    };
+    let get_request = Request {
+        headers: hashmap! {
+          "Accept".to_string() => vec![HeaderValue::basic("text/html")]
+        },
+        ..Request::default()
+    };
+    let post_request = Request {
+        method: "POST".to_string(),
+        ..get_request.clone()
+    };
+    expect!(matching_content_type(&resource, &get_request)).to(be_some().value("text/html"));
+    expect!(matching_content_type(&resource, &post_request)).to(be_none());
+    expect!(matching_content_type(&resource, &Request::default())).to(be_some().value("text/html"));
+}
+
+#[test]
+fn matches_keep_the_parameters_of_the_produced_media_type() {
+    let resource = Resource {
+        produces: vec![
+            "application/vnd.orders+json;v=3",
+            "application/vnd.orders+json;v=2",
+        ],
+        ..Resource::default()
+    };
+    let request = |accept: &str| Request {
+        headers: hashmap! { "Accept".to_string() => vec![h!(accept)] },
+        ..Request::default()
+    };
+    expect!(matching_content_type(&resource, &request("application/vnd.orders+json")))
+        .to(be_some().value("application/vnd.orders+json;v=3"));
+    expect!(matching_content_type(&resource, &request("application/vnd.orders+json;v=2")))
+        .to(be_some().value("application/vnd.orders+json;v=2"));
+    expect!(matching_content_type(&resource, &request("application/*")))
+        .to(be_some().value("application/vnd.orders+json;v=3"));
+    expect!(matching_content_type(&resource, &request("application/vnd.orders+json;v=4")))
+        .to(be_none());
+}
+
 #[test]
 fn sort_media_types_basic_test() {
     expect!(sort_media_types(&vec![h!("text/plain")])).to(be_equal_to(vec![h!("text/plain")]));
@@ -167,37 +215,83 @@ fn sort_media_types_with_quality_weighting() {
     ]));
 }
 
+#[test]
+fn sort_media_types_ranks_media_types_with_parameters_higher() {
+    expect!(sort_media_types(&vec![
+        h!("text/*"),
+        h!("text/plain"),
+        h!("text/plain;format=flowed"),
+        h!("*/*")
+    ]))
+    .to(be_equal_to(vec![
+        h!("text/plain;format=flowed"),
+        h!("text/plain"),
+        h!("text/*"),
+        h!("*/*"),
+    ]));
+    expect!(sort_media_types(&vec![
+        h!("text/html"),
+        h!("text/html;level=1;q=1"),
+        h!("text/html;level=2;q=0.4")
+    ]))
+    .to(be_equal_to(vec![
+        h!("text/html;level=1;q=1"),
+        h!("text/html"),
+        h!("text/html;level=2;q=0.4"),
+    ]));
+}
+
+#[test]
+fn parse_media_type_with_parameters_test() {
+    expect!(MediaType::parse_string("text/html; Level=1; q=0.5; ext=x")).to(be_equal_to(
+        MediaType {
+            main: "text".to_string(),
+            sub: "html".to_string(),
+            weight: 0.5,
+            params: hashmap! { "level".to_string() => "1".to_string() },
+        },
+    ));
+    expect!(h!("text/html;level=1;q=0.7").as_media_type().weight()).to(be_equal_to((0.7, 0)));
+    expect!(h!("text/html;q=0.7").as_media_type().weight()).to(be_equal_to((0.7, 1)));
+}
+
 #[test]
 fn parse_media_type_test() {
     expect!(MediaType::parse_string("text/plain")).to(be_equal_to(MediaType {
         main: "text".to_string(),
         sub: "plain".to_string(),
         weight: 1.0,
+        params: HashMap::new(),
     }));
     expect!(MediaType::parse_string("text/*")).to(be_equal_to(MediaType {
         main: "text".to_string(),
         sub: "*".to_string(),
         weight: 1.0,
+        params: HashMap::new(),
     }));
     expect!(MediaType::parse_string("*/*")).to(be_equal_to(MediaType {
         main: "*".to_string(),
         sub: "*".to_string(),
         weight: 1.0,
+        params: HashMap::new(),
     }));
     expect!(MediaType::parse_string("text/")).to(be_equal_to(MediaType {
         main: "text".to_string(),
         sub: "*".to_string(),
         weight: 1.0,
+        params: HashMap::new(),
     }));
     expect!(MediaType::parse_string("text")).to(be_equal_to(MediaType {
         main: "text".to_string(),
         sub: "*".to_string(),
         weight: 1.0,
+        params: HashMap::new(),
     }));
     expect!(MediaType::parse_string("")).to(be_equal_to(MediaType {
         main: "*".to_string(),
         sub: "*".to_string(),
         weight: 1.0,
+        params: HashMap::new(),
     }));
 }
 
@@ -207,29 +301,34 @@ fn media_type_matches_test() {
         main: "application".to_string(),
         sub: "json".to_string(),
         weight: 1.0,
+        params: HashMap::new(),
     };
     expect!(media_type.matches(&MediaType {
         main: "application".to_string(),
         sub: "json".to_string(),
-        weight: 1.0
+        weight: 1.0,
+        params: HashMap::new(),
     }))
     .to(be_equal_to(MediaTypeMatch::Full));
     expect!(media_type.matches(&MediaType {
         main: "application".to_string(),
         sub: "*".to_string(),
-        weight: 1.0
+        weight: 1.0,
+        params: HashMap::new(),
     }))
     .to(be_equal_to(MediaTypeMatch::SubStar));
     expect!(media_type.matches(&MediaType {
         main: "*".to_string(),
         sub: "*".to_string(),
-        weight: 1.0
+        weight: 1.0,
+        params: HashMap::new(),
     }))
     .to(be_equal_to(MediaTypeMatch::Star));
     expect!(media_type.matches(&MediaType {
         main: "application".to_string(),
         sub: "application".to_string(),
-        weight: 1.0
+        weight: 1.0,
+        params: HashMap::new(),
     }))
     .to(be_equal_to(MediaTypeMatch::None));
 }
@@ -785,3 +884,93 @@ fn encoding_matches_test() {
     expect!(Encoding::parse_string("gzip").matches(&Encoding::parse_string("GZip"))).to(be_true());
     expect!(Encoding::parse_string("compress").matches(&Encoding::parse_string("*"))).to(be_true());
 }
+
+#[test]
+fn matching_variant_negotiates_media_type_and_language_together() {
+    let resource = Resource {
+        variants: vec![
+            Variant::new("text/html", Some("en")),
+            Variant::new("application/json", Some("en")),
+            Variant::new("text/html", Some("de")),
+        ],
+        ..Resource::default()
+    };
+    let request = |accept: &str, accept_language: &str| Request {
+        headers: hashmap! {
+          "Accept".to_string() => HeaderValue::parse_list(accept),
+          "Accept-Language".to_string() => HeaderValue::parse_list(accept_language)
+        },
+        ..Request::default()
+    };
+    // German is preferred, but only exists as HTML, which is preferable to English JSON
+    let german_preferred = request("application/json, text/html;q=0.8", "de, en;q=0.5");
+    expect!(matching_variant(&resource, &german_preferred)).to(be_some().value(2));
+    // German JSON does not exist, and German HTML is not acceptable
+    expect!(matching_variant(&resource, &request("application/json", "de, en;q=0.5")))
+        .to(be_some().value(1));
+    expect!(matching_variant(&resource, &request("application/json", "de"))).to(be_none());
+    // the range de-AT does not match the language tag de
+    expect!(matching_variant(&resource, &request("*/*", "de-AT"))).to(be_none());
+    expect!(matching_variant(&resource, &request("*/*", "en-GB, en;q=0.1")))
+        .to(be_some().value(0));
+    expect!(matching_variant(&resource, &Request::default())).to(be_some().value(0));
+}
+
+#[test]
+fn classifies_requests_by_their_user_agent() {
+    let classifier = UserAgentClassifier::default();
+    let request = |user_agent: &str| Request {
+        headers: hashmap! {
+          "User-Agent".to_string() => vec![HeaderValue::basic(user_agent)]
+        },
+        ..Request::default()
+    };
+    let chrome = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) \
+        Chrome/120.0.0.0 Safari/537.36";
+    let googlebot = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+    expect!(classifier.classify(&request(chrome))).to(be_equal_to(UserAgentClass::Browser));
+    expect!(classifier.classify(&request(googlebot))).to(be_equal_to(UserAgentClass::Bot));
+    expect!(classifier.classify(&request("curl/8.4.0")))
+        .to(be_equal_to(UserAgentClass::CommandLine));
+    expect!(classifier.classify(&request("my-service/1.0"))).to(be_equal_to(UserAgentClass::Other));
+    expect!(classifier.classify(&Request::default())).to(be_equal_to(UserAgentClass::Other));
+}
+
+#[test]
+fn user_agent_negotiation_prefers_html_for_browsers_without_an_accept_header() {
+    let resource = Resource {
+        produces: vec!["application/json", "text/html"],
+        ..Resource::default()
+    };
+    let negotiation = UserAgentNegotiation::default();
+    let request = negotiation
+        .apply(&Request::default(), UserAgentClass::Browser)
+        .unwrap();
+    expect!(matching_content_type(&resource, &request)).to(be_some().value("text/html"));
+    let request = negotiation
+        .apply(&Request::default(), UserAgentClass::Bot)
+        .unwrap();
+    expect!(matching_content_type(&resource, &request)).to(be_some().value("application/json"));
+
+    let resource = Resource {
+        produces: vec!["text/csv"],
+        ..Resource::default()
+    };
+    expect!(matching_content_type(&resource, &request)).to(be_some().value("text/csv"));
+    let request = Request {
+        headers: hashmap! { "Accept".to_string() => vec![HeaderValue::basic("text/csv")] },
+        ..Request::default()
+    };
+    expect!(negotiation.apply(&request, UserAgentClass::Browser)).to(be_none());
+}
+
+#[test]
+fn normalize_accept_encoding_ignores_header_noise() {
+    let normalized =
+        normalize_accept_encoding(&[h!("GZIP;q=1.0"), h!("identity;q=0.5"), h!("br")]);
+    expect!(normalized.clone()).to(be_equal_to("gzip, br, identity;q=0.5"));
+    expect!(normalize_accept_encoding(&[h!("br"), h!("gzip"), h!("identity;q=0.5")]))
+        .to_not(be_equal_to(normalized.clone()));
+    expect!(normalize_accept_encoding(&[h!("gzip"), h!("br"), h!("identity; q=0.50")]))
+        .to(be_equal_to(normalized));
+}